@@ -0,0 +1,122 @@
+//! A serial console over USB (CDC-ACM), offering the command shell from
+//! `display-shell`. Connect with eg `picocom /dev/ttyACM0` and type `help`.
+//!
+//! There's no executor here, so the console is serviced from the main loop,
+//! after each poll of the USB device.
+
+use core::fmt;
+
+use display_shell::{Board, Input, LineEditor};
+use heapless::Vec;
+use usb_device::bus::UsbBus;
+use usbd_serial::SerialPort;
+
+/// Room for the longest response, the help text, and then some
+const OUTPUT_LEN: usize = 512;
+
+pub struct Console {
+    editor: LineEditor,
+    out: Output,
+    /// The host had the port open when last serviced
+    connected: bool,
+}
+
+impl Console {
+    pub const fn new() -> Self {
+        Console {
+            editor: LineEditor::new(),
+            out: Output { buf: Vec::new() },
+            connected: false,
+        }
+    }
+
+    /// Run what the host has sent, and send it what's waiting
+    pub fn service<B: UsbBus>(&mut self, serial: &mut SerialPort<B>, board: &mut impl Board) {
+        // The host sets DTR when a terminal opens the port
+        let connected = serial.dtr();
+        if connected && !self.connected {
+            self.editor = LineEditor::new();
+            self.out.buf.clear();
+            self.out.push(display_shell::PROMPT.as_bytes());
+        }
+        self.connected = connected;
+        if !connected {
+            return;
+        }
+
+        // Take input a byte at a time, and only while there's room for a
+        // response, leaving the rest in the serial port's buffer until
+        // what's waiting has been sent
+        let mut byte = [0];
+        while self.out.buf.len() < OUTPUT_LEN / 2 {
+            match serial.read(&mut byte) {
+                Ok(1) => self.input(byte[0], board),
+                _ => break,
+            }
+        }
+
+        if !self.out.buf.is_empty() {
+            if let Ok(n) = serial.write(&self.out.buf) {
+                let remaining = self.out.buf.len() - n;
+                self.out.buf.copy_within(n.., 0);
+                self.out.buf.truncate(remaining);
+            }
+        }
+    }
+
+    fn input(&mut self, b: u8, board: &mut impl Board) {
+        let echo = board.settings().echo;
+        let out = &mut self.out;
+        match self.editor.push(b) {
+            Input::None => {}
+            Input::Char(c) => {
+                if echo {
+                    out.push(&[c]);
+                }
+            }
+            Input::Erase => {
+                if echo {
+                    out.push(display_shell::ERASE);
+                }
+            }
+            Input::Line(line) => {
+                if echo {
+                    out.push(display_shell::NEWLINE);
+                }
+                let len = out.buf.len();
+                if display_shell::run(board, line, out).is_err() {
+                    out.buf.truncate(len);
+                    out.push(b"error: response too long\r\n");
+                }
+                out.push(display_shell::PROMPT.as_bytes());
+            }
+            Input::Invalid => {
+                if echo {
+                    out.push(display_shell::NEWLINE);
+                }
+                out.push(b"error: line too long\r\n");
+                out.push(display_shell::PROMPT.as_bytes());
+            }
+        }
+    }
+}
+
+/// Collects what's to be sent to the host
+struct Output {
+    buf: Vec<u8, OUTPUT_LEN>,
+}
+
+impl Output {
+    /// Add bytes to be echoed, dropping them if there's no room
+    fn push(&mut self, bytes: &[u8]) {
+        let _ = self.buf.extend_from_slice(bytes);
+    }
+}
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.buf
+            .extend_from_slice(s.as_bytes())
+            .map_err(|_| fmt::Error)
+    }
+}
@@ -1,6 +1,7 @@
 //! Blinks the LED on a Pico board
 //!
 //! This will blink an LED attached to GP25, which is the pin the Pico uses for the on-board LED.
+//! The LED can also be driven from a serial console over USB, see `console`.
 #![no_std]
 #![no_main]
 
@@ -23,8 +24,14 @@ use bsp::hal::{
     gpio, pac,
     sio::Sio,
     spi,
+    timer::Timer,
+    usb::UsbBus,
     watchdog::Watchdog,
 };
+use usb_device::{class_prelude::UsbBusAllocator, prelude::*};
+use usbd_serial::SerialPort;
+
+use display_shell::{Board, Calibration, Indicator, LedMode, LogView, Settings};
 
 use embedded_graphics::{
     mono_font::MonoTextStyle,
@@ -37,6 +44,11 @@ use embedded_graphics::{
 use display_interface_spi::SPIInterface;
 use ili9341::{Ili9341, Orientation};
 
+mod console;
+
+/// The LED blinks until told otherwise from the console
+const DEFAULT_BLINK_MS: u32 = 1000;
+
 type Display = Ili9341<
     SPIInterface<
         spi::Spi<spi::Enabled, pac::SPI1, 8>,
@@ -104,6 +116,23 @@ fn main() -> ! {
     };
     let in1_pin = pins.gpio16.into_pull_up_input();
 
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS);
+    let usb_bus = UsbBusAllocator::new(UsbBus::new(
+        pac.USBCTRL_REGS,
+        pac.USBCTRL_DPRAM,
+        clocks.usb_clock,
+        true,
+        &mut pac.RESETS,
+    ));
+    let mut serial = SerialPort::new(&usb_bus);
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .manufacturer("pico demos")
+        .product("display-basic console")
+        .serial_number("0")
+        .device_class(usbd_serial::USB_CLASS_CDC)
+        .build();
+    let mut console = console::Console::new();
+
     let character_style = MonoTextStyle::new(&profont::PROFONT_24_POINT, Rgb565::WHITE);
     let text_style = TextStyle::with_baseline(Baseline::Top);
     let black_fill = PrimitiveStyleBuilder::new()
@@ -120,23 +149,107 @@ fn main() -> ! {
         .draw(&mut display)
         .unwrap();
 
-    let mut blink = false;
+    let mut board = BasicBoard {
+        led: LedMode::Blink {
+            period_ms: DEFAULT_BLINK_MS,
+        },
+        led_on: false,
+        button: false,
+        settings: Settings::new(DEFAULT_BLINK_MS),
+        now_us: 0,
+    };
+    let mut next_blink = 0;
+    // What the indicators last showed, so they're only drawn on a change
+    let mut shown = None;
 
+    // The USB device has to be polled often, so rather than sleeping the loop
+    // keeps going, and blinks by the timer
     loop {
-        if blink {
+        usb_dev.poll(&mut [&mut serial]);
+        if usb_dev.state() == UsbDeviceState::Configured {
+            console.service(&mut serial, &mut board);
+        }
+
+        board.now_us = timer.get_counter().ticks();
+        match board.led {
+            LedMode::Off => board.led_on = false,
+            LedMode::On => board.led_on = true,
+            LedMode::Blink { period_ms } => {
+                if board.now_us >= next_blink {
+                    board.led_on = !board.led_on;
+                    next_blink = board.now_us + period_ms as u64 * 500;
+                }
+            }
+        }
+        if board.led_on {
             led_pin.set_high().unwrap();
         } else {
             led_pin.set_low().unwrap();
         }
-        render_indicator(&mut display, Point::new(120, 120), blink);
-        render_indicator(
-            &mut display,
-            Point::new(180, 120),
-            in1_pin.is_high().unwrap(),
-        );
+        board.button = in1_pin.is_high().unwrap();
+
+        if shown != Some((board.led_on, board.button)) {
+            render_indicator(&mut display, Point::new(120, 120), board.led_on);
+            render_indicator(&mut display, Point::new(180, 120), board.button);
+            shown = Some((board.led_on, board.button));
+        }
+    }
+}
+
+/// The board, as the console's shell sees it
+struct BasicBoard {
+    led: LedMode,
+    led_on: bool,
+    button: bool,
+    settings: Settings,
+    now_us: u64,
+}
+
+impl Board for BasicBoard {
+    fn led(&self) -> LedMode {
+        self.led
+    }
+
+    fn set_led(&mut self, mode: LedMode) {
+        self.led = mode;
+    }
+
+    fn indicator(&self, i: usize) -> Option<(&'static str, Indicator)> {
+        match i {
+            0 => Some(("led", Indicator::from_bool(self.led_on))),
+            1 => Some(("button", Indicator::from_bool(self.button))),
+            _ => None,
+        }
+    }
+
+    /// There's no touch screen
+    fn calibration(&self) -> Option<Calibration> {
+        None
+    }
+
+    fn set_calibration(&mut self, _calibration: Calibration) {}
+
+    fn last_touch(&self) -> Option<display_shell::Touch> {
+        None
+    }
+
+    /// The display isn't used for logging
+    fn log_view(&self) -> Option<LogView> {
+        None
+    }
+
+    fn set_log_view(&mut self, _view: LogView) {}
+
+    fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
 
-        blink = !blink;
-        delay.delay_ms(500);
+    fn uptime_ms(&self) -> u64 {
+        self.now_us / 1000
     }
 }
 
@@ -0,0 +1,191 @@
+//! A serial console over USB (CDC-ACM), offering the command shell from
+//! `display-shell`. Connect with eg `picocom /dev/ttyACM0` and type `help`.
+
+use core::fmt;
+
+use defmt::*;
+use display_shell::{Board, Calibration, Indicator, Input, LedMode, LineEditor, LogView, Settings};
+use embassy_executor::Spawner;
+use embassy_time::Instant;
+use embassy_usb::{
+    class::cdc_acm::{CdcAcmClass, State},
+    driver::EndpointError,
+    Builder,
+};
+use heapless::Vec;
+use static_cell::StaticCell;
+
+use crate::usb::UsbDriver;
+use crate::{DISPLAY_STATE, LED_STATE, SHELL_STATE};
+
+/// Room for the longest response, the help text
+const OUTPUT_LEN: usize = 512;
+
+static CDC_STATE: StaticCell<State> = StaticCell::new();
+
+/// Add the serial port to the USB device, and start the console on it
+pub fn start(spawner: &Spawner, builder: &mut Builder<'static, UsbDriver>) {
+    let class = CdcAcmClass::new(builder, CDC_STATE.init(State::new()), 64);
+    unwrap!(spawner.spawn(console_task(class)));
+}
+
+#[embassy_executor::task]
+async fn console_task(mut class: CdcAcmClass<'static, UsbDriver>) -> ! {
+    loop {
+        class.wait_connection().await;
+        log_info!("console connected");
+        if let Err(e) = session(&mut class).await {
+            log_info!("console disconnected: {:?}", e);
+        }
+    }
+}
+
+/// Run the shell until the host goes away
+async fn session(class: &mut CdcAcmClass<'static, UsbDriver>) -> Result<(), EndpointError> {
+    let mut editor: LineEditor = LineEditor::new();
+    let mut out = Output::default();
+    let mut packet = [0; 64];
+    write_all(class, display_shell::PROMPT.as_bytes()).await?;
+    loop {
+        let n = class.read_packet(&mut packet).await?;
+        let echo = SHELL_STATE.lock(|s| s.borrow().settings.echo);
+        for &b in &packet[..n] {
+            match editor.push(b) {
+                Input::None => {}
+                Input::Char(c) => {
+                    if echo {
+                        out.push(&[c]);
+                    }
+                }
+                Input::Erase => {
+                    if echo {
+                        out.push(display_shell::ERASE);
+                    }
+                }
+                Input::Line(line) => {
+                    if echo {
+                        out.push(display_shell::NEWLINE);
+                    }
+                    write_all(class, &out.buf).await?;
+                    out.buf.clear();
+                    log_info!("console: {}", line);
+                    if display_shell::run(&mut ConsoleBoard, line, &mut out).is_err() {
+                        out.buf.clear();
+                        out.push(b"error: response too long\r\n");
+                    }
+                    out.push(display_shell::PROMPT.as_bytes());
+                }
+                Input::Invalid => {
+                    if echo {
+                        out.push(display_shell::NEWLINE);
+                    }
+                    out.push(b"error: line too long\r\n");
+                    out.push(display_shell::PROMPT.as_bytes());
+                }
+            }
+            // Flush once half full, so a pasted run of commands can't
+            // overflow the buffer
+            if out.buf.len() > OUTPUT_LEN / 2 {
+                write_all(class, &out.buf).await?;
+                out.buf.clear();
+            }
+        }
+        write_all(class, &out.buf).await?;
+        out.buf.clear();
+    }
+}
+
+/// Send `data` as a single transfer, in packets
+async fn write_all(
+    class: &mut CdcAcmClass<'static, UsbDriver>,
+    data: &[u8],
+) -> Result<(), EndpointError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    let max = class.max_packet_size() as usize;
+    for chunk in data.chunks(max) {
+        class.write_packet(chunk).await?;
+    }
+    // A full last packet doesn't end the transfer, so follow it with an
+    // empty one
+    if data.len() % max == 0 {
+        class.write_packet(&[]).await?;
+    }
+    Ok(())
+}
+
+/// Collects what's to be sent to the host
+#[derive(Default)]
+struct Output {
+    buf: Vec<u8, OUTPUT_LEN>,
+}
+
+impl Output {
+    /// Add bytes to be echoed, dropping them if there's no room
+    fn push(&mut self, bytes: &[u8]) {
+        let _ = self.buf.extend_from_slice(bytes);
+    }
+}
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.buf
+            .extend_from_slice(s.as_bytes())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+/// The board, as the shell sees it
+struct ConsoleBoard;
+
+impl Board for ConsoleBoard {
+    fn led(&self) -> LedMode {
+        LED_STATE.state.lock(|s| *s.borrow())
+    }
+
+    fn set_led(&mut self, mode: LedMode) {
+        LED_STATE.update(|s| *s = mode);
+    }
+
+    fn indicator(&self, i: usize) -> Option<(&'static str, Indicator)> {
+        let name = *["led", "button", "touch"].get(i)?;
+        let indicator = DISPLAY_STATE.state.lock(|s| {
+            let s = s.borrow();
+            [s.indicator1, s.indicator2, s.indicator3][i]
+        });
+        Some((name, indicator))
+    }
+
+    fn calibration(&self) -> Option<Calibration> {
+        Some(SHELL_STATE.lock(|s| s.borrow().calibration))
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        SHELL_STATE.lock(|s| s.borrow_mut().calibration = calibration);
+    }
+
+    fn last_touch(&self) -> Option<display_shell::Touch> {
+        SHELL_STATE.lock(|s| s.borrow().last_touch)
+    }
+
+    fn log_view(&self) -> Option<LogView> {
+        Some(DISPLAY_STATE.state.lock(|s| s.borrow().log_view))
+    }
+
+    fn set_log_view(&mut self, view: LogView) {
+        DISPLAY_STATE.update(|s| s.log_view = view);
+    }
+
+    fn settings(&self) -> Settings {
+        SHELL_STATE.lock(|s| s.borrow().settings)
+    }
+
+    fn set_settings(&mut self, settings: Settings) {
+        SHELL_STATE.lock(|s| s.borrow_mut().settings = settings);
+    }
+
+    fn uptime_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+}
@@ -39,26 +39,9 @@ pub fn init_touch_spi_config() -> spi::Config {
 
 /// Driver for the XPT2046 resistive touchscreen sensor
 pub mod touch {
+    use display_shell::Calibration;
     use embedded_hal_1::spi::{Operation, SpiDevice};
 
-    struct Calibration {
-        x1: i32,
-        x2: i32,
-        y1: i32,
-        y2: i32,
-        sx: i32,
-        sy: i32,
-    }
-
-    const CALIBRATION: Calibration = Calibration {
-        x1: 3880,
-        x2: 340,
-        y1: 262,
-        y2: 3850,
-        sx: 320,
-        sy: 240,
-    };
-
     pub struct Touch<SPI: SpiDevice> {
         spi: SPI,
     }
@@ -71,7 +54,8 @@ pub mod touch {
             Self { spi }
         }
 
-        pub fn read(&mut self) -> Option<(i32, i32)> {
+        /// Read the touch position, mapped to the display with `calibration`
+        pub fn read(&mut self, calibration: &Calibration) -> Option<display_shell::Touch> {
             let mut x = [0; 2];
             let mut y = [0; 2];
             self.spi
@@ -85,16 +69,7 @@ pub mod touch {
 
             let x = (u16::from_be_bytes(x) >> 3) as i32;
             let y = (u16::from_be_bytes(y) >> 3) as i32;
-
-            let cal = &CALIBRATION;
-
-            let x = ((x - cal.x1) * cal.sx / (cal.x2 - cal.x1)).clamp(0, cal.sx);
-            let y = ((y - cal.y1) * cal.sy / (cal.y2 - cal.y1)).clamp(0, cal.sy);
-            if x == 0 && y == 0 {
-                None
-            } else {
-                Some((x, y))
-            }
+            calibration.apply(x, y)
         }
     }
 }
@@ -0,0 +1,80 @@
+//! The touch screen and button as a USB HID device, so the board works as a
+//! touch screen and a key for the PC it's plugged into. The reports are made
+//! by `display_shell::hid`.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::*;
+use display_shell::hid::{KEY_REPORT_LEN, REPORT_DESCRIPTOR, TOUCH_REPORT_LEN};
+use embassy_executor::Spawner;
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel};
+use embassy_usb::{
+    class::hid::{Config, HidWriter, State},
+    Builder,
+};
+use static_cell::StaticCell;
+
+use crate::usb::UsbDriver;
+
+/// Longest report, and the interrupt endpoint's packet size
+const MAX_REPORT: usize = 16;
+
+pub enum Report {
+    Touch([u8; TOUCH_REPORT_LEN]),
+    Key([u8; KEY_REPORT_LEN]),
+}
+
+impl Report {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Report::Touch(report) => report,
+            Report::Key(report) => report,
+        }
+    }
+}
+
+static REPORTS: Channel<ThreadModeRawMutex, Report, 8> = Channel::new();
+
+static HID_STATE: StaticCell<State> = StaticCell::new();
+
+/// Add the HID interface to the USB device, and start sending reports on it
+pub fn start(spawner: &Spawner, builder: &mut Builder<'static, UsbDriver>) {
+    let config = Config {
+        report_descriptor: REPORT_DESCRIPTOR,
+        request_handler: None,
+        poll_ms: 10,
+        max_packet_size: MAX_REPORT as u16,
+    };
+    let writer = HidWriter::<_, MAX_REPORT>::new(builder, HID_STATE.init(State::new()), config);
+    unwrap!(spawner.spawn(hid_task(writer)));
+}
+
+/// Reports are being dropped, so it's only logged once
+static DROPPING: AtomicBool = AtomicBool::new(false);
+
+/// Queue a report for the host, dropping it if the queue is full
+pub fn send(report: Report) {
+    if REPORTS.try_send(report).is_ok() {
+        DROPPING.store(false, Ordering::Relaxed);
+    } else if !DROPPING.load(Ordering::Relaxed) {
+        DROPPING.store(true, Ordering::Relaxed);
+        log_warn!("hid reports dropped, the host isn't taking them");
+    }
+}
+
+#[embassy_executor::task]
+async fn hid_task(mut writer: HidWriter<'static, UsbDriver, MAX_REPORT>) -> ! {
+    loop {
+        writer.ready().await;
+        // Reports queued while unplugged are stale
+        while REPORTS.try_receive().is_ok() {}
+        log_info!("hid connected");
+        loop {
+            let report = REPORTS.receive().await;
+            if let Err(e) = writer.write(report.as_bytes()).await {
+                log_info!("hid disconnected: {:?}", e);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! An on-screen log: the recent log lines, kept in a ring buffer, and shown
+//! on the display coloured by level and wrapped to the font's width.
+//!
+//! New lines are added using the controller's hardware vertical scrolling,
+//! so what's already shown isn't redrawn. That scrolls along the panel's long
+//! side, so the log is shown with the display in portrait.
+
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+
+use display_shell::LogView;
+use embassy_sync::blocking_mutex::ThreadModeMutex;
+use embassy_time::Instant;
+use embedded_graphics::{
+    mono_font::{MonoFont, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use heapless::{Deque, String};
+
+use crate::hardware::MyDisplay;
+
+/// Lines kept, and the longest kept
+const LINES: usize = 32;
+const MAX_LINE: usize = 96;
+
+/// The display's size in portrait
+const WIDTH: u16 = 240;
+const HEIGHT: u16 = 320;
+
+/// Lines above the log when it's shown as a panel, for the title and
+/// indicators
+pub const PANEL_TOP: u16 = 120;
+
+#[derive(Clone, Copy)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn colour(&self) -> Rgb565 {
+        match self {
+            Level::Info => Rgb565::WHITE,
+            Level::Warn => Rgb565::YELLOW,
+            Level::Error => Rgb565::RED,
+        }
+    }
+}
+
+struct Line {
+    level: Level,
+    uptime_ms: u64,
+    text: String<MAX_LINE>,
+}
+
+struct Log {
+    lines: Deque<Line, LINES>,
+    /// Lines logged since startup, including those since dropped
+    count: u32,
+}
+
+static LOG: ThreadModeMutex<RefCell<Log>> = ThreadModeMutex::new(RefCell::new(Log {
+    lines: Deque::new(),
+    count: 0,
+}));
+
+/// Add a line to the log, dropping the oldest if it's full. Used by the
+/// logging macros.
+pub fn log(level: Level, args: fmt::Arguments) {
+    let mut text = String::new();
+    // Truncated if it doesn't fit
+    text.write_fmt(args).ok();
+    let line = Line {
+        level,
+        uptime_ms: Instant::now().as_millis(),
+        text,
+    };
+    LOG.lock(|log| {
+        let mut log = log.borrow_mut();
+        if log.lines.is_full() {
+            log.lines.pop_front();
+        }
+        log.lines.push_back(line).ok();
+        log.count = log.count.wrapping_add(1);
+    });
+    crate::DISPLAY_STATE.signal.signal(());
+}
+
+/// Shows the log on the display, in a scrolling area at the bottom.
+///
+/// The scrolling area is a ring of rows. Each new row is drawn over the
+/// oldest, which is shown at the top of the area, and then the area is
+/// scrolled by a row, so the new one is shown at the bottom.
+pub struct LogConsole {
+    font: &'static MonoFont<'static>,
+    /// The first display line of the scrolling area
+    top: u16,
+    /// Rows of text that fit in the scrolling area
+    rows: u16,
+    /// The row to be drawn next
+    next: u16,
+    /// The log's count when it was last drawn
+    drawn: u32,
+}
+
+impl LogConsole {
+    pub const fn new(font: &'static MonoFont<'static>) -> Self {
+        LogConsole {
+            font,
+            top: 0,
+            rows: 1,
+            next: 0,
+            drawn: 0,
+        }
+    }
+
+    fn row_height(&self) -> u16 {
+        self.font.character_size.height as u16
+    }
+
+    /// Characters that fit on a row
+    fn columns(&self) -> usize {
+        let width = self.font.character_size.width + self.font.character_spacing;
+        WIDTH as usize / width as usize
+    }
+
+    /// Set up the scrolling area for `view`, with the display in portrait,
+    /// and draw the kept lines in it
+    pub fn show(&mut self, display: &mut MyDisplay, view: LogView) {
+        let fixed = match view {
+            LogView::Panel => PANEL_TOP,
+            LogView::Off | LogView::Full => 0,
+        };
+        // A whole number of rows, so they line up as the area wraps
+        let height = self.row_height();
+        self.rows = (HEIGHT - fixed) / height;
+        self.top = HEIGHT - self.rows * height;
+        self.next = 0;
+        Rectangle::new(
+            Point::new(0, fixed as i32),
+            Size::new(WIDTH as u32, (HEIGHT - fixed) as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(Rgb565::BLACK))
+        .draw(display)
+        .unwrap();
+        self.scroll(display);
+
+        self.drawn = LOG.lock(|log| {
+            let log = log.borrow();
+            log.count.wrapping_sub(log.lines.len() as u32)
+        });
+        self.draw_new(display);
+    }
+
+    /// Stop scrolling, for the display's usual layout
+    pub fn hide(&mut self, display: &mut MyDisplay) {
+        let mut scroller = display.configure_vertical_scroll(0, 0).unwrap();
+        display.scroll_vertically(&mut scroller, 0).unwrap();
+    }
+
+    /// Draw the lines logged since last drawn
+    pub fn draw_new(&mut self, display: &mut MyDisplay) {
+        // A line at a time, so the log isn't locked while drawing
+        while let Some(line) = self.take_new() {
+            self.draw_line(display, &line);
+        }
+    }
+
+    /// A copy of the oldest line not yet drawn. Lines dropped from the log
+    /// before they were drawn are skipped.
+    fn take_new(&mut self) -> Option<Line> {
+        LOG.lock(|log| {
+            let log = log.borrow();
+            let new = log
+                .count
+                .wrapping_sub(self.drawn)
+                .min(log.lines.len() as u32);
+            if new == 0 {
+                return None;
+            }
+            self.drawn = log.count.wrapping_sub(new - 1);
+            let line = log.lines.iter().nth(log.lines.len() - new as usize)?;
+            Some(Line {
+                level: line.level,
+                uptime_ms: line.uptime_ms,
+                text: line.text.clone(),
+            })
+        })
+    }
+
+    /// Draw a line, after its time, wrapped over as many rows as it needs
+    fn draw_line(&mut self, display: &mut MyDisplay, line: &Line) {
+        let mut text: String<{ MAX_LINE + 16 }> = String::new();
+        let secs = line.uptime_ms / 1000;
+        let tenths = line.uptime_ms % 1000 / 100;
+        write!(text, "{}.{} {}", secs, tenths, line.text).ok();
+
+        let columns = self.columns();
+        let mut rest = text.as_str();
+        while !rest.is_empty() {
+            let end = rest
+                .char_indices()
+                .nth(columns)
+                .map_or(rest.len(), |(i, _)| i);
+            let (row, r) = rest.split_at(end);
+            self.draw_row(display, row, line.level.colour());
+            rest = r;
+        }
+    }
+
+    fn draw_row(&mut self, display: &mut MyDisplay, text: &str, colour: Rgb565) {
+        let height = self.row_height();
+        let at = Point::new(0, (self.top + self.next * height) as i32);
+        Rectangle::new(at, Size::new(WIDTH as u32, height as u32))
+            .into_styled(PrimitiveStyle::with_fill(Rgb565::BLACK))
+            .draw(display)
+            .unwrap();
+        let style = MonoTextStyle::new(self.font, colour);
+        Text::with_baseline(text, at, style, Baseline::Top)
+            .draw(display)
+            .unwrap();
+        self.next = (self.next + 1) % self.rows;
+        self.scroll(display);
+    }
+
+    /// Show the row to be drawn next, the oldest, at the top of the
+    /// scrolling area, and so the newest at the bottom
+    fn scroll(&mut self, display: &mut MyDisplay) {
+        // The position is set from the start of the area each time, rather
+        // than relying on how Scroller wraps
+        let mut scroller = display.configure_vertical_scroll(self.top, 0).unwrap();
+        display
+            .scroll_vertically(&mut scroller, self.next * self.row_height())
+            .unwrap();
+    }
+}
@@ -4,10 +4,13 @@
 use crate::hardware::{init_touch_spi_config, touch::Touch, MyTouch};
 use core::cell::RefCell;
 use defmt::*;
+use display_shell::hid::{key_report, Digitizer};
+use display_shell::{Calibration, Indicator, LedMode, LogView, Settings};
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDeviceWithConfig;
 use embassy_executor::Spawner;
+use embassy_futures::select::select;
 use embassy_rp::gpio;
-use embassy_sync::blocking_mutex::NoopMutex;
+use embassy_sync::blocking_mutex::{NoopMutex, ThreadModeMutex};
 use embassy_time::{Duration, Timer};
 use gpio::{Input, Level, Output, Pull};
 use static_cell::StaticCell;
@@ -18,7 +21,7 @@ use {defmt_rtt as _, panic_probe as _};
 use embedded_graphics::{
     pixelcolor::Rgb565,
     prelude::*,
-    primitives::{Circle, PrimitiveStyleBuilder, Rectangle},
+    primitives::{Circle, PrimitiveStyleBuilder},
     text::Text,
 };
 
@@ -26,18 +29,45 @@ use ili9341::Ili9341;
 use ili9341::Orientation;
 
 use hardware::{init_display_spi_config, init_my_spi_bus, MyDisplay, MySpiBus};
+use logview::LogConsole;
 
+/// Log with defmt, and also to the on-screen log. The message is formatted
+/// twice, so the arguments must suit both defmt and core::fmt: plain `{}`
+/// with integers and strings, or `{:?}`.
+macro_rules! tee_log {
+    ($level:ident, $log_level:ident, $($arg:tt)*) => {{
+        defmt::$level!($($arg)*);
+        crate::logview::log(crate::logview::Level::$log_level, format_args!($($arg)*));
+    }};
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => { tee_log!(info, Info, $($arg)*) };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => { tee_log!(warn, Warn, $($arg)*) };
+}
+
+#[allow(unused_macros)]
+macro_rules! log_error {
+    ($($arg:tt)*) => { tee_log!(error, Error, $($arg)*) };
+}
+
+mod console;
 mod display;
 mod hardware;
+mod hid;
+mod logview;
+mod usb;
 mod utils;
 
 static SPI_BUS: StaticCell<NoopMutex<RefCell<MySpiBus>>> = StaticCell::new();
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
-    info!("Program start");
-
     let p = embassy_rp::init(Default::default());
+    log_info!("Program start");
 
     let spi_bus = SPI_BUS.init(NoopMutex::new(RefCell::new(init_my_spi_bus(
         p.PIN_12, p.PIN_11, p.PIN_10, p.SPI1,
@@ -74,110 +104,192 @@ async fn main(spawner: Spawner) {
     let led = Output::new(p.PIN_25, Level::Low);
     let button = Input::new(p.PIN_16, Pull::Up);
 
-    unwrap!(spawner.spawn(blinker(led, Duration::from_millis(200))));
+    unwrap!(spawner.spawn(blinker(led)));
     unwrap!(spawner.spawn(button_monitor(button)));
     unwrap!(spawner.spawn(touch_monitor(touch)));
     unwrap!(spawner.spawn(display_refresh(display)));
+    usb::start(&spawner, p.USB);
 }
 
-/// Blink the physical LED, and a matching indicator on the LCD display
+/// Drive the physical LED as set from the console, and a matching indicator
+/// on the LCD display
 #[embassy_executor::task]
-async fn blinker(mut led: Output<'static>, interval: Duration) {
-    let mut blink = false;
+async fn blinker(mut led: Output<'static>) {
+    let mut on = false;
     loop {
-        led.set_level(if blink { Level::Low } else { Level::High });
-        let istate = IndicatorState::from_bool(blink);
-        DISPLAY_STATE.update(|s| s.indicator1 = istate);
-        blink = !blink;
-        Timer::after(interval).await;
+        let mode = LED_STATE.state.lock(|s| *s.borrow());
+        let interval = match mode {
+            LedMode::Off | LedMode::On => {
+                on = mode == LedMode::On;
+                None
+            }
+            LedMode::Blink { period_ms } => {
+                on = !on;
+                Some(Duration::from_millis(period_ms as u64 / 2))
+            }
+        };
+        led.set_level(if on { Level::High } else { Level::Low });
+        DISPLAY_STATE.update(|s| s.indicator1 = Indicator::from_bool(on));
+        match interval {
+            Some(interval) => {
+                select(LED_STATE.signal.wait(), Timer::after(interval)).await;
+            }
+            None => LED_STATE.signal.wait().await,
+        }
     }
 }
 
-/// Monitor the button, and show an indicator on the LCD display
+/// Monitor the button, show an indicator on the LCD display, and type the
+/// configured key while it's pressed
 #[embassy_executor::task]
 async fn button_monitor(mut button: Input<'static>) {
+    let mut pressed = false;
     loop {
         button.wait_for_any_edge().await;
+        // Let the contacts settle
+        Timer::after_millis(10).await;
         let level = button.get_level();
-        let istate = IndicatorState::from_bool(level == Level::High);
+        let istate = Indicator::from_bool(level == Level::High);
         DISPLAY_STATE.update(|s| s.indicator2 = istate);
+
+        // The button pulls the pin low when pressed
+        if pressed != (level == Level::Low) {
+            pressed = level == Level::Low;
+            log_info!("button {}", if pressed { "pressed" } else { "released" });
+            let key = SHELL_STATE.lock(|s| s.borrow().settings.key);
+            hid::send(hid::Report::Key(key_report(key.filter(|_| pressed))));
+        }
     }
 }
 
-/// Monitor the touch screen, and show an indicator on the LCD display
+/// Monitor the touch screen, show an indicator on the LCD display, and report
+/// touches to the host
 #[embassy_executor::task]
 async fn touch_monitor(mut touch: MyTouch) {
+    let mut digitizer = Digitizer::new();
+    let mut touching = false;
     loop {
-        Timer::after_millis(100).await;
-        if let Some((x, y)) = touch.read() {
-            let istate = match (x < 120, y < 160) {
-                (false, false) => IndicatorState::GRAY,
-                (false, true) => IndicatorState::GREEN,
-                (true, false) => IndicatorState::BLUE,
-                (true, true) => IndicatorState::RED,
+        Timer::after_millis(20).await;
+        let calibration = SHELL_STATE.lock(|s| s.borrow().calibration);
+        let sample = touch.read(&calibration);
+        if let Some(report) = digitizer.report(sample) {
+            hid::send(hid::Report::Touch(report));
+        }
+        if let Some(sample) = sample {
+            if !touching {
+                log_info!("touch at {},{}", sample.x, sample.y);
+            }
+            SHELL_STATE.lock(|s| s.borrow_mut().last_touch = Some(sample));
+            let istate = match (sample.x < 120, sample.y < 160) {
+                (false, false) => Indicator::Gray,
+                (false, true) => Indicator::Green,
+                (true, false) => Indicator::Blue,
+                (true, true) => Indicator::Red,
             };
             DISPLAY_STATE.update(|s| s.indicator3 = istate);
         }
+        touching = sample.is_some();
     }
 }
 
 #[derive(Clone)]
 struct DisplayState {
-    indicator1: IndicatorState,
-    indicator2: IndicatorState,
-    indicator3: IndicatorState,
+    indicator1: Indicator,
+    indicator2: Indicator,
+    indicator3: Indicator,
+    log_view: LogView,
 }
 
-#[derive(Clone, Copy)]
-enum IndicatorState {
-    GRAY,
-    RED,
-    GREEN,
-    BLUE,
-}
+static DISPLAY_STATE: StateAndSignal<DisplayState, ()> = StateAndSignal::new(DisplayState {
+    indicator1: Indicator::Gray,
+    indicator2: Indicator::Gray,
+    indicator3: Indicator::Gray,
+    log_view: LogView::Panel,
+});
 
-impl IndicatorState {
-    fn from_bool(v: bool) -> Self {
-        if v {
-            IndicatorState::GREEN
-        } else {
-            IndicatorState::GRAY
-        }
-    }
-}
+/// The LED blinks until told otherwise from the console
+const DEFAULT_BLINK_MS: u32 = 400;
 
-static DISPLAY_STATE: StateAndSignal<DisplayState, ()> = StateAndSignal::new(DisplayState {
-    indicator1: IndicatorState::GRAY,
-    indicator2: IndicatorState::GRAY,
-    indicator3: IndicatorState::GRAY,
+static LED_STATE: StateAndSignal<LedMode, ()> = StateAndSignal::new(LedMode::Blink {
+    period_ms: DEFAULT_BLINK_MS,
 });
 
+/// What the console can see and change, besides the LED
+struct ShellState {
+    settings: Settings,
+    calibration: Calibration,
+    last_touch: Option<display_shell::Touch>,
+}
+
+static SHELL_STATE: ThreadModeMutex<RefCell<ShellState>> =
+    ThreadModeMutex::new(RefCell::new(ShellState {
+        settings: Settings::new(DEFAULT_BLINK_MS),
+        calibration: Calibration::DEFAULT,
+        last_touch: None,
+    }));
+
 // Keep the display up to date
 #[embassy_executor::task]
 async fn display_refresh(mut display: MyDisplay) {
     let styles = display::Styles::new();
-    render_background(&mut display, &styles);
+    let mut log = LogConsole::new(&profont::PROFONT_9_POINT);
+    let mut view = None;
     loop {
-        let state = DISPLAY_STATE.wait(|_, s| s.clone()).await;
-        render_indicator(&mut display, Point::new(100, 120), state.indicator1);
-        render_indicator(&mut display, Point::new(160, 120), state.indicator2);
-        render_indicator(&mut display, Point::new(220, 120), state.indicator3);
+        let state = DISPLAY_STATE.state.lock(|s| s.borrow().clone());
+        if view != Some(state.log_view) {
+            view = Some(state.log_view);
+            // The log needs the display in portrait, see logview
+            match state.log_view {
+                LogView::Off => {
+                    log.hide(&mut display);
+                    display
+                        .set_orientation(Orientation::LandscapeFlipped)
+                        .unwrap();
+                    render_background(&mut display, &styles, Point::new(60, 0));
+                }
+                LogView::Panel => {
+                    display.set_orientation(Orientation::Portrait).unwrap();
+                    render_background(&mut display, &styles, Point::new(24, 0));
+                    log.show(&mut display, state.log_view);
+                }
+                LogView::Full => {
+                    display.set_orientation(Orientation::Portrait).unwrap();
+                    log.show(&mut display, state.log_view);
+                }
+            }
+        }
+
+        let (first, y) = match state.log_view {
+            LogView::Off => (100, 120),
+            LogView::Panel => (60, logview::PANEL_TOP as i32 * 2 / 3),
+            LogView::Full => (0, 0),
+        };
+        if state.log_view != LogView::Full {
+            render_indicator(&mut display, Point::new(first, y), state.indicator1);
+            render_indicator(&mut display, Point::new(first + 60, y), state.indicator2);
+            render_indicator(&mut display, Point::new(first + 120, y), state.indicator3);
+        }
+        if state.log_view != LogView::Off {
+            log.draw_new(&mut display);
+        }
+        DISPLAY_STATE.signal.wait().await;
     }
 }
 
-fn render_background(display: &mut MyDisplay, styles: &display::Styles) {
+fn render_background(display: &mut MyDisplay, styles: &display::Styles, title_at: Point) {
     let test_text = "Pixel Blinky";
-    Rectangle::new(Point::new(0, 0), Size::new(320, 240))
+    display
+        .bounding_box()
         .into_styled(styles.black_fill)
         .draw(display)
         .unwrap();
-    Text::with_text_style(test_text, Point::new(60, 0), styles.char, styles.text)
+    Text::with_text_style(test_text, title_at, styles.char, styles.text)
         .draw(display)
         .unwrap();
 }
 
 /// Draw an "LED" on the LCD display
-fn render_indicator(display: &mut MyDisplay, centre: Point, state: IndicatorState) -> () {
+fn render_indicator(display: &mut MyDisplay, centre: Point, state: Indicator) -> () {
     let led_size: u32 = 30;
     let led_at = Point::new(
         centre.x - (led_size as i32) / 2,
@@ -185,10 +297,10 @@ fn render_indicator(display: &mut MyDisplay, centre: Point, state: IndicatorStat
     );
 
     let color = match state {
-        IndicatorState::GRAY => Rgb565::CSS_DARK_GRAY,
-        IndicatorState::RED => Rgb565::RED,
-        IndicatorState::GREEN => Rgb565::GREEN,
-        IndicatorState::BLUE => Rgb565::BLUE,
+        Indicator::Gray => Rgb565::CSS_DARK_GRAY,
+        Indicator::Red => Rgb565::RED,
+        Indicator::Green => Rgb565::GREEN,
+        Indicator::Blue => Rgb565::BLUE,
     };
 
     Circle::new(led_at, led_size)
@@ -0,0 +1,56 @@
+//! The USB device: a composite of the serial console, and the touch screen
+//! and key as a HID device.
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::{bind_interrupts, peripherals::USB, usb};
+use embassy_usb::{Builder, Config, UsbDevice};
+use static_cell::StaticCell;
+
+use crate::{console, hid};
+
+bind_interrupts!(struct Irqs {
+    USBCTRL_IRQ => usb::InterruptHandler<USB>;
+});
+
+pub type UsbDriver = usb::Driver<'static, USB>;
+
+static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+
+/// Set up the USB device and its functions, and start the tasks running them
+pub fn start(spawner: &Spawner, usb: USB) {
+    let driver = usb::Driver::new(usb, Irqs);
+
+    let mut config = Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("pico demos");
+    config.product = Some("display-embassy");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+    // Interface association descriptors, so hosts group the CDC interfaces
+    // apart from the HID one
+    config.device_class = 0xef;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config.composite_with_iads = true;
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        CONFIG_DESCRIPTOR.init([0; 256]),
+        BOS_DESCRIPTOR.init([0; 256]),
+        &mut [],
+        CONTROL_BUF.init([0; 64]),
+    );
+    console::start(spawner, &mut builder);
+    hid::start(spawner, &mut builder);
+    let usb = builder.build();
+
+    unwrap!(spawner.spawn(usb_task(usb)));
+}
+
+#[embassy_executor::task]
+async fn usb_task(mut usb: UsbDevice<'static, UsbDriver>) -> ! {
+    usb.run().await
+}
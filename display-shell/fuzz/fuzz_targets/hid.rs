@@ -0,0 +1,203 @@
+//! Feeds arbitrary touch samples and key names to the HID reports, checking
+//! them against the fields that the report descriptor declares: that each
+//! report is as long as its fields, and that the fields hold what was
+//! touched or pressed.
+#![no_main]
+
+use display_shell::hid::{
+    key_report, Digitizer, KEY_REPORT_ID, KEY_REPORT_LEN, LOGICAL_MAX, REPORT_DESCRIPTOR,
+    TOUCH_REPORT_ID, TOUCH_REPORT_LEN,
+};
+use display_shell::{Key, Touch, SCREEN_HEIGHT, SCREEN_WIDTH};
+use libfuzzer_sys::fuzz_target;
+
+/// An input field, as declared by the descriptor
+#[derive(Debug)]
+struct Field {
+    report_id: u8,
+    /// Bits from the start of the report, after its ID
+    offset: usize,
+    size: usize,
+    count: usize,
+    constant: bool,
+    usage_page: u32,
+    usages: Vec<u32>,
+    logical_max: u32,
+}
+
+/// Parse the short items of a report descriptor into its input fields, and
+/// the length of each report, in bits
+fn parse(descriptor: &[u8]) -> (Vec<Field>, Vec<(u8, usize)>) {
+    let mut fields = Vec::new();
+    let mut lengths: Vec<(u8, usize)> = Vec::new();
+    let (mut usage_page, mut logical_max, mut size, mut count, mut report_id) = (0, 0, 0, 0, 0);
+    let mut usages = Vec::new();
+    let mut usage_min = None;
+    let mut depth = 0;
+    let mut rest = descriptor;
+    while let [prefix, tail @ ..] = rest {
+        assert_ne!(*prefix, 0xfe, "long items aren't used");
+        let len = [0, 1, 2, 4][(prefix & 3) as usize];
+        let data = tail[..len].iter().rev().fold(0, |v, &b| v << 8 | b as u32);
+        rest = &tail[len..];
+        match (prefix >> 2 & 3, prefix >> 4) {
+            // Input
+            (0, 0x8) => {
+                let bits = lengths.iter_mut().find(|(id, _)| *id == report_id);
+                let bits = match bits {
+                    Some((_, bits)) => bits,
+                    None => {
+                        lengths.push((report_id, 0));
+                        &mut lengths.last_mut().unwrap().1
+                    }
+                };
+                fields.push(Field {
+                    report_id,
+                    offset: *bits,
+                    size,
+                    count,
+                    constant: data & 1 != 0,
+                    usage_page,
+                    usages: std::mem::take(&mut usages),
+                    logical_max,
+                });
+                *bits += size * count;
+            }
+            // Collection, End Collection
+            (0, 0xa) => {
+                depth += 1;
+                usages.clear();
+            }
+            (0, 0xc) => {
+                assert!(depth > 0);
+                depth -= 1;
+            }
+            (1, 0x0) => usage_page = data,
+            (1, 0x1) => assert_eq!(data, 0, "logical minimums are all 0"),
+            (1, 0x2) => logical_max = data,
+            (1, 0x7) => size = data as usize,
+            (1, 0x8) => report_id = data as u8,
+            (1, 0x9) => count = data as usize,
+            (2, 0x0) => usages.push(data),
+            (2, 0x1) => usage_min = Some(data),
+            (2, 0x2) => usages.extend(usage_min.take().unwrap()..=data),
+            item => panic!("unexpected item {:?}", item),
+        }
+    }
+    assert_eq!(depth, 0);
+    (fields, lengths)
+}
+
+/// The field with the given usage, and the bit it's at
+fn find(fields: &[Field], usage_page: u32, usage: u32) -> (&Field, usize) {
+    fields
+        .iter()
+        .filter(|f| !f.constant && f.usage_page == usage_page)
+        .find_map(|f| {
+            let i = f.usages.iter().position(|&u| u == usage)?;
+            Some((f, f.offset + i * f.size))
+        })
+        .unwrap()
+}
+
+/// Read a little endian field from a report, after its ID
+fn read(report: &[u8], offset: usize, size: usize) -> u32 {
+    (0..size).fold(0, |v, i| {
+        let bit = offset + i;
+        v | ((report[1 + bit / 8] >> (bit % 8) & 1) as u32) << i
+    })
+}
+
+/// Scale a reported position back to the display's `0..max`, which gives the
+/// touch that was reported, as the reports are rounded down
+fn unscale(v: u32, max: i32) -> i32 {
+    (v * (max - 1) as u32).div_ceil(LOGICAL_MAX as u32) as i32
+}
+
+fuzz_target!(|data: &[u8]| {
+    let (fields, lengths) = parse(REPORT_DESCRIPTOR);
+    assert_eq!(
+        lengths,
+        [
+            (TOUCH_REPORT_ID, (TOUCH_REPORT_LEN - 1) * 8),
+            (KEY_REPORT_ID, (KEY_REPORT_LEN - 1) * 8)
+        ]
+    );
+    let (tip, tip_at) = find(&fields, 0x0d, 0x42);
+    let (x, x_at) = find(&fields, 0x01, 0x30);
+    let (y, y_at) = find(&fields, 0x01, 0x31);
+    assert!(tip.report_id == TOUCH_REPORT_ID && x.report_id == TOUCH_REPORT_ID);
+    assert!(x.logical_max == LOGICAL_MAX as u32 && y.logical_max == LOGICAL_MAX as u32);
+    let keys = fields
+        .iter()
+        .find(|f| f.report_id == KEY_REPORT_ID && f.count == 6)
+        .unwrap();
+
+    // Key names before a zero byte, and touch samples after it
+    let (names, samples) = match data.iter().position(|&b| b == 0) {
+        Some(i) => (&data[..i], &data[i + 1..]),
+        None => (data, &[][..]),
+    };
+    for name in String::from_utf8_lossy(names).split(' ') {
+        let Some(key) = Key::from_name(name) else {
+            continue;
+        };
+        assert_eq!(key.name(), name);
+        let report = key_report(Some(key));
+        assert_eq!(report[0], KEY_REPORT_ID);
+        assert_eq!(read(&report, keys.offset, keys.size), key.usage() as u32);
+        let report = key_report(None);
+        assert!((0..keys.count).all(|i| read(&report, keys.offset + i * keys.size, keys.size) == 0));
+    }
+
+    let mut digitizer = Digitizer::new();
+    let mut last: Option<(u32, u32)> = None;
+    let mut touching = false;
+    for sample in samples.chunks_exact(5) {
+        let touch = (sample[0] & 1 == 1).then(|| {
+            let x = i16::from_le_bytes([sample[1], sample[2]]) as i32;
+            let y = i16::from_le_bytes([sample[3], sample[4]]) as i32;
+            Touch {
+                x,
+                y,
+                raw_x: 0,
+                raw_y: 0,
+            }
+        });
+        let report = digitizer.report(touch);
+        let Some(report) = report else {
+            // Nothing changed
+            match touch {
+                Some(touch) => {
+                    assert!(touching);
+                    let (lx, ly) = last.unwrap();
+                    assert_eq!(unscale(lx, SCREEN_WIDTH), touch.x.clamp(0, SCREEN_WIDTH - 1));
+                    assert_eq!(unscale(ly, SCREEN_HEIGHT), touch.y.clamp(0, SCREEN_HEIGHT - 1));
+                }
+                None => assert!(!touching),
+            }
+            continue;
+        };
+        assert_eq!(report[0], TOUCH_REPORT_ID);
+        let rx = read(&report, x_at, x.size);
+        let ry = read(&report, y_at, y.size);
+        assert!(rx <= LOGICAL_MAX as u32 && ry <= LOGICAL_MAX as u32);
+        match touch {
+            Some(touch) => {
+                assert_eq!(read(&report, tip_at, 1), 1);
+                assert_eq!(unscale(rx, SCREEN_WIDTH), touch.x.clamp(0, SCREEN_WIDTH - 1));
+                assert_eq!(unscale(ry, SCREEN_HEIGHT), touch.y.clamp(0, SCREEN_HEIGHT - 1));
+                assert!(!touching || last != Some((rx, ry)));
+                touching = true;
+            }
+            None => {
+                // A release, where the touch was
+                assert!(touching);
+                assert_eq!(read(&report, tip_at, 1), 0);
+                assert_eq!(last, Some((rx, ry)));
+                touching = false;
+            }
+        }
+        last = Some((rx, ry));
+    }
+});
@@ -0,0 +1,141 @@
+//! Types arbitrary bytes at the console, as a terminal would send them,
+//! checking that the shell never panics, that every command is answered with
+//! `ok` or an error, and that what it sets is what it then reports.
+#![no_main]
+
+use display_shell::{
+    Board, Calibration, Indicator, Input, LedMode, LineEditor, LogView, Settings, Touch,
+    SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+use libfuzzer_sys::fuzz_target;
+
+/// A board with a touch screen, remembering what the shell did to it
+struct Model {
+    led: LedMode,
+    calibration: Calibration,
+    touch: Option<Touch>,
+    log_view: LogView,
+    settings: Settings,
+}
+
+impl Board for Model {
+    fn led(&self) -> LedMode {
+        self.led
+    }
+
+    fn set_led(&mut self, mode: LedMode) {
+        self.led = mode;
+    }
+
+    fn indicator(&self, i: usize) -> Option<(&'static str, Indicator)> {
+        match i {
+            0 => Some(("led", Indicator::from_bool(self.led == LedMode::On))),
+            1 => Some(("button", Indicator::Gray)),
+            _ => None,
+        }
+    }
+
+    fn calibration(&self) -> Option<Calibration> {
+        Some(self.calibration)
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    fn last_touch(&self) -> Option<Touch> {
+        self.touch
+    }
+
+    fn log_view(&self) -> Option<LogView> {
+        Some(self.log_view)
+    }
+
+    fn set_log_view(&mut self, view: LogView) {
+        self.log_view = view;
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    fn uptime_ms(&self) -> u64 {
+        u64::MAX
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut board = Model {
+        led: LedMode::Off,
+        calibration: Calibration::DEFAULT,
+        touch: None,
+        log_view: LogView::Off,
+        settings: Settings::new(500),
+    };
+    let mut editor: LineEditor = LineEditor::new();
+    for (i, &b) in data.iter().enumerate() {
+        let line = match editor.push(b) {
+            Input::Line(line) => line.to_string(),
+            _ => continue,
+        };
+        assert!(line.len() <= display_shell::MAX_LINE);
+        assert!(!line.contains(['\r', '\n']));
+
+        let before = board.settings;
+        let mut out = String::new();
+        display_shell::run(&mut board, &line, &mut out).unwrap();
+        if line.trim().is_empty() {
+            assert!(out.is_empty());
+            continue;
+        }
+        let last = out.trim_end_matches("\r\n").rsplit("\r\n").next().unwrap();
+        assert!(
+            last == "ok" || last.starts_with("error: "),
+            "{:?} gave {:?}",
+            line,
+            out
+        );
+        if last != "ok" {
+            assert_eq!(board.settings, before);
+        }
+
+        // Whatever was set reads back
+        let mut settings = String::new();
+        display_shell::run(&mut board, "settings?", &mut settings).unwrap();
+        let echo = if board.settings.echo { "on" } else { "off" };
+        let key = board.settings.key.map_or("none", |key| key.name());
+        assert_eq!(
+            settings,
+            format!(
+                "echo: {}\r\nblink: {}\r\nkey: {}\r\nok\r\n",
+                echo, board.settings.blink_ms, key
+            )
+        );
+        let c = board.calibration;
+        let mut calibration = String::new();
+        display_shell::run(&mut board, "calibrate", &mut calibration).unwrap();
+        assert_eq!(
+            calibration,
+            format!("calibration: {} {} {} {}\r\nok\r\n", c.x1, c.x2, c.y1, c.y2)
+        );
+        let mut log = String::new();
+        display_shell::run(&mut board, "log", &mut log).unwrap();
+        assert_eq!(log, format!("log: {}\r\nok\r\n", board.log_view.label()));
+
+        // Any raw reading maps onto the screen, with the calibration set
+        let raw = (i as i32 * 7919) % 8192;
+        board.touch = c.apply(raw, 8191 - raw);
+        if let Some(touch) = board.touch {
+            assert!(
+                (0..=SCREEN_WIDTH).contains(&touch.x) && (0..=SCREEN_HEIGHT).contains(&touch.y)
+            );
+        }
+        let mut status = String::new();
+        display_shell::run(&mut board, "status", &mut status).unwrap();
+        assert!(status.ends_with("ok\r\n"));
+    }
+});
@@ -0,0 +1,57 @@
+//! Mapping the XPT2046 touch controller's raw readings to display
+//! coordinates.
+
+/// The display's size in landscape, which touches are mapped to
+pub const SCREEN_WIDTH: i32 = 320;
+pub const SCREEN_HEIGHT: i32 = 240;
+
+/// The raw readings at the display's edges. They needn't be in order, as
+/// the touch panel can be mounted either way round.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Calibration {
+    /// At the left and right
+    pub x1: i32,
+    pub x2: i32,
+    /// At the top and bottom
+    pub y1: i32,
+    pub y2: i32,
+}
+
+/// A touch, in display coordinates and as read
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Touch {
+    pub x: i32,
+    pub y: i32,
+    pub raw_x: i32,
+    pub raw_y: i32,
+}
+
+impl Calibration {
+    /// Measured on the 2.8" ILI9341 module
+    pub const DEFAULT: Calibration = Calibration {
+        x1: 3880,
+        x2: 340,
+        y1: 262,
+        y2: 3850,
+    };
+
+    /// A calibration, or None if the edges of either axis are the same
+    pub fn new(x1: i32, x2: i32, y1: i32, y2: i32) -> Option<Calibration> {
+        if x1 == x2 || y1 == y2 {
+            return None;
+        }
+        Some(Calibration { x1, x2, y1, y2 })
+    }
+
+    /// Map a raw reading to the display, or None if it isn't a touch. The
+    /// controller reads as the top left corner when not pressed.
+    pub fn apply(&self, raw_x: i32, raw_y: i32) -> Option<Touch> {
+        let x = ((raw_x - self.x1) * SCREEN_WIDTH / (self.x2 - self.x1)).clamp(0, SCREEN_WIDTH);
+        let y = ((raw_y - self.y1) * SCREEN_HEIGHT / (self.y2 - self.y1)).clamp(0, SCREEN_HEIGHT);
+        if x == 0 && y == 0 {
+            None
+        } else {
+            Some(Touch { x, y, raw_x, raw_y })
+        }
+    }
+}
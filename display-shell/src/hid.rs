@@ -0,0 +1,323 @@
+//! USB HID reports, making the board a touch screen and a one key keyboard
+//! for the PC it's plugged into.
+//!
+//! Both are in the one report descriptor, told apart by their report IDs.
+//! The touch screen is a single touch digitizer, with the tip switch set while
+//! touched, and absolute X and Y scaled from the display's pixels to
+//! `0..=LOGICAL_MAX`. The keyboard has the usual boot keyboard layout, of
+//! modifiers, a reserved byte, and six keys, though only one is ever pressed.
+
+use crate::{Touch, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const TOUCH_REPORT_ID: u8 = 1;
+pub const KEY_REPORT_ID: u8 = 2;
+
+/// Report lengths, including the report ID
+pub const TOUCH_REPORT_LEN: usize = 6;
+pub const KEY_REPORT_LEN: usize = 9;
+
+/// X and Y are reported from 0 to this, whatever the display's size
+pub const LOGICAL_MAX: u16 = 32767;
+
+const TIP_SWITCH: u8 = 0x01;
+const IN_RANGE: u8 = 0x02;
+
+#[rustfmt::skip]
+pub const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0d,             // Usage Page (Digitizer)
+    0x09, 0x04,             // Usage (Touch Screen)
+    0xa1, 0x01,             // Collection (Application)
+    0x85, TOUCH_REPORT_ID,  //   Report ID
+    0x09, 0x22,             //   Usage (Finger)
+    0xa1, 0x02,             //   Collection (Logical)
+    0x09, 0x42,             //     Usage (Tip Switch)
+    0x09, 0x32,             //     Usage (In Range)
+    0x15, 0x00,             //     Logical Minimum (0)
+    0x25, 0x01,             //     Logical Maximum (1)
+    0x75, 0x01,             //     Report Size (1)
+    0x95, 0x02,             //     Report Count (2)
+    0x81, 0x02,             //     Input (Data, Variable, Absolute)
+    0x95, 0x06,             //     Report Count (6)
+    0x81, 0x03,             //     Input (Constant), padding
+    0x05, 0x01,             //     Usage Page (Generic Desktop)
+    0x09, 0x30,             //     Usage (X)
+    0x09, 0x31,             //     Usage (Y)
+    0x16, 0x00, 0x00,       //     Logical Minimum (0)
+    0x26, 0xff, 0x7f,       //     Logical Maximum (32767)
+    0x75, 0x10,             //     Report Size (16)
+    0x95, 0x02,             //     Report Count (2)
+    0x81, 0x02,             //     Input (Data, Variable, Absolute)
+    0xc0,                   //   End Collection
+    0xc0,                   // End Collection
+
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x06,             // Usage (Keyboard)
+    0xa1, 0x01,             // Collection (Application)
+    0x85, KEY_REPORT_ID,    //   Report ID
+    0x05, 0x07,             //   Usage Page (Keyboard)
+    0x19, 0xe0,             //   Usage Minimum (Left Control)
+    0x29, 0xe7,             //   Usage Maximum (Right GUI)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x25, 0x01,             //   Logical Maximum (1)
+    0x75, 0x01,             //   Report Size (1)
+    0x95, 0x08,             //   Report Count (8)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute), modifiers
+    0x75, 0x08,             //   Report Size (8)
+    0x95, 0x01,             //   Report Count (1)
+    0x81, 0x03,             //   Input (Constant), reserved
+    0x19, 0x00,             //   Usage Minimum (0)
+    0x29, 0xff,             //   Usage Maximum (255)
+    0x26, 0xff, 0x00,       //   Logical Maximum (255)
+    0x95, 0x06,             //   Report Count (6)
+    0x81, 0x00,             //   Input (Data, Array), keys
+    0xc0,                   // End Collection
+];
+
+/// Turns touch screen samples into touch reports
+pub struct Digitizer {
+    /// The last position reported, in logical units
+    position: (u16, u16),
+    touching: bool,
+}
+
+impl Digitizer {
+    pub const fn new() -> Self {
+        Digitizer {
+            position: (0, 0),
+            touching: false,
+        }
+    }
+
+    /// The report for a sample, or None if it's the same as the last. When
+    /// the touch ends the last position is reported again, with the tip
+    /// switch off, so the pointer stays where it was.
+    pub fn report(&mut self, touch: Option<Touch>) -> Option<[u8; TOUCH_REPORT_LEN]> {
+        match touch {
+            Some(touch) => {
+                let position = (scale(touch.x, SCREEN_WIDTH), scale(touch.y, SCREEN_HEIGHT));
+                if self.touching && position == self.position {
+                    return None;
+                }
+                self.touching = true;
+                self.position = position;
+            }
+            None if self.touching => self.touching = false,
+            None => return None,
+        }
+
+        let (x, y) = self.position;
+        let flags = if self.touching {
+            TIP_SWITCH | IN_RANGE
+        } else {
+            0
+        };
+        let [x0, x1] = x.to_le_bytes();
+        let [y0, y1] = y.to_le_bytes();
+        Some([TOUCH_REPORT_ID, flags, x0, x1, y0, y1])
+    }
+}
+
+impl Default for Digitizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scale a display coordinate from `0..max` to the logical range, so the
+/// last pixel is at `LOGICAL_MAX`
+fn scale(v: i32, max: i32) -> u16 {
+    (v.clamp(0, max - 1) * LOGICAL_MAX as i32 / (max - 1)) as u16
+}
+
+/// A key on the keyboard, by its HID usage ID
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Key(u8);
+
+const LETTERS: &str = "abcdefghijklmnopqrstuvwxyz";
+const DIGITS: &str = "1234567890";
+const FUNCTION_KEYS: [&str; 12] = [
+    "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12",
+];
+const NAMED_KEYS: [(&str, u8); 15] = [
+    ("enter", 0x28),
+    ("esc", 0x29),
+    ("backspace", 0x2a),
+    ("tab", 0x2b),
+    ("space", 0x2c),
+    ("insert", 0x49),
+    ("home", 0x4a),
+    ("pageup", 0x4b),
+    ("delete", 0x4c),
+    ("end", 0x4d),
+    ("pagedown", 0x4e),
+    ("right", 0x4f),
+    ("left", 0x50),
+    ("down", 0x51),
+    ("up", 0x52),
+];
+
+impl Key {
+    pub const SPACE: Key = Key(0x2c);
+
+    /// The key with the given name: a letter or digit, `f1` to `f12`, or one
+    /// of `enter`, `esc`, `backspace`, `tab`, `space`, `insert`, `home`,
+    /// `pageup`, `delete`, `end`, `pagedown`, `right`, `left`, `down` or `up`
+    pub fn from_name(name: &str) -> Option<Key> {
+        if name.len() == 1 {
+            if let Some(i) = LETTERS.find(name) {
+                return Some(Key(0x04 + i as u8));
+            }
+            if let Some(i) = DIGITS.find(name) {
+                return Some(Key(0x1e + i as u8));
+            }
+        }
+        if let Some(i) = FUNCTION_KEYS.iter().position(|&f| f == name) {
+            return Some(Key(0x3a + i as u8));
+        }
+        NAMED_KEYS
+            .iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, usage)| Key(usage))
+    }
+
+    pub fn name(&self) -> &'static str {
+        let i = self.0 as usize;
+        match self.0 {
+            0x04..=0x1d => &LETTERS[i - 0x04..i - 0x03],
+            0x1e..=0x27 => &DIGITS[i - 0x1e..i - 0x1d],
+            0x3a..=0x45 => FUNCTION_KEYS[i - 0x3a],
+            usage => NAMED_KEYS
+                .iter()
+                .find(|&&(_, u)| u == usage)
+                .map_or("?", |&(n, _)| n),
+        }
+    }
+
+    pub fn usage(&self) -> u8 {
+        self.0
+    }
+}
+
+/// The keyboard report with `key` pressed, or with nothing pressed
+pub fn key_report(key: Option<Key>) -> [u8; KEY_REPORT_LEN] {
+    let mut report = [0; KEY_REPORT_LEN];
+    report[0] = KEY_REPORT_ID;
+    if let Some(key) = key {
+        report[3] = key.usage();
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn touch(x: i32, y: i32) -> Option<Touch> {
+        Some(Touch {
+            x,
+            y,
+            raw_x: 0,
+            raw_y: 0,
+        })
+    }
+
+    #[test]
+    fn reports_touches() {
+        let mut digitizer = Digitizer::new();
+        assert_eq!(digitizer.report(None), None);
+        // The corners are at the ends of the logical range
+        assert_eq!(digitizer.report(touch(0, 0)), Some([1, 3, 0, 0, 0, 0]));
+        assert_eq!(digitizer.report(touch(319, 239)), Some([1, 3, 0xff, 0x7f, 0xff, 0x7f]));
+        // Off the display is at its edge
+        assert_eq!(digitizer.report(touch(-5, 400)), Some([1, 3, 0, 0, 0xff, 0x7f]));
+        assert_eq!(digitizer.report(touch(160, 120)), Some([1, 3, 0x32, 0x40, 0x44, 0x40]));
+        // Released where it was
+        assert_eq!(digitizer.report(None), Some([1, 0, 0x32, 0x40, 0x44, 0x40]));
+        assert_eq!(digitizer.report(None), None);
+    }
+
+    #[test]
+    fn suppresses_duplicates() {
+        let mut digitizer = Digitizer::new();
+        let report = digitizer.report(touch(16, 32));
+        assert_eq!(report, Some([1, 3, 0x6b, 0x06, 0x23, 0x11]));
+        assert_eq!(digitizer.report(touch(16, 32)), None);
+        // Beyond the edge is the same as at it
+        digitizer.report(touch(319, 0));
+        assert_eq!(digitizer.report(touch(320, -1)), None);
+        // A new touch in the same place is reported again
+        assert_eq!(digitizer.report(None), Some([1, 0, 0xff, 0x7f, 0, 0]));
+        assert_eq!(digitizer.report(touch(319, 0)), Some([1, 3, 0xff, 0x7f, 0, 0]));
+    }
+
+    #[test]
+    fn reports_keys() {
+        assert_eq!(key_report(None), [2, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(key_report(Some(Key::SPACE)), [2, 0, 0, 0x2c, 0, 0, 0, 0, 0]);
+        assert_eq!(key_report(Key::from_name("a")), [2, 0, 0, 0x04, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn names_keys() {
+        let names = LETTERS
+            .split("")
+            .chain(DIGITS.split(""))
+            .filter(|name| !name.is_empty())
+            .chain(FUNCTION_KEYS)
+            .chain(NAMED_KEYS.iter().map(|&(name, _)| name));
+        let mut usages = Vec::new();
+        for name in names {
+            let key = Key::from_name(name).unwrap();
+            assert_eq!(key.name(), name);
+            usages.push(key.usage());
+        }
+        usages.sort_unstable();
+        usages.dedup();
+        assert_eq!(usages.len(), 26 + 10 + 12 + NAMED_KEYS.len());
+        assert_eq!(Key::from_name("z"), Some(Key(0x1d)));
+        assert_eq!(Key::from_name("0"), Some(Key(0x27)));
+        assert_eq!(Key::from_name("f12"), Some(Key(0x45)));
+        for name in ["", "A", "ab", "f0", "f13", "Space", "?"] {
+            assert_eq!(Key::from_name(name), None, "{}", name);
+        }
+        assert_eq!(Key(0xe0).name(), "?");
+    }
+
+    /// The reports' lengths in bits, after their IDs, from the input items
+    /// in the descriptor
+    fn report_lengths() -> Vec<(u8, usize)> {
+        let mut lengths: Vec<(u8, usize)> = Vec::new();
+        let (mut size, mut count) = (0, 0);
+        let mut rest = REPORT_DESCRIPTOR;
+        while let [prefix, tail @ ..] = rest {
+            let len = [0, 1, 2, 4][(prefix & 3) as usize];
+            let data = tail[..len].iter().rev().fold(0, |v, &b| v << 8 | b as usize);
+            rest = &tail[len..];
+            match prefix & 0xfc {
+                // Report Size, Report Count, Report ID
+                0x74 => size = data,
+                0x94 => count = data,
+                0x84 => lengths.push((data as u8, 0)),
+                // Input
+                0x80 => lengths.last_mut().unwrap().1 += size * count,
+                _ => {}
+            }
+        }
+        lengths
+    }
+
+    #[test]
+    fn describes_the_reports() {
+        assert_eq!(
+            report_lengths(),
+            [
+                (TOUCH_REPORT_ID, (TOUCH_REPORT_LEN - 1) * 8),
+                (KEY_REPORT_ID, (KEY_REPORT_LEN - 1) * 8)
+            ]
+        );
+    }
+}
@@ -0,0 +1,675 @@
+//! The command shell of the display demos' USB serial consoles, shared by
+//! `display-embassy` and `display-basic`, and the USB HID reports of
+//! `display-embassy`'s touch screen and key, in `hid`.
+//!
+//! Commands:
+//!
+//! ```text
+//! led on|off|blink [<ms>]
+//! indicators?
+//! touch?
+//! calibrate [<x1> <x2> <y1> <y2> | default]
+//! log [off|panel|full]
+//! settings?
+//! set echo on|off
+//! set blink <ms>
+//! set key <name>|none
+//! status
+//! help
+//! ```
+//!
+//! Each command gets one or more lines of response, the last starting with
+//! `ok` or `error:`. Like the wifi-example's shell, parsing and dispatch don't
+//! allocate and only depend on the `Board` trait, so they build and run on
+//! the host too. The wifi-example's shell is built from the same parts: the
+//! `LedMode`, the argument parsing helpers and errors, and the `LineEditor`.
+//! `no_std`, with no dependencies but `defmt`, optionally.
+#![no_std]
+
+mod calibration;
+pub mod hid;
+mod line;
+
+use core::fmt::{self, Write};
+
+pub use calibration::{Calibration, Touch, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use hid::Key;
+pub use line::{Input, LineEditor, ERASE, NEWLINE};
+
+/// Longest accepted command line, excluding the line terminator
+pub const MAX_LINE: usize = 64;
+
+/// Shortest and longest accepted blink periods, in milliseconds
+pub const MIN_BLINK_MS: u32 = 20;
+pub const MAX_BLINK_MS: u32 = 10_000;
+
+/// Raw touch readings are 12 bits
+const MAX_RAW: u32 = 4095;
+
+/// Shown before each command line
+pub const PROMPT: &str = "> ";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedMode {
+    Off,
+    On,
+    Blink { period_ms: u32 },
+}
+
+impl LedMode {
+    /// The mode's name, without a blink's period
+    pub fn label(&self) -> &'static str {
+        match self {
+            LedMode::Off => "off",
+            LedMode::On => "on",
+            LedMode::Blink { .. } => "blink",
+        }
+    }
+}
+
+/// The colour of one of the indicators drawn on the display
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Indicator {
+    Gray,
+    Red,
+    Green,
+    Blue,
+}
+
+impl Indicator {
+    pub fn from_bool(v: bool) -> Indicator {
+        if v {
+            Indicator::Green
+        } else {
+            Indicator::Gray
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Indicator::Gray => "gray",
+            Indicator::Red => "red",
+            Indicator::Green => "green",
+            Indicator::Blue => "blue",
+        }
+    }
+}
+
+/// Settings that can be changed from the console. They're kept in RAM, so
+/// go back to the defaults on reset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Settings {
+    /// Whether the console echoes what's typed, for terminals that don't
+    pub echo: bool,
+    /// The blink period used by `led blink` without one
+    pub blink_ms: u32,
+    /// The key the button types, on boards that are a USB keyboard
+    pub key: Option<Key>,
+}
+
+impl Settings {
+    pub const fn new(blink_ms: u32) -> Settings {
+        Settings {
+            echo: true,
+            blink_ms,
+            key: Some(Key::SPACE),
+        }
+    }
+}
+
+/// How the log is shown on the display
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogView {
+    /// Not shown
+    Off,
+    /// Below the indicators
+    Panel,
+    /// On the whole display
+    Full,
+}
+
+impl LogView {
+    pub fn label(&self) -> &'static str {
+        match self {
+            LogView::Off => "off",
+            LogView::Panel => "panel",
+            LogView::Full => "full",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Setting {
+    Echo(bool),
+    BlinkMs(u32),
+    Key(Option<Key>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    /// A mode, or blink with the default period if None
+    Led(Option<LedMode>),
+    Indicators,
+    Touch,
+    /// Show the calibration, or set it
+    Calibrate(Option<Calibration>),
+    /// Show how the log is shown, or change it
+    Log(Option<LogView>),
+    Settings,
+    Set(Setting),
+    Status,
+    Help,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    MissingArgument,
+    BadArgument,
+    TooManyArguments,
+}
+
+impl ParseError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ParseError::Empty => "empty command",
+            ParseError::UnknownCommand => "unknown command, try help",
+            ParseError::MissingArgument => "missing argument",
+            ParseError::BadArgument => "bad argument",
+            ParseError::TooManyArguments => "too many arguments",
+        }
+    }
+}
+
+/// Parse a single command line, without its line terminator
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let (word, rest) = split_word(line.trim());
+    let command = match word {
+        "" => return Err(ParseError::Empty),
+        "led" => Command::Led(parse_led(rest)?),
+        "indicators?" => no_more(rest, Command::Indicators)?,
+        "touch?" => no_more(rest, Command::Touch)?,
+        "calibrate" => Command::Calibrate(parse_calibration(rest)?),
+        "log" => Command::Log(parse_log_view(rest)?),
+        "settings?" => no_more(rest, Command::Settings)?,
+        "set" => Command::Set(parse_setting(rest)?),
+        "status" => no_more(rest, Command::Status)?,
+        "help" => no_more(rest, Command::Help)?,
+        _ => return Err(ParseError::UnknownCommand),
+    };
+    Ok(command)
+}
+
+/// Parse the arguments of the led command: `on`, `off` or `blink [<ms>]`
+fn parse_led(args: &str) -> Result<Option<LedMode>, ParseError> {
+    let (mode, rest) = split_word(args);
+    let mode = match mode {
+        "on" => LedMode::On,
+        "off" => LedMode::Off,
+        "blink" if rest.is_empty() => return Ok(None),
+        "blink" => {
+            let (period, rest) = split_word(rest);
+            let period_ms = parse_blink_ms(period)?;
+            return no_more(rest, Some(LedMode::Blink { period_ms }));
+        }
+        "" => return Err(ParseError::MissingArgument),
+        _ => return Err(ParseError::BadArgument),
+    };
+    no_more(rest, Some(mode))
+}
+
+/// Parse the arguments of the calibrate command: nothing, `default`, or the
+/// raw readings at the left, right, top and bottom edges
+fn parse_calibration(args: &str) -> Result<Option<Calibration>, ParseError> {
+    match split_word(args) {
+        ("", _) => return Ok(None),
+        ("default", rest) => return no_more(rest, Some(Calibration::DEFAULT)),
+        _ => {}
+    }
+    let mut edges = [0; 4];
+    let mut rest = args;
+    for edge in edges.iter_mut() {
+        let (word, r) = split_word(rest);
+        let raw = parse_number(word)?;
+        if raw > MAX_RAW {
+            return Err(ParseError::BadArgument);
+        }
+        *edge = raw as i32;
+        rest = r;
+    }
+    let [x1, x2, y1, y2] = edges;
+    let calibration = Calibration::new(x1, x2, y1, y2).ok_or(ParseError::BadArgument)?;
+    no_more(rest, Some(calibration))
+}
+
+/// Parse the arguments of the log command: nothing, or how to show it
+fn parse_log_view(args: &str) -> Result<Option<LogView>, ParseError> {
+    let (view, rest) = split_word(args);
+    let view = match view {
+        "" => return Ok(None),
+        "off" => LogView::Off,
+        "panel" => LogView::Panel,
+        "full" => LogView::Full,
+        _ => return Err(ParseError::BadArgument),
+    };
+    no_more(rest, Some(view))
+}
+
+/// Parse the arguments of the set command: `<name> <value>`
+fn parse_setting(args: &str) -> Result<Setting, ParseError> {
+    let (name, rest) = split_word(args);
+    let (value, rest) = split_word(rest);
+    let setting = match name {
+        "echo" => Setting::Echo(match value {
+            "on" => true,
+            "off" => false,
+            "" => return Err(ParseError::MissingArgument),
+            _ => return Err(ParseError::BadArgument),
+        }),
+        "blink" => Setting::BlinkMs(parse_blink_ms(value)?),
+        "key" => Setting::Key(match value {
+            "none" => None,
+            "" => return Err(ParseError::MissingArgument),
+            name => Some(Key::from_name(name).ok_or(ParseError::BadArgument)?),
+        }),
+        "" => return Err(ParseError::MissingArgument),
+        _ => return Err(ParseError::BadArgument),
+    };
+    no_more(rest, setting)
+}
+
+/// Parse a blink period, in milliseconds
+pub fn parse_blink_ms(s: &str) -> Result<u32, ParseError> {
+    let period_ms = parse_number(s)?;
+    if !(MIN_BLINK_MS..=MAX_BLINK_MS).contains(&period_ms) {
+        return Err(ParseError::BadArgument);
+    }
+    Ok(period_ms)
+}
+
+/// Split off the first space separated word, returning it and the remainder
+pub fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.split_once(' ') {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// `result`, if there are no more arguments after it
+pub fn no_more<T>(rest: &str, result: T) -> Result<T, ParseError> {
+    if rest.is_empty() {
+        Ok(result)
+    } else {
+        Err(ParseError::TooManyArguments)
+    }
+}
+
+pub fn parse_number(s: &str) -> Result<u32, ParseError> {
+    if s.is_empty() {
+        return Err(ParseError::MissingArgument);
+    }
+    s.parse().map_err(|_| ParseError::BadArgument)
+}
+
+/// What the shell can do to the board
+pub trait Board {
+    fn led(&self) -> LedMode;
+    fn set_led(&mut self, mode: LedMode);
+    /// The indicator drawn `i`th from the left, with its name, or None past
+    /// the last
+    fn indicator(&self, i: usize) -> Option<(&'static str, Indicator)>;
+    /// The touch screen's calibration, or None if there's no touch screen
+    fn calibration(&self) -> Option<Calibration>;
+    fn set_calibration(&mut self, calibration: Calibration);
+    /// The most recent touch
+    fn last_touch(&self) -> Option<Touch>;
+    /// How the log is shown, or None if it can't be shown on the display
+    fn log_view(&self) -> Option<LogView>;
+    fn set_log_view(&mut self, view: LogView);
+    fn settings(&self) -> Settings;
+    fn set_settings(&mut self, settings: Settings);
+    /// Time since the board started
+    fn uptime_ms(&self) -> u64;
+}
+
+/// Parse and run a command line, writing the response to `out`
+pub fn run<B: Board, W: Write>(board: &mut B, line: &str, out: &mut W) -> fmt::Result {
+    let command = match parse(line) {
+        Ok(command) => command,
+        Err(ParseError::Empty) => return Ok(()),
+        Err(e) => return write!(out, "error: {}\r\n", e.message()),
+    };
+
+    match command {
+        Command::Led(mode) => {
+            let mode = mode.unwrap_or(LedMode::Blink {
+                period_ms: board.settings().blink_ms,
+            });
+            board.set_led(mode)
+        }
+        Command::Indicators => write_indicators(board, out)?,
+        Command::Touch => {
+            if board.calibration().is_none() {
+                return write!(out, "error: no touch screen\r\n");
+            }
+            match board.last_touch() {
+                Some(touch) => write!(
+                    out,
+                    "touch: {},{} raw {},{}\r\n",
+                    touch.x, touch.y, touch.raw_x, touch.raw_y
+                )?,
+                None => write!(out, "touch: none\r\n")?,
+            }
+        }
+        Command::Calibrate(calibration) => match (board.calibration(), calibration) {
+            (None, _) => return write!(out, "error: no touch screen\r\n"),
+            (Some(current), None) => write!(
+                out,
+                "calibration: {} {} {} {}\r\n",
+                current.x1, current.x2, current.y1, current.y2
+            )?,
+            (Some(_), Some(calibration)) => board.set_calibration(calibration),
+        },
+        Command::Log(view) => match (board.log_view(), view) {
+            (None, _) => return write!(out, "error: no on-screen log\r\n"),
+            (Some(current), None) => write!(out, "log: {}\r\n", current.label())?,
+            (Some(_), Some(view)) => board.set_log_view(view),
+        },
+        Command::Settings => {
+            let settings = board.settings();
+            let echo = if settings.echo { "on" } else { "off" };
+            write!(out, "echo: {}\r\nblink: {}\r\n", echo, settings.blink_ms)?;
+            let key = settings.key.map_or("none", |key| key.name());
+            write!(out, "key: {}\r\n", key)?;
+        }
+        Command::Set(setting) => {
+            let mut settings = board.settings();
+            match setting {
+                Setting::Echo(echo) => settings.echo = echo,
+                Setting::BlinkMs(ms) => settings.blink_ms = ms,
+                Setting::Key(key) => settings.key = key,
+            }
+            board.set_settings(settings);
+        }
+        Command::Status => write_status(board, out)?,
+        Command::Help => {
+            out.write_str("led on|off|blink [<ms>]\r\nindicators?\r\ntouch?\r\n")?;
+            out.write_str("calibrate [<x1> <x2> <y1> <y2> | default]\r\n")?;
+            out.write_str("log [off|panel|full]\r\n")?;
+            out.write_str("settings?\r\nset echo on|off\r\nset blink <ms>\r\n")?;
+            out.write_str("set key <name>|none\r\nstatus\r\n")?;
+        }
+    }
+    out.write_str("ok\r\n")
+}
+
+fn write_indicators<B: Board, W: Write>(board: &B, out: &mut W) -> fmt::Result {
+    let mut i = 0;
+    while let Some((name, indicator)) = board.indicator(i) {
+        write!(out, "{}: {}\r\n", name, indicator.label())?;
+        i += 1;
+    }
+    Ok(())
+}
+
+fn write_status<B: Board, W: Write>(board: &B, out: &mut W) -> fmt::Result {
+    let secs = board.uptime_ms() / 1000;
+    write!(out, "uptime: {}s\r\n", secs)?;
+    match board.led() {
+        LedMode::Off => out.write_str("led: off\r\n")?,
+        LedMode::On => out.write_str("led: on\r\n")?,
+        LedMode::Blink { period_ms } => write!(out, "led: blink {}\r\n", period_ms)?,
+    }
+    write_indicators(board, out)?;
+    if board.calibration().is_some() {
+        match board.last_touch() {
+            Some(touch) => write!(out, "touch: {},{}\r\n", touch.x, touch.y)?,
+            None => out.write_str("touch: none\r\n")?,
+        }
+    }
+    if let Some(view) = board.log_view() {
+        write!(out, "log: {}\r\n", view.label())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    /// A board with a touch screen and on-screen log, unless they're taken
+    /// away
+    struct MockBoard {
+        led: LedMode,
+        calibration: Option<Calibration>,
+        last_touch: Option<Touch>,
+        log_view: Option<LogView>,
+        settings: Settings,
+    }
+
+    impl MockBoard {
+        fn new() -> MockBoard {
+            MockBoard {
+                led: LedMode::Off,
+                calibration: Some(Calibration::DEFAULT),
+                last_touch: None,
+                log_view: Some(LogView::Panel),
+                settings: Settings::new(500),
+            }
+        }
+
+        /// Run a command line, returning the response
+        fn run(&mut self, line: &str) -> String {
+            let mut out = String::new();
+            run(self, line, &mut out).unwrap();
+            out
+        }
+    }
+
+    impl Board for MockBoard {
+        fn led(&self) -> LedMode {
+            self.led
+        }
+
+        fn set_led(&mut self, mode: LedMode) {
+            self.led = mode;
+        }
+
+        fn indicator(&self, i: usize) -> Option<(&'static str, Indicator)> {
+            [("usb", Indicator::Green), ("button", Indicator::Gray)].get(i).copied()
+        }
+
+        fn calibration(&self) -> Option<Calibration> {
+            self.calibration
+        }
+
+        fn set_calibration(&mut self, calibration: Calibration) {
+            self.calibration = Some(calibration);
+        }
+
+        fn last_touch(&self) -> Option<Touch> {
+            self.last_touch
+        }
+
+        fn log_view(&self) -> Option<LogView> {
+            self.log_view
+        }
+
+        fn set_log_view(&mut self, view: LogView) {
+            self.log_view = Some(view);
+        }
+
+        fn settings(&self) -> Settings {
+            self.settings
+        }
+
+        fn set_settings(&mut self, settings: Settings) {
+            self.settings = settings;
+        }
+
+        fn uptime_ms(&self) -> u64 {
+            61_999
+        }
+    }
+
+    #[test]
+    fn sets_the_led() {
+        let mut board = MockBoard::new();
+        assert_eq!(board.run("led on"), "ok\r\n");
+        assert_eq!(board.led, LedMode::On);
+        assert_eq!(board.run("  led   off "), "ok\r\n");
+        assert_eq!(board.led, LedMode::Off);
+        assert_eq!(board.run("led blink 100"), "ok\r\n");
+        assert_eq!(board.led, LedMode::Blink { period_ms: 100 });
+        // At the period in the settings
+        board.run("set blink 250");
+        assert_eq!(board.run("led blink"), "ok\r\n");
+        assert_eq!(board.led, LedMode::Blink { period_ms: 250 });
+
+        for (line, error) in [
+            ("led", "missing argument"),
+            ("led dim", "bad argument"),
+            ("led blink 19", "bad argument"),
+            ("led blink 10001", "bad argument"),
+            ("led blink fast", "bad argument"),
+            ("led on now", "too many arguments"),
+        ] {
+            assert_eq!(board.run(line), std::format!("error: {}\r\n", error), "{}", line);
+        }
+        assert_eq!(board.led, LedMode::Blink { period_ms: 250 });
+    }
+
+    #[test]
+    fn shows_indicators_and_touches() {
+        let mut board = MockBoard::new();
+        assert_eq!(board.run("indicators?"), "usb: green\r\nbutton: gray\r\nok\r\n");
+        assert_eq!(board.run("touch?"), "touch: none\r\nok\r\n");
+        board.last_touch = Some(Touch {
+            x: 160,
+            y: 120,
+            raw_x: 2110,
+            raw_y: 2056,
+        });
+        assert_eq!(board.run("touch?"), "touch: 160,120 raw 2110,2056\r\nok\r\n");
+        assert_eq!(board.run("touch? now"), "error: too many arguments\r\n");
+
+        board.calibration = None;
+        assert_eq!(board.run("touch?"), "error: no touch screen\r\n");
+    }
+
+    #[test]
+    fn calibrates() {
+        let mut board = MockBoard::new();
+        assert_eq!(board.run("calibrate"), "calibration: 3880 340 262 3850\r\nok\r\n");
+        assert_eq!(board.run("calibrate 100 4000 200 3900"), "ok\r\n");
+        assert_eq!(board.calibration, Calibration::new(100, 4000, 200, 3900));
+        assert_eq!(board.run("calibrate default"), "ok\r\n");
+        assert_eq!(board.calibration, Some(Calibration::DEFAULT));
+
+        for (line, error) in [
+            ("calibrate 100 4000 200", "missing argument"),
+            ("calibrate 100 4000 200 4096", "bad argument"),
+            ("calibrate 100 100 200 3900", "bad argument"),
+            ("calibrate 100 4000 200 3900 0", "too many arguments"),
+            ("calibrate default now", "too many arguments"),
+        ] {
+            assert_eq!(board.run(line), std::format!("error: {}\r\n", error), "{}", line);
+        }
+        assert_eq!(board.calibration, Some(Calibration::DEFAULT));
+
+        board.calibration = None;
+        assert_eq!(board.run("calibrate"), "error: no touch screen\r\n");
+        assert_eq!(board.run("calibrate default"), "error: no touch screen\r\n");
+        assert_eq!(board.calibration, None);
+    }
+
+    #[test]
+    fn changes_the_log_view() {
+        let mut board = MockBoard::new();
+        assert_eq!(board.run("log"), "log: panel\r\nok\r\n");
+        assert_eq!(board.run("log full"), "ok\r\n");
+        assert_eq!(board.log_view, Some(LogView::Full));
+        assert_eq!(board.run("log off"), "ok\r\n");
+        assert_eq!(board.run("log"), "log: off\r\nok\r\n");
+        assert_eq!(board.run("log all"), "error: bad argument\r\n");
+
+        board.log_view = None;
+        assert_eq!(board.run("log"), "error: no on-screen log\r\n");
+        assert_eq!(board.run("log full"), "error: no on-screen log\r\n");
+        assert_eq!(board.log_view, None);
+    }
+
+    #[test]
+    fn changes_settings() {
+        let mut board = MockBoard::new();
+        assert_eq!(board.run("settings?"), "echo: on\r\nblink: 500\r\nkey: space\r\nok\r\n");
+        assert_eq!(board.run("set echo off"), "ok\r\n");
+        assert_eq!(board.run("set blink 20"), "ok\r\n");
+        assert_eq!(board.run("set key f12"), "ok\r\n");
+        assert_eq!(board.run("settings?"), "echo: off\r\nblink: 20\r\nkey: f12\r\nok\r\n");
+        assert_eq!(board.run("set key none"), "ok\r\n");
+        assert_eq!(board.settings.key, None);
+
+        for (line, error) in [
+            ("set", "missing argument"),
+            ("set echo", "missing argument"),
+            ("set echo maybe", "bad argument"),
+            ("set blink 5", "bad argument"),
+            ("set key", "missing argument"),
+            ("set key hyper", "bad argument"),
+            ("set colour red", "bad argument"),
+            ("set echo on off", "too many arguments"),
+        ] {
+            assert_eq!(board.run(line), std::format!("error: {}\r\n", error), "{}", line);
+        }
+        assert_eq!(
+            board.settings,
+            Settings {
+                echo: false,
+                blink_ms: 20,
+                key: None
+            }
+        );
+    }
+
+    #[test]
+    fn shows_the_status() {
+        let mut board = MockBoard::new();
+        board.led = LedMode::Blink { period_ms: 100 };
+        assert_eq!(
+            board.run("status"),
+            "uptime: 61s\r\nled: blink 100\r\nusb: green\r\nbutton: gray\r\n\
+             touch: none\r\nlog: panel\r\nok\r\n"
+        );
+
+        // Without what the board doesn't have
+        board.led = LedMode::On;
+        board.calibration = None;
+        board.log_view = None;
+        assert_eq!(board.run("status"), "uptime: 61s\r\nled: on\r\nusb: green\r\nbutton: gray\r\nok\r\n");
+    }
+
+    #[test]
+    fn answers_everything_else() {
+        let mut board = MockBoard::new();
+        // Blank lines are ignored
+        assert_eq!(board.run(""), "");
+        assert_eq!(board.run("   "), "");
+        assert_eq!(board.run("reboot"), "error: unknown command, try help\r\n");
+        assert_eq!(board.run("help now"), "error: too many arguments\r\n");
+        let help = board.run("help");
+        assert!(help.ends_with("status\r\nok\r\n"), "{}", help);
+        // Every command is listed
+        for command in ["led", "indicators?", "touch?", "calibrate", "log", "settings?", "set", "status"] {
+            assert!(help.lines().any(|line| line.starts_with(command)), "{}", command);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Line editing for a serial terminal: assembling typed bytes into lines,
+//! with backspace, and saying what to echo.
+
+use crate::MAX_LINE;
+
+/// Echoed to rub out the last character
+pub const ERASE: &[u8] = b"\x08 \x08";
+
+/// Echoed at the end of a line
+pub const NEWLINE: &[u8] = b"\r\n";
+
+/// What a received byte amounts to
+#[derive(PartialEq, Eq, Debug)]
+pub enum Input<'a> {
+    /// Nothing to do, eg the line feed after a carriage return
+    None,
+    /// A character was added to the line, and should be echoed
+    Char(u8),
+    /// The last character was removed, echo `ERASE`
+    Erase,
+    /// A line was completed, echo `NEWLINE`
+    Line(&'a str),
+    /// A line was completed that was too long, or wasn't valid utf8
+    Invalid,
+}
+
+/// Assembles received bytes into lines of up to `N` bytes. Lines end with a
+/// carriage return, line feed, or both, as terminals differ.
+pub struct LineEditor<const N: usize = MAX_LINE> {
+    buf: [u8; N],
+    len: usize,
+    overflowed: bool,
+    /// The last byte was a carriage return, so a line feed following it
+    /// doesn't end another line
+    after_cr: bool,
+}
+
+impl<const N: usize> LineEditor<N> {
+    pub const fn new() -> Self {
+        LineEditor {
+            buf: [0; N],
+            len: 0,
+            overflowed: false,
+            after_cr: false,
+        }
+    }
+
+    pub fn push(&mut self, b: u8) -> Input<'_> {
+        let after_cr = core::mem::replace(&mut self.after_cr, b == b'\r');
+        match b {
+            b'\n' if after_cr => Input::None,
+            b'\r' | b'\n' => {
+                let overflowed = self.overflowed;
+                let len = self.len;
+                self.len = 0;
+                self.overflowed = false;
+                match core::str::from_utf8(&self.buf[..len]) {
+                    Ok(line) if !overflowed => Input::Line(line),
+                    _ => Input::Invalid,
+                }
+            }
+            // Backspace or delete, as terminals differ
+            0x08 | 0x7f => {
+                if self.overflowed || self.len == 0 {
+                    // Overflowed lines are thrown away anyway
+                    Input::None
+                } else {
+                    // Remove a whole utf8 character
+                    self.len -= 1;
+                    while self.len > 0 && self.buf[self.len] & 0xc0 == 0x80 {
+                        self.len -= 1;
+                    }
+                    Input::Erase
+                }
+            }
+            // Other control characters, eg from arrow keys, aren't echoed
+            b if b < 0x20 => Input::None,
+            b => {
+                if self.len < N {
+                    self.buf[self.len] = b;
+                    self.len += 1;
+                } else {
+                    self.overflowed = true;
+                }
+                Input::Char(b)
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for LineEditor<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Push `input`, returning the last line completed, if any
+    fn push_all<'a, const N: usize>(editor: &'a mut LineEditor<N>, input: &[u8]) -> Input<'a> {
+        let (last, rest) = input.split_last().unwrap();
+        for b in rest {
+            editor.push(*b);
+        }
+        editor.push(*last)
+    }
+
+    #[test]
+    fn echoes_what_is_typed() {
+        let mut editor: LineEditor = LineEditor::new();
+        assert_eq!(editor.push(b'l'), Input::Char(b'l'));
+        // Control characters, eg from an arrow key's escape sequence, aren't
+        // kept or echoed
+        assert_eq!(editor.push(0x1b), Input::None);
+        assert_eq!(editor.push(b'['), Input::Char(b'['));
+        assert_eq!(push_all(&mut editor, b"A\x08\x08ed\r"), Input::Line("led"));
+    }
+
+    #[test]
+    fn ends_lines_with_cr_lf_or_both() {
+        let mut editor: LineEditor = LineEditor::new();
+        assert_eq!(push_all(&mut editor, b"status\r"), Input::Line("status"));
+        // The line feed after a carriage return ends nothing
+        assert_eq!(editor.push(b'\n'), Input::None);
+        assert_eq!(push_all(&mut editor, b"help\n"), Input::Line("help"));
+        // A line feed on its own ends an empty line, as does a second
+        // carriage return
+        assert_eq!(editor.push(b'\n'), Input::Line(""));
+        assert_eq!(push_all(&mut editor, b"\r\r"), Input::Line(""));
+    }
+
+    #[test]
+    fn erases_whole_characters() {
+        let mut editor: LineEditor = LineEditor::new();
+        // Nothing to erase
+        assert_eq!(editor.push(0x08), Input::None);
+        assert_eq!(push_all(&mut editor, b"led of"), Input::Char(b'f'));
+        // Backspace and delete both erase
+        assert_eq!(editor.push(0x7f), Input::Erase);
+        assert_eq!(editor.push(0x08), Input::Erase);
+        assert_eq!(push_all(&mut editor, b"n\r"), Input::Line("led n"));
+
+        // Multi-byte characters go in one
+        push_all(&mut editor, "caf\u{e9}".as_bytes());
+        assert_eq!(editor.push(0x08), Input::Erase);
+        assert_eq!(editor.push(b'\r'), Input::Line("caf"));
+    }
+
+    #[test]
+    fn rejects_long_lines() {
+        let mut editor = LineEditor::<4>::new();
+        assert_eq!(push_all(&mut editor, b"abcd\r"), Input::Line("abcd"));
+        // Overflowing characters are still echoed, but the line is thrown
+        // away, so there's nothing to erase
+        assert_eq!(push_all(&mut editor, b"abcde"), Input::Char(b'e'));
+        assert_eq!(editor.push(0x08), Input::None);
+        assert_eq!(editor.push(b'\r'), Input::Invalid);
+        // And the next line is unaffected
+        assert_eq!(push_all(&mut editor, b"\nok\r"), Input::Line("ok"));
+
+        assert_eq!(push_all(&mut editor, b"\xff\r"), Input::Invalid);
+    }
+}
@@ -0,0 +1,560 @@
+//! Firmware update slots, shared by the bootloader and the application.
+//!
+//! The application runs from the active slot. An update is written to the
+//! dfu slot and checked, then requested in the state sector. On the next
+//! reset the bootloader swaps the two slots, page by page through a scratch
+//! page, and boots the update as a trial. If the update marks itself booted
+//! it stays; if instead the board resets again first, the bootloader swaps
+//! the slots back.
+//!
+//! The state sector is only ever programmed, never erased, between an update
+//! being requested and the next one, so every change to it is a single
+//! write. Writing an update to the dfu slot leaves it alone, so an update
+//! that's never requested can't disturb a trial or rollback. Each step of a swap sets a progress byte, so a swap
+//! interrupted by a reset carries on where it left off.
+//!
+//! ```text
+//! state sector: update:[u8; 4] = "UPDT" length:u32 crc32:u32
+//!               swapped:u8 trial:u8 confirmed:u8 reverted:u8
+//!               swap progress:[u8; steps] revert progress:[u8; steps]
+//! ```
+//!
+//! Erased flash reads as 0xff, and a flag or progress byte is set by
+//! programming it to 0.
+#![no_std]
+
+use wifi_protocol::crc32::Crc32;
+use wifi_protocol::ota::ImageHeader;
+
+/// The flash erase size, and the unit the slots are swapped in
+pub const PAGE_SIZE: u32 = 4096;
+
+/// Where flash is mapped for reading (XIP)
+pub const FLASH_BASE: u32 = 0x1000_0000;
+
+const UPDATE_MAGIC: [u8; 4] = *b"UPDT";
+
+const SWAPPED: u32 = 12;
+const TRIAL: u32 = 13;
+const CONFIRMED: u32 = 14;
+const REVERTED: u32 = 15;
+const PROGRESS: u32 = 16;
+
+/// Swap steps per page: active to scratch, dfu to active, scratch to dfu
+const STEPS_PER_PAGE: u32 = 3;
+
+/// Bytes of the state sector in use, enough for the progress of a full slot
+/// swap and revert
+pub const STATE_LEN: usize = 1024;
+
+/// Copies between pages go through a buffer this size
+const CHUNK: usize = 256;
+
+/// Where the partitions are, as offsets from the start of flash
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Layout {
+    pub state: u32,
+    pub scratch: u32,
+    pub active: u32,
+    pub dfu: u32,
+    pub slot_size: u32,
+}
+
+/// The layout on the pico w, matching the memory.x files of the bootloader
+/// and the application
+pub const PICO_W: Layout = Layout {
+    state: 0x8000,
+    scratch: 0x9000,
+    active: 0xa000,
+    dfu: 0x7a000,
+    slot_size: 0x70000,
+};
+
+impl Layout {
+    fn steps(&self) -> u32 {
+        self.slot_size / PAGE_SIZE * STEPS_PER_PAGE
+    }
+
+    fn swap_progress(&self) -> u32 {
+        self.state + PROGRESS
+    }
+
+    fn revert_progress(&self) -> u32 {
+        self.swap_progress() + self.steps()
+    }
+}
+
+/// Flash that can be read, erased in pages, and programmed a byte at a time.
+/// Offsets are from the start of flash. As with NOR flash, programming can
+/// only clear bits.
+pub trait Flash {
+    type Error;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Erase the pages from `from` up to `to`
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error>;
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error<E> {
+    Flash(E),
+    /// The image is larger than a slot
+    TooLarge,
+    /// The image in the dfu slot doesn't match its header
+    BadImage,
+    /// The running image is an unconfirmed trial, and must be marked booted
+    /// before it can be updated
+    Unconfirmed,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Flash(e)
+    }
+}
+
+/// How the running image came to be running
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BootStatus {
+    Normal,
+    /// An update, on trial until marked booted
+    Trial,
+    /// An update failed its trial, and the previous image was put back
+    RolledBack,
+}
+
+/// The contents of the state sector
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct State {
+    pub update: Option<ImageHeader>,
+    pub swapped: bool,
+    pub trial: bool,
+    pub confirmed: bool,
+    pub reverted: bool,
+    /// Swap steps completed
+    pub swap_progress: u32,
+    /// Revert steps completed
+    pub revert_progress: u32,
+}
+
+impl State {
+    /// Parse the first `STATE_LEN` bytes of the state sector
+    pub fn parse(sector: &[u8], layout: &Layout) -> State {
+        let word = |at: usize| u32::from_le_bytes([sector[at], sector[at + 1], sector[at + 2], sector[at + 3]]);
+        let flag = |at: u32| sector[at as usize] == 0;
+        let progress = |from: u32| {
+            let from = (from - layout.state) as usize;
+            let marks = &sector[from..from + layout.steps() as usize];
+            marks.iter().take_while(|b| **b == 0).count() as u32
+        };
+        let update = (sector[0..4] == UPDATE_MAGIC).then(|| ImageHeader {
+            length: word(4),
+            crc32: word(8),
+        });
+        State {
+            update,
+            swapped: flag(SWAPPED),
+            trial: flag(TRIAL),
+            confirmed: flag(CONFIRMED),
+            reverted: flag(REVERTED),
+            swap_progress: progress(layout.swap_progress()),
+            revert_progress: progress(layout.revert_progress()),
+        }
+    }
+
+    /// How the image the state leads to booting came to be running
+    pub fn status(&self) -> BootStatus {
+        match self.update {
+            Some(_) if self.reverted => BootStatus::RolledBack,
+            Some(_) if self.trial && !self.confirmed => BootStatus::Trial,
+            _ => BootStatus::Normal,
+        }
+    }
+}
+
+fn read_state<F: Flash>(flash: &mut F, layout: &Layout) -> Result<State, F::Error> {
+    assert!(PROGRESS as usize + 2 * layout.steps() as usize <= STATE_LEN);
+    let mut sector = [0u8; STATE_LEN];
+    flash.read(layout.state, &mut sector)?;
+    Ok(State::parse(&sector, layout))
+}
+
+fn set_flag<F: Flash>(flash: &mut F, at: u32) -> Result<(), F::Error> {
+    flash.write(at, &[0])
+}
+
+/// Pages taken by `length` bytes
+// div_ceil isn't stable on the firmware's toolchain
+#[allow(clippy::manual_div_ceil)]
+fn pages(length: u32) -> u32 {
+    (length + PAGE_SIZE - 1) / PAGE_SIZE
+}
+
+/// Whether the image at `from` matches the header
+fn verify<F: Flash>(flash: &mut F, from: u32, header: &ImageHeader) -> Result<bool, F::Error> {
+    let mut crc = Crc32::new();
+    let mut buf = [0u8; CHUNK];
+    let mut at = 0;
+    while at < header.length {
+        let n = (header.length - at).min(CHUNK as u32) as usize;
+        flash.read(from + at, &mut buf[..n])?;
+        crc.update(&buf[..n]);
+        at += n as u32;
+    }
+    Ok(crc.finish() == header.crc32)
+}
+
+fn copy_page<F: Flash>(flash: &mut F, from: u32, to: u32) -> Result<(), F::Error> {
+    flash.erase(to, to + PAGE_SIZE)?;
+    let mut buf = [0u8; CHUNK];
+    for at in (0..PAGE_SIZE).step_by(CHUNK) {
+        flash.read(from + at, &mut buf)?;
+        flash.write(to + at, &buf)?;
+    }
+    Ok(())
+}
+
+/// Swap the first `pages` pages of the slots, starting from step `done` and
+/// recording progress at `progress`
+fn swap<F: Flash>(flash: &mut F, layout: &Layout, pages: u32, done: u32, progress: u32) -> Result<(), F::Error> {
+    for step in done..pages * STEPS_PER_PAGE {
+        let page = step / STEPS_PER_PAGE * PAGE_SIZE;
+        let (active, dfu) = (layout.active + page, layout.dfu + page);
+        match step % STEPS_PER_PAGE {
+            0 => copy_page(flash, active, layout.scratch)?,
+            1 => copy_page(flash, dfu, active)?,
+            _ => copy_page(flash, layout.scratch, dfu)?,
+        }
+        set_flag(flash, progress + step)?;
+    }
+    Ok(())
+}
+
+/// Called by the bootloader on every reset. Carries out any requested
+/// update or rollback, and returns how the active image should be booted.
+pub fn prepare_boot<F: Flash>(flash: &mut F, layout: &Layout) -> Result<BootStatus, Error<F::Error>> {
+    let state = read_state(flash, layout)?;
+    let header = match state.update {
+        Some(header) => header,
+        None => return Ok(BootStatus::Normal),
+    };
+    if header.length > layout.slot_size {
+        // Can't happen unless the sector is corrupt; swap nothing
+        flash.erase(layout.state, layout.state + PAGE_SIZE)?;
+        return Ok(BootStatus::Normal);
+    }
+    if state.reverted {
+        return Ok(BootStatus::RolledBack);
+    }
+    if state.confirmed {
+        return Ok(BootStatus::Normal);
+    }
+
+    if !state.swapped {
+        if state.swap_progress == 0 && !verify(flash, layout.dfu, &header)? {
+            // Nothing has been swapped yet, so just forget the update
+            flash.erase(layout.state, layout.state + PAGE_SIZE)?;
+            return Ok(BootStatus::Normal);
+        }
+        swap(flash, layout, pages(header.length), state.swap_progress, layout.swap_progress())?;
+        set_flag(flash, layout.state + SWAPPED)?;
+    }
+
+    let reverting = state.trial || state.revert_progress > 0;
+    if !reverting && verify(flash, layout.active, &header)? {
+        set_flag(flash, layout.state + TRIAL)?;
+        return Ok(BootStatus::Trial);
+    }
+
+    // The trial didn't mark itself booted before the reset, or the swap
+    // went wrong, so put the previous image back
+    swap(flash, layout, pages(header.length), state.revert_progress, layout.revert_progress())?;
+    set_flag(flash, layout.state + REVERTED)?;
+    Ok(BootStatus::RolledBack)
+}
+
+/// Start writing an update of `length` bytes to the dfu slot. The state
+/// sector isn't touched until the update is finished.
+pub fn begin_update<F: Flash>(flash: &mut F, layout: &Layout, length: u32) -> Result<(), Error<F::Error>> {
+    if length > layout.slot_size {
+        return Err(Error::TooLarge);
+    }
+    if read_state(flash, layout)?.status() == BootStatus::Trial {
+        return Err(Error::Unconfirmed);
+    }
+    flash.erase(layout.dfu, layout.dfu + pages(length) * PAGE_SIZE)?;
+    Ok(())
+}
+
+/// Write part of the update, at `offset` in the image
+pub fn write_update<F: Flash>(flash: &mut F, layout: &Layout, offset: u32, data: &[u8]) -> Result<(), Error<F::Error>> {
+    if offset as usize + data.len() > layout.slot_size as usize {
+        return Err(Error::TooLarge);
+    }
+    flash.write(layout.dfu + offset, data)?;
+    Ok(())
+}
+
+/// Check the update written to the dfu slot against its header, and if it
+/// matches, request that it be swapped in on the next reset, replacing the
+/// state of any earlier update
+pub fn finish_update<F: Flash>(flash: &mut F, layout: &Layout, header: &ImageHeader) -> Result<(), Error<F::Error>> {
+    if header.length > layout.slot_size {
+        return Err(Error::TooLarge);
+    }
+    if read_state(flash, layout)?.status() == BootStatus::Trial {
+        return Err(Error::Unconfirmed);
+    }
+    if !verify(flash, layout.dfu, header)? {
+        return Err(Error::BadImage);
+    }
+    flash.erase(layout.state, layout.state + PAGE_SIZE)?;
+    let mut record = [0u8; 12];
+    record[0..4].copy_from_slice(&UPDATE_MAGIC);
+    record[4..8].copy_from_slice(&header.length.to_le_bytes());
+    record[8..12].copy_from_slice(&header.crc32.to_le_bytes());
+    flash.write(layout.state, &record)?;
+    Ok(())
+}
+
+/// Called by a trial image once it's working, so that it's kept
+pub fn mark_booted<F: Flash>(flash: &mut F, layout: &Layout) -> Result<(), Error<F::Error>> {
+    if read_state(flash, layout)?.status() == BootStatus::Trial {
+        set_flag(flash, layout.state + CONFIRMED)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+
+    const LAYOUT: Layout = Layout {
+        state: 0,
+        scratch: PAGE_SIZE,
+        active: 2 * PAGE_SIZE,
+        dfu: 5 * PAGE_SIZE,
+        slot_size: 3 * PAGE_SIZE,
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct PowerLoss;
+
+    /// NOR flash in memory, that loses power after a number of erases and
+    /// writes
+    struct RamFlash {
+        data: Vec<u8>,
+        ops_left: Option<usize>,
+    }
+
+    impl RamFlash {
+        fn new() -> RamFlash {
+            RamFlash {
+                data: vec![0xff; (LAYOUT.dfu + LAYOUT.slot_size) as usize],
+                ops_left: None,
+            }
+        }
+
+        fn slot(&self, at: u32, length: usize) -> &[u8] {
+            &self.data[at as usize..at as usize + length]
+        }
+
+        fn power(&mut self) -> Result<(), PowerLoss> {
+            match &mut self.ops_left {
+                Some(0) => Err(PowerLoss),
+                Some(n) => {
+                    *n -= 1;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl Flash for RamFlash {
+        type Error = PowerLoss;
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), PowerLoss> {
+            buf.copy_from_slice(&self.data[offset as usize..offset as usize + buf.len()]);
+            Ok(())
+        }
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), PowerLoss> {
+            self.power()?;
+            self.data[from as usize..to as usize].fill(0xff);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), PowerLoss> {
+            self.power()?;
+            for (byte, new) in self.data[offset as usize..].iter_mut().zip(data) {
+                *byte &= new;
+            }
+            Ok(())
+        }
+    }
+
+    /// An image of two and a half pages
+    fn image(seed: u8) -> Vec<u8> {
+        (0..5 * PAGE_SIZE / 2).map(|i| (i as u8).wrapping_mul(seed) ^ seed).collect()
+    }
+
+    fn update(flash: &mut RamFlash, image: &[u8]) -> Result<(), Error<PowerLoss>> {
+        begin_update(flash, &LAYOUT, image.len() as u32)?;
+        for (i, chunk) in image.chunks(1000).enumerate() {
+            write_update(flash, &LAYOUT, i as u32 * 1000, chunk)?;
+        }
+        finish_update(flash, &LAYOUT, &ImageHeader::for_image(image))
+    }
+
+    /// A board running `old`, with `new` requested as an update
+    fn updating(old: &[u8], new: &[u8]) -> RamFlash {
+        let mut flash = RamFlash::new();
+        flash.write(LAYOUT.active, old).unwrap();
+        update(&mut flash, new).unwrap();
+        flash
+    }
+
+    fn status(flash: &mut RamFlash) -> BootStatus {
+        read_state(flash, &LAYOUT).unwrap().status()
+    }
+
+    #[test]
+    fn boots_confirmed_updates() {
+        let (old, new) = (image(3), image(5));
+        let mut flash = RamFlash::new();
+        flash.write(LAYOUT.active, &old).unwrap();
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Normal));
+
+        update(&mut flash, &new).unwrap();
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Trial));
+        assert_eq!(flash.slot(LAYOUT.active, new.len()), &new[..]);
+        assert_eq!(flash.slot(LAYOUT.dfu, old.len()), &old[..]);
+        assert_eq!(begin_update(&mut flash, &LAYOUT, 10), Err(Error::Unconfirmed));
+
+        mark_booted(&mut flash, &LAYOUT).unwrap();
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Normal));
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Normal));
+        assert_eq!(flash.slot(LAYOUT.active, new.len()), &new[..]);
+        assert_eq!(status(&mut flash), BootStatus::Normal);
+    }
+
+    #[test]
+    fn rolls_back_unconfirmed_trials() {
+        let (old, new) = (image(3), image(5));
+        let mut flash = updating(&old, &new);
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Trial));
+
+        // Reset without marking the update booted
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::RolledBack));
+        assert_eq!(flash.slot(LAYOUT.active, old.len()), &old[..]);
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::RolledBack));
+        assert_eq!(flash.slot(LAYOUT.active, old.len()), &old[..]);
+
+        // Marking the rolled back image booted changes nothing, and it can
+        // be updated again
+        mark_booted(&mut flash, &LAYOUT).unwrap();
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::RolledBack));
+        update(&mut flash, &new).unwrap();
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Trial));
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        let (old, new) = (image(3), image(5));
+        let mut flash = RamFlash::new();
+        flash.write(LAYOUT.active, &old).unwrap();
+
+        let mut header = ImageHeader::for_image(&new);
+        header.crc32 ^= 1;
+        begin_update(&mut flash, &LAYOUT, new.len() as u32).unwrap();
+        write_update(&mut flash, &LAYOUT, 0, &new).unwrap();
+        assert_eq!(finish_update(&mut flash, &LAYOUT, &header), Err(Error::BadImage));
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Normal));
+        assert_eq!(flash.slot(LAYOUT.active, old.len()), &old[..]);
+
+        assert_eq!(begin_update(&mut flash, &LAYOUT, LAYOUT.slot_size + 1), Err(Error::TooLarge));
+        assert_eq!(write_update(&mut flash, &LAYOUT, LAYOUT.slot_size, &[0]), Err(Error::TooLarge));
+        header.length = LAYOUT.slot_size + 1;
+        assert_eq!(finish_update(&mut flash, &LAYOUT, &header), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn keeps_the_state_until_an_update_is_requested() {
+        let (old, new) = (image(3), image(5));
+        let mut flash = updating(&old, &new);
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Trial));
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::RolledBack));
+        let state = flash.slot(LAYOUT.state, PAGE_SIZE as usize).to_vec();
+
+        // An update that's written but fails its check
+        let mut header = ImageHeader::for_image(&new);
+        header.crc32 ^= 1;
+        begin_update(&mut flash, &LAYOUT, new.len() as u32).unwrap();
+        write_update(&mut flash, &LAYOUT, 0, &new).unwrap();
+        assert_eq!(finish_update(&mut flash, &LAYOUT, &header), Err(Error::BadImage));
+        assert_eq!(flash.slot(LAYOUT.state, PAGE_SIZE as usize), &state[..]);
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::RolledBack));
+        assert_eq!(flash.slot(LAYOUT.active, old.len()), &old[..]);
+    }
+
+    #[test]
+    fn forgets_updates_corrupted_in_flash() {
+        let (old, new) = (image(3), image(5));
+        let mut flash = updating(&old, &new);
+        flash.write(LAYOUT.dfu + 100, &[0]).unwrap();
+
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Normal));
+        assert_eq!(flash.slot(LAYOUT.active, old.len()), &old[..]);
+        assert_eq!(read_state(&mut flash, &LAYOUT).unwrap().update, None);
+    }
+
+    /// Boot with power lost after each number of flash operations in turn,
+    /// then boot again with it restored, and check how the board boots
+    fn interrupt_each_step(flash: &RamFlash, check: impl Fn(&RamFlash, BootStatus)) {
+        for ops in 0.. {
+            let mut flash = RamFlash {
+                data: flash.data.clone(),
+                ops_left: Some(ops),
+            };
+            if let Ok(status) = prepare_boot(&mut flash, &LAYOUT) {
+                check(&flash, status);
+                // Enough for a whole swap
+                assert!(ops > 3 * 3 * 2);
+                return;
+            }
+            flash.ops_left = None;
+            let status = prepare_boot(&mut flash, &LAYOUT).unwrap();
+            check(&flash, status);
+        }
+    }
+
+    #[test]
+    fn finishes_interrupted_swaps() {
+        let (old, new) = (image(3), image(5));
+        interrupt_each_step(&updating(&old, &new), |flash, status| {
+            assert_eq!(status, BootStatus::Trial);
+            assert_eq!(flash.slot(LAYOUT.active, new.len()), &new[..]);
+            assert_eq!(flash.slot(LAYOUT.dfu, old.len()), &old[..]);
+        });
+    }
+
+    #[test]
+    fn finishes_interrupted_rollbacks() {
+        let (old, new) = (image(3), image(5));
+        let mut flash = updating(&old, &new);
+        assert_eq!(prepare_boot(&mut flash, &LAYOUT), Ok(BootStatus::Trial));
+        interrupt_each_step(&flash, |flash, status| {
+            assert_eq!(status, BootStatus::RolledBack);
+            assert_eq!(flash.slot(LAYOUT.active, old.len()), &old[..]);
+            assert_eq!(flash.slot(LAYOUT.dfu, new.len()), &new[..]);
+        });
+    }
+}
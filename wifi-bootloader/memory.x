@@ -0,0 +1,6 @@
+MEMORY {
+    BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
+    /* The rest of the first 32K; see ../wifi-boot for the whole layout */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 32K - 0x100
+    RAM   : ORIGIN = 0x20000000, LENGTH = 256K
+}
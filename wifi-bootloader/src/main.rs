@@ -0,0 +1,109 @@
+//! Boots the application in the active slot, first swapping in an update or
+//! rolling back a failed one. See ../wifi-boot for the flash layout and the
+//! update process.
+
+#![no_std]
+#![no_main]
+
+use cortex_m_rt::entry;
+use defmt::*;
+use embassy_rp::flash::{self, Blocking, Flash};
+use embassy_rp::pac;
+use embassy_rp::peripherals::FLASH;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use wifi_boot::{BootStatus, FLASH_BASE, PICO_W};
+use {defmt_rtt as _, panic_probe as _};
+
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// Time a trial image has to feed the watchdog, before it's reset and
+/// rolled back. Close to the most the watchdog can count.
+const TRIAL_WATCHDOG_US: u32 = 8_000_000;
+
+struct BootFlash<'d>(Flash<'d, FLASH, Blocking, FLASH_SIZE>);
+
+impl<'d> wifi_boot::Flash for BootFlash<'d> {
+    type Error = flash::Error;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), flash::Error> {
+        ReadNorFlash::read(&mut self.0, offset, buf)
+    }
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), flash::Error> {
+        NorFlash::erase(&mut self.0, from, to)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), flash::Error> {
+        NorFlash::write(&mut self.0, offset, data)
+    }
+}
+
+/// Start the watchdog, resetting everything but the oscillators when it
+/// runs out. The tick was set up by `embassy_rp::init`.
+fn start_watchdog(us: u32) {
+    pac::PSM.wdsel().write(|w| {
+        w.0 = 0x0001ffff;
+        w.set_xosc(false);
+        w.set_rosc(false);
+    });
+    // The counter goes down by two each tick (RP2040-E1)
+    pac::WATCHDOG.load().write(|w| w.set_load(us * 2));
+    pac::WATCHDOG.ctrl().write(|w| {
+        w.set_pause_dbg0(true);
+        w.set_pause_dbg1(true);
+        w.set_pause_jtag(true);
+        w.set_enable(true);
+    });
+}
+
+/// Undo what `embassy_rp::init` set up, so the application starts as it
+/// would from a reset: the peripherals it took out of reset back in, and no
+/// interrupts enabled or pending. The clocks are left running, as the
+/// application's `embassy_rp::init` switches them over cleanly.
+fn reset_peripherals() {
+    cortex_m::interrupt::disable();
+    let mut peris = pac::resets::regs::Peripherals(0x01ff_ffff);
+    // The ones `embassy_rp::init` leaves alone: flash, the clocks, and
+    // the rtc
+    peris.set_io_qspi(false);
+    peris.set_pads_qspi(false);
+    peris.set_pll_sys(false);
+    peris.set_pll_usb(false);
+    peris.set_usbctrl(false);
+    peris.set_syscfg(false);
+    peris.set_rtc(false);
+    unsafe {
+        pac::RESETS.reset().write_value(peris);
+        let mut p = cortex_m::Peripherals::steal();
+        p.SYST.disable_interrupt();
+        p.SYST.disable_counter();
+        p.NVIC.icer[0].write(0xffff_ffff);
+        p.NVIC.icpr[0].write(0xffff_ffff);
+        cortex_m::interrupt::enable();
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_rp::init(Default::default());
+    let mut flash = BootFlash(Flash::new_blocking(p.FLASH));
+
+    match wifi_boot::prepare_boot(&mut flash, &PICO_W) {
+        Ok(BootStatus::Normal) => {}
+        Ok(BootStatus::Trial) => {
+            info!("booting update on trial");
+            start_watchdog(TRIAL_WATCHDOG_US);
+        }
+        Ok(BootStatus::RolledBack) => warn!("update failed, rolled back"),
+        // Whatever happened, the active slot is the best bet
+        Err(e) => error!("update failed: {:?}", Debug2Format(&e)),
+    }
+
+    reset_peripherals();
+    let start = FLASH_BASE + PICO_W.active;
+    unsafe {
+        let p = cortex_m::Peripherals::steal();
+        p.SCB.vtor.write(start);
+        cortex_m::asm::bootload(start as *const u32)
+    }
+}
@@ -1,5 +1,12 @@
 MEMORY {
+    /* Not flashed with over-the-air updates; strip .boot2 from the image */
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 1024K - 0x100
+    /* The active slot, after the bootloader; see ../wifi-boot for the layout */
+    FLASH : ORIGIN = 0x1000A000, LENGTH = 448K
+    /* The cyw43 firmware and CLM blobs, each with a header, see src/blobs.rs */
+    CYW43_FW : ORIGIN = 0x10100000, LENGTH = 256K
+    CYW43_CLM : ORIGIN = 0x10140000, LENGTH = 16K
+    /* Network configuration text, see src/netconfig.rs */
+    CONFIG : ORIGIN = 0x10180000, LENGTH = 4K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
-}
\ No newline at end of file
+}
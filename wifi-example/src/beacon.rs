@@ -0,0 +1,47 @@
+//! Broadcasts a discovery beacon, so host tools can find the board without
+//! knowing its address. See `wifi_protocol::beacon` for the format, and
+//! `udplisten` in wifi-tools for a listener.
+
+use defmt::*;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address};
+use embassy_time::{Duration, Timer};
+use heapless::String;
+use wifi_protocol::beacon::{self, Beacon};
+
+use crate::netconfig::MAX_HOSTNAME;
+use crate::NetStack;
+
+const INTERVAL: Duration = Duration::from_secs(5);
+
+const BUFFER_SIZE: usize = 128;
+
+#[embassy_executor::task]
+pub async fn beacon_task(stack: &'static NetStack, hostname: String<MAX_HOSTNAME>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 0];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; BUFFER_SIZE];
+    let mut out = [0; BUFFER_SIZE];
+
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(beacon::PORT));
+    let broadcast = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), beacon::PORT);
+
+    loop {
+        if let Some(config) = stack.config_v4() {
+            let beacon = Beacon {
+                board_id: crate::mac_address(stack),
+                address: config.address.address().0,
+                version: env!("CARGO_PKG_VERSION"),
+                hostname: &hostname,
+            };
+            // The hostname is short enough to always fit
+            let n = unwrap!(beacon.encode(&mut out).ok());
+            if let Err(e) = socket.send_to(&out[..n], broadcast).await {
+                warn!("beacon send error: {:?}", e);
+            }
+        }
+        Timer::after(INTERVAL).await;
+    }
+}
@@ -0,0 +1,84 @@
+//! The cyw43 firmware and Country Locale Matrix (CLM) blobs, read from their
+//! own flash partitions rather than built into the program. Each is written
+//! with a header giving its length and checksum (see `wifi_protocol::blob`),
+//! made by `fwpack` in wifi-tools:
+//!
+//! ```text
+//! cargo run --bin fwpack -- ../wifi-example/firmware/43439A0.bin 43439A0.blob
+//! cargo run --bin fwpack -- ../wifi-example/firmware/43439A0_clm.bin 43439A0_clm.blob
+//! probe-rs download 43439A0.blob --format bin --chip RP2040 --base-address 0x10100000
+//! probe-rs download 43439A0_clm.blob --format bin --chip RP2040 --base-address 0x10140000
+//! ```
+
+use defmt::Format;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::Text;
+use wifi_protocol::blob;
+
+use crate::display::Display;
+
+/// Where the blobs live, matching the CYW43_FW and CYW43_CLM regions in
+/// memory.x
+pub const FW_FLASH_ADDR: usize = 0x1010_0000;
+pub const FW_FLASH_LEN: usize = 256 * 1024;
+pub const CLM_FLASH_ADDR: usize = 0x1014_0000;
+pub const CLM_FLASH_LEN: usize = 16 * 1024;
+
+pub struct Blobs {
+    pub fw: &'static [u8],
+    pub clm: &'static [u8],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Which {
+    Firmware,
+    Clm,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LoadError {
+    pub which: Which,
+    pub error: blob::Error,
+}
+
+#[cfg(target_os = "none")]
+fn open(which: Which, addr: usize, len: usize) -> Result<&'static [u8], LoadError> {
+    // Safety: the partition is reserved in memory.x, and flash is mapped for
+    // reading via XIP.
+    let partition = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    blob::open(partition).map_err(|error| LoadError { which, error })
+}
+
+/// Find and check both blobs
+#[cfg(target_os = "none")]
+pub fn load() -> Result<Blobs, LoadError> {
+    let fw = open(Which::Firmware, FW_FLASH_ADDR, FW_FLASH_LEN)?;
+    let clm = open(Which::Clm, CLM_FLASH_ADDR, CLM_FLASH_LEN)?;
+    defmt::info!("wifi firmware {} bytes, clm {} bytes", fw.len(), clm.len());
+    Ok(Blobs { fw, clm })
+}
+
+/// Explain on the display why the wifi can't start
+pub fn render_error(display: &mut Display, error: &LoadError) {
+    let which = match error.which {
+        Which::Firmware => "Wifi firmware",
+        Which::Clm => "Wifi CLM blob",
+    };
+    let problem = match error.error {
+        blob::Error::Missing => "is missing",
+        blob::Error::Unsupported => "has an unknown format",
+        blob::Error::TooLarge => "is truncated",
+        blob::Error::Corrupt => "is corrupt",
+    };
+    let lines = [which, problem, "", "Flash it with fwpack,", "see the README"];
+    for (row, line) in lines.iter().enumerate() {
+        Text::with_text_style(
+            line,
+            Point::new(14, row as i32 * 20),
+            display.styles.char,
+            display.styles.text,
+        )
+        .draw(&mut display.interface)
+        .unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+//! Wall clock time, kept as an offset from `embassy_time::Instant` and set
+//! by SNTP.
+
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_futures::select::select;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+use crate::display_state_update;
+use crate::netconfig::Ipv4;
+use crate::sntp::{self, Timestamp};
+use crate::NetStack;
+
+/// Port the SNTP requests are sent from
+const LOCAL_PORT: u16 = 50123;
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the address is checked for a new DHCP lease
+const ADDRESS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Microseconds to add to `Instant::now()` to get unix time, once synced
+static OFFSET: Mutex<CriticalSectionRawMutex, Cell<Option<i64>>> = Mutex::new(Cell::new(None));
+
+/// Microseconds since the unix epoch, if the clock has been set
+pub fn unix_micros() -> Option<i64> {
+    OFFSET
+        .lock(|offset| offset.get())
+        .map(|offset| Instant::now().as_micros() as i64 + offset)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct TimeOfDay {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+impl TimeOfDay {
+    /// The local time of day, given unix time and the timezone's offset from UTC
+    pub fn from_unix(unix_secs: i64, tz_offset_mins: i16) -> TimeOfDay {
+        let secs = (unix_secs + tz_offset_mins as i64 * 60).rem_euclid(24 * 60 * 60);
+        TimeOfDay {
+            hours: (secs / 3600) as u8,
+            minutes: (secs / 60 % 60) as u8,
+            seconds: (secs % 60) as u8,
+        }
+    }
+}
+
+/// The UTC date, as (year, month, day), given unix time
+pub fn date_from_unix(unix_secs: i64) -> (i32, u8, u8) {
+    // Howard Hinnant's civil_from_days, counting from 0000-03-01 so leap
+    // days come at the end of each year
+    let days = unix_secs.div_euclid(24 * 60 * 60) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year as i32, month, day)
+}
+
+#[derive(Debug, Format)]
+enum SyncError {
+    Send(embassy_net::udp::SendError),
+    Recv(embassy_net::udp::RecvError),
+    Sntp(sntp::Error),
+    Timeout,
+}
+
+/// Set the clock from an SNTP server, and keep it in step. It's set again
+/// whenever the network configuration comes back after being lost, or changes.
+#[embassy_executor::task]
+pub async fn sntp_task(stack: &'static NetStack, server: (Ipv4, u16)) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 128];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 128];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(LOCAL_PORT));
+    let server = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address(server.0)), server.1);
+
+    loop {
+        let address = stack.config_v4().map(|c| c.address);
+        let wait = match sync(&mut socket, server).await {
+            Ok(offset) => {
+                let previous = OFFSET.lock(|o| o.replace(Some(offset)));
+                match previous {
+                    Some(previous) => info!("clock set by {:?}, adjusted by {}us", server, offset - previous),
+                    None => info!("clock set by {:?}", server),
+                }
+                SYNC_INTERVAL
+            }
+            Err(e) => {
+                warn!("SNTP sync with {:?} failed: {:?}", server, e);
+                RETRY_INTERVAL
+            }
+        };
+        select(Timer::after(wait), address_change(stack, address)).await;
+    }
+}
+
+/// Wait for the address to differ from `address`, or to come back after
+/// being lost, as when DHCP gets a new lease
+async fn address_change(stack: &'static NetStack, address: Option<Ipv4Cidr>) {
+    let mut lost = address.is_none();
+    loop {
+        Timer::after(ADDRESS_POLL_INTERVAL).await;
+        match stack.config_v4().map(|c| c.address) {
+            None => lost = true,
+            Some(now) if lost || Some(now) != address => return,
+            Some(_) => {}
+        }
+    }
+}
+
+/// Ask the server for the time, returning the clock offset
+async fn sync(socket: &mut UdpSocket<'_>, server: IpEndpoint) -> Result<i64, SyncError> {
+    let sent = Instant::now().as_micros() as i64;
+    // The send time doubles as a nonce, to match the reply
+    let transmit = Timestamp(sent as u64);
+    socket
+        .send_to(&sntp::encode_request(transmit), server)
+        .await
+        .map_err(SyncError::Send)?;
+
+    let mut buf = [0; 64];
+    with_timeout(REPLY_TIMEOUT, async {
+        loop {
+            let (n, from) = socket.recv_from(&mut buf).await.map_err(SyncError::Recv)?;
+            let received = Instant::now().as_micros() as i64;
+            if from != server {
+                continue;
+            }
+            match sntp::decode_reply(&buf[..n], transmit) {
+                Ok(reply) => return Ok(sntp::clock_offset(&reply, sent, received)),
+                // A late reply to an earlier request
+                Err(sntp::Error::WrongOrigin) => continue,
+                Err(e) => return Err(SyncError::Sntp(e)),
+            }
+        }
+    })
+    .await
+    .map_err(|_| SyncError::Timeout)?
+}
+
+/// Show the local time on the display, to the minute. The display is only
+/// redrawn when the minute shown changes.
+#[embassy_executor::task]
+pub async fn clock_task(tz_offset_mins: i16) -> ! {
+    let mut shown = None;
+    loop {
+        match unix_micros() {
+            Some(micros) => {
+                let time = TimeOfDay::from_unix(micros.div_euclid(1_000_000), tz_offset_mins);
+                let minute = (time.hours, time.minutes);
+                if shown != Some(minute) {
+                    display_state_update(|ds| ds.clock = Some(time));
+                    shown = Some(minute);
+                }
+                // Timezones are offset by whole minutes, so local minutes
+                // begin with UTC's
+                let until_next_minute = 60_000_000 - micros.rem_euclid(60_000_000);
+                Timer::after(Duration::from_micros(until_next_minute as u64)).await;
+            }
+            None => Timer::after(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{date_from_unix, TimeOfDay};
+
+    #[test]
+    fn finds_the_time_of_day() {
+        let time = |hours, minutes, seconds| TimeOfDay { hours, minutes, seconds };
+        assert_eq!(TimeOfDay::from_unix(0, 0), time(0, 0, 0));
+        // 2024-03-01 12:34:56 UTC
+        assert_eq!(TimeOfDay::from_unix(1_709_296_496, 0), time(12, 34, 56));
+        assert_eq!(TimeOfDay::from_unix(1_709_296_496, 330), time(18, 4, 56));
+        assert_eq!(TimeOfDay::from_unix(1_709_296_496, -14 * 60), time(22, 34, 56));
+        // Before 1970
+        assert_eq!(TimeOfDay::from_unix(-1, 0), time(23, 59, 59));
+    }
+
+    #[test]
+    fn finds_the_date() {
+        assert_eq!(date_from_unix(0), (1970, 1, 1));
+        assert_eq!(date_from_unix(-1), (1969, 12, 31));
+        assert_eq!(date_from_unix(951_782_400), (2000, 2, 29));
+        assert_eq!(date_from_unix(951_868_800), (2000, 3, 1));
+        assert_eq!(date_from_unix(1_709_164_800), (2024, 2, 29));
+        assert_eq!(date_from_unix(1_709_296_496), (2024, 3, 1));
+        assert_eq!(date_from_unix(4_107_542_399), (2100, 2, 28));
+        assert_eq!(date_from_unix(4_107_542_400), (2100, 3, 1));
+    }
+}
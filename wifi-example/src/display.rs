@@ -1,4 +1,10 @@
-use embassy_rp::{gpio, peripherals, spi};
+//! The display and the styles shared by what's drawn on it. The panel is the
+//! ILI9341 in `panel` on the board, and simulated in `host`.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
 use embedded_graphics::{
     mono_font::MonoTextStyle,
     pixelcolor::Rgb565,
@@ -6,64 +12,33 @@ use embedded_graphics::{
     primitives::{PrimitiveStyle, PrimitiveStyleBuilder},
     text::{Baseline, TextStyle},
 };
-use gpio::{Level, Output};
-use {defmt_rtt as _, panic_probe as _};
 
-use display_interface_spi::SPIInterface;
-use ili9341::{Ili9341, Orientation};
+#[cfg(not(target_os = "none"))]
+use crate::host::Panel;
+use crate::mirror::Mirrored;
+#[cfg(target_os = "none")]
+use crate::panel::Panel;
 
-pub type DisplayInterface = Ili9341<
-    SPIInterface<
-        embassy_rp::spi::Spi<'static, peripherals::SPI1, embassy_rp::spi::Blocking>,
-        Output<'static, peripherals::PIN_15>,
-        Output<'static, peripherals::PIN_13>,
-    >,
-    Output<'static, peripherals::PIN_14>,
->;
+/// Everything drawn on the panel is mirrored, so it can be streamed
+pub type DisplayInterface = Mirrored<Panel>;
 
 pub struct Display {
     pub interface: DisplayInterface,
     pub styles: Styles,
 }
 
-pub fn init(
-    miso: peripherals::PIN_12,
-    mosi: peripherals::PIN_11,
-    clk: peripherals::PIN_10,
-    cs: peripherals::PIN_13,
-    reset: peripherals::PIN_14,
-    dc: peripherals::PIN_15,
-    spi: peripherals::SPI1,
-) -> Display {
-    let cs = Output::new(cs, Level::High);
-    let reset = Output::new(reset, Level::Low);
-    let dc = Output::new(dc, Level::Low);
-
-    let display_spi = {
-        let mut config = spi::Config::default();
-        config.frequency = 32_000_000;
-        config.polarity = spi::Polarity::IdleLow;
-        config.phase = spi::Phase::CaptureOnFirstTransition;
-        spi::Spi::new_blocking(spi, clk, mosi, miso, config)
-    };
-
-    let interface: DisplayInterface = {
-        let mut delay = embassy_time::Delay {};
-        Ili9341::new(
-            SPIInterface::new(display_spi, dc, cs),
-            reset,
-            &mut delay,
-            Orientation::LandscapeFlipped,
-            ili9341::DisplaySize240x320,
-        )
-        .unwrap()
-    };
-
-    let styles = Styles::new();
-
-    Display { interface, styles }
+impl Display {
+    pub fn new(panel: Panel) -> Display {
+        Display {
+            interface: Mirrored { inner: panel },
+            styles: Styles::new(),
+        }
+    }
 }
 
+/// The display, shared by the tasks that draw on it
+pub type SharedDisplay = Mutex<ThreadModeRawMutex, RefCell<Display>>;
+
 /// Some shared styles
 pub struct Styles {
     pub char: MonoTextStyle<'static, Rgb565>,
@@ -0,0 +1,356 @@
+//! Resolves hostnames to IPv4 addresses, using the DNS servers given by
+//! DHCP or the static configuration, and opens outbound tcp connections by
+//! hostname.
+
+use defmt::*;
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_net::udp::{self, PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{with_timeout, Duration};
+
+use crate::dnswire::{self, Answers, Writer, CLASS_IN, TYPE_A};
+use crate::entropy;
+use crate::netconfig::{self, Ipv4};
+use crate::NetStack;
+
+pub const PORT: u16 = 53;
+
+const MAX_MESSAGE: usize = 512;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Times each server is tried
+const ATTEMPTS: usize = 2;
+
+/// Local ports are picked at random from the dynamic range
+const FIRST_LOCAL_PORT: u16 = 49152;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Error {
+    /// There are no DNS servers configured
+    NoServers,
+    Bind,
+    Send(udp::SendError),
+    Recv(udp::RecvError),
+    Dns(dnswire::Error),
+    /// The name doesn't exist, or has no IPv4 address
+    NotFound,
+    /// The server failed, with this response code
+    ServerFailure(u16),
+    Timeout,
+}
+
+impl From<dnswire::Error> for Error {
+    fn from(e: dnswire::Error) -> Self {
+        Error::Dns(e)
+    }
+}
+
+/// One lookup at a time, so only one socket is needed
+static LOOKUP: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// Build a query for the IPv4 address of `name`, returning its length
+pub fn encode_query(buf: &mut [u8], id: u16, name: &str) -> Result<usize, dnswire::Error> {
+    let mut w = Writer::new(buf, id, dnswire::FLAGS_QUERY)?;
+    w.question(name, TYPE_A, CLASS_IN)?;
+    Ok(w.finish())
+}
+
+/// Find the address in the reply to query `id`. Returns None if the
+/// message is not that reply.
+pub fn decode_reply(msg: &[u8], id: u16) -> Result<Option<Ipv4>, Error> {
+    let header = dnswire::parse_header(msg)?;
+    if header.id != id || header.flags & dnswire::FLAG_QR == 0 {
+        return Ok(None);
+    }
+    match header.flags & dnswire::RCODE_MASK {
+        0 => {}
+        dnswire::RCODE_NAME_ERROR => return Err(Error::NotFound),
+        rcode => return Err(Error::ServerFailure(rcode)),
+    }
+    // Any CNAME records come first, and the address records that follow
+    // are for the name they lead to
+    for record in Answers::new(msg)? {
+        let record = record?;
+        if record.rtype == TYPE_A && record.class == CLASS_IN && record.data.len() == 4 {
+            let mut address = [0; 4];
+            address.copy_from_slice(record.data);
+            return Ok(Some(address));
+        }
+    }
+    Err(Error::NotFound)
+}
+
+/// Look up the IPv4 address of `name`. Dotted quads are returned as is.
+pub async fn resolve(stack: &'static NetStack, name: &str) -> Result<Ipv4Address, Error> {
+    if let Some(address) = netconfig::parse_ipv4(name) {
+        return Ok(Ipv4Address(address));
+    }
+    let servers = stack.config_v4().map(|c| c.dns_servers).unwrap_or_default();
+    if servers.is_empty() {
+        return Err(Error::NoServers);
+    }
+
+    let _lookup = LOOKUP.lock().await;
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; MAX_MESSAGE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; MAX_MESSAGE];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    // A random port and id make forged replies harder
+    let local_port = FIRST_LOCAL_PORT + (entropy::next_u32() % (u16::MAX - FIRST_LOCAL_PORT) as u32) as u16;
+    socket.bind(local_port).map_err(|_| Error::Bind)?;
+    let id = entropy::next_u32() as u16;
+
+    lookup(&mut socket, &servers, name, id).await
+}
+
+/// What a lookup needs of a udp socket
+trait Socket {
+    async fn send_to(&mut self, buf: &[u8], to: IpEndpoint) -> Result<(), Error>;
+    async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), Error>;
+}
+
+impl Socket for UdpSocket<'_> {
+    async fn send_to(&mut self, buf: &[u8], to: IpEndpoint) -> Result<(), Error> {
+        UdpSocket::send_to(self, buf, to).await.map_err(Error::Send)
+    }
+
+    async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), Error> {
+        UdpSocket::recv_from(self, buf).await.map_err(Error::Recv)
+    }
+}
+
+/// Ask the servers in turn for the address of `name`, with query `id`
+async fn lookup<S: Socket>(socket: &mut S, servers: &[Ipv4Address], name: &str, id: u16) -> Result<Ipv4Address, Error> {
+    let mut query = [0; MAX_MESSAGE];
+    let len = encode_query(&mut query, id, name)?;
+    let mut buf = [0; MAX_MESSAGE];
+    let mut error = Error::Timeout;
+    for _ in 0..ATTEMPTS {
+        for server in servers {
+            let server = IpEndpoint::new(IpAddress::Ipv4(*server), PORT);
+            socket.send_to(&query[..len], server).await?;
+            let reply = with_timeout(REPLY_TIMEOUT, async {
+                loop {
+                    let (n, from) = socket.recv_from(&mut buf).await?;
+                    if from != server {
+                        continue;
+                    }
+                    if let Some(address) = decode_reply(&buf[..n], id)? {
+                        return Ok(Ipv4Address(address));
+                    }
+                }
+            })
+            .await;
+            match reply {
+                Ok(Ok(address)) => {
+                    debug!("resolved {} to {:?}", name, address);
+                    return Ok(address);
+                }
+                // The name doesn't exist, there's no point asking again
+                Ok(Err(Error::NotFound)) => return Err(Error::NotFound),
+                Ok(Err(e)) => error = e,
+                Err(_) => error = Error::Timeout,
+            }
+        }
+    }
+    Err(error)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum ConnectError {
+    Resolve(Error),
+    Tcp(tcp::ConnectError),
+}
+
+/// Connect `socket` to `port` on `host`, a hostname or dotted quad
+pub async fn connect(
+    stack: &'static NetStack,
+    socket: &mut TcpSocket<'_>,
+    host: &str,
+    port: u16,
+) -> Result<(), ConnectError> {
+    let address = resolve(stack, host).await.map_err(ConnectError::Resolve)?;
+    socket
+        .connect(IpEndpoint::new(IpAddress::Ipv4(address), port))
+        .await
+        .map_err(ConnectError::Tcp)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use embassy_net::{IpAddress, IpEndpoint, Ipv4Address};
+
+    use super::{decode_reply, encode_query, lookup, Error, Socket, PORT};
+    use crate::dnswire::{self, Questions, RData, Record, Section, Writer, CLASS_IN};
+    use crate::host::run_test;
+
+    const SERVERS: [Ipv4Address; 2] = [Ipv4Address([10, 0, 0, 1]), Ipv4Address([10, 0, 0, 2])];
+
+    fn endpoint(server: Ipv4Address) -> IpEndpoint {
+        IpEndpoint::new(IpAddress::Ipv4(server), PORT)
+    }
+
+    /// A reply to `query` with response code `rcode`, and these answers
+    fn reply(query: &[u8], rcode: u16, answers: &[RData]) -> Vec<u8> {
+        let header = dnswire::parse_header(query).unwrap();
+        let mut questions = Questions::new(query).unwrap();
+        for question in &mut questions {
+            question.unwrap();
+        }
+        let mut buf = [0; 512];
+        let mut w = Writer::new(&mut buf, header.id, 0x8180 | rcode).unwrap();
+        w.raw_questions(&query[dnswire::HEADER_LEN..questions.offset()], header.questions)
+            .unwrap();
+        for data in answers {
+            let record = Record {
+                name: "pico.example",
+                class: CLASS_IN,
+                ttl: 60,
+                data: *data,
+            };
+            w.record(Section::Answer, &record).unwrap();
+        }
+        let n = w.finish();
+        buf[..n].to_vec()
+    }
+
+    /// Stands in for a socket and the servers it sends to, which answer
+    /// with what `serve` gives for each query
+    struct StandIn<F> {
+        serve: F,
+        /// The servers asked, in order
+        asked: Vec<IpEndpoint>,
+        replies: VecDeque<(Vec<u8>, IpEndpoint)>,
+    }
+
+    impl<F: FnMut(&[u8], IpEndpoint) -> Vec<(Vec<u8>, IpEndpoint)>> StandIn<F> {
+        fn new(serve: F) -> Self {
+            StandIn {
+                serve,
+                asked: Vec::new(),
+                replies: VecDeque::new(),
+            }
+        }
+    }
+
+    impl<F: FnMut(&[u8], IpEndpoint) -> Vec<(Vec<u8>, IpEndpoint)>> Socket for StandIn<F> {
+        async fn send_to(&mut self, buf: &[u8], to: IpEndpoint) -> Result<(), Error> {
+            self.asked.push(to);
+            let replies = (self.serve)(buf, to);
+            self.replies.extend(replies);
+            Ok(())
+        }
+
+        async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), Error> {
+            match self.replies.pop_front() {
+                Some((reply, from)) => {
+                    buf[..reply.len()].copy_from_slice(&reply);
+                    Ok((reply.len(), from))
+                }
+                // A silent server
+                None => core::future::pending().await,
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_queries() {
+        let mut buf = [0; 64];
+        let n = encode_query(&mut buf, 0xbeef, "pico.example").unwrap();
+        assert_eq!(
+            &buf[..n],
+            b"\xbe\xef\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x04pico\x07example\x00\x00\x01\x00\x01"
+        );
+    }
+
+    #[test]
+    fn decodes_replies() {
+        let mut query = [0; 64];
+        let n = encode_query(&mut query, 7, "pico.example").unwrap();
+        let query = &query[..n];
+        // Records of other types are passed over
+        let found = reply(query, 0, &[RData::Ptr("x.example"), RData::A([10, 0, 0, 9])]);
+        assert_eq!(decode_reply(&found, 7), Ok(Some([10, 0, 0, 9])));
+        // Not the reply to this query
+        assert_eq!(decode_reply(&found, 8), Ok(None));
+        assert_eq!(decode_reply(query, 7), Ok(None));
+        assert_eq!(decode_reply(&reply(query, 0, &[]), 7), Err(Error::NotFound));
+        assert_eq!(decode_reply(&reply(query, 3, &[]), 7), Err(Error::NotFound));
+        assert_eq!(decode_reply(&reply(query, 2, &[]), 7), Err(Error::ServerFailure(2)));
+        assert_eq!(
+            decode_reply(&found[..found.len() - 1], 7),
+            Err(Error::Dns(dnswire::Error::Truncated))
+        );
+    }
+
+    #[test]
+    fn ignores_strangers_and_other_replies() {
+        run_test(|| async {
+            let mut socket = StandIn::new(|query, to| {
+                let mut stale = query.to_vec();
+                stale[1] ^= 1;
+                let stranger = endpoint(Ipv4Address([10, 0, 0, 66]));
+                vec![
+                    (reply(query, 0, &[RData::A([6, 6, 6, 6])]), stranger),
+                    (reply(&stale, 0, &[RData::A([7, 7, 7, 7])]), to),
+                    (reply(query, 0, &[RData::A([10, 0, 0, 9])]), to),
+                ]
+            });
+            let address = lookup(&mut socket, &SERVERS, "pico.example", 0x1234).await;
+            assert_eq!(address, Ok(Ipv4Address([10, 0, 0, 9])));
+            assert_eq!(socket.asked, [endpoint(SERVERS[0])]);
+        });
+    }
+
+    #[test]
+    fn tries_the_next_server_after_a_failure() {
+        run_test(|| async {
+            let mut socket = StandIn::new(|query, to| {
+                let rcode = if to == endpoint(SERVERS[0]) { 2 } else { 0 };
+                vec![(reply(query, rcode, &[RData::A([10, 0, 0, 9])]), to)]
+            });
+            let address = lookup(&mut socket, &SERVERS, "pico.example", 1).await;
+            assert_eq!(address, Ok(Ipv4Address([10, 0, 0, 9])));
+            assert_eq!(socket.asked, [endpoint(SERVERS[0]), endpoint(SERVERS[1])]);
+        });
+    }
+
+    #[test]
+    fn stops_when_the_name_doesnt_exist() {
+        run_test(|| async {
+            let mut socket = StandIn::new(|query, to| vec![(reply(query, 3, &[]), to)]);
+            assert_eq!(
+                lookup(&mut socket, &SERVERS, "nowhere.example", 1).await,
+                Err(Error::NotFound)
+            );
+            assert_eq!(socket.asked, [endpoint(SERVERS[0])]);
+        });
+    }
+
+    #[test]
+    fn gives_the_last_error_after_every_attempt() {
+        run_test(|| async {
+            let mut socket = StandIn::new(|query, to| vec![(reply(query, 5, &[]), to)]);
+            let result = lookup(&mut socket, &SERVERS, "pico.example", 1).await;
+            assert_eq!(result, Err(Error::ServerFailure(5)));
+            assert_eq!(socket.asked.len(), 4);
+        });
+    }
+
+    #[test]
+    fn rejects_names_that_cant_be_asked() {
+        run_test(|| async {
+            let mut socket = StandIn::new(|_: &[u8], _| Vec::new());
+            let result = lookup(&mut socket, &SERVERS, "pico..example", 1).await;
+            assert_eq!(result, Err(Error::Dns(dnswire::Error::BadLabel)));
+            assert!(socket.asked.is_empty());
+        });
+    }
+}
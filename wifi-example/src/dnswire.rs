@@ -0,0 +1,568 @@
+//! The DNS message format (RFC 1035), as used by mDNS and the resolver:
+//! reading questions and answers from a message, and writing messages with
+//! uncompressed names.
+
+use defmt::Format;
+
+use crate::netconfig::{Ipv4, Ipv6};
+
+pub const HEADER_LEN: usize = 12;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+
+pub const CLASS_IN: u16 = 1;
+
+/// Response, authoritative answer
+pub const FLAGS_RESPONSE: u16 = 0x8400;
+/// Standard query, recursion desired
+pub const FLAGS_QUERY: u16 = 0x0100;
+/// The query/response bit of the flags
+pub const FLAG_QR: u16 = 0x8000;
+/// The response code bits of the flags
+pub const RCODE_MASK: u16 = 0x000f;
+pub const RCODE_NAME_ERROR: u16 = 3;
+
+/// Pointers followed in a single name before giving up, to stop loops
+const MAX_POINTERS: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Error {
+    Truncated,
+    BadLabel,
+    /// Too many compression pointers, probably a loop
+    PointerLoop,
+    BufferTooSmall,
+    /// A record written to an earlier section than the last one
+    OutOfOrder,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct Header {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: u16,
+    pub answers: u16,
+    pub authorities: u16,
+    pub additionals: u16,
+}
+
+fn u16_at(msg: &[u8], at: usize) -> Result<u16, Error> {
+    match msg.get(at..at + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => Err(Error::Truncated),
+    }
+}
+
+pub fn parse_header(msg: &[u8]) -> Result<Header, Error> {
+    if msg.len() < HEADER_LEN {
+        return Err(Error::Truncated);
+    }
+    Ok(Header {
+        id: u16_at(msg, 0)?,
+        flags: u16_at(msg, 2)?,
+        questions: u16_at(msg, 4)?,
+        answers: u16_at(msg, 6)?,
+        authorities: u16_at(msg, 8)?,
+        additionals: u16_at(msg, 10)?,
+    })
+}
+
+/// Visit the labels of the name at `at`, following compression pointers,
+/// returning the offset just past the name where it appears in the message.
+fn walk_name<F>(msg: &[u8], at: usize, mut visit: F) -> Result<usize, Error>
+where
+    F: FnMut(&[u8]),
+{
+    let mut at = at;
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *msg.get(at).ok_or(Error::Truncated)? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => return Ok(end.unwrap_or(at + 1)),
+            0x00 => {
+                let label = msg.get(at + 1..at + 1 + len).ok_or(Error::Truncated)?;
+                visit(label);
+                at += 1 + len;
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(Error::PointerLoop);
+                }
+                end.get_or_insert(at + 2);
+                at = (u16_at(msg, at)? & 0x3fff) as usize;
+            }
+            _ => return Err(Error::BadLabel),
+        }
+    }
+}
+
+/// Whether the name at `at` is `name`, given dotted, ignoring case
+fn name_eq(msg: &[u8], at: usize, name: &str) -> Result<bool, Error> {
+    let mut expected = name.split('.');
+    let mut equal = true;
+    walk_name(msg, at, |label| {
+        equal = equal
+            && match expected.next() {
+                Some(e) => e.as_bytes().eq_ignore_ascii_case(label),
+                None => false,
+            };
+    })?;
+    Ok(equal && expected.next().is_none())
+}
+
+#[derive(Clone, Copy)]
+pub struct Question<'a> {
+    msg: &'a [u8],
+    name: usize,
+    pub qtype: u16,
+    /// The class, with mDNS's unicast response bit at the top
+    pub qclass: u16,
+}
+
+impl<'a> Question<'a> {
+    /// Whether the question is about `name`, given dotted
+    pub fn name_is(&self, name: &str) -> bool {
+        name_eq(self.msg, self.name, name).unwrap_or(false)
+    }
+
+    /// Whether the question is for `rtype` records, directly or via ANY
+    pub fn wants(&self, rtype: u16) -> bool {
+        self.qtype == rtype || self.qtype == TYPE_ANY
+    }
+}
+
+/// The questions of a message
+pub struct Questions<'a> {
+    msg: &'a [u8],
+    at: usize,
+    remaining: u16,
+}
+
+impl<'a> Questions<'a> {
+    pub fn new(msg: &'a [u8]) -> Result<Questions<'a>, Error> {
+        let header = parse_header(msg)?;
+        Ok(Questions {
+            msg,
+            at: HEADER_LEN,
+            remaining: header.questions,
+        })
+    }
+
+    /// The offset just past the questions read so far
+    pub fn offset(&self) -> usize {
+        self.at
+    }
+}
+
+impl<'a> Iterator for Questions<'a> {
+    type Item = Result<Question<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let question = (|| {
+            let name = self.at;
+            let end = walk_name(self.msg, name, |_| {})?;
+            let question = Question {
+                msg: self.msg,
+                name,
+                qtype: u16_at(self.msg, end)?,
+                qclass: u16_at(self.msg, end + 2)?,
+            };
+            self.at = end + 4;
+            Ok(question)
+        })();
+        if question.is_err() {
+            self.remaining = 0;
+        }
+        Some(question)
+    }
+}
+
+/// A resource record read from a message
+#[derive(Clone, Copy)]
+pub struct ResourceRecord<'a> {
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub data: &'a [u8],
+}
+
+/// The records of a message's answer section
+pub struct Answers<'a> {
+    msg: &'a [u8],
+    at: usize,
+    remaining: u16,
+}
+
+impl<'a> Answers<'a> {
+    pub fn new(msg: &'a [u8]) -> Result<Answers<'a>, Error> {
+        let header = parse_header(msg)?;
+        let mut questions = Questions::new(msg)?;
+        for question in &mut questions {
+            question?;
+        }
+        Ok(Answers {
+            msg,
+            at: questions.offset(),
+            remaining: header.answers,
+        })
+    }
+}
+
+impl<'a> Iterator for Answers<'a> {
+    type Item = Result<ResourceRecord<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let record = (|| {
+            let end = walk_name(self.msg, self.at, |_| {})?;
+            let data_len = u16_at(self.msg, end + 8)? as usize;
+            let data_at = end + 10;
+            let data = self.msg.get(data_at..data_at + data_len).ok_or(Error::Truncated)?;
+            let record = ResourceRecord {
+                rtype: u16_at(self.msg, end)?,
+                class: u16_at(self.msg, end + 2)?,
+                ttl: (u16_at(self.msg, end + 4)? as u32) << 16 | u16_at(self.msg, end + 6)? as u32,
+                data,
+            };
+            self.at = data_at + data_len;
+            Ok(record)
+        })();
+        if record.is_err() {
+            self.remaining = 0;
+        }
+        Some(record)
+    }
+}
+
+/// Record data, for the record types written
+#[derive(Clone, Copy)]
+pub enum RData<'a> {
+    A(Ipv4),
+    Aaaa(Ipv6),
+    Ptr(&'a str),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: &'a str,
+    },
+    Txt(&'a [&'a str]),
+}
+
+impl<'a> RData<'a> {
+    fn rtype(&self) -> u16 {
+        match self {
+            RData::A(_) => TYPE_A,
+            RData::Aaaa(_) => TYPE_AAAA,
+            RData::Ptr(_) => TYPE_PTR,
+            RData::Srv { .. } => TYPE_SRV,
+            RData::Txt(_) => TYPE_TXT,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Record<'a> {
+    pub name: &'a str,
+    /// The class, with mDNS's cache flush bit at the top
+    pub class: u16,
+    pub ttl: u32,
+    pub data: RData<'a>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Section {
+    Question,
+    Answer,
+    /// Nothing here writes authority records, but the section keeps its
+    /// place in the message
+    #[allow(dead_code)]
+    Authority,
+    Additional,
+}
+
+/// Writes a message, section by section
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    section: Section,
+    counts: [u16; 4],
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8], id: u16, flags: u16) -> Result<Writer<'a>, Error> {
+        let mut w = Writer {
+            buf,
+            len: 0,
+            section: Section::Question,
+            counts: [0; 4],
+        };
+        w.u16(id)?;
+        w.u16(flags)?;
+        // The counts are filled in by finish()
+        w.put(&[0; 8])?;
+        Ok(w)
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn u16(&mut self, v: u16) -> Result<(), Error> {
+        self.put(&v.to_be_bytes())
+    }
+
+    fn u32(&mut self, v: u32) -> Result<(), Error> {
+        self.put(&v.to_be_bytes())
+    }
+
+    /// A dotted name, uncompressed
+    fn name(&mut self, name: &str) -> Result<(), Error> {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(Error::BadLabel);
+            }
+            self.put(&[label.len() as u8])?;
+            self.put(label.as_bytes())?;
+        }
+        self.put(&[0])
+    }
+
+    fn enter(&mut self, section: Section) -> Result<(), Error> {
+        if section < self.section {
+            return Err(Error::OutOfOrder);
+        }
+        self.section = section;
+        self.counts[section as usize] += 1;
+        Ok(())
+    }
+
+    pub fn question(&mut self, name: &str, qtype: u16, qclass: u16) -> Result<(), Error> {
+        self.enter(Section::Question)?;
+        self.name(name)?;
+        self.u16(qtype)?;
+        self.u16(qclass)
+    }
+
+    /// Copy `count` questions verbatim from another message
+    pub fn raw_questions(&mut self, questions: &[u8], count: u16) -> Result<(), Error> {
+        if self.section > Section::Question {
+            return Err(Error::OutOfOrder);
+        }
+        self.put(questions)?;
+        self.counts[Section::Question as usize] += count;
+        Ok(())
+    }
+
+    pub fn record(&mut self, section: Section, record: &Record) -> Result<(), Error> {
+        if section == Section::Question {
+            return Err(Error::OutOfOrder);
+        }
+        self.enter(section)?;
+        self.name(record.name)?;
+        self.u16(record.data.rtype())?;
+        self.u16(record.class)?;
+        self.u32(record.ttl)?;
+        // The data length is filled in once the data is written
+        let length_at = self.len;
+        self.u16(0)?;
+        match record.data {
+            RData::A(address) => self.put(&address)?,
+            RData::Aaaa(address) => self.put(&address)?,
+            RData::Ptr(target) => self.name(target)?,
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                self.u16(priority)?;
+                self.u16(weight)?;
+                self.u16(port)?;
+                self.name(target)?;
+            }
+            RData::Txt(strings) => {
+                // An empty TXT record still holds one empty string
+                if strings.is_empty() {
+                    self.put(&[0])?;
+                }
+                for s in strings {
+                    if s.len() > 255 {
+                        return Err(Error::BadLabel);
+                    }
+                    self.put(&[s.len() as u8])?;
+                    self.put(s.as_bytes())?;
+                }
+            }
+        }
+        let data_len = (self.len - length_at - 2) as u16;
+        self.buf[length_at..length_at + 2].copy_from_slice(&data_len.to_be_bytes());
+        Ok(())
+    }
+
+    /// Fill in the section counts, returning the message length
+    pub fn finish(self) -> usize {
+        for (i, count) in self.counts.iter().enumerate() {
+            let at = 4 + i * 2;
+            self.buf[at..at + 2].copy_from_slice(&count.to_be_bytes());
+        }
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reply for `a.example` as a server writes it: the answer's name is
+    /// a pointer to the question's, and the CNAME's target is partly one
+    const COMPRESSED: &[u8] = b"\x12\x34\x81\x80\x00\x01\x00\x02\x00\x00\x00\x00\
+        \x01a\x07example\x00\x00\x01\x00\x01\
+        \xc0\x0c\x00\x05\x00\x01\x00\x00\x00\x3c\x00\x04\x01b\xc0\x0e\
+        \x01b\xc0\x0e\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\x0a\x00\x00\x07";
+
+    #[test]
+    fn parses_headers() {
+        let header = parse_header(COMPRESSED).unwrap();
+        assert_eq!(
+            header,
+            Header {
+                id: 0x1234,
+                flags: 0x8180,
+                questions: 1,
+                answers: 2,
+                authorities: 0,
+                additionals: 0
+            }
+        );
+        assert_eq!(parse_header(&COMPRESSED[..11]), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn reads_compressed_names() {
+        let question = Questions::new(COMPRESSED).unwrap().next().unwrap().unwrap();
+        assert!(question.name_is("a.example"));
+        assert!(question.name_is("A.EXAMPLE"));
+        assert!(!question.name_is("a.example.com"));
+        assert!(!question.name_is("example"));
+        assert_eq!((question.qtype, question.qclass), (TYPE_A, CLASS_IN));
+
+        // The names are skipped whether compressed or not
+        let answers: Result<Vec<_>, _> = Answers::new(COMPRESSED).unwrap().collect();
+        let answers = answers.unwrap();
+        assert_eq!(answers.len(), 2);
+        assert_eq!((answers[0].rtype, answers[0].ttl, answers[0].data), (5, 60, &b"\x01b\xc0\x0e"[..]));
+        assert_eq!((answers[1].rtype, answers[1].class, answers[1].data), (TYPE_A, CLASS_IN, &[10, 0, 0, 7][..]));
+        // The second answer's name is b.example
+        assert_eq!(name_eq(COMPRESSED, 43, "b.example"), Ok(true));
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        let mut looped = COMPRESSED.to_vec();
+        // The question's name points to itself
+        looped[12..14].copy_from_slice(&[0xc0, 0x0c]);
+        assert_eq!(Questions::new(&looped).unwrap().next().unwrap().err(), Some(Error::PointerLoop));
+
+        let mut reserved = COMPRESSED.to_vec();
+        reserved[12] = 0x41;
+        assert_eq!(Questions::new(&reserved).unwrap().next().unwrap().err(), Some(Error::BadLabel));
+
+        // A pointer past the end
+        let mut wild = COMPRESSED.to_vec();
+        wild[12..14].copy_from_slice(&[0xc1, 0x00]);
+        assert_eq!(Questions::new(&wild).unwrap().next().unwrap().err(), Some(Error::Truncated));
+
+        // Each cut short
+        for len in HEADER_LEN..COMPRESSED.len() {
+            let errors = Answers::new(&COMPRESSED[..len]).map(|answers| answers.filter(|a| a.is_err()).count());
+            assert!(matches!(errors, Err(Error::Truncated) | Ok(1)), "{}", len);
+        }
+    }
+
+    #[test]
+    fn round_trips_records() {
+        let mut buf = [0; 512];
+        let mut w = Writer::new(&mut buf, 7, FLAGS_RESPONSE).unwrap();
+        w.question("pico.local", TYPE_ANY, CLASS_IN).unwrap();
+        let record = |data| Record {
+            name: "pico.local",
+            class: CLASS_IN | 0x8000,
+            ttl: 120,
+            data,
+        };
+        w.record(Section::Answer, &record(RData::A([10, 0, 0, 2]))).unwrap();
+        let mut v6 = [0; 16];
+        v6[0] = 0xfe;
+        v6[1] = 0x80;
+        v6[15] = 1;
+        w.record(Section::Answer, &record(RData::Aaaa(v6))).unwrap();
+        w.record(Section::Answer, &record(RData::Ptr("x.local"))).unwrap();
+        let srv = RData::Srv {
+            priority: 1,
+            weight: 2,
+            port: 80,
+            target: "pico.local",
+        };
+        w.record(Section::Additional, &record(srv)).unwrap();
+        w.record(Section::Additional, &record(RData::Txt(&[]))).unwrap();
+        w.record(Section::Additional, &record(RData::Txt(&["a=1", "b"]))).unwrap();
+        assert_eq!(w.record(Section::Answer, &record(RData::A([0; 4]))), Err(Error::OutOfOrder));
+        let n = w.finish();
+
+        let msg = &buf[..n];
+        let header = parse_header(msg).unwrap();
+        assert_eq!((header.id, header.flags), (7, FLAGS_RESPONSE));
+        assert_eq!((header.questions, header.answers, header.authorities, header.additionals), (1, 3, 0, 3));
+        let question = Questions::new(msg).unwrap().next().unwrap().unwrap();
+        assert!(question.name_is("pico.local"));
+        assert!(question.wants(TYPE_A) && question.wants(TYPE_SRV));
+
+        let answers: Vec<_> = Answers::new(msg).unwrap().map(|a| a.unwrap()).collect();
+        assert_eq!(answers.len(), 3);
+        assert_eq!((answers[0].rtype, answers[0].class, answers[0].ttl), (TYPE_A, 0x8001, 120));
+        assert_eq!(answers[0].data, [10, 0, 0, 2]);
+        assert_eq!((answers[1].rtype, answers[1].data), (TYPE_AAAA, &v6[..]));
+        assert_eq!((answers[2].rtype, answers[2].data), (TYPE_PTR, &b"\x01x\x05local\x00"[..]));
+        // The additionals follow
+        let additionals: &[u8] = b"\
+            \x04pico\x05local\x00\x00\x21\x80\x01\x00\x00\x00\x78\x00\x12\x00\x01\x00\x02\x00\x50\x04pico\x05local\x00\
+            \x04pico\x05local\x00\x00\x10\x80\x01\x00\x00\x00\x78\x00\x01\x00\
+            \x04pico\x05local\x00\x00\x10\x80\x01\x00\x00\x00\x78\x00\x06\x03a=1\x01b";
+        assert!(msg.ends_with(additionals));
+    }
+
+    #[test]
+    fn rejects_what_cant_be_written() {
+        let mut buf = [0; 32];
+        let mut w = Writer::new(&mut buf, 0, FLAGS_QUERY).unwrap();
+        assert_eq!(w.question("a..local", TYPE_A, CLASS_IN), Err(Error::BadLabel));
+        let long = [b'x'; 64];
+        let long = core::str::from_utf8(&long).unwrap();
+        let mut w = Writer::new(&mut buf, 0, FLAGS_QUERY).unwrap();
+        assert_eq!(w.question(long, TYPE_A, CLASS_IN), Err(Error::BadLabel));
+        let mut w = Writer::new(&mut buf, 0, FLAGS_QUERY).unwrap();
+        assert_eq!(w.question("a-rather-long-name.local", TYPE_A, CLASS_IN), Err(Error::BufferTooSmall));
+        assert_eq!(Writer::new(&mut buf[..11], 0, FLAGS_QUERY).err(), Some(Error::BufferTooSmall));
+    }
+}
@@ -0,0 +1,69 @@
+//! A tcp echo server, serving several clients at once.
+
+use defmt::*;
+use embassy_net::tcp::TcpSocket;
+use embedded_io_async::Write;
+
+use crate::{metrics, set_peer, NetStack};
+
+pub const PORT: u16 = 7;
+
+/// The number of clients that can be served at once, each by its own task.
+/// Must match the `pool_size` of `echo_task`.
+pub const MAX_CONNECTIONS: usize = 4;
+
+const BUFFER_SIZE: usize = 2048;
+
+/// Serve one client at a time, recording the client in `slot` of the
+/// displayed peer list.
+#[embassy_executor::task(pool_size = 4)]
+pub async fn echo_task(stack: &'static NetStack, slot: usize) -> ! {
+    let mut rx_buffer = [0; BUFFER_SIZE];
+    let mut tx_buffer = [0; BUFFER_SIZE];
+    let mut buf = [0; BUFFER_SIZE];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
+
+        info!("[{}] Listening on TCP:{}...", slot, PORT);
+        if let Err(e) = socket.accept(PORT).await {
+            warn!("[{}] accept error: {:?}", slot, e);
+            continue;
+        }
+
+        info!("[{}] Received connection from {:?}", slot, socket.remote_endpoint());
+        set_peer(slot, socket.remote_endpoint());
+        metrics::ECHO_CONNECTIONS.inc();
+        metrics::ECHO_ACTIVE.inc();
+
+        loop {
+            let n = match socket.read(&mut buf).await {
+                Ok(0) => {
+                    warn!("[{}] read EOF", slot);
+                    break;
+                }
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("[{}] read error: {:?}", slot, e);
+                    metrics::ECHO_ERRORS.inc();
+                    break;
+                }
+            };
+
+            info!("[{}] rxd {:02x}", slot, &buf[..n]);
+
+            match socket.write_all(&buf[..n]).await {
+                Ok(()) => metrics::ECHO_BYTES.add(n as u32),
+                Err(e) => {
+                    warn!("[{}] write error: {:?}", slot, e);
+                    metrics::ECHO_ERRORS.inc();
+                    break;
+                }
+            };
+        }
+
+        set_peer(slot, None);
+        metrics::ECHO_ACTIVE.dec();
+    }
+}
@@ -0,0 +1,404 @@
+//! Random numbers from hardware entropy.
+//!
+//! Raw bits come from the ring oscillator's RANDOMBIT register and from the
+//! noise in the least significant bits of ADC readings. The ROSC bits are
+//! debiased with a von Neumann extractor, both sources are checked with the
+//! SP 800-90B repetition count and adaptive proportion health tests, and the
+//! results are mixed into a pool which seeds a ChaCha20 generator. On the
+//! host the generator is seeded from the operating system instead.
+
+use core::cell::RefCell;
+
+#[cfg(target_os = "none")]
+use embassy_rp::adc::{self, Adc};
+#[cfg(target_os = "none")]
+use embassy_rp::interrupt::typelevel::{Binding, ADC_IRQ_FIFO};
+#[cfg(target_os = "none")]
+use embassy_rp::peripherals::{ADC, ADC_TEMP_SENSOR};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use rand_chacha::ChaCha20Rng;
+use rand_core::{RngCore, SeedableRng};
+
+/// Bits of ROSC output to gather, after debiasing
+const ROSC_BITS: usize = 1024;
+
+/// ADC samples to gather
+const ADC_SAMPLES: usize = 256;
+
+/// Attempts at gathering entropy before giving up
+const MAX_ATTEMPTS: usize = 4;
+
+/// A von Neumann extractor, removing bias from a stream of independent bits
+#[derive(Default)]
+pub struct VonNeumann {
+    first: Option<bool>,
+}
+
+impl VonNeumann {
+    /// Feed a raw bit, returning an unbiased bit for each differing pair
+    pub fn feed(&mut self, bit: bool) -> Option<bool> {
+        match self.first.take() {
+            None => {
+                self.first = Some(bit);
+                None
+            }
+            Some(first) if first != bit => Some(first),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Fails when the same sample repeats too many times in a row, indicating a
+/// stuck source.
+pub struct RepetitionCountTest<T> {
+    cutoff: u32,
+    last: Option<T>,
+    count: u32,
+}
+
+impl<T: PartialEq + Copy> RepetitionCountTest<T> {
+    pub const fn new(cutoff: u32) -> Self {
+        RepetitionCountTest {
+            cutoff,
+            last: None,
+            count: 0,
+        }
+    }
+
+    /// Returns false if the source has failed
+    pub fn feed(&mut self, sample: T) -> bool {
+        if self.last == Some(sample) {
+            self.count += 1;
+        } else {
+            self.last = Some(sample);
+            self.count = 1;
+        }
+        self.count < self.cutoff
+    }
+}
+
+/// Fails when the first sample of a window recurs too often within the
+/// window, indicating a loss of entropy.
+pub struct AdaptiveProportionTest<T> {
+    window: u32,
+    cutoff: u32,
+    first: Option<T>,
+    seen: u32,
+    count: u32,
+}
+
+impl<T: PartialEq + Copy> AdaptiveProportionTest<T> {
+    pub const fn new(window: u32, cutoff: u32) -> Self {
+        AdaptiveProportionTest {
+            window,
+            cutoff,
+            first: None,
+            seen: 0,
+            count: 0,
+        }
+    }
+
+    /// Returns false if the source has failed
+    pub fn feed(&mut self, sample: T) -> bool {
+        match self.first {
+            None => {
+                self.first = Some(sample);
+                self.seen = 1;
+                self.count = 1;
+            }
+            Some(first) => {
+                self.seen += 1;
+                if first == sample {
+                    self.count += 1;
+                }
+                if self.seen >= self.window {
+                    self.first = None;
+                }
+            }
+        }
+        self.count < self.cutoff
+    }
+}
+
+/// Health tests for a stream of bits, with cutoffs for a false positive rate
+/// of around 2^-20 assuming at least 0.5 bits of entropy per bit.
+pub struct BitHealth {
+    rct: RepetitionCountTest<bool>,
+    apt: AdaptiveProportionTest<bool>,
+}
+
+impl BitHealth {
+    pub const fn new() -> Self {
+        BitHealth {
+            rct: RepetitionCountTest::new(41),
+            apt: AdaptiveProportionTest::new(1024, 840),
+        }
+    }
+
+    /// Returns false if the source has failed
+    pub fn feed(&mut self, bit: bool) -> bool {
+        // Run both tests on every sample
+        self.rct.feed(bit) & self.apt.feed(bit)
+    }
+}
+
+/// Accumulates entropy, whitening it by mixing each input through a
+/// multiply-xorshift finaliser.
+pub struct Pool {
+    state: [u64; 4],
+    next: usize,
+}
+
+impl Pool {
+    pub const fn new() -> Self {
+        Pool {
+            state: [
+                0x6a09_e667_f3bc_c908,
+                0xbb67_ae85_84ca_a73b,
+                0x3c6e_f372_fe94_f82b,
+                0xa54f_f53a_5f1d_36f1,
+            ],
+            next: 0,
+        }
+    }
+
+    pub fn mix(&mut self, input: u64) {
+        let i = self.next;
+        let mut x = self.state[i] ^ input ^ self.state[(i + 3) % 4].rotate_left(17);
+        x ^= x >> 30;
+        x = x.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x ^= x >> 27;
+        x = x.wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^= x >> 31;
+        self.state[i] = x;
+        self.next = (i + 1) % 4;
+    }
+
+    /// Collapse the pool into a generator seed
+    pub fn seed(&self) -> [u8; 32] {
+        let mut seed = [0u8; 32];
+        for (chunk, word) in seed.chunks_exact_mut(8).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        seed
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Error {
+    RoscHealth,
+    AdcHealth,
+}
+
+#[cfg(target_os = "none")]
+fn rosc_bit() -> bool {
+    embassy_rp::pac::ROSC.randombit().read().randombit()
+}
+
+/// Gather entropy from the ring oscillator
+#[cfg(target_os = "none")]
+fn gather_rosc(pool: &mut Pool) -> Result<(), Error> {
+    let mut health = BitHealth::new();
+    let mut debias = VonNeumann::default();
+    let mut word = 0u64;
+    let mut gathered = 0;
+    while gathered < ROSC_BITS {
+        let raw = rosc_bit();
+        if !health.feed(raw) {
+            return Err(Error::RoscHealth);
+        }
+        if let Some(bit) = debias.feed(raw) {
+            word = (word << 1) | bit as u64;
+            gathered += 1;
+            if gathered % 64 == 0 {
+                pool.mix(word);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Gather entropy from the noise in ADC readings of the temperature sensor
+#[cfg(target_os = "none")]
+async fn gather_adc(
+    adc: &mut Adc<'_, adc::Async>,
+    temp_sensor: &mut adc::Channel<'_>,
+    pool: &mut Pool,
+) -> Result<(), Error> {
+    let mut rct = RepetitionCountTest::new(16);
+    let mut word = 0u64;
+    for i in 0..ADC_SAMPLES {
+        let sample = adc.read(temp_sensor).await.map_err(|_| Error::AdcHealth)?;
+        if !rct.feed(sample) {
+            return Err(Error::AdcHealth);
+        }
+        // Only the bottom couple of bits are noise
+        word = (word << 2) | (sample & 0x3) as u64;
+        if i % 32 == 31 {
+            pool.mix(word);
+        }
+    }
+    Ok(())
+}
+
+static RNG: Mutex<CriticalSectionRawMutex, RefCell<Option<ChaCha20Rng>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Gather entropy and seed the generator. Panics if the entropy sources fail
+/// their health tests repeatedly.
+#[cfg(target_os = "none")]
+pub async fn init(
+    adc: ADC,
+    temp_sensor: ADC_TEMP_SENSOR,
+    irqs: impl Binding<ADC_IRQ_FIFO, adc::InterruptHandler>,
+) {
+    let mut adc = Adc::new(adc, irqs, adc::Config::default());
+    let mut temp_sensor = adc::Channel::new_temp_sensor(temp_sensor);
+    let mut pool = Pool::new();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let result = match gather_rosc(&mut pool) {
+            Ok(()) => gather_adc(&mut adc, &mut temp_sensor, &mut pool).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => break,
+            Err(e) if attempts < MAX_ATTEMPTS => defmt::warn!("entropy health test failed: {}", e),
+            Err(e) => defmt::panic!("entropy unavailable: {}", e),
+        }
+    }
+    let rng = ChaCha20Rng::from_seed(pool.seed());
+    RNG.lock(|r| r.replace(Some(rng)));
+}
+
+/// Seed the generator from the host's own entropy
+#[cfg(not(target_os = "none"))]
+pub fn init_from_seed(seed: [u8; 32]) {
+    RNG.lock(|r| r.replace(Some(ChaCha20Rng::from_seed(seed))));
+}
+
+/// Fill `dest` with random bytes. Panics if called before `init`.
+pub fn fill_bytes(dest: &mut [u8]) {
+    RNG.lock(|r| {
+        r.borrow_mut()
+            .as_mut()
+            .expect("entropy not initialised")
+            .fill_bytes(dest)
+    })
+}
+
+pub fn next_u32() -> u32 {
+    let mut b = [0u8; 4];
+    fill_bytes(&mut b);
+    u32::from_le_bytes(b)
+}
+
+pub fn next_u64() -> u64 {
+    let mut b = [0u8; 8];
+    fill_bytes(&mut b);
+    u64::from_le_bytes(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn von_neumann_keeps_first_of_differing_pairs() {
+        let mut vn = VonNeumann::default();
+        let raw = [false, true, true, false, true, true, false, false, true, false];
+        let out: Vec<bool> = raw.iter().filter_map(|bit| vn.feed(*bit)).collect();
+        assert_eq!(out, [false, true, true]);
+    }
+
+    #[test]
+    fn von_neumann_removes_bias() {
+        // Heavily biased, but independent, bits: 1 in 8 is set
+        let mut vn = VonNeumann::default();
+        let mut state = 0x1234_5678_u32;
+        let mut ones = 0;
+        let mut total = 0;
+        for _ in 0..100_000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if let Some(bit) = vn.feed(state % 8 == 0) {
+                ones += bit as u32;
+                total += 1;
+            }
+        }
+        assert!(total > 1000);
+        let proportion = ones as f64 / total as f64;
+        assert!((0.45..0.55).contains(&proportion), "{}", proportion);
+    }
+
+    #[test]
+    fn repetition_count_fails_at_cutoff() {
+        let mut rct = RepetitionCountTest::new(4);
+        assert!(rct.feed(1u16));
+        assert!(rct.feed(1));
+        assert!(rct.feed(1));
+        assert!(!rct.feed(1));
+
+        // A different sample restarts the count
+        let mut rct = RepetitionCountTest::new(4);
+        for sample in [1u16, 1, 1, 2, 2, 2, 1, 1, 1] {
+            assert!(rct.feed(sample));
+        }
+    }
+
+    #[test]
+    fn adaptive_proportion_fails_at_cutoff() {
+        // The first sample of the window recurring `cutoff` times fails
+        let mut apt = AdaptiveProportionTest::new(8, 4);
+        assert!(apt.feed(true));
+        assert!(apt.feed(false));
+        assert!(apt.feed(true));
+        assert!(apt.feed(false));
+        assert!(apt.feed(true));
+        assert!(!apt.feed(true));
+    }
+
+    #[test]
+    fn adaptive_proportion_restarts_each_window() {
+        // Three of the first sample per window of six never reaches four
+        let mut apt = AdaptiveProportionTest::new(6, 4);
+        for _ in 0..10 {
+            for sample in [true, true, true, false, false, false] {
+                assert!(apt.feed(sample));
+            }
+        }
+    }
+
+    #[test]
+    fn bit_health() {
+        let mut health = BitHealth::new();
+        for i in 0..10_000 {
+            assert!(health.feed(i % 3 == 0));
+        }
+
+        // A stuck source
+        let mut health = BitHealth::new();
+        assert!((0..41).map(|_| health.feed(true)).any(|ok| !ok));
+
+        // A source set 85% of the time, without long runs
+        let mut health = BitHealth::new();
+        let biased = (0..1024).map(|i| i % 20 < 17);
+        assert!(biased.map(|bit| health.feed(bit)).any(|ok| !ok));
+    }
+
+    #[test]
+    fn pool_mixes_every_input() {
+        let mut a = Pool::new();
+        let mut b = Pool::new();
+        for i in 0..8 {
+            a.mix(i);
+            b.mix(i);
+        }
+        assert_eq!(a.seed(), b.seed());
+        b.mix(0);
+        assert_ne!(a.seed(), b.seed());
+    }
+}
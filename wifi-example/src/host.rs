@@ -0,0 +1,210 @@
+//! Running the network services on Linux, over a TAP interface in place of
+//! the wifi chip. The display is simulated: what's drawn is streamed by the
+//! mirror as usual, so can be watched with fbviewer. There's no touch screen,
+//! button or LED.
+//!
+//! The interface is `tap0`, or given by `WIFI_EXAMPLE_TAP`. The network
+//! configuration is read from the file given by `WIFI_EXAMPLE_CONFIG`, in the
+//! same format as the configuration sector.
+
+use core::cell::RefCell;
+use std::io::Read;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+use heapless::String;
+use static_cell::StaticCell;
+
+use crate::display::{Display, SharedDisplay};
+use crate::mirror::ReadPixels;
+use crate::syslog::Severity;
+use crate::tuntap::TunTapDevice;
+use crate::{display_refresh, entropy, netconfig};
+
+const DEFAULT_TAP: &str = "tap0";
+
+/// Longest message written to stderr, longer ones are truncated
+const MAX_LOG_LINE: usize = 256;
+
+pub async fn main(spawner: Spawner) {
+    static DISPLAY: StaticCell<SharedDisplay> = StaticCell::new();
+    let display = &*DISPLAY.init(SharedDisplay::new(RefCell::new(self::display())));
+    unwrap!(spawner.spawn(display_refresh(display)));
+
+    let tap = std::env::var("WIFI_EXAMPLE_TAP").unwrap_or_else(|_| DEFAULT_TAP.into());
+    let net_device = match TunTapDevice::new(&tap) {
+        Ok(device) => device,
+        Err(e) => core::panic!("can't open {}: {}", tap, e),
+    };
+
+    let net_config = match std::env::var("WIFI_EXAMPLE_CONFIG") {
+        Ok(path) => match std::fs::read(&path) {
+            Ok(bytes) => netconfig::load_from(&bytes),
+            Err(e) => core::panic!("can't read {}: {}", path, e),
+        },
+        Err(_) => netconfig::load_from(&[]),
+    };
+
+    let mut seed = [0; 32];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut seed))
+        .expect("can't read /dev/urandom");
+    entropy::init_from_seed(seed);
+
+    let stack = crate::new_stack(spawner, net_device, &net_config);
+
+    // The interface stands in for the network joined
+    let mut ssid = String::<32>::new();
+    ssid.push_str(&tap[..tap.len().min(32)]).ok();
+
+    log_info!("running on {}", tap.as_str());
+    crate::start_services(spawner, stack, display, ssid, &net_config).await;
+}
+
+/// Stands in for the panel on the host, keeping what's drawn so the mirror
+/// can read it back and it can be seen with fbviewer
+pub struct Panel {
+    pixels: std::vec::Vec<Rgb565>,
+}
+
+impl OriginDimensions for Panel {
+    fn size(&self) -> Size {
+        Size::new(crate::mirror::WIDTH as u32, crate::mirror::HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for Panel {
+    type Color = Rgb565;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let bounds = self.bounding_box();
+        for Pixel(p, color) in pixels {
+            if bounds.contains(p) {
+                self.pixels[p.y as usize * crate::mirror::WIDTH + p.x as usize] = color;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReadPixels for Panel {
+    type Error = core::convert::Infallible;
+
+    fn read_pixels(&mut self, area: &Rectangle, out: &mut [Rgb565]) -> Result<(), Self::Error> {
+        let points = area.intersection(&self.bounding_box()).points();
+        for (pixel, p) in out.iter_mut().zip(points) {
+            *pixel = self.pixels[p.y as usize * crate::mirror::WIDTH + p.x as usize];
+        }
+        Ok(())
+    }
+}
+
+/// The simulated display
+pub fn display() -> Display {
+    Display::new(Panel {
+        pixels: std::vec![Rgb565::BLACK; crate::mirror::WIDTH * crate::mirror::HEIGHT],
+    })
+}
+
+/// Stop, as there's nothing to reset
+pub fn reboot() -> ! {
+    std::process::exit(0)
+}
+
+/// Write a message logged with `log_info!`, `log_warn!` or `log_error!` to
+/// stderr
+pub fn log(severity: Severity, write: impl FnOnce(&mut String<MAX_LOG_LINE>) -> Result<(), ()>) {
+    let mut message = String::new();
+    // Truncated if it doesn't fit
+    write(&mut message).ok();
+    let level = match severity {
+        Severity::Error => "ERROR",
+        Severity::Warning => "WARN",
+        Severity::Info => "INFO",
+    };
+    eprintln!("{:<5} {}", level, message);
+}
+
+// defmt's output is only useful with the firmware's symbols, which the host
+// program doesn't have, so it's dropped. What's also logged with `log_info!`
+// and the like goes to stderr instead, by `log`.
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+#[defmt::panic_handler]
+fn panic() -> ! {
+    core::panic!("defmt panic, its message is dropped on the host")
+}
+
+/// Run an async test on the tests' executor, as timers need an executor's
+/// timer queue. `test` makes the future on the executor's thread, so the
+/// future needn't be `Send`.
+#[cfg(test)]
+pub fn run_test<F, Fut>(test: F)
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: core::future::Future<Output = ()> + 'static,
+{
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::{mpsc, Once};
+
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::channel::Channel;
+
+    type Test = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()>>> + Send>;
+
+    /// A test's future, finishing early with an error if it panics
+    struct Caught(Pin<Box<dyn Future<Output = ()>>>);
+
+    impl Future for Caught {
+        type Output = Result<(), ()>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            match catch_unwind(AssertUnwindSafe(|| self.0.as_mut().poll(cx))) {
+                Ok(Poll::Ready(())) => Poll::Ready(Ok(())),
+                Ok(Poll::Pending) => Poll::Pending,
+                Err(_) => Poll::Ready(Err(())),
+            }
+        }
+    }
+
+    // There's one executor for all the tests, as each executor takes one of
+    // the time driver's few alarms for good
+    static TESTS: Channel<CriticalSectionRawMutex, (Test, mpsc::Sender<Result<(), ()>>), 1> = Channel::new();
+    static EXECUTOR: Once = Once::new();
+
+    // The tests take turns
+    #[embassy_executor::task]
+    async fn run_tests() {
+        loop {
+            let (test, done) = TESTS.receive().await;
+            done.send(Caught(test()).await).ok();
+        }
+    }
+
+    EXECUTOR.call_once(|| {
+        std::thread::spawn(|| {
+            let executor = Box::leak(Box::new(embassy_executor::Executor::new()));
+            executor.run(|spawner| unwrap!(spawner.spawn(run_tests())))
+        });
+    });
+    let (done, finished) = mpsc::channel();
+    let test: Test = Box::new(move || Box::pin(test()));
+    embassy_futures::block_on(TESTS.send((test, done)));
+    core::assert!(finished.recv() == Ok(Ok(())), "the test panicked");
+}
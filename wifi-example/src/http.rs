@@ -0,0 +1,412 @@
+//! A minimal HTTP/1.1 request parser and router for the status and control
+//! endpoints:
+//!
+//! - `GET /` a dashboard page
+//! - `GET /status` the board status as json
+//! - `POST /led` with a body of `on`, `off` or `blink <ms>`
+//! - `POST /display` with a body of `<row> <msg>`
+//! - `GET /events` upgraded to a WebSocket of live events, see `websocket`
+//! - `GET /metrics` the metrics in the Prometheus text format, see `metrics`
+//!
+//! Requests are parsed from a fixed buffer, and every response closes the
+//! connection. Like the shell, this only depends on the `Board` trait.
+
+use ufmt::{uWrite, uwrite};
+
+use crate::shell::{self, Board};
+use crate::status;
+
+/// Longest accepted request body
+pub const MAX_BODY: usize = 256;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Method {
+    Get,
+    Post,
+    Other,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Request<'a> {
+    pub method: Method,
+    pub path: &'a str,
+    pub body: &'a [u8],
+    /// The `Sec-WebSocket-Key`, if the client asked to upgrade to a WebSocket
+    pub websocket_key: Option<&'a str>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum StatusCode {
+    Ok,
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    InternalServerError,
+    ServiceUnavailable,
+}
+
+impl StatusCode {
+    pub fn code(&self) -> u16 {
+        match self {
+            StatusCode::Ok => 200,
+            StatusCode::BadRequest => 400,
+            StatusCode::NotFound => 404,
+            StatusCode::MethodNotAllowed => 405,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::InternalServerError => 500,
+            StatusCode::ServiceUnavailable => 503,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            StatusCode::Ok => "OK",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+        }
+    }
+}
+
+/// Parse a request from the start of `buf`. Returns the request and its
+/// length once it has been completely received, None if more is needed, or the
+/// status code with which to reject it.
+///
+/// `full` indicates that `buf` can't hold any more data, so an incomplete
+/// request is too large.
+pub fn parse_request(buf: &[u8], full: bool) -> Result<Option<(Request<'_>, usize)>, StatusCode> {
+    let head_len = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(i) => i + 4,
+        None if full => return Err(StatusCode::PayloadTooLarge),
+        None => return Ok(None),
+    };
+    let head = core::str::from_utf8(&buf[..head_len - 4]).map_err(|_| StatusCode::BadRequest)?;
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = match request_line.next() {
+        Some("GET") => Method::Get,
+        Some("POST") => Method::Post,
+        Some(m) if !m.is_empty() => Method::Other,
+        _ => return Err(StatusCode::BadRequest),
+    };
+    let path = request_line.next().ok_or(StatusCode::BadRequest)?;
+    match request_line.next() {
+        Some(version) if version.starts_with("HTTP/1.") => {}
+        _ => return Err(StatusCode::BadRequest),
+    }
+    // Ignore any query string
+    let path = path.split('?').next().unwrap_or(path);
+
+    let mut content_length = 0;
+    let mut upgrade = false;
+    let mut websocket_key = None;
+    for header in lines {
+        let (name, value) = header.split_once(':').ok_or(StatusCode::BadRequest)?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().map_err(|_| StatusCode::BadRequest)?;
+        } else if name.eq_ignore_ascii_case("upgrade") {
+            upgrade = value.eq_ignore_ascii_case("websocket");
+        } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+            websocket_key = Some(value);
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(StatusCode::PayloadTooLarge);
+    }
+
+    let len = head_len + content_length;
+    if buf.len() < len {
+        return if full {
+            Err(StatusCode::PayloadTooLarge)
+        } else {
+            Ok(None)
+        };
+    }
+    let request = Request {
+        method,
+        path,
+        body: &buf[head_len..len],
+        websocket_key: websocket_key.filter(|_| upgrade),
+    };
+    Ok(Some((request, len)))
+}
+
+/// The response to a request, other than any body written by `handle`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub content_type: &'static str,
+    /// A fixed body, used instead of anything written by `handle`
+    pub static_body: Option<&'static str>,
+}
+
+impl Response {
+    fn json(status: StatusCode) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            static_body: None,
+        }
+    }
+
+    /// The response when a dynamic body doesn't fit its buffer
+    pub fn too_large() -> Response {
+        Response {
+            status: StatusCode::InternalServerError,
+            content_type: "application/json",
+            static_body: Some("{\"error\":\"response too large\"}"),
+        }
+    }
+}
+
+/// Route a request, writing any dynamic body to `body`
+pub fn handle<B: Board, W: uWrite>(
+    board: &mut B,
+    request: &Request,
+    body: &mut W,
+) -> Result<Response, W::Error> {
+    let response = match (request.method, request.path) {
+        (Method::Get, "/") => Response {
+            status: StatusCode::Ok,
+            content_type: "text/html; charset=utf-8",
+            static_body: Some(DASHBOARD),
+        },
+        (Method::Get, "/status") => {
+            status::write_json(&board.status(), body)?;
+            Response::json(StatusCode::Ok)
+        }
+        (Method::Post, "/led") => {
+            match text_body(request).and_then(|args| shell::parse_led(args).ok()) {
+                Some(mode) => {
+                    board.set_led(mode);
+                    return ok(body);
+                }
+                None => return error(body, StatusCode::BadRequest, "expected on, off or blink <ms>"),
+            }
+        }
+        (Method::Post, "/display") => {
+            match text_body(request).and_then(|args| shell::parse_display_text(args).ok()) {
+                Some((row, text)) => {
+                    board.display_text(row, text);
+                    return ok(body);
+                }
+                None => return error(body, StatusCode::BadRequest, "expected <row> <msg>"),
+            }
+        }
+        // Upgrades are taken over by the server before they get here
+        (Method::Get, "/events") => return error(body, StatusCode::BadRequest, "expected a websocket upgrade"),
+        (_, "/" | "/status" | "/led" | "/display" | "/events" | "/metrics") => {
+            return error(body, StatusCode::MethodNotAllowed, "method not allowed")
+        }
+        _ => return error(body, StatusCode::NotFound, "not found"),
+    };
+    Ok(response)
+}
+
+/// The response to a request that couldn't be parsed
+pub fn reject<W: uWrite>(status: StatusCode, body: &mut W) -> Result<Response, W::Error> {
+    error(body, status, status.reason())
+}
+
+fn text_body<'a>(request: &Request<'a>) -> Option<&'a str> {
+    core::str::from_utf8(request.body).ok().map(|s| s.trim())
+}
+
+fn ok<W: uWrite>(body: &mut W) -> Result<Response, W::Error> {
+    body.write_str("{\"ok\":true}")?;
+    Ok(Response::json(StatusCode::Ok))
+}
+
+fn error<W: uWrite>(body: &mut W, status: StatusCode, message: &str) -> Result<Response, W::Error> {
+    body.write_str("{\"error\":")?;
+    status::write_json_str(body, message)?;
+    body.write_str("}")?;
+    Ok(Response::json(status))
+}
+
+/// Write the status line and headers for a response
+pub fn write_head<W: uWrite>(out: &mut W, response: &Response, content_length: usize) -> Result<(), W::Error> {
+    uwrite!(
+        out,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status.code(),
+        response.status.reason(),
+        response.content_type,
+        content_length
+    )
+}
+
+/// Write the response accepting an upgrade to a WebSocket, given the
+/// `Sec-WebSocket-Accept` value
+pub fn write_upgrade_head<W: uWrite>(out: &mut W, accept: &str) -> Result<(), W::Error> {
+    uwrite!(
+        out,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::tests::MockBoard;
+    use crate::shell::LedMode;
+    use heapless::String;
+
+    fn parse(text: &str) -> Result<Option<(Request<'_>, usize)>, StatusCode> {
+        parse_request(text.as_bytes(), false)
+    }
+
+    #[test]
+    fn parses_get() {
+        let text = "GET /status?x=1 HTTP/1.1\r\nHost: pico\r\n\r\nextra";
+        let (request, len) = parse(text).unwrap().unwrap();
+        assert_eq!(len, text.len() - "extra".len());
+        assert_eq!(
+            request,
+            Request {
+                method: Method::Get,
+                path: "/status",
+                body: b"",
+                websocket_key: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_post_body() {
+        let text = "POST /led HTTP/1.0\r\ncontent-LENGTH: 9\r\n\r\nblink 100";
+        let (request, len) = parse(text).unwrap().unwrap();
+        assert_eq!(len, text.len());
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.body, b"blink 100");
+
+        // The body hasn't all arrived
+        assert_eq!(parse(&text[..text.len() - 1]), Ok(None));
+        assert_eq!(
+            parse_request(&text.as_bytes()[..text.len() - 1], true),
+            Err(StatusCode::PayloadTooLarge)
+        );
+    }
+
+    #[test]
+    fn waits_for_the_whole_head() {
+        assert_eq!(parse("GET / HTTP/1.1\r\nHost: pico\r\n"), Ok(None));
+        assert_eq!(
+            parse_request(b"GET / HTTP/1.1\r\nHost: pico\r\n", true),
+            Err(StatusCode::PayloadTooLarge)
+        );
+    }
+
+    #[test]
+    fn parses_websocket_upgrade() {
+        let text = "GET /events HTTP/1.1\r\nUpgrade: WebSocket\r\nSec-WebSocket-Key: abc==\r\n\r\n";
+        let (request, _) = parse(text).unwrap().unwrap();
+        assert_eq!(request.websocket_key, Some("abc=="));
+
+        // A key without the upgrade doesn't count
+        let text = "GET /events HTTP/1.1\r\nSec-WebSocket-Key: abc==\r\n\r\n";
+        assert_eq!(parse(text).unwrap().unwrap().0.websocket_key, None);
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        assert_eq!(parse("\r\n\r\n"), Err(StatusCode::BadRequest));
+        assert_eq!(parse("GET\r\n\r\n"), Err(StatusCode::BadRequest));
+        assert_eq!(parse("GET / SPDY/3\r\n\r\n"), Err(StatusCode::BadRequest));
+        assert_eq!(parse("GET / HTTP/1.1\r\nno colon\r\n\r\n"), Err(StatusCode::BadRequest));
+        assert_eq!(parse("GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), Err(StatusCode::BadRequest));
+        assert_eq!(
+            parse("POST / HTTP/1.1\r\nContent-Length: 257\r\n\r\n"),
+            Err(StatusCode::PayloadTooLarge)
+        );
+        assert_eq!(parse_request(b"GET /\xff HTTP/1.1\r\n\r\n", false), Err(StatusCode::BadRequest));
+        let (request, _) = parse("DELETE / HTTP/1.1\r\n\r\n").unwrap().unwrap();
+        assert_eq!(request.method, Method::Other);
+    }
+
+    fn request<'a>(method: Method, path: &'a str, body: &'a [u8]) -> Request<'a> {
+        Request {
+            method,
+            path,
+            body,
+            websocket_key: None,
+        }
+    }
+
+    fn respond(board: &mut MockBoard, request: &Request) -> (StatusCode, String<512>) {
+        let mut body = String::new();
+        let response = handle(board, request, &mut body).unwrap();
+        (response.status, body)
+    }
+
+    #[test]
+    fn routes() {
+        let mut board = MockBoard::default();
+        let mut body = String::<16>::new();
+        let response = handle(&mut board, &request(Method::Get, "/", b""), &mut body).unwrap();
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.static_body, Some(DASHBOARD));
+        assert!(body.is_empty());
+
+        let (status, body) = respond(&mut board, &request(Method::Get, "/status", b""));
+        assert_eq!(status, StatusCode::Ok);
+        assert!(body.starts_with("{\"ssid\":\"home\""));
+
+        let ok = (StatusCode::Ok, "{\"ok\":true}".try_into().unwrap());
+        assert_eq!(respond(&mut board, &request(Method::Post, "/led", b" on\n")), ok);
+        assert_eq!(board.led, Some(LedMode::On));
+        assert_eq!(respond(&mut board, &request(Method::Post, "/display", b"0 hello")), ok);
+        assert_eq!(board.text, Some((0, "hello".into())));
+    }
+
+    #[test]
+    fn route_errors() {
+        let mut board = MockBoard::default();
+        assert_eq!(
+            respond(&mut board, &request(Method::Post, "/led", b"dim")),
+            (
+                StatusCode::BadRequest,
+                "{\"error\":\"expected on, off or blink <ms>\"}".try_into().unwrap()
+            )
+        );
+        assert_eq!(respond(&mut board, &request(Method::Post, "/display", b"\xff")).0, StatusCode::BadRequest);
+        assert_eq!(respond(&mut board, &request(Method::Get, "/events", b"")).0, StatusCode::BadRequest);
+        assert_eq!(respond(&mut board, &request(Method::Post, "/status", b"")).0, StatusCode::MethodNotAllowed);
+        assert_eq!(respond(&mut board, &request(Method::Other, "/metrics", b"")).0, StatusCode::MethodNotAllowed);
+        assert_eq!(respond(&mut board, &request(Method::Get, "/nope", b"")).0, StatusCode::NotFound);
+        assert_eq!(board.led, None);
+    }
+
+    #[test]
+    fn body_too_large_for_its_buffer() {
+        let mut body = String::<16>::new();
+        assert!(handle(&mut MockBoard::default(), &request(Method::Get, "/status", b""), &mut body).is_err());
+        assert_eq!(Response::too_large().status.code(), 500);
+    }
+
+    #[test]
+    fn writes_heads() {
+        let mut head = String::<256>::new();
+        write_head(&mut head, &Response::json(StatusCode::NotFound), 12).unwrap();
+        assert_eq!(
+            head,
+            "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: 12\r\nConnection: close\r\n\r\n"
+        );
+        let mut head = String::<256>::new();
+        write_upgrade_head(&mut head, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=").unwrap();
+        assert_eq!(
+            head,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n"
+        );
+    }
+}
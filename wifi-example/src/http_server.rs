@@ -0,0 +1,139 @@
+//! Serves the status and control endpoints over HTTP.
+
+use defmt::*;
+use embassy_net::tcp::TcpSocket;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_io_async::Write;
+use heapless::String;
+use wifi_protocol::websocket::ACCEPT_LEN;
+
+use crate::http::{self, Method, Response, StatusCode};
+use crate::shell_server::RemoteBoard;
+use crate::{metrics, ui_events, websocket, NetStack};
+
+pub const PORT: u16 = 80;
+
+/// The number of HTTP requests that can be served at once, each by its own task.
+/// Must match the `pool_size` of `http_task`. Websocket clients hold on to a
+/// task, so there's one more than they can take.
+pub const MAX_CONNECTIONS: usize = ui_events::MAX_SUBSCRIBERS + 1;
+
+const BUFFER_SIZE: usize = 1024;
+
+/// Longest dynamic response body
+const MAX_RESPONSE: usize = 512;
+
+/// The metrics are too long for each task to have room for, so they share
+/// one buffer and take turns
+static METRICS_TEXT: Mutex<CriticalSectionRawMutex, String<{ metrics::MAX_TEXT }>> = Mutex::new(String::new());
+
+/// Serve one HTTP request at a time
+#[embassy_executor::task(pool_size = 3)]
+pub async fn http_task(stack: &'static NetStack, id: usize) -> ! {
+    let mut rx_buffer = [0; BUFFER_SIZE];
+    let mut tx_buffer = [0; BUFFER_SIZE];
+    let mut buf = [0; BUFFER_SIZE];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
+
+        if let Err(e) = socket.accept(PORT).await {
+            warn!("[http {}] accept error: {:?}", id, e);
+            continue;
+        }
+        debug!("[http {}] connection from {:?}", id, socket.remote_endpoint());
+
+        // Read until a complete request is buffered
+        let mut len = 0;
+        let parsed = loop {
+            let full = len == buf.len();
+            match http::parse_request(&buf[..len], full) {
+                Ok(Some((_, request_len))) => break Ok(request_len),
+                Ok(None) => {}
+                Err(status) => break Err(status),
+            }
+            match socket.read(&mut buf[len..]).await {
+                Ok(0) => break Err(StatusCode::BadRequest),
+                Ok(n) => len += n,
+                Err(e) => {
+                    warn!("[http {}] read error: {:?}", id, e);
+                    break Err(StatusCode::BadRequest);
+                }
+            }
+        };
+
+        // Parse again, as the request can't be held across the reads above
+        let request = parsed.map(|len| unwrap!(http::parse_request(&buf[..len], false).ok().flatten()).0);
+        if let Ok(request) = &request {
+            info!("[http {}] {} {}", id, request.method, request.path);
+        }
+
+        if matches!(&request, Ok(r) if r.method == Method::Get && r.path == metrics::PATH) {
+            let mut text = METRICS_TEXT.lock().await;
+            text.clear();
+            // Sized to fit
+            metrics::write_text(&mut *text).ok();
+            let response = Response {
+                status: StatusCode::Ok,
+                content_type: metrics::CONTENT_TYPE,
+                static_body: None,
+            };
+            respond(&mut socket, &response, text.as_bytes(), id).await;
+            continue;
+        }
+
+        let mut body = String::<MAX_RESPONSE>::new();
+        let response = match request {
+            Ok(request) => match request.websocket_key.filter(|_| request.path == websocket::PATH) {
+                Some(key) => match ui_events::subscribe() {
+                    Some(events) => {
+                        let mut accept = [0; ACCEPT_LEN];
+                        let accept = wifi_protocol::websocket::accept_key(key, &mut accept);
+                        let mut head = String::<160>::new();
+                        http::write_upgrade_head(&mut head, accept).ok();
+                        match socket.write_all(head.as_bytes()).await {
+                            Ok(()) => websocket::serve(&mut socket, events, id).await,
+                            Err(e) => warn!("[http {}] write error: {:?}", id, e),
+                        }
+                        socket.close();
+                        continue;
+                    }
+                    None => http::reject(StatusCode::ServiceUnavailable, &mut body),
+                },
+                None => http::handle(&mut RemoteBoard, &request, &mut body),
+            },
+            Err(status) => http::reject(status, &mut body),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(()) => {
+                warn!("[http {}] response too large", id);
+                Response::too_large()
+            }
+        };
+
+        let body = match response.static_body {
+            Some(b) => b.as_bytes(),
+            None => body.as_bytes(),
+        };
+        respond(&mut socket, &response, body, id).await;
+    }
+}
+
+/// Send a response, then close the connection
+async fn respond(socket: &mut TcpSocket<'_>, response: &Response, body: &[u8], id: usize) {
+    let mut head = String::<128>::new();
+    http::write_head(&mut head, response, body.len()).ok();
+
+    let result = match socket.write_all(head.as_bytes()).await {
+        Ok(()) => socket.write_all(body).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        warn!("[http {}] write error: {:?}", id, e);
+    }
+    socket.flush().await.ok();
+    socket.close();
+}
@@ -0,0 +1,153 @@
+//! Monitoring the quality of the wifi link, and widgets to show it.
+
+#[cfg(target_os = "none")]
+use cyw43::IoctlType;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle};
+use heapless::HistoryBuffer;
+
+use crate::display::Display;
+use crate::metrics;
+
+// Broadcom WLC ioctl commands
+const WLC_GET_RATE: u32 = 12;
+const WLC_GET_CHANNEL: u32 = 29;
+const WLC_GET_RSSI: u32 = 127;
+const WLC_GET_PKTCNTS: u32 = 166;
+
+/// Number of rssi samples kept for the sparkline
+pub const HISTORY_LEN: usize = 48;
+
+/// The weakest and strongest rssi shown on the sparkline, in dBm
+const SPARKLINE_MIN_RSSI: i32 = -95;
+const SPARKLINE_MAX_RSSI: i32 = -35;
+
+#[derive(Clone, Copy, Default)]
+pub struct LinkStats {
+    /// Signal strength in dBm
+    pub rssi: i32,
+    pub channel: u32,
+    /// Transmit rate in kbps
+    pub tx_rate_kbps: u32,
+    pub rx_packets: u32,
+    pub tx_packets: u32,
+    pub rx_errors: u32,
+    pub tx_errors: u32,
+}
+
+impl LinkStats {
+    /// Read the current link statistics from the wifi chip
+    #[cfg(target_os = "none")]
+    pub async fn poll(control: &mut cyw43::Control<'_>) -> LinkStats {
+        let rssi = get_u32(control, WLC_GET_RSSI).await as i32;
+        // channel_info_t, the first field being the channel in use
+        let channel = get_u32(control, WLC_GET_CHANNEL).await;
+        // reported in units of 500kbps
+        let tx_rate_kbps = get_u32(control, WLC_GET_RATE).await * 500;
+
+        let mut counts = [0u8; 20];
+        control
+            .ioctl(IoctlType::Get, WLC_GET_PKTCNTS, 0, &mut counts)
+            .await;
+        let count = |i: usize| u32::from_le_bytes(counts[i * 4..i * 4 + 4].try_into().unwrap());
+
+        LinkStats {
+            rssi,
+            channel,
+            tx_rate_kbps,
+            rx_packets: count(0),
+            rx_errors: count(1),
+            tx_packets: count(2),
+            tx_errors: count(3),
+        }
+    }
+
+    /// Publish the statistics as metrics
+    pub fn record_metrics(&self) {
+        metrics::WIFI_POLLS.inc();
+        metrics::WIFI_RSSI.set(self.rssi);
+        metrics::WIFI_CHANNEL.set(self.channel as i32);
+        metrics::WIFI_TX_RATE.set(self.tx_rate_kbps as i32);
+        metrics::WIFI_RX_PACKETS.set(self.rx_packets);
+        metrics::WIFI_TX_PACKETS.set(self.tx_packets);
+        metrics::WIFI_RX_ERRORS.set(self.rx_errors);
+        metrics::WIFI_TX_ERRORS.set(self.tx_errors);
+    }
+
+    /// Signal strength as a number of bars, from 0 to 4
+    pub fn bars(&self) -> u32 {
+        match self.rssi {
+            r if r >= -55 => 4,
+            r if r >= -67 => 3,
+            r if r >= -75 => 2,
+            r if r >= -85 => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(target_os = "none")]
+async fn get_u32(control: &mut cyw43::Control<'_>, cmd: u32) -> u32 {
+    let mut buf = [0u8; 4];
+    control.ioctl(IoctlType::Get, cmd, 0, &mut buf).await;
+    u32::from_le_bytes(buf)
+}
+
+pub type RssiHistory = HistoryBuffer<i8, HISTORY_LEN>;
+
+/// Draw a signal strength indicator, with `top_left` being the corner of the
+/// leftmost (shortest) bar's bounding box.
+pub fn render_bars(display: &mut Display, top_left: Point, stats: &Option<LinkStats>) {
+    let bar_width = 8;
+    let bar_gap = 3;
+    let height = 24;
+    let bars = stats.map(|s| s.bars()).unwrap_or(0);
+    let gray_fill = PrimitiveStyleBuilder::new()
+        .fill_color(Rgb565::CSS_DARK_GRAY)
+        .build();
+
+    for i in 0..4 {
+        let bar_height = height * (i + 1) / 4;
+        let at = top_left + Point::new((i * (bar_width + bar_gap)) as i32, (height - bar_height) as i32);
+        let style = if i < bars {
+            display.styles.white_fill
+        } else {
+            gray_fill
+        };
+        Rectangle::new(at, Size::new(bar_width, bar_height))
+            .into_styled(style)
+            .draw(&mut display.interface)
+            .unwrap();
+    }
+}
+
+/// Draw the rssi history as a line graph within `area`, oldest sample on the left.
+pub fn render_sparkline(display: &mut Display, area: Rectangle, history: &RssiHistory) {
+    Rectangle::new(area.top_left, area.size)
+        .into_styled(PrimitiveStyle::with_stroke(Rgb565::CSS_DARK_GRAY, 1))
+        .draw(&mut display.interface)
+        .unwrap();
+
+    let width = area.size.width as i32 - 2;
+    let height = area.size.height as i32 - 2;
+    let to_point = |i: usize, rssi: i8| {
+        let rssi = (rssi as i32).clamp(SPARKLINE_MIN_RSSI, SPARKLINE_MAX_RSSI);
+        let x = i as i32 * width / (HISTORY_LEN as i32 - 1);
+        let y = height - (rssi - SPARKLINE_MIN_RSSI) * height / (SPARKLINE_MAX_RSSI - SPARKLINE_MIN_RSSI);
+        area.top_left + Point::new(1 + x, 1 + y)
+    };
+
+    let line_style = PrimitiveStyle::with_stroke(Rgb565::GREEN, 1);
+    let mut prev: Option<Point> = None;
+    for (i, rssi) in history.oldest_ordered().enumerate() {
+        let p = to_point(i, *rssi);
+        if let Some(prev) = prev {
+            Line::new(prev, p)
+                .into_styled(line_style)
+                .draw(&mut display.interface)
+                .unwrap();
+        }
+        prev = Some(p);
+    }
+}
@@ -1,277 +1,720 @@
-#![no_std]
-#![no_main]
-#![feature(type_alias_impl_trait)]
-#![feature(async_fn_in_trait)]
-#![allow(incomplete_features)]
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
 
 use core::cell::RefCell;
 
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_net::tcp::TcpSocket;
-use embassy_net::{Config, Stack, StackResources, StaticConfig, Ipv4Cidr, IpAddress};
-use embassy_rp::gpio::{Flex, Level, Output};
-use embassy_rp::peripherals::{PIN_23, PIN_25};
+use embassy_net::{Config, ConfigV4, ConfigV6, Stack, StackResources, StaticConfigV4, StaticConfigV6, Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr, IpAddress, IpEndpoint};
 use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::signal::Signal;
-use embassy_time::{Duration, Timer};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::Rectangle;
 use embedded_graphics::text::Text;
-use embedded_hal_async::spi::{ExclusiveDevice};
-use embedded_io::asynch::Write;
 use static_cell::StaticCell;
-use {defmt_rtt as _, panic_probe as _};
-use heapless::String;
-use ufmt::uwrite;
-
-use wifi_spi::WifiSpi;
+use heapless::{String, Vec};
+use ufmt::{uWrite, uwrite};
+
+use link::{LinkStats, RssiHistory};
+use netconfig::{ActiveMode, NetConfig, StaticIp, StaticIp6};
+use shell::{LedMode, TEXT_ROWS};
+use scan::{Networks, ScanView};
+use clock::TimeOfDay;
+use display::{Display, SharedDisplay};
+
+/// Log with defmt, and also to syslog if it's configured, and to stderr on
+/// the host. The message is formatted more than once, so the arguments must
+/// suit both defmt and ufmt: plain `{}` with integers and strings.
+macro_rules! tee_log {
+    ($level:ident, $severity:ident, $($arg:tt)*) => {{
+        defmt::$level!($($arg)*);
+        #[cfg(not(target_os = "none"))]
+        crate::host::log(crate::syslog::Severity::$severity, |w| ufmt::uwrite!(w, $($arg)*));
+        crate::syslog::log(crate::syslog::Severity::$severity, |w| ufmt::uwrite!(w, $($arg)*));
+    }};
+}
 
-mod wifi_spi;
-mod display;
+macro_rules! log_info {
+    ($($arg:tt)*) => { tee_log!(info, Info, $($arg)*) };
+}
 
-macro_rules! singleton {
-    ($val:expr) => {{
-        type T = impl Sized;
-        static STATIC_CELL: StaticCell<T> = StaticCell::new();
-        STATIC_CELL.init_with(move || $val)
-    }};
+macro_rules! log_warn {
+    ($($arg:tt)*) => { tee_log!(warn, Warning, $($arg)*) };
 }
 
-#[embassy_executor::task]
-async fn wifi_task(
-    runner: cyw43::Runner<'static, Output<'static, PIN_23>, ExclusiveDevice<WifiSpi, Output<'static, PIN_25>>>,
-) -> ! {
-    runner.run().await
+// Only the board has errors worth sending to syslog
+#[cfg_attr(not(target_os = "none"), allow(unused_macros))]
+macro_rules! log_error {
+    ($($arg:tt)*) => { tee_log!(error, Error, $($arg)*) };
 }
 
+#[cfg(target_os = "none")]
+mod pico;
+#[cfg(not(target_os = "none"))]
+mod host;
+#[cfg(not(target_os = "none"))]
+mod tuntap;
+mod beacon;
+// The board's hardware, which the host has no counterpart for: the wifi
+// chip, the touch screen and the entropy sources
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+mod blobs;
+mod clock;
+mod display;
+mod dns;
+mod dnswire;
+mod echo;
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+mod entropy;
+mod http;
+mod http_server;
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+mod link;
+mod mdns;
+mod metrics;
+mod mirror;
+mod mqtt;
+mod mqtt_client;
+mod netconfig;
+#[cfg(target_os = "none")]
+mod panel;
+// Only the board can take updates
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+mod ota;
+mod remote_draw;
+mod report;
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+mod scan;
+mod shell;
+mod shell_server;
+mod slaac;
+mod sntp;
+mod status;
+mod syslog;
+mod telemetry;
+mod tls_shell;
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+mod touch;
+mod udp_echo;
+mod ui_events;
+mod websocket;
+
+#[cfg(target_os = "none")]
+use pico::reboot;
+#[cfg(not(target_os = "none"))]
+use host::reboot;
+
+#[cfg(target_os = "none")]
+type NetDriver = cyw43::NetDriver<'static>;
+#[cfg(not(target_os = "none"))]
+type NetDriver = tuntap::TunTapDevice;
+
+/// The network stack, over the wifi chip on the pico w or a TAP interface on
+/// the host
+pub type NetStack = Stack<slaac::RouterWatch<NetDriver>>;
+
 #[embassy_executor::task]
-async fn net_task(stack: &'static Stack<cyw43::NetDriver<'static>>) -> ! {
+async fn net_task(stack: &'static NetStack) -> ! {
     stack.run().await
 }
 
+/// Requests to change the LED
+static LED_COMMANDS: Signal<CriticalSectionRawMutex, LedMode> = Signal::new();
+
+#[cfg(target_os = "none")]
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
+    pico::main(spawner).await
+}
 
-    let p = embassy_rp::init(Default::default());
-
-    // Include the WiFi firmware and Country Locale Matrix (CLM) blobs.
-    let fw = include_bytes!("../firmware/43439A0.bin");
-    let clm = include_bytes!("../firmware/43439A0_clm.bin");
-
-    // To make flashing faster for development, you may want to flash the firmwares independently
-    // at hardcoded addresses, instead of baking them into the program with `include_bytes!`:
-    //     probe-rs-cli download 43439A0.bin --format bin --chip RP2040 --base-address 0x10100000
-    //     probe-rs-cli download 43439A0.clm_blob --format bin --chip RP2040 --base-address 0x10140000
-    //let fw = unsafe { core::slice::from_raw_parts(0x10100000 as *const u8, 224190) };
-    //let clm = unsafe { core::slice::from_raw_parts(0x10140000 as *const u8, 4752) };
-
-    let pwr = Output::new(p.PIN_23, Level::Low);
-    let cs = Output::new(p.PIN_25, Level::High);
-    let clk = Output::new(p.PIN_29, Level::Low);
-    let mut dio = Flex::new(p.PIN_24);
-    dio.set_low();
-    dio.set_as_output();
-
-    let bus = WifiSpi { clk, dio };
-    let spi = ExclusiveDevice::new(bus, cs);
-
-    let state = singleton!(cyw43::State::new());
-    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
-
-    spawner.spawn(wifi_task(runner)).unwrap();
-
-    control.init(clm).await;
-    control
-        .set_power_management(cyw43::PowerManagementMode::PowerSave)
-        .await;
-
-    //control.join_open(env!("WIFI_NETWORK")).await;
-    let ssid = env!("WIFI_NETWORK");
-    let password =  env!("WIFI_PASSWORD");
-
-    control.join_wpa2(ssid, password).await;
-
-    let config = Config::Dhcp(Default::default());
-    //let config = embassy_net::Config::Static(embassy_net::Config {
-    //    address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
-    //    dns_servers: Vec::new(),
-    //    gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
-    //});
-
-    // Generate random seed
-    let seed = 0x0123_4567_89ab_cdef; // chosen by fair dice roll. guarenteed to be random.
+#[cfg(not(target_os = "none"))]
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    host::main(spawner).await
+}
 
-    // Init network stack
-    let stack = &*singleton!(Stack::new(
-        net_device,
+/// Start the network stack over `device`, configured as `net_config` says.
+/// The entropy source must be initialised first, for the seed.
+fn new_stack(spawner: Spawner, device: NetDriver, net_config: &NetConfig) -> &'static NetStack {
+    let config = match net_config.initial_static() {
+        Some(ip) => Config::ipv4_static(static_config(ip)),
+        None => Config::dhcpv4(Default::default()),
+    };
+    let seed = entropy::next_u64();
+
+    static STACK: StaticCell<NetStack> = StaticCell::new();
+    static RESOURCES: StaticCell<StackResources<SOCKETS>> = StaticCell::new();
+    let stack = &*STACK.init(Stack::new(
+        slaac::RouterWatch::new(device),
         config,
-        singleton!(StackResources::<2>::new()),
-        seed
+        RESOURCES.init(StackResources::new()),
+        seed,
     ));
-
+    // IPv6 alongside: the configured address, or else the link-local one
+    // until a router advertises a prefix
+    let mac = mac_address(stack);
+    stack.set_config_v6(ConfigV6::Static(static_config_v6(net_config.ipv6.as_ref(), mac)));
     unwrap!(spawner.spawn(net_task(stack)));
+    if net_config.ipv6.is_none() {
+        unwrap!(spawner.spawn(slaac::slaac_task(stack)));
+    }
+    stack
+}
 
-    // And now we can use it!
+/// The interface's MAC address
+fn mac_address(stack: &NetStack) -> [u8; 6] {
+    stack.hardware_address().as_bytes().try_into().unwrap()
+}
 
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
-    let mut buf = [0; 4096];
+/// The modified EUI-64 interface identifier for a MAC address (RFC 4291
+/// appendix A), the second half of its IPv6 addresses
+fn interface_id(mac: [u8; 6]) -> [u8; 8] {
+    [mac[0] ^ 0x02, mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5]]
+}
+
+/// The link-local IPv6 address for a MAC address
+fn link_local_address(mac: [u8; 6]) -> Ipv6Address {
+    let mut address = [0; 16];
+    address[..2].copy_from_slice(&[0xfe, 0x80]);
+    address[8..].copy_from_slice(&interface_id(mac));
+    Ipv6Address(address)
+}
+
+fn static_config_v6(ip: Option<&StaticIp6>, mac: [u8; 6]) -> StaticConfigV6 {
+    match ip {
+        Some(ip) => StaticConfigV6 {
+            address: Ipv6Cidr::new(Ipv6Address(ip.address), ip.prefix_len),
+            gateway: ip.gateway.map(Ipv6Address),
+            dns_servers: Vec::new(),
+        },
+        None => StaticConfigV6 {
+            address: Ipv6Cidr::new(link_local_address(mac), 64),
+            gateway: None,
+            dns_servers: Vec::new(),
+        },
+    }
+}
 
-    // Keep the display up to date
-    let display = display::init(p.PIN_12, p.PIN_11, p.PIN_10, p.PIN_13, p.PIN_14, p.PIN_15, p.SPI1);
-    unwrap!(spawner.spawn(display_refresh(display)));
+/// Once the network on `ssid` is configured, start the services and clients
+async fn start_services(
+    spawner: Spawner,
+    stack: &'static NetStack,
+    display: &'static SharedDisplay,
+    ssid: String<32>,
+    net_config: &NetConfig,
+) {
     display_state_update(|ds| {
-        ds.ssid = ssid;
-        ds.connected = None;
+        ds.screen = Screen::Status;
+        ds.ssid = ssid.clone();
     });
 
-    let config = wait_for_config(stack).await;
+    let (config, mode) = wait_for_network(stack, net_config).await;
     display_state_update(|ds| {
         ds.address = Some(config.address);
+        ds.address6 = stack.config_v6().map(|c| c.address);
+        ds.gateway = config.gateway;
+        ds.dns = config.dns_servers.first().copied();
+        ds.net_mode = Some(mode);
     });
 
-    loop {
-        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-        socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
-
-        info!("Listening on TCP:1234...");
-        if let Err(e) = socket.accept(1234).await {
-            warn!("accept error: {:?}", e);
-            continue;
-        }
-
-        info!("Received connection from {:?}", socket.remote_endpoint());
+    // Before the rest, so their messages are forwarded
+    if let Some(server) = net_config.syslog {
+        unwrap!(spawner.spawn(syslog::syslog_task(stack, server, net_config.hostname.clone())));
+    }
 
-        display_state_update(|ds| ds.connected = socket.remote_endpoint().map(|ep| ep.addr));
+    // Set the clock now that the network is up
+    unwrap!(spawner.spawn(clock::sntp_task(stack, net_config.ntp_server)));
+    unwrap!(spawner.spawn(clock::clock_task(net_config.tz_offset_mins)));
 
-        loop {
-            let n = match socket.read(&mut buf).await {
-                Ok(0) => {
-                    warn!("read EOF");
-                    break;
-                }
-                Ok(n) => n,
-                Err(e) => {
-                    warn!("read error: {:?}", e);
-                    break;
-                }
-            };
+    unwrap!(spawner.spawn(mdns::mdns_task(stack, net_config.hostname.clone())));
+    unwrap!(spawner.spawn(beacon::beacon_task(stack, net_config.hostname.clone())));
 
-            info!("rxd {:02x}", &buf[..n]);
+    // And now we can use it!
+    for slot in 0..echo::MAX_CONNECTIONS {
+        unwrap!(spawner.spawn(echo::echo_task(stack, slot)));
+    }
+    for i in 0..shell_server::MAX_CONNECTIONS {
+        let slot = echo::MAX_CONNECTIONS + i;
+        unwrap!(spawner.spawn(shell_server::shell_task(stack, slot)));
+    }
+    for id in 0..http_server::MAX_CONNECTIONS {
+        unwrap!(spawner.spawn(http_server::http_task(stack, id)));
+    }
+    unwrap!(spawner.spawn(udp_echo::udp_echo_task(stack)));
+    unwrap!(spawner.spawn(ui_events::ui_events_task()));
+    unwrap!(spawner.spawn(mirror::mirror_task(stack, display)));
+    unwrap!(spawner.spawn(remote_draw::remote_draw_task(stack, display)));
+    if let Some(mqtt) = net_config.mqtt.clone() {
+        unwrap!(spawner.spawn(mqtt_client::mqtt_task(stack, mqtt)));
+    }
+    if let Some(report) = net_config.report.clone() {
+        unwrap!(spawner.spawn(report::report_task(stack, report)));
+    }
+    if let Some(listener) = net_config.telemetry {
+        unwrap!(spawner.spawn(telemetry::telemetry_task(stack, listener)));
+    }
+    if let Some(tls) = net_config.tls.clone() {
+        let slot = echo::MAX_CONNECTIONS + shell_server::MAX_CONNECTIONS;
+        unwrap!(spawner.spawn(tls_shell::tls_shell_task(stack, slot, tls)));
+    }
+}
 
-            match socket.write_all(&buf[..n]).await {
-                Ok(()) => {}
-                Err(e) => {
-                    warn!("write error: {:?}", e);
-                    break;
+/// Wait for the network to be configured, falling back to a static address if
+/// DHCP takes too long and the configuration allows it.
+async fn wait_for_network(
+    stack: &'static NetStack,
+    net_config: &NetConfig,
+) -> (StaticConfigV4, ActiveMode) {
+    let fell_back = match net_config.fallback() {
+        Some((ip, timeout_secs)) => {
+            let timeout = Duration::from_secs(timeout_secs as u64);
+            match with_timeout(timeout, wait_for_config(stack)).await {
+                Ok(_) => false,
+                Err(_) => {
+                    log_warn!("no DHCP lease after {}s, using static address", timeout_secs);
+                    stack.set_config_v4(ConfigV4::Static(static_config(ip)));
+                    true
                 }
-            };
+            }
         }
+        None => false,
+    };
+    let config = wait_for_config(stack).await;
+    (config, net_config.active_mode(fell_back))
+}
 
-        display_state_update(|ds| ds.connected = None);
+fn static_config(ip: &StaticIp) -> StaticConfigV4 {
+    StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address(ip.address), ip.prefix_len),
+        gateway: ip.gateway.map(Ipv4Address),
+        dns_servers: ip.dns_servers.iter().map(|a| Ipv4Address(*a)).collect(),
     }
 }
 
-async fn wait_for_config(stack: &'static Stack<cyw43::NetDriver<'static>>) -> StaticConfig {
+async fn wait_for_config(stack: &'static NetStack) -> StaticConfigV4 {
     loop {
-        if let Some(config) = stack.config() {
-            return config.clone();
+        if let Some(config) = stack.config_v4() {
+            return config;
         }
         Timer::after(Duration::from_millis(500)).await;
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Screen {
+    Status,
+    Scan,
+    /// Drawn by a remote drawing client
+    Remote,
+    /// The wifi firmware couldn't be loaded
+    #[cfg_attr(not(target_os = "none"), allow(dead_code))]
+    BlobError(blobs::LoadError),
+}
+
+/// Slots in the displayed list of connected clients, shared by the tcp services
+const MAX_PEERS: usize = echo::MAX_CONNECTIONS + shell_server::MAX_CONNECTIONS + tls_shell::MAX_CONNECTIONS;
+
+/// Sockets used by the services (the peers, http, the mirror, remote
+/// drawing and updates), the clients (MQTT, SNTP, DNS, status reports and
+/// syslog), the udp services (mDNS, echo, the beacon and telemetry), plus one
+/// for DHCP
+const SOCKETS: usize = MAX_PEERS + http_server::MAX_CONNECTIONS + 3 + 5 + 4 + 1;
+
 #[derive(Clone)]
 struct DisplayState {
+    screen: Screen,
     address: Option<Ipv4Cidr>,
-    ssid: &'static str,
-    connected: Option<IpAddress>,
+    address6: Option<Ipv6Cidr>,
+    gateway: Option<Ipv4Address>,
+    dns: Option<Ipv4Address>,
+    net_mode: Option<ActiveMode>,
+    ssid: String<32>,
+    peers: [Option<IpEndpoint>; MAX_PEERS],
+    led: LedMode,
+    button_pressed: bool,
+    last_touch: Option<Point>,
+    /// The number of gestures so far, so a repeated gesture is a change
+    gestures: u32,
+    last_gesture: Option<touch::Gesture>,
+    text_rows: [String<{ shell::MAX_TEXT }>; TEXT_ROWS],
+    link: Option<LinkStats>,
+    rssi_history: RssiHistory,
+    networks: Networks,
+    scan_view: ScanView,
+    /// The network last chosen, if joining it failed
+    join_failed: Option<String<32>>,
+    clock: Option<TimeOfDay>,
 }
 
 static DISPLAY_STATE: Mutex<ThreadModeRawMutex, RefCell<DisplayState>> =
     Mutex::new(RefCell::new(DisplayState {
+        screen: Screen::Status,
         address: Option::None,
-        ssid: "???",
-        connected: None,
+        address6: None,
+        gateway: None,
+        dns: None,
+        net_mode: None,
+        ssid: String::new(),
+        peers: [None; MAX_PEERS],
+        led: LedMode::Off,
+        button_pressed: false,
+        last_touch: None,
+        gestures: 0,
+        last_gesture: None,
+        text_rows: [String::new(), String::new()],
+        link: None,
+        rssi_history: RssiHistory::new(),
+        networks: Vec::new(),
+        scan_view: ScanView { scroll: 0 },
+        join_failed: None,
+        clock: None,
     }));
 
 static DISPLAY_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
 fn display_state_update<F>(mut sfn: F)
 where
-    F: FnMut(&mut DisplayState) -> (),
+    F: FnMut(&mut DisplayState),
 {
     DISPLAY_STATE.lock(|s| sfn(&mut s.borrow_mut()));
     DISPLAY_SIGNAL.signal(());
+    ui_events::CHANGED.signal(());
+}
+
+fn display_state_read<F, T>(rfn: F) -> T
+where
+    F: FnOnce(&DisplayState) -> T,
+{
+    DISPLAY_STATE.lock(|s| rfn(&s.borrow()))
+}
+
+/// Record the client connected to a service's slot in the displayed peer list
+fn set_peer(slot: usize, peer: Option<IpEndpoint>) {
+    display_state_update(|ds| ds.peers[slot] = peer);
 }
 
 
 // Keep the display up to date
 #[embassy_executor::task]
-async fn display_refresh(mut display: display::Display) {
+async fn display_refresh(display: &'static SharedDisplay) {
     loop {
         DISPLAY_SIGNAL.wait().await;
 
-        Rectangle::new(Point::zero(), display.interface.size())
-        .into_styled(display.styles.black_fill)
+        let state = DISPLAY_STATE.lock(|s| s.borrow().clone());
+        if state.screen == Screen::Remote {
+            // A remote drawing client owns the display
+            metrics::DISPLAY_SKIPPED.inc();
+            continue;
+        }
+        let start = Instant::now();
+        display.lock(|d| render(&mut d.borrow_mut(), &state));
+        metrics::DISPLAY_REFRESHES.inc();
+        metrics::DISPLAY_REFRESH_TIME.observe(start.elapsed().as_millis() as u32);
+    }
+}
+
+fn render(display: &mut Display, state: &DisplayState) {
+    Rectangle::new(Point::zero(), display.interface.size())
+    .into_styled(display.styles.black_fill)
+    .draw(&mut display.interface)
+    .unwrap();
+
+    if state.screen == Screen::Scan {
+        scan::render(display, &state.networks, &state.scan_view, state.join_failed.as_deref());
+        return;
+    }
+    if let Screen::BlobError(error) = state.screen {
+        blobs::render_error(display, &error);
+        return;
+    }
+
+    Text::with_text_style(
+        "Wifi demo",
+        Point::new(14, 0),
+        display.styles.char,
+        display.styles.text,
+    )
+    .draw(&mut display.interface)
+    .unwrap();
+
+    if let Some(time) = state.clock {
+        let mut clock = String::<5>::new();
+        for (i, n) in [time.hours, time.minutes].into_iter().enumerate() {
+            if i > 0 {
+                uwrite!(clock, ":").unwrap();
+            }
+            if n < 10 {
+                uwrite!(clock, "0").unwrap();
+            }
+            uwrite!(clock, "{}", n).unwrap();
+        }
+        Text::with_text_style(
+            &clock,
+            Point::new(226, 0),
+            display.styles.char,
+            display.styles.text,
+        )
         .draw(&mut display.interface)
         .unwrap();
+    }
+
+    Text::with_text_style(
+        &state.ssid,
+        Point::new(14, 14),
+        display.styles.char,
+        display.styles.text,
+    )
+    .draw(&mut display.interface)
+    .unwrap();
+
+    {
+        let mut dhcp = String::<32>::new();
+        match (state.address, state.net_mode) {
+            (Some(addr), Some(mode)) => {
+                write_ipv4(&mut dhcp, addr.address()).unwrap();
+                uwrite!(dhcp, "/{} ({})", addr.prefix_len(), mode.label())
+            }
+            _ => uwrite!(dhcp, "awaiting DHCP..."),
+        }.unwrap();
 
         Text::with_text_style(
-            "Wifi demo",
-            Point::new(14, 0),
+            &dhcp, 
+            Point::new(14, 28),
             display.styles.char,
             display.styles.text,
         )
         .draw(&mut display.interface)
         .unwrap();
+    }
 
-        let state = DISPLAY_STATE.lock(|s| s.borrow().clone());
+    if state.address.is_some() {
+        let mut lease = String::<40>::new();
+        uwrite!(lease, "gw ").unwrap();
+        match state.gateway {
+            Some(gw) => write_ipv4(&mut lease, gw).unwrap(),
+            None => uwrite!(lease, "-").unwrap(),
+        }
+        uwrite!(lease, " dns ").unwrap();
+        match state.dns {
+            Some(dns) => write_ipv4(&mut lease, dns).unwrap(),
+            None => uwrite!(lease, "-").unwrap(),
+        }
+        Text::with_text_style(
+            &lease,
+            Point::new(14, 42),
+            display.styles.char,
+            display.styles.text,
+        )
+        .draw(&mut display.interface)
+        .unwrap();
+    }
+
+    if let Some(addr) = state.address6 {
+        let mut line = String::<48>::new();
+        write_ipv6(&mut line, addr.address()).unwrap();
+        uwrite!(line, "/{}", addr.prefix_len()).unwrap();
         Text::with_text_style(
-            state.ssid,
-            Point::new(14, 14),
+            &line,
+            Point::new(14, 56),
             display.styles.char,
             display.styles.text,
         )
         .draw(&mut display.interface)
         .unwrap();
+    }
 
-        {
-            let mut dhcp = String::<32>::new();
-            match state.address {
-                Some(addr) => uwrite!(dhcp, "{}.{}.{}.{}", addr.address().0[0], addr.address().0[1], addr.address().0[2], addr.address().0[3]),
-                None => uwrite!(dhcp, "awaiting DHCP..."),
-            }.unwrap();
-
-            Text::with_text_style(
-                &dhcp, 
-                Point::new(14, 28),
-                display.styles.char,
-                display.styles.text,
-            )
+    link::render_bars(display, Point::new(266, 4), &state.link);
+    if let Some(stats) = state.link {
+        let mut line = String::<40>::new();
+        uwrite!(
+            line,
+            "{} dBm  ch {}  {} kbps",
+            stats.rssi,
+            stats.channel,
+            stats.tx_rate_kbps
+        )
+        .unwrap();
+        Text::with_text_style(&line, Point::new(14, 84), display.styles.char, display.styles.text)
             .draw(&mut display.interface)
             .unwrap();
-        }
 
-        {
-            let mut client = String::<32>::new();
-            match state.connected {
-                Some(IpAddress::Ipv4(addr)) => {
-                    uwrite!(client, "client: {}.{}.{}.{}", addr.0[0], addr.0[1], addr.0[2], addr.0[3])
-                }
-                None => uwrite!(client, "accepting..."),
-            }.unwrap();
-            Text::with_text_style(
-                &client,
-                Point::new(14, 56),
-                display.styles.char,
-                display.styles.text,
-            )
+        let mut line = String::<40>::new();
+        uwrite!(
+            line,
+            "rx {}/{}  tx {}/{}",
+            stats.rx_packets,
+            stats.rx_errors,
+            stats.tx_packets,
+            stats.tx_errors
+        )
+        .unwrap();
+        Text::with_text_style(&line, Point::new(14, 98), display.styles.char, display.styles.text)
             .draw(&mut display.interface)
             .unwrap();
+    }
+    link::render_sparkline(
+        display,
+        Rectangle::new(Point::new(14, 114), Size::new(292, 38)),
+        &state.rssi_history,
+    );
+
+    for (row, text) in state.text_rows.iter().enumerate() {
+        Text::with_text_style(
+            text,
+            Point::new(14, 156 + 14 * row as i32),
+            display.styles.char,
+            display.styles.text,
+        )
+        .draw(&mut display.interface)
+        .unwrap();
+    }
+
+    let mut row = 0;
+    for peer in state.peers.iter().flatten() {
+        let mut client = String::<64>::new();
+        uwrite!(client, "client: ").unwrap();
+        write_endpoint(&mut client, peer).unwrap();
+        Text::with_text_style(
+            &client,
+            Point::new(14, 184 + 14 * row),
+            display.styles.char,
+            display.styles.text,
+        )
+        .draw(&mut display.interface)
+        .unwrap();
+        row += 1;
+    }
+    if row == 0 {
+        Text::with_text_style(
+            "accepting...",
+            Point::new(14, 184),
+            display.styles.char,
+            display.styles.text,
+        )
+        .draw(&mut display.interface)
+        .unwrap();
+    }
+}
+
+fn write_ipv4<W: uWrite + ?Sized>(w: &mut W, addr: Ipv4Address) -> Result<(), W::Error> {
+    uwrite!(w, "{}.{}.{}.{}", addr.0[0], addr.0[1], addr.0[2], addr.0[3])
+}
+
+/// Write an IPv6 address in its shortest form (RFC 5952), eg `fe80::1`
+fn write_ipv6<W: uWrite + ?Sized>(w: &mut W, addr: Ipv6Address) -> Result<(), W::Error> {
+    let mut groups = [0u16; 8];
+    for (g, b) in groups.iter_mut().zip(addr.0.chunks_exact(2)) {
+        *g = u16::from_be_bytes([b[0], b[1]]);
+    }
+    // The longest run of two or more zero groups, the first if there's a tie
+    let mut zeros = 0..0;
+    let mut start = 0;
+    for (i, g) in groups.iter().enumerate() {
+        if *g != 0 {
+            start = i + 1;
+        } else if i + 1 - start > zeros.len() {
+            zeros = start..i + 1;
+        }
+    }
+    if zeros.len() < 2 {
+        zeros = 0..0;
+    }
+
+    for (i, g) in groups.iter().enumerate() {
+        if zeros.contains(&i) {
+            if i == zeros.start {
+                uwrite!(w, "::")?;
+            }
+            continue;
         }
+        if i > 0 && i != zeros.end {
+            uwrite!(w, ":")?;
+        }
+        write_hex(w, *g)?;
     }
+    Ok(())
 }
 
+fn write_hex<W: uWrite + ?Sized>(w: &mut W, n: u16) -> Result<(), W::Error> {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut started = false;
+    for shift in [12, 8, 4, 0] {
+        let digit = (n >> shift) & 0xf;
+        if digit != 0 || started || shift == 0 {
+            w.write_char(HEX[digit as usize] as char)?;
+            started = true;
+        }
+    }
+    Ok(())
+}
+
+/// Write an endpoint, with an IPv6 address in brackets, eg `[fe80::1]:1234`
+fn write_endpoint<W: uWrite + ?Sized>(w: &mut W, endpoint: &IpEndpoint) -> Result<(), W::Error> {
+    match endpoint.addr {
+        IpAddress::Ipv4(addr) => write_ipv4(w, addr)?,
+        IpAddress::Ipv6(addr) => {
+            uwrite!(w, "[")?;
+            write_ipv6(w, addr)?;
+            uwrite!(w, "]")?;
+        }
+    }
+    uwrite!(w, ":{}", endpoint.port)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Ipv6Address};
+    use heapless::String;
+
+    use super::{link_local_address, write_endpoint, write_ipv6};
+
+    fn ipv6(groups: [u16; 8]) -> String<64> {
+        let [a, b, c, d, e, f, g, h] = groups;
+        let mut out = String::new();
+        write_ipv6(&mut out, Ipv6Address::new(a, b, c, d, e, f, g, h)).unwrap();
+        out
+    }
+
+    #[test]
+    fn writes_ipv6_in_its_shortest_form() {
+        assert_eq!(ipv6([0; 8]), "::");
+        assert_eq!(ipv6([0, 0, 0, 0, 0, 0, 0, 1]), "::1");
+        assert_eq!(ipv6([0xfe80, 0, 0, 0, 0, 0, 0, 0]), "fe80::");
+        assert_eq!(ipv6([0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x50]), "2001:db8::50");
+        // Leading zeros go, and a single zero group isn't compressed
+        assert_eq!(ipv6([0x2001, 0xdb8, 0, 1, 1, 1, 1, 1]), "2001:db8:0:1:1:1:1:1");
+        // The longest run is compressed, or the first of equal runs
+        assert_eq!(ipv6([0x2001, 0, 0, 1, 0, 0, 0, 1]), "2001:0:0:1::1");
+        assert_eq!(ipv6([0x2001, 0xdb8, 0, 0, 1, 0, 0, 1]), "2001:db8::1:0:0:1");
+        assert_eq!(ipv6([0xffff; 8]), "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff");
+    }
+
+    #[test]
+    fn makes_link_local_addresses() {
+        assert_eq!(
+            link_local_address([0x28, 0xcd, 0xc1, 0x01, 0x02, 0x03]),
+            Ipv6Address::new(0xfe80, 0, 0, 0, 0x2acd, 0xc1ff, 0xfe01, 0x0203)
+        );
+        // The universal/local bit is flipped either way
+        assert_eq!(
+            link_local_address([0x02, 0, 0, 0, 0, 0x01]),
+            Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0x00ff, 0xfe00, 0x0001)
+        );
+    }
+
+    #[test]
+    fn writes_endpoints() {
+        let mut out = String::<64>::new();
+        write_endpoint(&mut out, &IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::new(192, 168, 1, 50)), 7)).unwrap();
+        assert_eq!(out, "192.168.1.50:7");
+
+        let mut out = String::<64>::new();
+        let addr = IpAddress::Ipv6(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+        write_endpoint(&mut out, &IpEndpoint::new(addr, 1234)).unwrap();
+        assert_eq!(out, "[fe80::1]:1234");
+    }
+}
@@ -0,0 +1,519 @@
+//! An mDNS (RFC 6762) and DNS-SD (RFC 6763) responder, so the board can be
+//! reached as `<hostname>.local` and its services found by browsing.
+//!
+//! The responder answers for its own records only. It doesn't probe for
+//! name conflicts, so the hostname must be unique on the network.
+
+use defmt::*;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address};
+use embassy_time::{Duration, Timer};
+use heapless::String;
+use ufmt::uwrite;
+
+use crate::dnswire::{
+    self, Questions, RData, Record, Section, Writer, CLASS_IN, TYPE_A, TYPE_AAAA, TYPE_PTR, TYPE_SRV, TYPE_TXT,
+};
+use crate::netconfig::{Ipv4, Ipv6, MAX_HOSTNAME};
+use crate::{echo, http_server, shell_server, NetStack};
+
+pub const PORT: u16 = 5353;
+
+const GROUP: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+
+const TTL: u32 = 120;
+
+/// Class for records that only this board answers for
+const CLASS_IN_FLUSH: u16 = CLASS_IN | 0x8000;
+
+/// Names aren't compressed, so announcing everything takes more than the
+/// traditional 512 bytes. This still fits in a packet of IPv6's minimum MTU.
+const MAX_MESSAGE: usize = 1232;
+
+/// Room for `<hostname>.<service type>.local`
+const MAX_NAME: usize = MAX_HOSTNAME + 32;
+
+const SERVICE_ENUMERATION: &str = "_services._dns-sd._udp.local";
+
+/// Times the records are announced on startup
+const ANNOUNCEMENTS: usize = 2;
+
+pub struct Service {
+    /// eg `_http._tcp`
+    pub service_type: &'static str,
+    pub port: u16,
+}
+
+pub const SERVICES: [Service; 3] = [
+    Service {
+        service_type: "_echo._tcp",
+        port: echo::PORT,
+    },
+    Service {
+        service_type: "_http._tcp",
+        port: http_server::PORT,
+    },
+    Service {
+        service_type: "_pico-shell._tcp",
+        port: shell_server::PORT,
+    },
+];
+
+/// The board's addresses, for its host records
+#[derive(Clone, Copy)]
+pub struct Addresses {
+    pub v4: Ipv4,
+    pub v6: Option<Ipv6>,
+}
+
+/// The records in a response, with a bit per service in the masks
+#[derive(Clone, Copy, Default)]
+struct Records {
+    host: bool,
+    host6: bool,
+    enumeration: bool,
+    ptr: u8,
+    srv: u8,
+    txt: u8,
+}
+
+impl Records {
+    fn all() -> Records {
+        let every = (1 << SERVICES.len()) - 1;
+        Records {
+            host: true,
+            host6: true,
+            enumeration: true,
+            ptr: every,
+            srv: every,
+            txt: every,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.host && !self.host6 && !self.enumeration && self.ptr == 0 && self.srv == 0 && self.txt == 0
+    }
+
+    fn without(&self, other: &Records) -> Records {
+        Records {
+            host: self.host && !other.host,
+            host6: self.host6 && !other.host6,
+            enumeration: self.enumeration && !other.enumeration,
+            ptr: self.ptr & !other.ptr,
+            srv: self.srv & !other.srv,
+            txt: self.txt & !other.txt,
+        }
+    }
+}
+
+/// The names the board answers for
+pub struct Names {
+    /// `<hostname>.local`
+    host: String<MAX_NAME>,
+    /// `<service type>.local`, per service
+    types: [String<MAX_NAME>; SERVICES.len()],
+    /// `<hostname>.<service type>.local`, per service
+    instances: [String<MAX_NAME>; SERVICES.len()],
+}
+
+impl Names {
+    pub fn new(hostname: &str) -> Names {
+        let mut names = Names {
+            host: String::new(),
+            types: Default::default(),
+            instances: Default::default(),
+        };
+        // The hostname length is limited by the config, so these fit
+        uwrite!(names.host, "{}.local", hostname).ok();
+        for (i, service) in SERVICES.iter().enumerate() {
+            uwrite!(names.types[i], "{}.local", service.service_type).ok();
+            uwrite!(names.instances[i], "{}.{}.local", hostname, service.service_type).ok();
+        }
+        names
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+}
+
+/// Work out which records answer a query, and which are additional
+fn answer(query: &[u8], names: &Names) -> Result<(Records, Records), dnswire::Error> {
+    let mut answers = Records::default();
+    let mut additional = Records::default();
+    for question in Questions::new(query)? {
+        let question = question?;
+        if question.qclass & 0x7fff != CLASS_IN {
+            continue;
+        }
+        if question.name_is(&names.host) && question.wants(TYPE_A) {
+            answers.host = true;
+        }
+        if question.name_is(&names.host) && question.wants(TYPE_AAAA) {
+            answers.host6 = true;
+        }
+        if question.name_is(SERVICE_ENUMERATION) && question.wants(TYPE_PTR) {
+            answers.enumeration = true;
+        }
+        for i in 0..SERVICES.len() {
+            let bit = 1 << i;
+            if question.name_is(&names.types[i]) && question.wants(TYPE_PTR) {
+                answers.ptr |= bit;
+                additional.srv |= bit;
+                additional.txt |= bit;
+                additional.host = true;
+            }
+            if question.name_is(&names.instances[i]) {
+                if question.wants(TYPE_SRV) {
+                    answers.srv |= bit;
+                    additional.host = true;
+                }
+                if question.wants(TYPE_TXT) {
+                    answers.txt |= bit;
+                }
+            }
+        }
+    }
+    // Either address suggests the other will be wanted too (RFC 6762 6.2)
+    if additional.host || answers.host || answers.host6 {
+        additional.host = true;
+        additional.host6 = true;
+    }
+    let additional = additional.without(&answers);
+    Ok((answers, additional))
+}
+
+/// Write the records into a response
+fn write_records(
+    w: &mut Writer,
+    section: Section,
+    records: &Records,
+    names: &Names,
+    addresses: &Addresses,
+    unique_class: u16,
+) -> Result<(), dnswire::Error> {
+    if records.host {
+        w.record(
+            section,
+            &Record {
+                name: &names.host,
+                class: unique_class,
+                ttl: TTL,
+                data: RData::A(addresses.v4),
+            },
+        )?;
+    }
+    if let (true, Some(address)) = (records.host6, addresses.v6) {
+        w.record(
+            section,
+            &Record {
+                name: &names.host,
+                class: unique_class,
+                ttl: TTL,
+                data: RData::Aaaa(address),
+            },
+        )?;
+    }
+    for (i, service) in SERVICES.iter().enumerate() {
+        let bit = 1 << i;
+        if records.enumeration {
+            w.record(
+                section,
+                &Record {
+                    name: SERVICE_ENUMERATION,
+                    class: CLASS_IN,
+                    ttl: TTL,
+                    data: RData::Ptr(&names.types[i]),
+                },
+            )?;
+        }
+        if records.ptr & bit != 0 {
+            w.record(
+                section,
+                &Record {
+                    name: &names.types[i],
+                    class: CLASS_IN,
+                    ttl: TTL,
+                    data: RData::Ptr(&names.instances[i]),
+                },
+            )?;
+        }
+        if records.srv & bit != 0 {
+            w.record(
+                section,
+                &Record {
+                    name: &names.instances[i],
+                    class: unique_class,
+                    ttl: TTL,
+                    data: RData::Srv {
+                        priority: 0,
+                        weight: 0,
+                        port: service.port,
+                        target: &names.host,
+                    },
+                },
+            )?;
+        }
+        if records.txt & bit != 0 {
+            w.record(
+                section,
+                &Record {
+                    name: &names.instances[i],
+                    class: unique_class,
+                    ttl: TTL,
+                    data: RData::Txt(&[]),
+                },
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Build the response to a query, if it asks about the board's records.
+/// `legacy` queries, from a port other than 5353, are answered as plain DNS:
+/// with the query's id and question, and no cache flush bits.
+pub fn respond(
+    query: &[u8],
+    names: &Names,
+    addresses: &Addresses,
+    legacy: bool,
+    out: &mut [u8],
+) -> Result<Option<usize>, dnswire::Error> {
+    let header = dnswire::parse_header(query)?;
+    if header.flags & dnswire::FLAG_QR != 0 {
+        return Ok(None);
+    }
+    let (answers, additional) = answer(query, names)?;
+    if answers.is_empty() {
+        return Ok(None);
+    }
+
+    let (id, unique_class) = if legacy {
+        (header.id, CLASS_IN)
+    } else {
+        (0, CLASS_IN_FLUSH)
+    };
+    let mut w = Writer::new(out, id, dnswire::FLAGS_RESPONSE)?;
+    if legacy {
+        let mut questions = Questions::new(query)?;
+        for question in &mut questions {
+            question?;
+        }
+        w.raw_questions(&query[dnswire::HEADER_LEN..questions.offset()], header.questions)?;
+    }
+    write_records(&mut w, Section::Answer, &answers, names, addresses, unique_class)?;
+    write_records(&mut w, Section::Additional, &additional, names, addresses, unique_class)?;
+    Ok(Some(w.finish()))
+}
+
+/// An unsolicited response announcing all the records
+pub fn announcement(names: &Names, addresses: &Addresses, out: &mut [u8]) -> Result<usize, dnswire::Error> {
+    let mut w = Writer::new(out, 0, dnswire::FLAGS_RESPONSE)?;
+    write_records(&mut w, Section::Answer, &Records::all(), names, addresses, CLASS_IN_FLUSH)?;
+    Ok(w.finish())
+}
+
+/// Answer mDNS queries for the board's name and services
+#[embassy_executor::task]
+pub async fn mdns_task(stack: &'static NetStack, hostname: String<MAX_HOSTNAME>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; MAX_MESSAGE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; MAX_MESSAGE];
+    let mut buf = [0; MAX_MESSAGE];
+    let mut out = [0; MAX_MESSAGE];
+
+    let names = Names::new(&hostname);
+    let group = IpEndpoint::new(IpAddress::Ipv4(GROUP), PORT);
+
+    if let Err(e) = stack.join_multicast_group(GROUP).await {
+        warn!("mDNS couldn't join the multicast group: {:?}", e);
+    }
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(PORT));
+    info!("mDNS responding as {}", names.host());
+
+    let addresses = || {
+        stack.config_v4().map(|c| Addresses {
+            v4: c.address.address().0,
+            v6: stack.config_v6().map(|c| c.address.address().0),
+        })
+    };
+
+    for _ in 0..ANNOUNCEMENTS {
+        if let Some(addresses) = addresses() {
+            let n = unwrap!(announcement(&names, &addresses, &mut out));
+            if let Err(e) = socket.send_to(&out[..n], group).await {
+                warn!("mDNS send error: {:?}", e);
+            }
+        }
+        Timer::after(Duration::from_secs(1)).await;
+    }
+
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("mDNS receive error: {:?}", e);
+                continue;
+            }
+        };
+        let addresses = match addresses() {
+            Some(addresses) => addresses,
+            None => continue,
+        };
+        let legacy = from.port != PORT;
+        match respond(&buf[..n], &names, &addresses, legacy, &mut out) {
+            Ok(Some(len)) => {
+                let to = if legacy { from } else { group };
+                if let Err(e) = socket.send_to(&out[..len], to).await {
+                    warn!("mDNS send error: {:?}", e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => debug!("mDNS ignoring query from {:?}: {:?}", from, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        announcement, respond, Addresses, Names, CLASS_IN_FLUSH, MAX_MESSAGE, SERVICES, SERVICE_ENUMERATION, TTL,
+    };
+    use crate::dnswire::{
+        self, Answers, Writer, CLASS_IN, TYPE_A, TYPE_AAAA, TYPE_ANY, TYPE_PTR, TYPE_SRV, TYPE_TXT,
+    };
+    use crate::netconfig::MAX_HOSTNAME;
+    use crate::shell_server;
+
+    const ADDRESSES: Addresses = Addresses {
+        v4: [10, 0, 0, 2],
+        v6: Some([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+    };
+
+    fn ask(id: u16, questions: &[(&str, u16)]) -> ([u8; 512], usize) {
+        let mut buf = [0; 512];
+        let mut w = Writer::new(&mut buf, id, 0).unwrap();
+        for (name, qtype) in questions {
+            w.question(name, *qtype, CLASS_IN).unwrap();
+        }
+        let n = w.finish();
+        (buf, n)
+    }
+
+    /// The response's answer count, additional count, and answer types
+    fn summary(response: &[u8]) -> (u16, u16, std::vec::Vec<u16>) {
+        let header = dnswire::parse_header(response).unwrap();
+        let types = Answers::new(response).unwrap().map(|a| a.unwrap().rtype).collect();
+        (header.answers, header.additionals, types)
+    }
+
+    #[test]
+    fn answers_for_the_host() {
+        let names = Names::new("pico");
+        let (query, n) = ask(0, &[("pico.local", TYPE_A)]);
+        let mut out = [0; MAX_MESSAGE];
+        let len = respond(&query[..n], &names, &ADDRESSES, false, &mut out).unwrap().unwrap();
+        let response = &out[..len];
+        // The IPv6 address comes as an additional
+        assert_eq!(summary(response), (1, 1, vec![TYPE_A]));
+        assert_eq!(dnswire::parse_header(response).unwrap().id, 0);
+        let a = Answers::new(response).unwrap().next().unwrap().unwrap();
+        assert_eq!((a.class, a.ttl, a.data), (CLASS_IN_FLUSH, TTL, &[10, 0, 0, 2][..]));
+
+        let (query, n) = ask(0, &[("PICO.local", TYPE_ANY)]);
+        let len = respond(&query[..n], &names, &ADDRESSES, false, &mut out).unwrap().unwrap();
+        assert_eq!(summary(&out[..len]), (2, 0, vec![TYPE_A, TYPE_AAAA]));
+
+        // Without an IPv6 address there's no AAAA record to give
+        let v4_only = Addresses { v6: None, ..ADDRESSES };
+        let (query, n) = ask(0, &[("pico.local", TYPE_AAAA)]);
+        let len = respond(&query[..n], &names, &v4_only, false, &mut out).unwrap().unwrap();
+        assert_eq!(summary(&out[..len]), (0, 1, vec![]));
+    }
+
+    #[test]
+    fn answers_for_services() {
+        let names = Names::new("pico");
+        let mut out = [0; MAX_MESSAGE];
+        let (query, n) = ask(0, &[("_http._tcp.local", TYPE_PTR)]);
+        let len = respond(&query[..n], &names, &ADDRESSES, false, &mut out).unwrap().unwrap();
+        // The SRV, TXT and addresses come as additionals
+        assert_eq!(summary(&out[..len]), (1, 4, vec![TYPE_PTR]));
+        let ptr = Answers::new(&out[..len]).unwrap().next().unwrap().unwrap();
+        assert_eq!(ptr.class, CLASS_IN);
+        assert_eq!(ptr.data, b"\x04pico\x05_http\x04_tcp\x05local\x00");
+
+        let (query, n) = ask(0, &[("pico._pico-shell._tcp.local", TYPE_SRV)]);
+        let len = respond(&query[..n], &names, &ADDRESSES, false, &mut out).unwrap().unwrap();
+        assert_eq!(summary(&out[..len]), (1, 2, vec![TYPE_SRV]));
+        let srv = Answers::new(&out[..len]).unwrap().next().unwrap().unwrap();
+        // No priority or weight, then the port
+        assert_eq!(srv.data[..4], [0; 4]);
+        assert_eq!(srv.data[4..6], shell_server::PORT.to_be_bytes());
+
+        let (query, n) = ask(0, &[(SERVICE_ENUMERATION, TYPE_PTR)]);
+        let len = respond(&query[..n], &names, &ADDRESSES, false, &mut out).unwrap().unwrap();
+        assert_eq!(summary(&out[..len]), (3, 0, vec![TYPE_PTR; 3]));
+    }
+
+    #[test]
+    fn answers_legacy_queries_as_plain_dns() {
+        let names = Names::new("pico");
+        let mut out = [0; MAX_MESSAGE];
+        let (query, n) = ask(0x4242, &[("pico.local", TYPE_A)]);
+        let len = respond(&query[..n], &names, &ADDRESSES, true, &mut out).unwrap().unwrap();
+        let response = &out[..len];
+        let header = dnswire::parse_header(response).unwrap();
+        assert_eq!((header.id, header.questions), (0x4242, 1));
+        // The question is echoed back
+        assert_eq!(response[dnswire::HEADER_LEN..n], query[dnswire::HEADER_LEN..n]);
+        let a = Answers::new(response).unwrap().next().unwrap().unwrap();
+        assert_eq!(a.class, CLASS_IN);
+    }
+
+    #[test]
+    fn ignores_what_isnt_for_the_board() {
+        let names = Names::new("pico");
+        let mut out = [0; MAX_MESSAGE];
+        let (query, n) = ask(0, &[("other.local", TYPE_A), ("pico.local", TYPE_TXT)]);
+        assert_eq!(respond(&query[..n], &names, &ADDRESSES, false, &mut out), Ok(None));
+
+        // Responses from others are never answered
+        let (mut response, n) = ask(0, &[("pico.local", TYPE_A)]);
+        response[2] = 0x84;
+        assert_eq!(respond(&response[..n], &names, &ADDRESSES, false, &mut out), Ok(None));
+
+        let (query, n) = ask(0, &[("pico.local", TYPE_A)]);
+        assert_eq!(respond(&query[..n - 1], &names, &ADDRESSES, false, &mut out), Err(dnswire::Error::Truncated));
+    }
+
+    #[test]
+    fn answers_compressed_questions() {
+        let names = Names::new("pico");
+        let mut out = [0; MAX_MESSAGE];
+        // The second question, for pico._http._tcp.local, points into the first
+        let query = b"\x00\x00\x00\x00\x00\x02\x00\x00\x00\x00\x00\x00\
+            \x05_http\x04_tcp\x05local\x00\x00\x0c\x00\x01\
+            \x04pico\xc0\x0c\x00\x10\x00\x01";
+        let len = respond(query, &names, &ADDRESSES, false, &mut out).unwrap().unwrap();
+        assert_eq!(summary(&out[..len]), (2, 3, vec![TYPE_PTR, TYPE_TXT]));
+    }
+
+    #[test]
+    fn announces_everything_for_the_longest_hostname() {
+        let hostname = "a-hostname-of-thirty-two-letters";
+        assert_eq!(hostname.len(), MAX_HOSTNAME);
+        let names = Names::new(hostname);
+        assert!(names.instances.iter().all(|name| name.starts_with(hostname)));
+
+        let mut out = [0; MAX_MESSAGE];
+        let len = announcement(&names, &ADDRESSES, &mut out).unwrap();
+        let response = &out[..len];
+        let header = dnswire::parse_header(response).unwrap();
+        let records = 2 + SERVICES.len() * 4;
+        assert_eq!((header.answers as usize, header.additionals), (records, 0));
+        assert_eq!(Answers::new(response).unwrap().filter(|a| a.is_ok()).count(), records);
+    }
+}
@@ -0,0 +1,362 @@
+//! Counters, gauges and histograms kept by the services and drivers, served
+//! in the Prometheus text format from `GET /metrics`.
+//!
+//! Each metric is a static, updated where the thing it measures happens, and
+//! listed in `REGISTRY` to be served.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::sync::atomic::Ordering;
+
+// The RP2040 has no atomic read-modify-write instructions
+use portable_atomic::{AtomicI32, AtomicU32};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+use ufmt::uWrite;
+use wifi_protocol::metrics::{self, Encoder, Kind, Label};
+
+pub use wifi_protocol::metrics::CONTENT_TYPE;
+
+/// Served by the HTTP server, rather than routed like the other endpoints
+pub const PATH: &str = "/metrics";
+
+/// Longest text served, with room to spare. Every metric at its longest
+/// comes to about 3.5kB.
+pub const MAX_TEXT: usize = 4096;
+
+/// Buckets in each histogram, as well as the one for larger observations
+const BUCKETS: usize = 8;
+
+pub struct Counter(AtomicU32);
+
+impl Counter {
+    pub const fn new() -> Counter {
+        Counter(AtomicU32::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u32) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Set a total kept elsewhere, eg by the wifi chip
+    #[cfg_attr(not(target_os = "none"), allow(dead_code))]
+    pub fn set(&self, total: u32) {
+        self.0.store(total, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Gauge(AtomicI32);
+
+impl Gauge {
+    pub const fn new() -> Gauge {
+        Gauge(AtomicI32::new(0))
+    }
+
+    #[cfg_attr(not(target_os = "none"), allow(dead_code))]
+    pub fn set(&self, value: i32) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i32 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Observations {
+    counts: [u32; BUCKETS],
+    overflow: u32,
+    sum: u64,
+}
+
+pub struct Histogram {
+    /// The buckets' upper bounds, ascending
+    bounds: [u32; BUCKETS],
+    /// The bounds and observations are in units of 10^-decimals of the
+    /// metric's unit
+    decimals: u8,
+    observations: Mutex<CriticalSectionRawMutex, RefCell<Observations>>,
+}
+
+impl Histogram {
+    pub const fn new(bounds: [u32; BUCKETS], decimals: u8) -> Histogram {
+        Histogram {
+            bounds,
+            decimals,
+            observations: Mutex::new(RefCell::new(Observations {
+                counts: [0; BUCKETS],
+                overflow: 0,
+                sum: 0,
+            })),
+        }
+    }
+
+    pub fn observe(&self, value: u32) {
+        self.observations.lock(|o| {
+            let mut o = o.borrow_mut();
+            match self.bounds.iter().position(|&bound| value <= bound) {
+                Some(i) => o.counts[i] = o.counts[i].wrapping_add(1),
+                None => o.overflow = o.overflow.wrapping_add(1),
+            }
+            o.sum = o.sum.wrapping_add(value as u64);
+        });
+    }
+}
+
+/// Where a metric's value comes from
+enum Source {
+    Counter(&'static Counter),
+    Gauge(&'static Gauge),
+    Histogram(&'static Histogram),
+    /// A gauge worked out when served
+    Computed(fn() -> i64),
+}
+
+struct Metric {
+    name: &'static str,
+    /// Only used for the first of a family's series
+    help: &'static str,
+    labels: &'static [Label<'static>],
+    source: Source,
+}
+
+impl Metric {
+    fn kind(&self) -> Kind {
+        match self.source {
+            Source::Counter(_) => Kind::Counter,
+            Source::Gauge(_) | Source::Computed(_) => Kind::Gauge,
+            Source::Histogram(_) => Kind::Histogram,
+        }
+    }
+}
+
+// The echo server
+pub static ECHO_CONNECTIONS: Counter = Counter::new();
+pub static ECHO_ACTIVE: Gauge = Gauge::new();
+pub static ECHO_BYTES: Counter = Counter::new();
+pub static ECHO_ERRORS: Counter = Counter::new();
+
+// The wifi link, polled from the chip
+pub static WIFI_POLLS: Counter = Counter::new();
+pub static WIFI_RSSI: Gauge = Gauge::new();
+pub static WIFI_CHANNEL: Gauge = Gauge::new();
+pub static WIFI_TX_RATE: Gauge = Gauge::new();
+pub static WIFI_RX_PACKETS: Counter = Counter::new();
+pub static WIFI_TX_PACKETS: Counter = Counter::new();
+pub static WIFI_RX_ERRORS: Counter = Counter::new();
+pub static WIFI_TX_ERRORS: Counter = Counter::new();
+
+// The display
+pub static DISPLAY_REFRESHES: Counter = Counter::new();
+pub static DISPLAY_SKIPPED: Counter = Counter::new();
+/// In milliseconds
+pub static DISPLAY_REFRESH_TIME: Histogram = Histogram::new([10, 25, 50, 75, 100, 150, 250, 500], 3);
+
+// The touch screen
+pub static TOUCH_PRESSES: Counter = Counter::new();
+pub static TOUCH_DROPPED: Counter = Counter::new();
+pub static TOUCH_TAPS: Counter = Counter::new();
+pub static TOUCH_SWIPES: Counter = Counter::new();
+/// In milliseconds
+pub static TOUCH_PRESS_TIME: Histogram = Histogram::new([50, 100, 200, 300, 500, 750, 1000, 2000], 3);
+
+static REGISTRY: &[Metric] = &[
+    Metric {
+        name: "uptime_seconds",
+        help: "Time since the board started.",
+        labels: &[],
+        source: Source::Computed(|| Instant::now().as_secs() as i64),
+    },
+    Metric {
+        name: "echo_connections_total",
+        help: "Connections accepted by the echo server.",
+        labels: &[],
+        source: Source::Counter(&ECHO_CONNECTIONS),
+    },
+    Metric {
+        name: "echo_active_connections",
+        help: "Clients connected to the echo server.",
+        labels: &[],
+        source: Source::Gauge(&ECHO_ACTIVE),
+    },
+    Metric {
+        name: "echo_bytes_total",
+        help: "Bytes echoed.",
+        labels: &[],
+        source: Source::Counter(&ECHO_BYTES),
+    },
+    Metric {
+        name: "echo_errors_total",
+        help: "Echo connections ended by a read or write error.",
+        labels: &[],
+        source: Source::Counter(&ECHO_ERRORS),
+    },
+    Metric {
+        name: "wifi_link_polls_total",
+        help: "Times the link statistics were read from the wifi chip.",
+        labels: &[],
+        source: Source::Counter(&WIFI_POLLS),
+    },
+    Metric {
+        name: "wifi_rssi_dbm",
+        help: "Signal strength.",
+        labels: &[],
+        source: Source::Gauge(&WIFI_RSSI),
+    },
+    Metric {
+        name: "wifi_channel",
+        help: "Channel in use.",
+        labels: &[],
+        source: Source::Gauge(&WIFI_CHANNEL),
+    },
+    Metric {
+        name: "wifi_tx_rate_kbps",
+        help: "Transmit rate.",
+        labels: &[],
+        source: Source::Gauge(&WIFI_TX_RATE),
+    },
+    Metric {
+        name: "wifi_packets_total",
+        help: "Packets counted by the wifi chip.",
+        labels: &[("direction", "rx")],
+        source: Source::Counter(&WIFI_RX_PACKETS),
+    },
+    Metric {
+        name: "wifi_packets_total",
+        help: "",
+        labels: &[("direction", "tx")],
+        source: Source::Counter(&WIFI_TX_PACKETS),
+    },
+    Metric {
+        name: "wifi_errors_total",
+        help: "Errors counted by the wifi chip.",
+        labels: &[("direction", "rx")],
+        source: Source::Counter(&WIFI_RX_ERRORS),
+    },
+    Metric {
+        name: "wifi_errors_total",
+        help: "",
+        labels: &[("direction", "tx")],
+        source: Source::Counter(&WIFI_TX_ERRORS),
+    },
+    Metric {
+        name: "display_refreshes_total",
+        help: "Times the display was redrawn.",
+        labels: &[],
+        source: Source::Counter(&DISPLAY_REFRESHES),
+    },
+    Metric {
+        name: "display_refreshes_skipped_total",
+        help: "Redraws skipped while a remote drawing client had the display.",
+        labels: &[],
+        source: Source::Counter(&DISPLAY_SKIPPED),
+    },
+    Metric {
+        name: "display_refresh_seconds",
+        help: "Time taken to redraw the display.",
+        labels: &[],
+        source: Source::Histogram(&DISPLAY_REFRESH_TIME),
+    },
+    Metric {
+        name: "touch_presses_total",
+        help: "Presses on the touch screen.",
+        labels: &[],
+        source: Source::Counter(&TOUCH_PRESSES),
+    },
+    Metric {
+        name: "touch_presses_dropped_total",
+        help: "Presses dropped as nothing was waiting for them.",
+        labels: &[],
+        source: Source::Counter(&TOUCH_DROPPED),
+    },
+    Metric {
+        name: "touch_gestures_total",
+        help: "Gestures recognised on the touch screen.",
+        labels: &[("gesture", "tap")],
+        source: Source::Counter(&TOUCH_TAPS),
+    },
+    Metric {
+        name: "touch_gestures_total",
+        help: "",
+        labels: &[("gesture", "swipe")],
+        source: Source::Counter(&TOUCH_SWIPES),
+    },
+    Metric {
+        name: "touch_press_seconds",
+        help: "How long the touch screen was pressed.",
+        labels: &[],
+        source: Source::Histogram(&TOUCH_PRESS_TIME),
+    },
+];
+
+/// Write every metric in the text format
+pub fn write_text<W: uWrite>(out: &mut W) -> Result<(), W::Error> {
+    let mut encoder = Encoder::new(Adapter { out, error: None });
+    // Only the adapter's errors can fail the encoding
+    if encode(&mut encoder).is_err() {
+        if let Some(e) = encoder.into_inner().error {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn encode<W: fmt::Write>(encoder: &mut Encoder<W>) -> fmt::Result {
+    let mut last = "";
+    for metric in REGISTRY {
+        // A family's series follow each other, and are described once
+        if metric.name != last {
+            encoder.family(metric.name, metric.kind(), metric.help)?;
+            last = metric.name;
+        }
+        match metric.source {
+            Source::Counter(c) => encoder.sample(metric.name, metric.labels, c.get() as i64)?,
+            Source::Gauge(g) => encoder.sample(metric.name, metric.labels, g.get() as i64)?,
+            Source::Computed(f) => encoder.sample(metric.name, metric.labels, f())?,
+            Source::Histogram(h) => {
+                let o = h.observations.lock(|o| *o.borrow());
+                let histogram = metrics::Histogram {
+                    bounds: &h.bounds,
+                    counts: &o.counts,
+                    overflow: o.overflow,
+                    sum: o.sum,
+                    decimals: h.decimals,
+                };
+                encoder.histogram(metric.name, metric.labels, &histogram)?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lets the encoder write to a `uWrite`, keeping its error
+struct Adapter<'a, W: uWrite> {
+    out: &'a mut W,
+    error: Option<W::Error>,
+}
+
+impl<W: uWrite> fmt::Write for Adapter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_str(s).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
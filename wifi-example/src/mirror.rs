@@ -0,0 +1,244 @@
+//! Everything drawn on the display, streamed to a tcp client as rectangles of
+//! RGB565 pixels. See `wifi_protocol::mirror` for the framing.
+//!
+//! Only the area drawn on since the last update is kept. Its pixels are read
+//! back from the panel as they're sent, so there's no copy of the display in
+//! RAM.
+
+use core::cell::Cell;
+
+use defmt::*;
+use embassy_futures::select::{select, Either};
+use embassy_net::tcp::TcpSocket;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_io_async::Write;
+use wifi_protocol::mirror::{self as framing, RectHeader};
+
+use crate::display::SharedDisplay;
+use crate::NetStack;
+
+pub const PORT: u16 = 7000;
+
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 240;
+
+/// Rows of pixels sent per rectangle message
+const BAND_ROWS: usize = 8;
+
+/// A panel whose pixels can be read back
+pub trait ReadPixels {
+    type Error;
+
+    /// Read the pixels of `area`, which lies within the display, into `out`
+    /// in row major order
+    fn read_pixels(&mut self, area: &Rectangle, out: &mut [Rgb565]) -> Result<(), Self::Error>;
+}
+
+fn bounds() -> Rectangle {
+    Rectangle::new(Point::zero(), Size::new(WIDTH as u32, HEIGHT as u32))
+}
+
+fn union(a: &Rectangle, b: &Rectangle) -> Rectangle {
+    let (a_br, b_br) = (a.bottom_right().unwrap(), b.bottom_right().unwrap());
+    Rectangle::with_corners(
+        Point::new(a.top_left.x.min(b.top_left.x), a.top_left.y.min(b.top_left.y)),
+        Point::new(a_br.x.max(b_br.x), a_br.y.max(b_br.y)),
+    )
+}
+
+/// The area drawn on since last sent
+static DIRTY: Mutex<CriticalSectionRawMutex, Cell<Option<Rectangle>>> = Mutex::new(Cell::new(None));
+
+/// Signalled whenever something is drawn
+static MIRROR_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+fn mark_dirty(area: Rectangle) {
+    let area = area.intersection(&bounds());
+    if area.is_zero_sized() {
+        return;
+    }
+    DIRTY.lock(|dirty| {
+        dirty.set(Some(match dirty.get() {
+            Some(dirty) => union(&dirty, &area),
+            None => area,
+        }))
+    });
+    MIRROR_SIGNAL.signal(());
+}
+
+/// A draw target that notes the area drawn on `inner`, to be sent
+pub struct Mirrored<D> {
+    pub inner: D,
+}
+
+impl<D> OriginDimensions for Mirrored<D>
+where
+    D: OriginDimensions,
+{
+    fn size(&self) -> Size {
+        self.inner.size()
+    }
+}
+
+impl<D> DrawTarget for Mirrored<D>
+where
+    D: DrawTarget<Color = Rgb565> + OriginDimensions,
+{
+    type Color = Rgb565;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let mut min = Point::new(i32::MAX, i32::MAX);
+        let mut max = Point::new(i32::MIN, i32::MIN);
+        let result = self.inner.draw_iter(pixels.into_iter().inspect(|Pixel(p, _)| {
+            min = min.component_min(*p);
+            max = max.component_max(*p);
+        }));
+        if min.x <= max.x {
+            mark_dirty(Rectangle::with_corners(min, max));
+        }
+        result
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let result = self.inner.fill_contiguous(area, colors);
+        mark_dirty(*area);
+        result
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let result = self.inner.fill_solid(area, color);
+        mark_dirty(*area);
+        result
+    }
+}
+
+/// Stream the display to one client at a time
+#[embassy_executor::task]
+pub async fn mirror_task(stack: &'static NetStack, display: &'static SharedDisplay) -> ! {
+    let mut rx_buffer = [0; 64];
+    let mut tx_buffer = [0; 2048];
+    let mut pixels = [Rgb565::BLACK; WIDTH * BAND_ROWS];
+    let mut buf = [0u8; framing::RECT_HEADER_LEN + WIDTH * BAND_ROWS * 2];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
+        // A client that vanishes while the display is unchanged is noticed
+        // by the keepalives going unanswered
+        socket.set_keep_alive(Some(embassy_time::Duration::from_secs(5)));
+
+        info!("Mirror listening on TCP:{}...", PORT);
+        if let Err(e) = socket.accept(PORT).await {
+            warn!("mirror accept error: {:?}", e);
+            continue;
+        }
+        info!("Mirroring to {:?}", socket.remote_endpoint());
+
+        // A new client needs the whole display
+        mark_dirty(bounds());
+        let hello = framing::encode_hello(WIDTH as u16, HEIGHT as u16);
+        if let Err(e) = socket.write_all(&hello).await {
+            warn!("mirror write error: {:?}", e);
+            continue;
+        }
+
+        'connection: loop {
+            let dirty = match DIRTY.lock(|dirty| dirty.take()) {
+                Some(dirty) => dirty,
+                None => {
+                    // The client has nothing to say, so reading only ends
+                    // when it goes away
+                    let mut discard = [0; 16];
+                    match select(MIRROR_SIGNAL.wait(), socket.read(&mut discard)).await {
+                        Either::First(()) | Either::Second(Ok(1..)) => continue,
+                        Either::Second(Ok(0)) => info!("mirror client closed"),
+                        Either::Second(Err(e)) => warn!("mirror read error: {:?}", e),
+                    }
+                    break 'connection;
+                }
+            };
+
+            let width = dirty.size.width as usize;
+            let x0 = dirty.top_left.x as usize;
+            let mut y = dirty.top_left.y as usize;
+            let y_end = y + dirty.size.height as usize;
+            while y < y_end {
+                let rows = BAND_ROWS.min(y_end - y);
+                let band = Rectangle::new(
+                    Point::new(x0 as i32, y as i32),
+                    Size::new(width as u32, rows as u32),
+                );
+                let pixels = &mut pixels[..width * rows];
+                let read = display.lock(|d| d.borrow_mut().interface.inner.read_pixels(&band, pixels));
+                if read.is_err() {
+                    warn!("mirror can't read the display");
+                    break 'connection;
+                }
+
+                let header = RectHeader {
+                    x: x0 as u16,
+                    y: y as u16,
+                    width: width as u16,
+                    height: rows as u16,
+                };
+                let len = framing::RECT_HEADER_LEN + pixels.len() * 2;
+                buf[..framing::RECT_HEADER_LEN].copy_from_slice(&framing::encode_rect_header(&header));
+                for (out, pixel) in buf[framing::RECT_HEADER_LEN..len].chunks_exact_mut(2).zip(pixels.iter()) {
+                    out.copy_from_slice(&RawU16::from(*pixel).into_inner().to_be_bytes());
+                }
+                if let Err(e) = socket.write_all(&buf[..len]).await {
+                    warn!("mirror write error: {:?}", e);
+                    break 'connection;
+                }
+                y += rows;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::pixelcolor::Rgb565;
+    use embedded_graphics::prelude::*;
+    use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+
+    use super::{bounds, ReadPixels, DIRTY};
+
+    #[test]
+    fn keeps_the_area_drawn_and_reads_it_back() {
+        let mut display = crate::host::display();
+        DIRTY.lock(|dirty| dirty.take());
+
+        Rectangle::new(Point::new(10, 20), Size::new(5, 5))
+            .into_styled(PrimitiveStyle::with_fill(Rgb565::RED))
+            .draw(&mut display.interface)
+            .unwrap();
+        Pixel(Point::new(100, 2), Rgb565::GREEN).draw(&mut display.interface).unwrap();
+        let dirty = Rectangle::with_corners(Point::new(10, 2), Point::new(100, 24));
+        assert_eq!(DIRTY.lock(|dirty| dirty.take()), Some(dirty));
+
+        let mut out = [Rgb565::BLUE; 4];
+        let area = Rectangle::new(Point::new(99, 2), Size::new(2, 2));
+        display.interface.inner.read_pixels(&area, &mut out).unwrap();
+        assert_eq!(out, [Rgb565::BLACK, Rgb565::GREEN, Rgb565::BLACK, Rgb565::BLACK]);
+
+        // Drawing off the display is clipped
+        Pixel(Point::new(-1, 300), Rgb565::GREEN).draw(&mut display.interface).unwrap();
+        assert_eq!(DIRTY.lock(|dirty| dirty.take()), None);
+        display.interface.clear(Rgb565::BLACK).unwrap();
+        assert_eq!(DIRTY.lock(|dirty| dirty.take()), Some(bounds()));
+    }
+}
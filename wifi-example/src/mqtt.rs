@@ -0,0 +1,338 @@
+//! The parts of MQTT 3.1.1 needed by a QoS 0 client: encoding the packets it
+//! sends, and decoding the packets a broker sends back. Kept free of
+//! networking and allocation.
+
+use defmt::Format;
+
+pub const DEFAULT_PORT: u16 = 1883;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// Connect flag: start a fresh session
+const CLEAN_SESSION: u8 = 0x02;
+
+/// Publish flag: the broker keeps the message for future subscribers
+const RETAIN: u8 = 0x01;
+
+/// The largest remaining length that fits in the four byte encoding
+const MAX_REMAINING_LEN: usize = 268_435_455;
+
+/// The longest fixed header: the type and flags, and a four byte length
+pub const MAX_HEADER: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Error {
+    /// The output buffer is too small for the packet
+    BufferTooSmall,
+    /// The packet is larger than the input buffer can ever hold
+    PacketTooLarge,
+    BadRemainingLength,
+    /// The packet's contents don't match its type
+    Malformed,
+    BadUtf8,
+}
+
+/// Packets sent by the broker
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Packet<'a> {
+    ConnAck {
+        session_present: bool,
+        /// 0 if accepted
+        return_code: u8,
+    },
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+    },
+    SubAck {
+        packet_id: u16,
+        /// One per topic subscribed, 0x80 for a refusal
+        return_codes: &'a [u8],
+    },
+    PingResp,
+    /// Some other packet, by type, that a QoS 0 client can ignore
+    Other(u8),
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, v: u8) -> Result<(), Error> {
+        self.put(&[v])
+    }
+
+    fn u16(&mut self, v: u16) -> Result<(), Error> {
+        self.put(&v.to_be_bytes())
+    }
+
+    fn str(&mut self, s: &str) -> Result<(), Error> {
+        if s.len() > u16::MAX as usize {
+            return Err(Error::PacketTooLarge);
+        }
+        self.u16(s.len() as u16)?;
+        self.put(s.as_bytes())
+    }
+
+    /// The fixed header: packet type, flags and remaining length
+    fn header(&mut self, packet_type: u8, flags: u8, remaining_len: usize) -> Result<(), Error> {
+        if remaining_len > MAX_REMAINING_LEN {
+            return Err(Error::PacketTooLarge);
+        }
+        self.u8(packet_type << 4 | flags)?;
+        let mut len = remaining_len;
+        loop {
+            let mut byte = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                byte |= 0x80;
+            }
+            self.u8(byte)?;
+            if len == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Encode a CONNECT for a clean session, returning the encoded length
+pub fn encode_connect(buf: &mut [u8], client_id: &str, keepalive_secs: u16) -> Result<usize, Error> {
+    let mut w = Writer { buf, len: 0 };
+    // protocol name, level, flags and keepalive, then the client id
+    w.header(CONNECT, 0, 10 + 2 + client_id.len())?;
+    w.str("MQTT")?;
+    w.u8(4)?;
+    w.u8(CLEAN_SESSION)?;
+    w.u16(keepalive_secs)?;
+    w.str(client_id)?;
+    Ok(w.len)
+}
+
+/// Encode a QoS 0 PUBLISH, returning the encoded length
+pub fn encode_publish(buf: &mut [u8], topic: &str, payload: &[u8], retain: bool) -> Result<usize, Error> {
+    let mut w = Writer { buf, len: 0 };
+    w.header(PUBLISH, if retain { RETAIN } else { 0 }, 2 + topic.len() + payload.len())?;
+    w.str(topic)?;
+    w.put(payload)?;
+    Ok(w.len)
+}
+
+/// Encode a SUBSCRIBE to `topics` at QoS 0, returning the encoded length
+pub fn encode_subscribe(buf: &mut [u8], packet_id: u16, topics: &[&str]) -> Result<usize, Error> {
+    let mut w = Writer { buf, len: 0 };
+    let remaining_len = 2 + topics.iter().map(|t| 2 + t.len() + 1).sum::<usize>();
+    // SUBSCRIBE's reserved flags must be 0b0010
+    w.header(SUBSCRIBE, 0x02, remaining_len)?;
+    w.u16(packet_id)?;
+    for topic in topics {
+        w.str(topic)?;
+        w.u8(0)?;
+    }
+    Ok(w.len)
+}
+
+pub fn encode_pingreq(buf: &mut [u8]) -> Result<usize, Error> {
+    let mut w = Writer { buf, len: 0 };
+    w.header(PINGREQ, 0, 0)?;
+    Ok(w.len)
+}
+
+pub fn encode_disconnect(buf: &mut [u8]) -> Result<usize, Error> {
+    let mut w = Writer { buf, len: 0 };
+    w.header(DISCONNECT, 0, 0)?;
+    Ok(w.len)
+}
+
+/// Decode a packet from the start of `buf`, returning it and its length, or
+/// None if `buf` doesn't yet hold a complete packet. `capacity` is the most
+/// the caller can buffer; longer packets are an error.
+pub fn decode(buf: &[u8], capacity: usize) -> Result<Option<(Packet<'_>, usize)>, Error> {
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    let packet_type = buf[0] >> 4;
+    let flags = buf[0] & 0x0f;
+
+    let mut remaining_len = 0usize;
+    let mut header_len = 1;
+    loop {
+        if header_len > 4 {
+            return Err(Error::BadRemainingLength);
+        }
+        let byte = match buf.get(header_len) {
+            Some(byte) => *byte,
+            None => return Ok(None),
+        };
+        remaining_len |= ((byte & 0x7f) as usize) << (7 * (header_len - 1));
+        header_len += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let len = header_len + remaining_len;
+    if len > capacity {
+        return Err(Error::PacketTooLarge);
+    }
+    if buf.len() < len {
+        return Ok(None);
+    }
+    let body = &buf[header_len..len];
+
+    let packet = match packet_type {
+        CONNACK => match body {
+            [ack_flags, return_code] => Packet::ConnAck {
+                session_present: ack_flags & 0x01 != 0,
+                return_code: *return_code,
+            },
+            _ => return Err(Error::Malformed),
+        },
+        PUBLISH => {
+            if body.len() < 2 {
+                return Err(Error::Malformed);
+            }
+            let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+            // QoS 1 and 2 messages carry a packet id after the topic
+            let id_len = if flags & 0x06 != 0 { 2 } else { 0 };
+            if body.len() < 2 + topic_len + id_len {
+                return Err(Error::Malformed);
+            }
+            let topic = core::str::from_utf8(&body[2..2 + topic_len]).map_err(|_| Error::BadUtf8)?;
+            Packet::Publish {
+                topic,
+                payload: &body[2 + topic_len + id_len..],
+            }
+        }
+        SUBACK => {
+            if body.len() < 3 {
+                return Err(Error::Malformed);
+            }
+            Packet::SubAck {
+                packet_id: u16::from_be_bytes([body[0], body[1]]),
+                return_codes: &body[2..],
+            }
+        }
+        PINGRESP => Packet::PingResp,
+        other => Packet::Other(other),
+    };
+    Ok(Some((packet, len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_packets() {
+        let mut buf = [0; 64];
+        let n = encode_connect(&mut buf, "pico", 60).unwrap();
+        assert_eq!(&buf[..n], b"\x10\x10\x00\x04MQTT\x04\x02\x00\x3c\x00\x04pico");
+        let n = encode_publish(&mut buf, "a/b", b"on", true).unwrap();
+        assert_eq!(&buf[..n], b"\x31\x07\x00\x03a/bon");
+        let n = encode_subscribe(&mut buf, 1, &["a/led", "a/x"]).unwrap();
+        assert_eq!(&buf[..n], b"\x82\x10\x00\x01\x00\x05a/led\x00\x00\x03a/x\x00");
+        let n = encode_pingreq(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"\xc0\x00");
+        let n = encode_disconnect(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"\xe0\x00");
+
+        assert_eq!(
+            encode_publish(&mut buf, "a/b", &[0; 60], false),
+            Err(Error::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn round_trips_long_publishes() {
+        let payload = [0x5a; 300];
+        let mut buf = [0; 400];
+        let n = encode_publish(&mut buf, "t", &payload, false).unwrap();
+        // 303 bytes take two bytes of remaining length
+        assert_eq!(&buf[..3], &[0x30, 0xaf, 0x02]);
+        assert_eq!(n, 3 + 303);
+
+        let packet = Packet::Publish {
+            topic: "t",
+            payload: &payload,
+        };
+        assert_eq!(decode(&buf[..n], 400), Ok(Some((packet, n))));
+        // Followed by the start of another packet
+        buf[n] = 0xd0;
+        assert_eq!(decode(&buf[..n + 1], 400), Ok(Some((packet, n))));
+    }
+
+    #[test]
+    fn decodes_broker_packets() {
+        assert_eq!(
+            decode(b"\x20\x02\x01\x00", 64),
+            Ok(Some((
+                Packet::ConnAck {
+                    session_present: true,
+                    return_code: 0
+                },
+                4
+            )))
+        );
+        assert_eq!(
+            decode(b"\x90\x04\x00\x01\x00\x80", 64),
+            Ok(Some((
+                Packet::SubAck {
+                    packet_id: 1,
+                    return_codes: &[0, 0x80]
+                },
+                6
+            )))
+        );
+        assert_eq!(decode(b"\xd0\x00", 64), Ok(Some((Packet::PingResp, 2))));
+        assert_eq!(decode(b"\xb0\x02\x00\x07", 64), Ok(Some((Packet::Other(11), 4))));
+        // A QoS 1 publish has a packet id before the payload
+        assert_eq!(
+            decode(b"\x32\x07\x00\x01t\x00\x09hi", 64),
+            Ok(Some((
+                Packet::Publish {
+                    topic: "t",
+                    payload: b"hi"
+                },
+                9
+            )))
+        );
+    }
+
+    #[test]
+    fn waits_for_whole_packets() {
+        assert_eq!(decode(b"", 64), Ok(None));
+        assert_eq!(decode(b"\x30", 64), Ok(None));
+        assert_eq!(decode(b"\x30\x80", 64), Ok(None));
+        assert_eq!(decode(b"\x30\x05\x00\x01t", 64), Ok(None));
+    }
+
+    #[test]
+    fn rejects_bad_packets() {
+        assert_eq!(decode(b"\x30\xff\xff\xff\xff\x01", 64), Err(Error::BadRemainingLength));
+        assert_eq!(decode(b"\x30\x80\x01", 64), Err(Error::PacketTooLarge));
+        assert_eq!(decode(b"\x20\x01\x00", 64), Err(Error::Malformed));
+        assert_eq!(decode(b"\x30\x01\x00", 64), Err(Error::Malformed));
+        assert_eq!(decode(b"\x30\x04\x00\x05ab", 64), Err(Error::Malformed));
+        assert_eq!(decode(b"\x30\x03\x00\x01\xff", 64), Err(Error::BadUtf8));
+        assert_eq!(decode(b"\x90\x02\x00\x01", 64), Err(Error::Malformed));
+    }
+}
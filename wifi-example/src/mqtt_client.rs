@@ -0,0 +1,477 @@
+//! Connects to an MQTT broker, if one is configured, to publish the button,
+//! touch gestures and status, and to take LED and display commands.
+//!
+//! Topics, under the configured prefix:
+//!
+//! - `<prefix>/button`: `pressed` or `released`
+//! - `<prefix>/touch`: `tap <x> <y>` or `swipe <left|right|up|down>`
+//! - `<prefix>/status`: the status as json, retained
+//! - `<prefix>/led`, subscribed: `on`, `off` or `blink <ms>`
+//! - `<prefix>/display`, subscribed: `<row> <msg>`
+//!
+//! Everything is QoS 0. If the broker goes away the client reconnects.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::*;
+use embassy_futures::select::{select3, Either3};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{ErrorKind, Read, Write};
+use heapless::String;
+use ufmt::uwrite;
+
+use crate::mqtt::{self, Packet};
+use crate::netconfig::{MqttConfig, MAX_TOPIC_PREFIX};
+use crate::shell::{self, Board};
+use crate::shell_server::RemoteBoard;
+use crate::touch::Gesture;
+use crate::{entropy, status, NetStack};
+
+const BUFFER_SIZE: usize = 1024;
+
+/// Largest packet received
+const MAX_PACKET: usize = 512;
+
+const MAX_TOPIC: usize = MAX_TOPIC_PREFIX + 8;
+
+/// Largest packet sent, the status at its longest
+const MAX_OUT_PACKET: usize = mqtt::MAX_HEADER + 2 + MAX_TOPIC + status::MAX_JSON;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+const SUBSCRIBE_PACKET_ID: u16 = 1;
+
+/// Things that happen on the board, to be published
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+pub enum Event {
+    Button(bool),
+    Touch(Gesture),
+}
+
+static EVENTS: Channel<CriticalSectionRawMutex, Event, 8> = Channel::new();
+
+/// Whether a session is up, so events are only queued when they can be sent
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Publish an event, if connected to a broker. Events are dropped if they
+/// arrive faster than they can be sent.
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+pub fn publish_event(event: Event) {
+    if CONNECTED.load(Ordering::Relaxed) {
+        let _ = EVENTS.try_send(event);
+    }
+}
+
+#[derive(Debug, Format)]
+enum SessionError {
+    Io(ErrorKind),
+    Mqtt(mqtt::Error),
+    /// The broker refused the connection, with this return code
+    Refused(u8),
+    Timeout,
+    Closed,
+}
+
+impl<E: embedded_io_async::Error> From<E> for SessionError {
+    fn from(e: E) -> Self {
+        SessionError::Io(e.kind())
+    }
+}
+
+impl From<mqtt::Error> for SessionError {
+    fn from(e: mqtt::Error) -> Self {
+        SessionError::Mqtt(e)
+    }
+}
+
+struct Topics {
+    button: String<MAX_TOPIC>,
+    touch: String<MAX_TOPIC>,
+    status: String<MAX_TOPIC>,
+    led: String<MAX_TOPIC>,
+    display: String<MAX_TOPIC>,
+}
+
+impl Topics {
+    fn new(prefix: &str) -> Topics {
+        let topic = |name: &str| {
+            let mut topic = String::<MAX_TOPIC>::new();
+            // The prefix length is limited by the config, so this fits
+            uwrite!(topic, "{}/{}", prefix, name).ok();
+            topic
+        };
+        Topics {
+            button: topic("button"),
+            touch: topic("touch"),
+            status: topic("status"),
+            led: topic("led"),
+            display: topic("display"),
+        }
+    }
+}
+
+/// Keep a session with the broker, reconnecting whenever it's lost
+#[embassy_executor::task]
+pub async fn mqtt_task(stack: &'static NetStack, config: MqttConfig) -> ! {
+    let mut rx_buffer = [0; BUFFER_SIZE];
+    let mut tx_buffer = [0; BUFFER_SIZE];
+    let mut client = Client::new(RemoteBoard, &config);
+    uwrite!(client.client_id, "wifi-example-{}", entropy::next_u32()).ok();
+    let broker = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address(config.broker)), config.port);
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        // Pings keep the connection busy, so this only expires if the broker is gone
+        socket.set_timeout(Some(embassy_time::Duration::from_secs(config.keepalive_secs as u64 * 2)));
+
+        info!("MQTT connecting to {:?} as {}", broker, client.client_id);
+        match socket.connect(broker).await {
+            Ok(()) => match client.session(&mut socket).await {
+                Ok(never) => match never {},
+                Err(e) => {
+                    warn!("MQTT session ended: {:?}", e);
+                    // The broker sent something unexpected, but is still
+                    // there to be told the client is leaving
+                    if let SessionError::Mqtt(_) = e {
+                        if let Ok(n) = mqtt::encode_disconnect(&mut client.out) {
+                            socket.write_all(&client.out[..n]).await.ok();
+                            socket.flush().await.ok();
+                        }
+                    }
+                }
+            },
+            Err(e) => warn!("MQTT connect error: {:?}", e),
+        }
+        CONNECTED.store(false, Ordering::Relaxed);
+        socket.abort();
+        Timer::after(RECONNECT_DELAY).await;
+    }
+}
+
+struct Client<B> {
+    board: B,
+    topics: Topics,
+    client_id: String<32>,
+    keepalive_secs: u16,
+    /// Outgoing packets are encoded here
+    out: [u8; MAX_OUT_PACKET],
+    /// Incoming packets are gathered here
+    buf: [u8; MAX_PACKET],
+}
+
+impl<B: Board> Client<B> {
+    fn new(board: B, config: &MqttConfig) -> Self {
+        Client {
+            board,
+            topics: Topics::new(&config.prefix),
+            client_id: String::new(),
+            keepalive_secs: config.keepalive_secs,
+            out: [0; MAX_OUT_PACKET],
+            buf: [0; MAX_PACKET],
+        }
+    }
+
+    /// Run a session over a connection to the broker until it fails
+    async fn session<S: Read + Write>(&mut self, socket: &mut S) -> Result<core::convert::Infallible, SessionError> {
+        let keepalive = Duration::from_secs(self.keepalive_secs as u64);
+        let n = mqtt::encode_connect(&mut self.out, &self.client_id, self.keepalive_secs)?;
+        socket.write_all(&self.out[..n]).await?;
+
+        let mut connected = false;
+        let mut awaiting_ping = false;
+        let connect_deadline = Instant::now() + CONNECT_TIMEOUT;
+        let mut next_ping = Instant::now() + keepalive;
+        let mut next_status = Instant::now();
+        let mut len = 0;
+
+        loop {
+            let wake = if connected {
+                next_ping.min(next_status)
+            } else {
+                connect_deadline
+            };
+            match select3(socket.read(&mut self.buf[len..]), EVENTS.receive(), Timer::at(wake)).await {
+                Either3::First(result) => {
+                    let n = result?;
+                    if n == 0 {
+                        return Err(SessionError::Closed);
+                    }
+                    len += n;
+
+                    let mut used = 0;
+                    while let Some((packet, packet_len)) = mqtt::decode(&self.buf[used..len], MAX_PACKET)? {
+                        match packet {
+                            Packet::ConnAck { return_code: 0, .. } => {
+                                log_info!("MQTT connected");
+                                connected = true;
+                                CONNECTED.store(true, Ordering::Relaxed);
+                                let topics = [self.topics.led.as_str(), self.topics.display.as_str()];
+                                let n = mqtt::encode_subscribe(&mut self.out, SUBSCRIBE_PACKET_ID, &topics)?;
+                                socket.write_all(&self.out[..n]).await?;
+                            }
+                            Packet::ConnAck { return_code, .. } => return Err(SessionError::Refused(return_code)),
+                            Packet::SubAck { return_codes, .. } => {
+                                if return_codes.contains(&0x80) {
+                                    log_warn!("MQTT subscription refused");
+                                }
+                            }
+                            Packet::Publish { topic, payload } => {
+                                Self::command(&mut self.board, &self.topics, topic, payload)
+                            }
+                            Packet::PingResp => awaiting_ping = false,
+                            Packet::Other(packet_type) => debug!("MQTT ignoring packet type {}", packet_type),
+                        }
+                        used += packet_len;
+                    }
+                    self.buf.copy_within(used..len, 0);
+                    len -= used;
+                }
+                Either3::Second(event) => {
+                    let mut payload = String::<32>::new();
+                    let topic = match event {
+                        Event::Button(pressed) => {
+                            payload.push_str(if pressed { "pressed" } else { "released" }).ok();
+                            &self.topics.button
+                        }
+                        Event::Touch(Gesture::Tap(p)) => {
+                            uwrite!(payload, "tap {} {}", p.x, p.y).ok();
+                            &self.topics.touch
+                        }
+                        Event::Touch(Gesture::Swipe(direction)) => {
+                            uwrite!(payload, "swipe {}", direction.label()).ok();
+                            &self.topics.touch
+                        }
+                    };
+                    let n = mqtt::encode_publish(&mut self.out, topic, payload.as_bytes(), false)?;
+                    socket.write_all(&self.out[..n]).await?;
+                }
+                Either3::Third(()) => {
+                    if !connected {
+                        return Err(SessionError::Timeout);
+                    }
+                    let now = Instant::now();
+                    if now >= next_status {
+                        if let Some(n) = self.encode_status() {
+                            socket.write_all(&self.out[..n]).await?;
+                        }
+                        next_status = now + STATUS_INTERVAL;
+                    }
+                    if now >= next_ping {
+                        // The last ping was never answered
+                        if awaiting_ping {
+                            return Err(SessionError::Timeout);
+                        }
+                        let n = mqtt::encode_pingreq(&mut self.out)?;
+                        socket.write_all(&self.out[..n]).await?;
+                        awaiting_ping = true;
+                        next_ping = now + keepalive;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Encode a publish of the status, or None if it somehow doesn't fit.
+    /// A truncated status wouldn't be json, so it's better not sent.
+    fn encode_status(&mut self) -> Option<usize> {
+        let mut json = String::<{ status::MAX_JSON }>::new();
+        if status::write_json(&self.board.status(), &mut json).is_err() {
+            log_warn!("MQTT status is too long to publish");
+            return None;
+        }
+        match mqtt::encode_publish(&mut self.out, &self.topics.status, json.as_bytes(), true) {
+            Ok(n) => Some(n),
+            Err(_) => {
+                log_warn!("MQTT status doesn't fit a packet");
+                None
+            }
+        }
+    }
+
+    /// Act on a message from a subscribed topic
+    fn command(board: &mut B, topics: &Topics, topic: &str, payload: &[u8]) {
+        let args = match core::str::from_utf8(payload) {
+            Ok(args) => args.trim(),
+            Err(_) => {
+                log_warn!("MQTT {}: payload is not utf8", topic);
+                return;
+            }
+        };
+        if topic == topics.led.as_str() {
+            match shell::parse_led(args) {
+                Ok(mode) => board.set_led(mode),
+                Err(e) => log_warn!("MQTT {}: {}", topic, e.message()),
+            }
+        } else if topic == topics.display.as_str() {
+            match shell::parse_display_text(args) {
+                Ok((row, text)) => board.display_text(row, text),
+                Err(e) => log_warn!("MQTT {}: {}", topic, e.message()),
+            }
+        } else {
+            debug!("MQTT ignoring message on {}", topic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use embassy_futures::join::join;
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::pipe::Pipe;
+    use embedded_io_async::{ErrorType, Read, Write};
+
+    use super::{publish_event, Client, Event, SessionError, MAX_PACKET};
+    use crate::host::run_test;
+    use crate::mqtt::{self, Packet};
+    use crate::netconfig::MqttConfig;
+    use crate::shell::tests::MockBoard;
+    use crate::shell::LedMode;
+
+    type Wire = Pipe<CriticalSectionRawMutex, 1024>;
+
+    /// One end of a connection made of two pipes
+    struct End<'a> {
+        rx: &'a Wire,
+        tx: &'a Wire,
+    }
+
+    impl ErrorType for End<'_> {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for End<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            Ok(self.rx.read(buf).await)
+        }
+    }
+
+    impl Write for End<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            Ok(self.tx.write(buf).await)
+        }
+    }
+
+    /// A broker stand-in, reading the client's packets whole
+    struct Broker<'a> {
+        end: End<'a>,
+        buf: Vec<u8>,
+    }
+
+    impl Broker<'_> {
+        async fn receive(&mut self) -> Vec<u8> {
+            loop {
+                if let Some((_, len)) = mqtt::decode(&self.buf, usize::MAX).unwrap() {
+                    return self.buf.drain(..len).collect();
+                }
+                let mut chunk = [0; 256];
+                let n = self.end.read(&mut chunk).await.unwrap();
+                self.buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+
+        async fn receive_publish(&mut self) -> (std::string::String, Vec<u8>) {
+            let packet = self.receive().await;
+            match mqtt::decode(&packet, usize::MAX).unwrap() {
+                Some((Packet::Publish { topic, payload }, _)) => (topic.into(), payload.into()),
+                other => panic!("expected a publish, got {:?}", other),
+            }
+        }
+
+        async fn send(&mut self, packet: &[u8]) {
+            self.end.write_all(packet).await.unwrap();
+        }
+    }
+
+    fn config() -> MqttConfig {
+        MqttConfig {
+            broker: [10, 0, 0, 1],
+            port: mqtt::DEFAULT_PORT,
+            prefix: "pico".try_into().unwrap(),
+            keepalive_secs: 60,
+        }
+    }
+
+    #[test]
+    fn talks_to_a_broker() {
+        run_test(|| async {
+            let mut client = Client::new(MockBoard::default(), &config());
+            client.client_id.push_str("test").unwrap();
+            let (to_client, to_broker) = (Wire::new(), Wire::new());
+            let mut socket = End {
+                rx: &to_client,
+                tx: &to_broker,
+            };
+            let mut broker = Broker {
+                end: End {
+                    rx: &to_broker,
+                    tx: &to_client,
+                },
+                buf: Vec::new(),
+            };
+
+            let broker = async {
+                let mut connect = [0; 32];
+                let n = mqtt::encode_connect(&mut connect, "test", 60).unwrap();
+                assert_eq!(broker.receive().await, &connect[..n]);
+                broker.send(b"\x20\x02\x00\x00").await;
+
+                let mut subscribe = [0; 64];
+                let n = mqtt::encode_subscribe(&mut subscribe, 1, &["pico/led", "pico/display"]).unwrap();
+                assert_eq!(broker.receive().await, &subscribe[..n]);
+                broker.send(b"\x90\x04\x00\x01\x00\x00").await;
+
+                let (topic, payload) = broker.receive_publish().await;
+                assert_eq!(topic, "pico/status");
+                assert!(payload.starts_with(b"{\"ssid\":\"home\","));
+
+                publish_event(Event::Button(true));
+                assert_eq!(
+                    broker.receive_publish().await,
+                    ("pico/button".into(), b"pressed".to_vec())
+                );
+
+                let mut publish = [0; 64];
+                let n = mqtt::encode_publish(&mut publish, "pico/led", b"blink 200", false).unwrap();
+                broker.send(&publish[..n]).await;
+                let n = mqtt::encode_publish(&mut publish, "pico/display", b"1 hello", false).unwrap();
+                broker.send(&publish[..n]).await;
+                // Not subscribed, so ignored
+                let n = mqtt::encode_publish(&mut publish, "pico/other", b"?", false).unwrap();
+                broker.send(&publish[..n]).await;
+                // A refusal ends the session, once the commands before it are done
+                broker.send(b"\x20\x02\x00\x05").await;
+            };
+            let (result, ()) = join(client.session(&mut socket), broker).await;
+            assert!(matches!(result, Err(SessionError::Refused(5))));
+            assert_eq!(client.board.led, Some(LedMode::Blink { period_ms: 200 }));
+            assert_eq!(client.board.text, Some((1, "hello".into())));
+        });
+    }
+
+    #[test]
+    fn ends_the_session_on_oversized_packets() {
+        run_test(|| async {
+            let mut client = Client::new(MockBoard::default(), &config());
+            let (to_client, to_broker) = (Wire::new(), Wire::new());
+            let mut socket = End {
+                rx: &to_client,
+                tx: &to_broker,
+            };
+            let broker = async {
+                let mut publish = [0; MAX_PACKET + 16];
+                let n = mqtt::encode_publish(&mut publish, "pico/led", &[b' '; MAX_PACKET], false).unwrap();
+                to_client.write_all(&publish[..n]).await;
+            };
+            let (result, ()) = join(client.session(&mut socket), broker).await;
+            assert!(matches!(result, Err(SessionError::Mqtt(mqtt::Error::PacketTooLarge))));
+        });
+    }
+}
@@ -0,0 +1,849 @@
+//! The network configuration, read from a dedicated flash sector.
+//!
+//! The configuration is plain text, one `key = value` per line, eg:
+//!
+//! ```text
+//! # one of dhcp, static, or dhcp-fallback
+//! mode = dhcp-fallback
+//! address = 192.168.1.50/24
+//! gateway = 192.168.1.1
+//! dns = 192.168.1.1, 8.8.8.8
+//! # seconds to wait for a DHCP lease before using the static address
+//! fallback_timeout = 15
+//! # optional, an IPv6 address and router, in place of the one from SLAAC
+//! address6 = 2001:db8::50/64
+//! gateway6 = 2001:db8::1
+//! # optional, an MQTT broker to connect to, with the default port of 1883
+//! mqtt_broker = 192.168.1.10:1883
+//! # topics are <prefix>/button, <prefix>/led, etc, by default pico/...
+//! mqtt_prefix = pico
+//! mqtt_keepalive = 60
+//! # the SNTP server that sets the clock, and the local timezone
+//! ntp_server = 216.239.35.0
+//! tz_offset = +10:00
+//! # the board answers mDNS queries for <hostname>.local
+//! hostname = pico-demo
+//! # optional, where to post status reports, and how often in seconds
+//! report_url = http://collector.lan:8080/status
+//! report_interval = 60
+//! # optional, where to send the telemetry stream, with the default port of 1236
+//! telemetry = 192.168.1.20:1236
+//! # optional, a syslog server to forward log messages to, by default on port 514
+//! syslog = 192.168.1.20:514
+//! # optional, the pre-shared key for the TLS shell, in hex, and its identity
+//! tls_psk = 000102030405060708090a0b0c0d0e0f
+//! tls_identity = pico
+//! ```
+//!
+//! and is written to flash independently of the program:
+//!
+//! ```text
+//! probe-rs download netconfig.txt --format bin --chip RP2040 --base-address 0x10180000
+//! ```
+//!
+//! The text ends at the first erased (0xff) or nul byte. If the sector is
+//! blank, DHCP is used.
+
+use defmt::Format;
+use heapless::{String, Vec};
+
+use wifi_protocol::telemetry;
+
+use crate::{mqtt, sntp, syslog};
+
+/// Where the configuration lives, matching the CONFIG region in memory.x
+#[cfg(target_os = "none")]
+pub const CONFIG_FLASH_ADDR: usize = 0x1018_0000;
+#[cfg(target_os = "none")]
+pub const CONFIG_FLASH_LEN: usize = 4096;
+
+pub const MAX_DNS_SERVERS: usize = 3;
+
+const DEFAULT_FALLBACK_TIMEOUT_SECS: u32 = 15;
+
+pub const MAX_TOPIC_PREFIX: usize = 32;
+
+pub const MAX_HOSTNAME: usize = 32;
+
+const DEFAULT_HOSTNAME: &str = "pico-demo";
+
+pub const MAX_REPORT_HOST: usize = 64;
+pub const MAX_REPORT_PATH: usize = 64;
+
+const DEFAULT_REPORT_INTERVAL_SECS: u32 = 60;
+
+pub const MAX_TLS_IDENTITY: usize = 32;
+
+/// TLS PSKs are between 128 and 256 bits
+const MIN_TLS_PSK: usize = 16;
+pub const MAX_TLS_PSK: usize = 32;
+
+const DEFAULT_TLS_IDENTITY: &str = "pico";
+
+const DEFAULT_TOPIC_PREFIX: &str = "pico";
+const DEFAULT_KEEPALIVE_SECS: u16 = 60;
+
+/// time.google.com
+const DEFAULT_NTP_SERVER: Ipv4 = [216, 239, 35, 0];
+
+pub type Ipv4 = [u8; 4];
+pub type Ipv6 = [u8; 16];
+
+#[derive(Clone, PartialEq, Eq, Debug, Format)]
+pub struct StaticIp {
+    pub address: Ipv4,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4>,
+    pub dns_servers: Vec<Ipv4, MAX_DNS_SERVERS>,
+}
+
+/// An IPv6 address. There's room for only one, so a configured address
+/// replaces the link-local address made from the MAC address, and the
+/// addresses from router advertisements aren't used.
+#[derive(Clone, PartialEq, Eq, Debug, Format)]
+pub struct StaticIp6 {
+    pub address: Ipv6,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv6>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Format)]
+pub enum Mode {
+    Dhcp,
+    Static(StaticIp),
+    /// Use DHCP, but if no lease is obtained within the timeout use the
+    /// static address
+    DhcpWithFallback { fallback: StaticIp, timeout_secs: u32 },
+}
+
+/// How the address in use was obtained
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum ActiveMode {
+    Dhcp,
+    Static,
+    Fallback,
+}
+
+impl ActiveMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ActiveMode::Dhcp => "DHCP",
+            ActiveMode::Static => "static",
+            ActiveMode::Fallback => "fallback",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Format)]
+pub struct MqttConfig {
+    pub broker: Ipv4,
+    pub port: u16,
+    /// Prepended to every topic, eg `pico/button`
+    pub prefix: String<MAX_TOPIC_PREFIX>,
+    pub keepalive_secs: u16,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Format)]
+pub struct ReportConfig {
+    /// A hostname or dotted quad
+    pub host: String<MAX_REPORT_HOST>,
+    pub port: u16,
+    pub path: String<MAX_REPORT_PATH>,
+    pub interval_secs: u32,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub identity: String<MAX_TLS_IDENTITY>,
+    pub psk: Vec<u8, MAX_TLS_PSK>,
+}
+
+// The key is left out, so it isn't logged
+impl Format for TlsConfig {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "TlsConfig {{ identity: {} }}", self.identity.as_str())
+    }
+}
+
+impl core::fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TlsConfig").field("identity", &self.identity).finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Format)]
+pub struct NetConfig {
+    pub mode: Mode,
+    /// Without one, the link-local address is used
+    pub ipv6: Option<StaticIp6>,
+    /// The MQTT client only runs if a broker is configured
+    pub mqtt: Option<MqttConfig>,
+    pub ntp_server: (Ipv4, u16),
+    /// The local timezone's offset from UTC, in minutes
+    pub tz_offset_mins: i16,
+    pub hostname: String<MAX_HOSTNAME>,
+    /// Status reports are only sent if a collector is configured
+    pub report: Option<ReportConfig>,
+    /// Telemetry is only sent if a listener is configured
+    pub telemetry: Option<(Ipv4, u16)>,
+    /// Log messages are only forwarded if a syslog server is configured
+    pub syslog: Option<(Ipv4, u16)>,
+    /// The TLS shell only runs if a key is configured
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        NetConfig {
+            mode: Mode::Dhcp,
+            ipv6: None,
+            mqtt: None,
+            ntp_server: (DEFAULT_NTP_SERVER, sntp::PORT),
+            tz_offset_mins: 0,
+            hostname: DEFAULT_HOSTNAME.try_into().unwrap(),
+            report: None,
+            telemetry: None,
+            syslog: None,
+            tls: None,
+        }
+    }
+}
+
+impl NetConfig {
+    /// The static address to start with, or None to start with DHCP
+    pub fn initial_static(&self) -> Option<&StaticIp> {
+        match &self.mode {
+            Mode::Static(ip) => Some(ip),
+            _ => None,
+        }
+    }
+
+    /// The static address to fall back to, and how long to wait for DHCP before doing so
+    pub fn fallback(&self) -> Option<(&StaticIp, u32)> {
+        match &self.mode {
+            Mode::DhcpWithFallback {
+                fallback,
+                timeout_secs,
+            } => Some((fallback, *timeout_secs)),
+            _ => None,
+        }
+    }
+
+    /// Which mode is active once configured, given whether the fallback was needed
+    pub fn active_mode(&self, fell_back: bool) -> ActiveMode {
+        match (&self.mode, fell_back) {
+            (Mode::Static(_), _) => ActiveMode::Static,
+            (Mode::DhcpWithFallback { .. }, true) => ActiveMode::Fallback,
+            _ => ActiveMode::Dhcp,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum ErrorKind {
+    NotUtf8,
+    MissingEquals,
+    UnknownKey,
+    DuplicateKey,
+    BadMode,
+    BadAddress,
+    BadNumber,
+    TooManyDnsServers,
+    /// A static address is required by the mode, but none was given
+    MissingAddress,
+    TooLong,
+    /// Hostnames are letters, digits and inner hyphens
+    BadHostname,
+    /// Only `http://host[:port]/path` urls are supported
+    BadUrl,
+    /// Keys are 16 to 32 bytes, in hex
+    BadKey,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct ParseError {
+    /// 1-based line number of the error, or 0 if not specific to a line
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+/// Read the configuration from flash. An absent or invalid configuration
+/// results in DHCP.
+#[cfg(target_os = "none")]
+pub fn load() -> NetConfig {
+    // Safety: the CONFIG region is reserved in memory.x, and flash is mapped
+    // for reading via XIP.
+    let bytes =
+        unsafe { core::slice::from_raw_parts(CONFIG_FLASH_ADDR as *const u8, CONFIG_FLASH_LEN) };
+    load_from(bytes)
+}
+
+/// Parse the configuration, logging the result. An absent or invalid
+/// configuration results in DHCP.
+pub fn load_from(bytes: &[u8]) -> NetConfig {
+    match parse(bytes) {
+        Ok(config) => {
+            defmt::info!("network config: {}", config);
+            config
+        }
+        Err(e) => {
+            defmt::warn!("invalid network config, using DHCP: {}", e);
+            NetConfig::default()
+        }
+    }
+}
+
+/// Parse a configuration from the raw contents of its flash sector
+pub fn parse(bytes: &[u8]) -> Result<NetConfig, ParseError> {
+    let end = bytes
+        .iter()
+        .position(|b| *b == 0xff || *b == 0)
+        .unwrap_or(bytes.len());
+    let text = core::str::from_utf8(&bytes[..end]).map_err(|_| ParseError {
+        line: 0,
+        kind: ErrorKind::NotUtf8,
+    })?;
+
+    let mut mode: Option<&str> = None;
+    let mut address: Option<(Ipv4, u8)> = None;
+    let mut gateway: Option<Ipv4> = None;
+    let mut dns_servers: Option<Vec<Ipv4, MAX_DNS_SERVERS>> = None;
+    let mut timeout_secs: Option<u32> = None;
+    let mut address6: Option<(Ipv6, u8)> = None;
+    let mut gateway6: Option<Ipv6> = None;
+    let mut mqtt_broker: Option<(Ipv4, u16)> = None;
+    let mut mqtt_prefix: Option<&str> = None;
+    let mut mqtt_keepalive: Option<u16> = None;
+    let mut ntp_server: Option<(Ipv4, u16)> = None;
+    let mut tz_offset_mins: Option<i16> = None;
+    let mut hostname: Option<&str> = None;
+    let mut report_url: Option<(&str, u16, &str)> = None;
+    let mut report_interval: Option<u32> = None;
+    let mut telemetry: Option<(Ipv4, u16)> = None;
+    let mut syslog: Option<(Ipv4, u16)> = None;
+    let mut tls_psk: Option<Vec<u8, MAX_TLS_PSK>> = None;
+    let mut tls_identity: Option<&str> = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let err = |kind| ParseError { line: i + 1, kind };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or(err(ErrorKind::MissingEquals))?;
+        let value = value.trim();
+        let duplicate = match key.trim() {
+            "mode" => mode.replace(value).is_some(),
+            "address" => address
+                .replace(parse_cidr(value).ok_or(err(ErrorKind::BadAddress))?)
+                .is_some(),
+            "gateway" => gateway
+                .replace(parse_ipv4(value).ok_or(err(ErrorKind::BadAddress))?)
+                .is_some(),
+            "dns" => {
+                let mut servers = Vec::new();
+                for server in value.split(',') {
+                    let server = parse_ipv4(server.trim()).ok_or(err(ErrorKind::BadAddress))?;
+                    servers
+                        .push(server)
+                        .map_err(|_| err(ErrorKind::TooManyDnsServers))?;
+                }
+                dns_servers.replace(servers).is_some()
+            }
+            "address6" => address6
+                .replace(parse_cidr6(value).ok_or(err(ErrorKind::BadAddress))?)
+                .is_some(),
+            "gateway6" => gateway6
+                .replace(parse_ipv6(value).ok_or(err(ErrorKind::BadAddress))?)
+                .is_some(),
+            "fallback_timeout" => timeout_secs
+                .replace(value.parse().map_err(|_| err(ErrorKind::BadNumber))?)
+                .is_some(),
+            "mqtt_broker" => mqtt_broker
+                .replace(parse_endpoint(value, mqtt::DEFAULT_PORT).ok_or(err(ErrorKind::BadAddress))?)
+                .is_some(),
+            "mqtt_prefix" => {
+                if value.len() > MAX_TOPIC_PREFIX {
+                    return Err(err(ErrorKind::TooLong));
+                }
+                mqtt_prefix.replace(value).is_some()
+            }
+            "mqtt_keepalive" => {
+                // The client relies on the keepalive to notice a dead broker
+                let secs = value.parse().ok().filter(|secs| *secs > 0);
+                mqtt_keepalive.replace(secs.ok_or(err(ErrorKind::BadNumber))?).is_some()
+            }
+            "ntp_server" => ntp_server
+                .replace(parse_endpoint(value, sntp::PORT).ok_or(err(ErrorKind::BadAddress))?)
+                .is_some(),
+            "tz_offset" => tz_offset_mins
+                .replace(parse_tz_offset(value).ok_or(err(ErrorKind::BadNumber))?)
+                .is_some(),
+            "hostname" => {
+                if !valid_hostname(value) {
+                    return Err(err(ErrorKind::BadHostname));
+                }
+                hostname.replace(value).is_some()
+            }
+            "report_url" => {
+                let (host, port, path) = parse_http_url(value).ok_or(err(ErrorKind::BadUrl))?;
+                if host.len() > MAX_REPORT_HOST || path.len() > MAX_REPORT_PATH {
+                    return Err(err(ErrorKind::TooLong));
+                }
+                report_url.replace((host, port, path)).is_some()
+            }
+            "report_interval" => {
+                let secs = value.parse().ok().filter(|secs| *secs > 0);
+                report_interval.replace(secs.ok_or(err(ErrorKind::BadNumber))?).is_some()
+            }
+            "telemetry" => telemetry
+                .replace(parse_endpoint(value, telemetry::DEFAULT_PORT).ok_or(err(ErrorKind::BadAddress))?)
+                .is_some(),
+            "syslog" => syslog
+                .replace(parse_endpoint(value, syslog::DEFAULT_PORT).ok_or(err(ErrorKind::BadAddress))?)
+                .is_some(),
+            "tls_psk" => tls_psk
+                .replace(parse_key(value).ok_or(err(ErrorKind::BadKey))?)
+                .is_some(),
+            "tls_identity" => {
+                if value.is_empty() || value.len() > MAX_TLS_IDENTITY {
+                    return Err(err(ErrorKind::TooLong));
+                }
+                tls_identity.replace(value).is_some()
+            }
+            _ => return Err(err(ErrorKind::UnknownKey)),
+        };
+        if duplicate {
+            return Err(err(ErrorKind::DuplicateKey));
+        }
+    }
+
+    let static_ip = || -> Result<StaticIp, ParseError> {
+        let (address, prefix_len) = address.ok_or(ParseError {
+            line: 0,
+            kind: ErrorKind::MissingAddress,
+        })?;
+        Ok(StaticIp {
+            address,
+            prefix_len,
+            gateway,
+            dns_servers: dns_servers.clone().unwrap_or_default(),
+        })
+    };
+
+    let mode = match mode.unwrap_or("dhcp") {
+        "dhcp" => Mode::Dhcp,
+        "static" => Mode::Static(static_ip()?),
+        "dhcp-fallback" => Mode::DhcpWithFallback {
+            fallback: static_ip()?,
+            timeout_secs: timeout_secs.unwrap_or(DEFAULT_FALLBACK_TIMEOUT_SECS),
+        },
+        _ => {
+            return Err(ParseError {
+                line: 0,
+                kind: ErrorKind::BadMode,
+            })
+        }
+    };
+    let ipv6 = match (address6, gateway6) {
+        (Some((address, prefix_len)), gateway) => Some(StaticIp6 {
+            address,
+            prefix_len,
+            gateway,
+        }),
+        (None, Some(_)) => {
+            return Err(ParseError {
+                line: 0,
+                kind: ErrorKind::MissingAddress,
+            })
+        }
+        (None, None) => None,
+    };
+    let mqtt = mqtt_broker.map(|(broker, port)| MqttConfig {
+        broker,
+        port,
+        // Already checked to fit
+        prefix: mqtt_prefix.unwrap_or(DEFAULT_TOPIC_PREFIX).try_into().unwrap(),
+        keepalive_secs: mqtt_keepalive.unwrap_or(DEFAULT_KEEPALIVE_SECS),
+    });
+    Ok(NetConfig {
+        mode,
+        ipv6,
+        mqtt,
+        ntp_server: ntp_server.unwrap_or((DEFAULT_NTP_SERVER, sntp::PORT)),
+        tz_offset_mins: tz_offset_mins.unwrap_or(0),
+        // Already checked to fit
+        hostname: hostname.unwrap_or(DEFAULT_HOSTNAME).try_into().unwrap(),
+        report: report_url.map(|(host, port, path)| ReportConfig {
+            // Already checked to fit
+            host: host.try_into().unwrap(),
+            port,
+            path: path.try_into().unwrap(),
+            interval_secs: report_interval.unwrap_or(DEFAULT_REPORT_INTERVAL_SECS),
+        }),
+        telemetry,
+        syslog,
+        tls: tls_psk.map(|psk| TlsConfig {
+            // Already checked to fit
+            identity: tls_identity.unwrap_or(DEFAULT_TLS_IDENTITY).try_into().unwrap(),
+            psk,
+        }),
+    })
+}
+
+/// Parse a dotted quad, eg `192.168.1.1`
+pub fn parse_ipv4(s: &str) -> Option<Ipv4> {
+    let mut addr = [0u8; 4];
+    let mut parts = s.split('.');
+    for octet in addr.iter_mut() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    match parts.next() {
+        None => Some(addr),
+        Some(_) => None,
+    }
+}
+
+/// Parse an IPv6 address, eg `2001:db8::50`
+pub fn parse_ipv6(s: &str) -> Option<Ipv6> {
+    fn groups(s: &str) -> Option<Vec<u16, 8>> {
+        let mut groups = Vec::new();
+        if s.is_empty() {
+            return Some(groups);
+        }
+        for group in s.split(':') {
+            if group.is_empty() || group.len() > 4 || !group.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            groups.push(u16::from_str_radix(group, 16).ok()?).ok()?;
+        }
+        Some(groups)
+    }
+
+    // At most one run of zero groups is left out, as `::`
+    let (head, tail) = match s.split_once("::") {
+        Some((head, tail)) => {
+            let (head, tail) = (groups(head)?, groups(tail)?);
+            if head.len() + tail.len() > 7 {
+                return None;
+            }
+            (head, tail)
+        }
+        None => {
+            let head = groups(s)?;
+            if head.len() != 8 {
+                return None;
+            }
+            (head, Vec::new())
+        }
+    };
+    let mut addr = [0u8; 16];
+    let tail_at = 16 - tail.len() * 2;
+    for (i, group) in head.iter().enumerate() {
+        addr[i * 2..i * 2 + 2].copy_from_slice(&group.to_be_bytes());
+    }
+    for (i, group) in tail.iter().enumerate() {
+        addr[tail_at + i * 2..tail_at + i * 2 + 2].copy_from_slice(&group.to_be_bytes());
+    }
+    Some(addr)
+}
+
+/// Parse an address with an optional port, eg `192.168.1.10:1883`
+pub fn parse_endpoint(s: &str, default_port: u16) -> Option<(Ipv4, u16)> {
+    match s.split_once(':') {
+        Some((addr, port)) => Some((parse_ipv4(addr)?, port.parse().ok()?)),
+        None => Some((parse_ipv4(s)?, default_port)),
+    }
+}
+
+/// Split an http url, eg `http://collector.lan:8080/status`, into its host,
+/// port and path
+pub fn parse_http_url(s: &str) -> Option<(&str, u16, &str)> {
+    let rest = s.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(at) => rest.split_at(at),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.split_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host, port, path))
+}
+
+/// Parse a key in hex, eg `000102030405060708090a0b0c0d0e0f`
+fn parse_key(s: &str) -> Option<Vec<u8, MAX_TLS_PSK>> {
+    let mut key = Vec::new();
+    // An odd length leaves half a byte at the end, which fails to parse
+    for i in (0..s.len()).step_by(2) {
+        let byte = u8::from_str_radix(s.get(i..i + 2)?, 16).ok()?;
+        key.push(byte).ok()?;
+    }
+    (key.len() >= MIN_TLS_PSK).then_some(key)
+}
+
+/// Whether `s` is a single DNS label that fits, eg `pico-demo`
+fn valid_hostname(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= MAX_HOSTNAME
+        && !s.starts_with('-')
+        && !s.ends_with('-')
+        && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+/// Parse a timezone offset in hours and optional minutes, eg `+10`, `-03:30`,
+/// returning the offset in minutes
+pub fn parse_tz_offset(s: &str) -> Option<i16> {
+    let (sign, s) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => (1, s),
+    };
+    let (hours, minutes) = match s.split_once(':') {
+        Some((hours, minutes)) => (hours.parse::<i16>().ok()?, minutes.parse::<i16>().ok()?),
+        None => (s.parse::<i16>().ok()?, 0),
+    };
+    if !(0..=14).contains(&hours) || !(0..60).contains(&minutes) {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Parse an address with prefix length, eg `192.168.1.50/24`
+pub fn parse_cidr(s: &str) -> Option<(Ipv4, u8)> {
+    let (addr, prefix_len) = s.split_once('/')?;
+    let prefix_len: u8 = prefix_len.parse().ok()?;
+    if prefix_len > 32 {
+        return None;
+    }
+    Some((parse_ipv4(addr)?, prefix_len))
+}
+
+/// Parse an IPv6 address with prefix length, eg `2001:db8::50/64`
+pub fn parse_cidr6(s: &str) -> Option<(Ipv6, u8)> {
+    let (addr, prefix_len) = s.split_once('/')?;
+    let prefix_len: u8 = prefix_len.parse().ok()?;
+    if prefix_len > 128 {
+        return None;
+    }
+    Some((parse_ipv6(addr)?, prefix_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blank_sector_is_dhcp_with_defaults() {
+        let config = parse(&[0xff; 64]).unwrap();
+        assert_eq!(config, NetConfig::default());
+        assert_eq!(config.mode, Mode::Dhcp);
+        assert_eq!(config.ntp_server, (DEFAULT_NTP_SERVER, sntp::PORT));
+        assert_eq!(config.hostname.as_str(), DEFAULT_HOSTNAME);
+        assert_eq!(config.active_mode(false), ActiveMode::Dhcp);
+    }
+
+    #[test]
+    fn text_ends_at_erased_or_nul_byte() {
+        assert_eq!(parse(b"mode = dhcp\n\xffgarbage").unwrap().mode, Mode::Dhcp);
+        assert_eq!(parse(b"mode = dhcp\n\0garbage").unwrap().mode, Mode::Dhcp);
+    }
+
+    #[test]
+    fn full_example() {
+        let text = b"\
+# comment
+mode = dhcp-fallback
+address = 192.168.1.50/24
+gateway = 192.168.1.1
+dns = 192.168.1.1, 8.8.8.8
+fallback_timeout = 20
+address6 = 2001:db8::50/64
+gateway6 = 2001:db8::1
+mqtt_broker = 192.168.1.10
+mqtt_prefix = board
+mqtt_keepalive = 30
+ntp_server = 10.0.0.1:1123
+tz_offset = -03:30
+hostname = pico-2
+report_url = http://collector.lan:8080/status
+report_interval = 90
+telemetry = 192.168.1.20
+syslog = 192.168.1.20:5140
+tls_psk = 000102030405060708090a0b0c0d0e0f
+tls_identity = me
+";
+        let config = parse(text).unwrap();
+        let fallback = StaticIp {
+            address: [192, 168, 1, 50],
+            prefix_len: 24,
+            gateway: Some([192, 168, 1, 1]),
+            dns_servers: Vec::from_slice(&[[192, 168, 1, 1], [8, 8, 8, 8]]).unwrap(),
+        };
+        assert_eq!(
+            config.mode,
+            Mode::DhcpWithFallback {
+                fallback: fallback.clone(),
+                timeout_secs: 20
+            }
+        );
+        assert_eq!(config.fallback(), Some((&fallback, 20)));
+        assert_eq!(config.initial_static(), None);
+        assert_eq!(config.active_mode(true), ActiveMode::Fallback);
+        assert_eq!(
+            config.ipv6,
+            Some(StaticIp6 {
+                address: [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x50],
+                prefix_len: 64,
+                gateway: Some([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+            })
+        );
+        assert_eq!(
+            config.mqtt,
+            Some(MqttConfig {
+                broker: [192, 168, 1, 10],
+                port: mqtt::DEFAULT_PORT,
+                prefix: "board".try_into().unwrap(),
+                keepalive_secs: 30,
+            })
+        );
+        assert_eq!(config.ntp_server, ([10, 0, 0, 1], 1123));
+        assert_eq!(config.tz_offset_mins, -210);
+        assert_eq!(config.hostname.as_str(), "pico-2");
+        assert_eq!(
+            config.report,
+            Some(ReportConfig {
+                host: "collector.lan".try_into().unwrap(),
+                port: 8080,
+                path: "/status".try_into().unwrap(),
+                interval_secs: 90,
+            })
+        );
+        assert_eq!(config.telemetry, Some(([192, 168, 1, 20], telemetry::DEFAULT_PORT)));
+        assert_eq!(config.syslog, Some(([192, 168, 1, 20], 5140)));
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.identity.as_str(), "me");
+        assert_eq!(tls.psk.as_slice(), &core::array::from_fn::<u8, 16, _>(|i| i as u8));
+    }
+
+    #[test]
+    fn static_defaults() {
+        let config = parse(b"mode = static\naddress = 10.0.0.2/8\n").unwrap();
+        let ip = StaticIp {
+            address: [10, 0, 0, 2],
+            prefix_len: 8,
+            gateway: None,
+            dns_servers: Vec::new(),
+        };
+        assert_eq!(config.initial_static(), Some(&ip));
+        assert_eq!(config.active_mode(false), ActiveMode::Static);
+
+        let config = parse(b"mode = dhcp-fallback\naddress = 10.0.0.2/8\n").unwrap();
+        assert_eq!(config.fallback(), Some((&ip, DEFAULT_FALLBACK_TIMEOUT_SECS)));
+    }
+
+    #[test]
+    fn optional_defaults() {
+        let config = parse(b"mqtt_broker = 1.2.3.4:1884\nreport_url = http://host\ntls_psk = 000102030405060708090a0b0c0d0e0f\n").unwrap();
+        let mqtt = config.mqtt.unwrap();
+        assert_eq!(mqtt.port, 1884);
+        assert_eq!(mqtt.prefix.as_str(), DEFAULT_TOPIC_PREFIX);
+        assert_eq!(mqtt.keepalive_secs, DEFAULT_KEEPALIVE_SECS);
+        let report = config.report.unwrap();
+        assert_eq!((report.host.as_str(), report.port, report.path.as_str()), ("host", 80, "/"));
+        assert_eq!(report.interval_secs, DEFAULT_REPORT_INTERVAL_SECS);
+        assert_eq!(config.tls.unwrap().identity.as_str(), DEFAULT_TLS_IDENTITY);
+    }
+
+    fn error(text: &str) -> ParseError {
+        parse(text.as_bytes()).unwrap_err()
+    }
+
+    #[test]
+    fn errors() {
+        let at = |line, kind| ParseError { line, kind };
+        assert_eq!(parse(b"mode = \xc3\x28"), Err(at(0, ErrorKind::NotUtf8)));
+        assert_eq!(error("# ok\nmode dhcp"), at(2, ErrorKind::MissingEquals));
+        assert_eq!(error("colour = blue"), at(1, ErrorKind::UnknownKey));
+        assert_eq!(error("mode = dhcp\nmode = static"), at(2, ErrorKind::DuplicateKey));
+        assert_eq!(error("mode = auto"), at(0, ErrorKind::BadMode));
+        assert_eq!(error("address = 1.2.3/24"), at(1, ErrorKind::BadAddress));
+        assert_eq!(error("address = 1.2.3.4/33"), at(1, ErrorKind::BadAddress));
+        assert_eq!(error("fallback_timeout = soon"), at(1, ErrorKind::BadNumber));
+        assert_eq!(error("mqtt_keepalive = 0"), at(1, ErrorKind::BadNumber));
+        assert_eq!(error("report_interval = 0"), at(1, ErrorKind::BadNumber));
+        assert_eq!(error("tz_offset = +15"), at(1, ErrorKind::BadNumber));
+        assert_eq!(error("dns = 1.1.1.1,2.2.2.2,3.3.3.3,4.4.4.4"), at(1, ErrorKind::TooManyDnsServers));
+        assert_eq!(error("mode = static"), at(0, ErrorKind::MissingAddress));
+        assert_eq!(error("mode = dhcp-fallback"), at(0, ErrorKind::MissingAddress));
+        assert_eq!(error("gateway6 = fe80::1"), at(0, ErrorKind::MissingAddress));
+        assert_eq!(error(&format!("mqtt_prefix = {}", "a".repeat(33))), at(1, ErrorKind::TooLong));
+        assert_eq!(error("tls_identity = "), at(1, ErrorKind::TooLong));
+        assert_eq!(error("hostname = -pico"), at(1, ErrorKind::BadHostname));
+        assert_eq!(error("hostname = pico.local"), at(1, ErrorKind::BadHostname));
+        assert_eq!(error(&format!("hostname = {}", "a".repeat(33))), at(1, ErrorKind::BadHostname));
+        assert_eq!(error("report_url = https://host/"), at(1, ErrorKind::BadUrl));
+        assert_eq!(error("report_url = http://:80/"), at(1, ErrorKind::BadUrl));
+        assert_eq!(error("tls_psk = 0001"), at(1, ErrorKind::BadKey));
+        assert_eq!(error("tls_psk = 000102030405060708090a0b0c0d0e0"), at(1, ErrorKind::BadKey));
+        assert_eq!(error(&format!("tls_psk = {}", "00".repeat(33))), at(1, ErrorKind::BadKey));
+    }
+
+    #[test]
+    fn invalid_config_loads_as_dhcp() {
+        assert_eq!(load_from(b"mode = static"), NetConfig::default());
+    }
+
+    #[test]
+    fn ipv4() {
+        assert_eq!(parse_ipv4("192.168.1.1"), Some([192, 168, 1, 1]));
+        assert_eq!(parse_ipv4("192.168.1"), None);
+        assert_eq!(parse_ipv4("192.168.1.1.1"), None);
+        assert_eq!(parse_ipv4("192.168.1.256"), None);
+        assert_eq!(parse_endpoint("1.2.3.4", 7), Some(([1, 2, 3, 4], 7)));
+        assert_eq!(parse_endpoint("1.2.3.4:", 7), None);
+        assert_eq!(parse_cidr("1.2.3.4"), None);
+    }
+
+    #[test]
+    fn ipv6() {
+        let mut loopback = [0; 16];
+        loopback[15] = 1;
+        assert_eq!(parse_ipv6("::1"), Some(loopback));
+        assert_eq!(parse_ipv6("::"), Some([0; 16]));
+        assert_eq!(
+            parse_ipv6("fe80::1:2"),
+            Some([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2])
+        );
+        assert_eq!(
+            parse_ipv6("1:2:3:4:5:6:7:ffff"),
+            Some([0, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0, 7, 0xff, 0xff])
+        );
+        assert_eq!(
+            parse_ipv6("1::8"),
+            Some([0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 8])
+        );
+        assert_eq!(parse_ipv6("1:2:3:4:5:6:7"), None);
+        assert_eq!(parse_ipv6("1:2:3:4:5:6:7:8:9"), None);
+        assert_eq!(parse_ipv6("1:2:3:4::5:6:7:8"), None);
+        assert_eq!(parse_ipv6("1::2::3"), None);
+        assert_eq!(parse_ipv6("12345::"), None);
+        assert_eq!(parse_ipv6("g::"), None);
+        assert_eq!(parse_ipv6(":1::"), None);
+        assert_eq!(parse_cidr6("::1/128"), Some((loopback, 128)));
+        assert_eq!(parse_cidr6("::1/129"), None);
+    }
+
+    #[test]
+    fn tz_offsets() {
+        assert_eq!(parse_tz_offset("+10"), Some(600));
+        assert_eq!(parse_tz_offset("10:30"), Some(630));
+        assert_eq!(parse_tz_offset("-03:30"), Some(-210));
+        assert_eq!(parse_tz_offset("+14:00"), Some(840));
+        assert_eq!(parse_tz_offset("+10:60"), None);
+        assert_eq!(parse_tz_offset(""), None);
+    }
+}
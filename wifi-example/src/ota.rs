@@ -0,0 +1,427 @@
+//! Over-the-air updates. An image sent to tcp port 3232 is written to the
+//! dfu slot, then the board resets for the bootloader to swap it in. See
+//! `wifi_protocol::ota` for the protocol, and ../wifi-boot for the slots.
+//!
+//! Updates are authenticated with the `tls_psk` from the network config, and
+//! refused if there isn't one. The header's tag is checked before anything is
+//! written to flash, and the image's before the update is requested, so a
+//! client without the key can neither wear the flash nor disturb an update on
+//! trial, and an image that fails its tag is never booted.
+//!
+//! An update boots on trial, with the watchdog running. It's marked booted
+//! once the network is up; if that doesn't happen in time the board resets,
+//! and the bootloader rolls back to the previous image.
+
+use defmt::*;
+use embedded_io_async::{Error as _, ErrorKind, Read};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use wifi_boot::PICO_W;
+use wifi_protocol::ota::{self, ImageHeader};
+
+pub const PORT: u16 = 3232;
+
+#[derive(Debug)]
+enum UpdateError<E> {
+    Io(ErrorKind),
+    /// The connection closed before the whole image was received
+    Closed,
+    Header(ota::Error),
+    /// No key is configured to check updates with
+    NoKey,
+    /// A tag doesn't match what it covers, or the key is wrong
+    BadTag,
+    Slot(wifi_boot::Error<E>),
+}
+
+impl<E> From<wifi_boot::Error<E>> for UpdateError<E> {
+    fn from(e: wifi_boot::Error<E>) -> Self {
+        UpdateError::Slot(e)
+    }
+}
+
+impl<E> UpdateError<E> {
+    /// For the reply to the client
+    fn reason(&self) -> &'static str {
+        match self {
+            UpdateError::Io(_) => "connection error",
+            UpdateError::Closed => "image truncated",
+            UpdateError::Header(ota::Error::BadMagic) => "bad header",
+            UpdateError::Header(ota::Error::Unsupported) => "unsupported header",
+            UpdateError::NoKey => "updates need tls_psk configured",
+            UpdateError::BadTag => "authentication failed",
+            UpdateError::Slot(wifi_boot::Error::Flash(_)) => "flash error",
+            UpdateError::Slot(wifi_boot::Error::TooLarge) => "image too large",
+            UpdateError::Slot(wifi_boot::Error::BadImage) => "checksum mismatch",
+            UpdateError::Slot(wifi_boot::Error::Unconfirmed) => "running image not yet confirmed",
+        }
+    }
+}
+
+/// Read at most `buf.len()` bytes, but at least one
+async fn read<S: Read, E>(socket: &mut S, buf: &mut [u8]) -> Result<usize, UpdateError<E>> {
+    match socket.read(buf).await {
+        Ok(0) => Err(UpdateError::Closed),
+        Ok(n) => Ok(n),
+        Err(e) => Err(UpdateError::Io(e.kind())),
+    }
+}
+
+async fn read_exact<S: Read, E>(socket: &mut S, buf: &mut [u8]) -> Result<(), UpdateError<E>> {
+    let mut len = 0;
+    while len < buf.len() {
+        len += read(socket, &mut buf[len..]).await?;
+    }
+    Ok(())
+}
+
+/// Receive an update sent in answer to `challenge`, and if it's
+/// authenticated with `key`, write it to the dfu slot and request that it be
+/// swapped in
+async fn receive<S: Read, F: wifi_boot::Flash>(
+    socket: &mut S,
+    flash: &mut F,
+    key: &[u8],
+    challenge: &[u8; ota::CHALLENGE_LEN],
+    buf: &mut [u8],
+) -> Result<(), UpdateError<F::Error>> {
+    // Any length of key will do
+    let mut mac = unwrap!(<Hmac<Sha256> as Mac>::new_from_slice(key).ok());
+    mac.update(challenge);
+    let mut header = [0; ota::HEADER_LEN];
+    read_exact(socket, &mut header).await?;
+    mac.update(&header);
+    let mut tag = [0; ota::TAG_LEN];
+    read_exact(socket, &mut tag).await?;
+    mac.clone().verify_slice(&tag).map_err(|_| UpdateError::BadTag)?;
+    let header = ImageHeader::decode(&header).map_err(UpdateError::Header)?;
+    log_info!("OTA receiving {} bytes", header.length);
+
+    wifi_boot::begin_update(flash, &PICO_W, header.length)?;
+    let mut offset = 0;
+    while offset < header.length {
+        let want = buf.len().min((header.length - offset) as usize);
+        let n = read(socket, &mut buf[..want]).await?;
+        mac.update(&buf[..n]);
+        wifi_boot::write_update(flash, &PICO_W, offset, &buf[..n])?;
+        offset += n as u32;
+    }
+    read_exact(socket, &mut tag).await?;
+    mac.verify_slice(&tag).map_err(|_| UpdateError::BadTag)?;
+    wifi_boot::finish_update(flash, &PICO_W, &header)?;
+    Ok(())
+}
+
+#[cfg(target_os = "none")]
+pub use board::{boot_status, ota_task, watchdog_task};
+
+/// The flash, the watchdog, and the task taking updates over the network
+#[cfg(target_os = "none")]
+mod board {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use defmt::*;
+    use embassy_net::tcp::TcpSocket;
+    use embassy_rp::flash::{self, Blocking, Flash};
+    use embassy_rp::pac;
+    use embassy_rp::peripherals::FLASH;
+    use embassy_time::{Duration, Instant, Timer};
+    use embedded_io_async::Write;
+    use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+    use heapless::{String, Vec};
+    use ufmt::uwrite;
+    use wifi_boot::{BootStatus, State, FLASH_BASE, PICO_W, STATE_LEN};
+    use wifi_protocol::ota;
+
+    use super::{receive, UpdateError, PORT};
+    use crate::entropy;
+    use crate::netconfig::MAX_TLS_PSK;
+    use crate::NetStack;
+
+    const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+    pub type UpdateFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+
+    /// How long a trial update has to get the network up
+    const TRIAL_TIMEOUT: Duration = Duration::from_secs(120);
+
+    /// Matches the bootloader's watchdog timeout
+    const WATCHDOG_US: u32 = 8_000_000;
+
+    const FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Set once a trial update has been marked booted
+    static CONFIRMED: AtomicBool = AtomicBool::new(false);
+
+    /// The flash, as the update slots see it
+    struct SlotFlash<'a>(&'a mut UpdateFlash);
+
+    impl<'a> wifi_boot::Flash for SlotFlash<'a> {
+        type Error = flash::Error;
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), flash::Error> {
+            ReadNorFlash::read(self.0, offset, buf)
+        }
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), flash::Error> {
+            NorFlash::erase(self.0, from, to)
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), flash::Error> {
+            NorFlash::write(self.0, offset, data)
+        }
+    }
+
+    /// How the running image came to be running
+    pub fn boot_status() -> BootStatus {
+        let sector = unsafe { core::slice::from_raw_parts((FLASH_BASE + PICO_W.state) as *const u8, STATE_LEN) };
+        State::parse(sector, &PICO_W).status()
+    }
+
+    /// Keep the watchdog started by the bootloader fed while an update is on
+    /// trial. If it isn't marked booted in time, reset to roll it back.
+    #[embassy_executor::task]
+    pub async fn watchdog_task() -> ! {
+        let deadline = Instant::now() + TRIAL_TIMEOUT;
+        loop {
+            if !CONFIRMED.load(Ordering::Relaxed) && Instant::now() > deadline {
+                warn!("update not marked booted in time, rolling back");
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+            // The counter goes down by two each tick (RP2040-E1)
+            pac::WATCHDOG.load().write(|w| w.set_load(WATCHDOG_US * 2));
+            Timer::after(FEED_INTERVAL).await;
+        }
+    }
+
+    /// Mark a trial update booted, then take updates authenticated with `key`
+    #[embassy_executor::task]
+    pub async fn ota_task(stack: &'static NetStack, mut flash: UpdateFlash, key: Option<Vec<u8, MAX_TLS_PSK>>) -> ! {
+        let mut flash = SlotFlash(&mut flash);
+        match boot_status() {
+            BootStatus::Trial => match wifi_boot::mark_booted(&mut flash, &PICO_W) {
+                Ok(()) => {
+                    CONFIRMED.store(true, Ordering::Relaxed);
+                    log_info!("update marked booted");
+                }
+                Err(e) => log_error!("couldn't mark update booted: {}", UpdateError::Slot(e).reason()),
+            },
+            BootStatus::RolledBack => log_warn!("running the previous image, the last update failed"),
+            BootStatus::Normal => {}
+        }
+        if key.is_none() {
+            log_warn!("no tls_psk configured, OTA updates are refused");
+        }
+
+        let mut rx_buffer = [0; 2048];
+        let mut tx_buffer = [0; 64];
+        let mut buf = [0; 1024];
+
+        loop {
+            let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+            socket.set_timeout(Some(Duration::from_secs(10)));
+
+            info!("OTA listening on TCP:{}...", PORT);
+            if let Err(e) = socket.accept(PORT).await {
+                warn!("OTA accept error: {:?}", e);
+                continue;
+            }
+            info!("OTA update from {:?}", socket.remote_endpoint());
+
+            let mut challenge = [0; ota::CHALLENGE_LEN];
+            entropy::fill_bytes(&mut challenge);
+            if let Err(e) = socket.write_all(&challenge).await {
+                warn!("OTA write error: {:?}", e);
+                continue;
+            }
+            let result = match &key {
+                Some(key) => receive(&mut socket, &mut flash, key, &challenge, &mut buf).await,
+                None => Err(UpdateError::NoKey),
+            };
+            let mut reply = String::<64>::new();
+            match &result {
+                Ok(()) => uwrite!(reply, "ok\n"),
+                Err(e) => {
+                    warn!("OTA update failed: {:?}", Debug2Format(e));
+                    uwrite!(reply, "error: {}\n", e.reason())
+                }
+            }
+            .ok();
+            socket.write_all(reply.as_bytes()).await.ok();
+            socket.flush().await.ok();
+            socket.close();
+
+            if result.is_ok() {
+                log_warn!("rebooting into the update");
+                // Give the stack a chance to send the reply, and the log message
+                Timer::after(Duration::from_millis(200)).await;
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+    use std::vec::Vec;
+
+    use embedded_io_async::{ErrorType, Read};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use wifi_boot::{State, PAGE_SIZE, PICO_W, STATE_LEN};
+    use wifi_protocol::ota::{self, ImageHeader};
+
+    use super::{receive, UpdateError};
+    use crate::host::run_test;
+
+    const KEY: &[u8] = b"0123456789abcdef";
+    const CHALLENGE: [u8; ota::CHALLENGE_LEN] = [7; ota::CHALLENGE_LEN];
+
+    /// NOR flash in memory, recording what's erased and written
+    struct RamFlash {
+        data: Vec<u8>,
+        changed: Vec<(u32, u32)>,
+    }
+
+    impl RamFlash {
+        fn new() -> RamFlash {
+            RamFlash {
+                data: vec![0xff; (PICO_W.dfu + PICO_W.slot_size) as usize],
+                changed: Vec::new(),
+            }
+        }
+
+        /// Whether anything between `from` and `to` was erased or written
+        fn changed(&self, from: u32, to: u32) -> bool {
+            self.changed.iter().any(|&(start, end)| start < to && from < end)
+        }
+
+        fn state(&self) -> State {
+            let at = PICO_W.state as usize;
+            State::parse(&self.data[at..at + STATE_LEN], &PICO_W)
+        }
+    }
+
+    impl wifi_boot::Flash for RamFlash {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ()> {
+            buf.copy_from_slice(&self.data[offset as usize..offset as usize + buf.len()]);
+            Ok(())
+        }
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), ()> {
+            self.changed.push((from, to));
+            self.data[from as usize..to as usize].fill(0xff);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+            self.changed.push((offset, offset + data.len() as u32));
+            for (byte, new) in self.data[offset as usize..].iter_mut().zip(data) {
+                *byte &= new;
+            }
+            Ok(())
+        }
+    }
+
+    /// What the client sends, read in pieces of up to 100 bytes
+    struct Client(Vec<u8>);
+
+    impl ErrorType for Client {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for Client {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let n = buf.len().min(self.0.len()).min(100);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0.drain(..n);
+            Ok(n)
+        }
+    }
+
+    /// An update of `image` as a client with `key` sends it, in answer to
+    /// `challenge`
+    fn upload(key: &[u8], challenge: &[u8], image: &[u8]) -> Vec<u8> {
+        let header = ImageHeader::for_image(image).encode();
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap();
+        mac.update(challenge);
+        mac.update(&header);
+        let mut sent = header.to_vec();
+        sent.extend_from_slice(&mac.clone().finalize().into_bytes());
+        mac.update(image);
+        sent.extend_from_slice(image);
+        sent.extend_from_slice(&mac.finalize().into_bytes());
+        sent
+    }
+
+    fn image() -> Vec<u8> {
+        (0..3000u32).map(|i| (i * 7) as u8).collect()
+    }
+
+    async fn send(flash: &mut RamFlash, sent: Vec<u8>) -> Result<(), UpdateError<()>> {
+        let mut buf = [0; 256];
+        receive(&mut Client(sent), flash, KEY, &CHALLENGE, &mut buf).await
+    }
+
+    #[test]
+    fn requests_authenticated_updates() {
+        run_test(|| async {
+            let mut flash = RamFlash::new();
+            let image = image();
+            send(&mut flash, upload(KEY, &CHALLENGE, &image)).await.unwrap();
+            let dfu = PICO_W.dfu as usize;
+            assert_eq!(flash.data[dfu..dfu + image.len()], image[..]);
+            assert_eq!(flash.state().update, Some(ImageHeader::for_image(&image)));
+        });
+    }
+
+    #[test]
+    fn leaves_flash_alone_for_bad_headers() {
+        run_test(|| async {
+            let image = image();
+            // Wrong key, an answer to another challenge, a changed header
+            let mut changed = upload(KEY, &CHALLENGE, &image);
+            changed[4] ^= 1;
+            for sent in [
+                upload(b"not the key", &CHALLENGE, &image),
+                upload(KEY, &[8; ota::CHALLENGE_LEN], &image),
+                changed,
+            ] {
+                let mut flash = RamFlash::new();
+                assert!(matches!(send(&mut flash, sent).await, Err(UpdateError::BadTag)));
+                assert_eq!(flash.changed, []);
+            }
+
+            let mut flash = RamFlash::new();
+            let mut sent = upload(KEY, &CHALLENGE, &image);
+            sent.truncate(ota::HEADER_LEN + 10);
+            assert!(matches!(send(&mut flash, sent).await, Err(UpdateError::Closed)));
+            assert_eq!(flash.changed, []);
+        });
+    }
+
+    #[test]
+    fn leaves_the_state_alone_for_bad_images() {
+        run_test(|| async {
+            let image = image();
+            let mut sent = upload(KEY, &CHALLENGE, &image);
+            let last = sent.len() - 1;
+            sent[last] ^= 1;
+            let mut flash = RamFlash::new();
+            assert!(matches!(send(&mut flash, sent).await, Err(UpdateError::BadTag)));
+            assert!(flash.changed(PICO_W.dfu, PICO_W.dfu + PAGE_SIZE));
+            assert!(!flash.changed(PICO_W.state, PICO_W.state + PAGE_SIZE));
+            assert_eq!(flash.state().update, None);
+
+            // Or cut short
+            let mut sent = upload(KEY, &CHALLENGE, &image);
+            sent.truncate(sent.len() - 1);
+            let mut flash = RamFlash::new();
+            assert!(matches!(send(&mut flash, sent).await, Err(UpdateError::Closed)));
+            assert!(!flash.changed(PICO_W.state, PICO_W.state + PAGE_SIZE));
+        });
+    }
+}
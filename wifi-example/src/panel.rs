@@ -0,0 +1,201 @@
+//! The ILI9341 display panel, on SPI1 with the touch controller, drawn on by
+//! its driver and read back for the mirror.
+
+use core::cell::RefCell;
+
+use display_interface_spi::SPIInterface;
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::{peripherals, spi};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+use ili9341::{Ili9341, Orientation};
+use static_cell::StaticCell;
+
+use crate::display::Display;
+use crate::mirror::ReadPixels;
+use crate::touch::Touch;
+
+pub type SpiBus = spi::Spi<'static, peripherals::SPI1, spi::Blocking>;
+
+/// The display and the touch controller share SPI1, with separate chip selects
+pub type SharedSpiBus = Mutex<ThreadModeRawMutex, RefCell<SpiBus>>;
+
+pub const DISPLAY_SPI_FREQUENCY: u32 = 32_000_000;
+
+/// The panel's reads are much slower than its writes
+const DISPLAY_READ_FREQUENCY: u32 = 6_000_000;
+
+/// ILI9341 commands used for reading back the panel's memory
+const CASET: u8 = 0x2a;
+const PASET: u8 = 0x2b;
+const RAMRD: u8 = 0x2e;
+
+type Driver = Ili9341<SPIInterface<SharedSpi, SharedPin, SharedPin>, Output<'static>>;
+
+/// The ILI9341, drawn on by its driver and read back directly
+pub struct Panel {
+    driver: Driver,
+    bus: &'static SharedSpiBus,
+    dc: SharedPin,
+    cs: SharedPin,
+}
+
+static SPI_BUS: StaticCell<SharedSpiBus> = StaticCell::new();
+
+static DC: StaticCell<PinCell> = StaticCell::new();
+static CS: StaticCell<PinCell> = StaticCell::new();
+
+pub fn init(
+    miso: peripherals::PIN_12,
+    mosi: peripherals::PIN_11,
+    clk: peripherals::PIN_10,
+    cs: peripherals::PIN_13,
+    reset: peripherals::PIN_14,
+    dc: peripherals::PIN_15,
+    touch_cs: peripherals::PIN_9,
+    spi: peripherals::SPI1,
+) -> (Display, Touch) {
+    let cs = SharedPin(CS.init(Mutex::new(RefCell::new(Output::new(cs, Level::High)))));
+    let reset = Output::new(reset, Level::Low);
+    let dc = SharedPin(DC.init(Mutex::new(RefCell::new(Output::new(dc, Level::Low)))));
+    let touch_cs = Output::new(touch_cs, Level::High);
+
+    let bus: &'static SharedSpiBus = {
+        let mut config = spi::Config::default();
+        config.frequency = DISPLAY_SPI_FREQUENCY;
+        config.polarity = spi::Polarity::IdleLow;
+        config.phase = spi::Phase::CaptureOnFirstTransition;
+        let spi = spi::Spi::new_blocking(spi, clk, mosi, miso, config);
+        SPI_BUS.init(Mutex::new(RefCell::new(spi)))
+    };
+
+    let mut delay = embassy_time::Delay {};
+    let driver = Ili9341::new(
+        SPIInterface::new(SharedSpi { bus }, dc, cs),
+        reset,
+        &mut delay,
+        Orientation::LandscapeFlipped,
+        ili9341::DisplaySize240x320,
+    )
+    .unwrap();
+    let panel = Panel { driver, bus, dc, cs };
+
+    (Display::new(panel), Touch::new(bus, touch_cs))
+}
+
+/// The display's handle on the shared SPI bus
+pub struct SharedSpi {
+    bus: &'static SharedSpiBus,
+}
+
+impl embedded_hal_02::blocking::spi::Write<u8> for SharedSpi {
+    type Error = spi::Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.bus.lock(|bus| bus.borrow_mut().blocking_write(words))
+    }
+}
+
+type PinCell = Mutex<ThreadModeRawMutex, RefCell<Output<'static>>>;
+
+/// One of the panel's control pins, shared by its driver and the reads
+#[derive(Clone, Copy)]
+pub struct SharedPin(&'static PinCell);
+
+impl SharedPin {
+    fn set(&self, high: bool) {
+        self.0.lock(|pin| pin.borrow_mut().set_level(high.into()))
+    }
+}
+
+impl embedded_hal_02::digital::v2::OutputPin for SharedPin {
+    type Error = core::convert::Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set(true);
+        Ok(())
+    }
+}
+
+impl OriginDimensions for Panel {
+    fn size(&self) -> Size {
+        self.driver.size()
+    }
+}
+
+impl DrawTarget for Panel {
+    type Color = Rgb565;
+    type Error = display_interface::DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.driver.draw_iter(pixels)
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        self.driver.fill_contiguous(area, colors)
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.driver.fill_solid(area, color)
+    }
+}
+
+impl ReadPixels for Panel {
+    type Error = spi::Error;
+
+    fn read_pixels(&mut self, area: &Rectangle, out: &mut [Rgb565]) -> Result<(), Self::Error> {
+        let (dc, cs) = (self.dc, self.cs);
+        self.bus.lock(|bus| {
+            let mut bus = bus.borrow_mut();
+            cs.set(false);
+            let result = read_memory(&mut bus, dc, area, out);
+            cs.set(true);
+            bus.set_frequency(DISPLAY_SPI_FREQUENCY);
+            result
+        })
+    }
+}
+
+/// Read `area` of the panel's memory, with the panel selected
+fn read_memory(bus: &mut SpiBus, dc: SharedPin, area: &Rectangle, out: &mut [Rgb565]) -> Result<(), spi::Error> {
+    let Some(bottom_right) = area.bottom_right() else {
+        return Ok(());
+    };
+    let mut command = |command: u8, args: &[u8]| {
+        dc.set(false);
+        bus.blocking_write(&[command])?;
+        dc.set(true);
+        bus.blocking_write(args)
+    };
+    let window = |start: i32, end: i32| {
+        let (start, end) = ((start as u16).to_be_bytes(), (end as u16).to_be_bytes());
+        [start[0], start[1], end[0], end[1]]
+    };
+    command(CASET, &window(area.top_left.x, bottom_right.x))?;
+    command(PASET, &window(area.top_left.y, bottom_right.y))?;
+    command(RAMRD, &[])?;
+
+    // A dummy byte, then each pixel as 6 bits of each of red, green and
+    // blue, at the top of a byte each
+    bus.set_frequency(DISPLAY_READ_FREQUENCY);
+    let mut dummy = [0];
+    bus.blocking_read(&mut dummy)?;
+    for pixel in out.iter_mut() {
+        let mut rgb = [0; 3];
+        bus.blocking_read(&mut rgb)?;
+        *pixel = Rgb565::new(rgb[0] >> 3, rgb[1] >> 2, rgb[2] >> 3);
+    }
+    Ok(())
+}
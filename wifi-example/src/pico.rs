@@ -0,0 +1,273 @@
+//! Bringing up the pico w: the display and its touch screen, the button, and
+//! the wifi chip, which is joined to a network before the services start.
+
+use core::cell::RefCell;
+
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use cyw43_pio::PioSpi;
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_rp::peripherals::{DMA_CH0, PIO0};
+use embassy_rp::pio::Pio;
+use embassy_rp::{adc, bind_interrupts, pio};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use heapless::String;
+use static_cell::StaticCell;
+
+use crate::display::SharedDisplay;
+use crate::link::LinkStats;
+use crate::scan::{self, Networks, ScanEntry, ScanTouch, ScanView, Security};
+use crate::shell::LedMode;
+use crate::touch::{self, TOUCH_EVENTS};
+use crate::{blobs, display_refresh, display_state_update, entropy, mqtt_client, netconfig, ota, panel, telemetry};
+use crate::{Screen, LED_COMMANDS};
+
+bind_interrupts!(struct Irqs {
+    PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
+    ADC_IRQ_FIFO => adc::InterruptHandler;
+});
+
+#[embassy_executor::task]
+async fn wifi_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>) -> ! {
+    runner.run().await
+}
+
+/// How often the wifi link statistics are refreshed
+const LINK_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The pico w's LED is attached to the wifi chip's GPIO 0
+const LED_GPIO: u8 = 0;
+
+/// Own the wifi chip's control interface once joined: keep the link statistics
+/// on the display up to date, and drive the LED.
+#[embassy_executor::task]
+async fn control_task(mut control: cyw43::Control<'static>) -> ! {
+    let mut led = LedMode::Off;
+    let mut led_on = false;
+    let mut next_poll = Instant::now();
+    let mut next_blink = Instant::now();
+    loop {
+        let now = Instant::now();
+        if now >= next_poll {
+            let stats = LinkStats::poll(&mut control).await;
+            stats.record_metrics();
+            display_state_update(|ds| {
+                ds.link = Some(stats);
+                ds.rssi_history.write(stats.rssi.clamp(i8::MIN as i32, i8::MAX as i32) as i8);
+            });
+            next_poll = now + LINK_POLL_INTERVAL;
+        }
+        if let LedMode::Blink { period_ms } = led {
+            if now >= next_blink {
+                led_on = !led_on;
+                control.gpio_set(LED_GPIO, led_on).await;
+                next_blink = now + Duration::from_millis(period_ms as u64 / 2);
+            }
+        }
+
+        let wake = match led {
+            LedMode::Blink { .. } => next_poll.min(next_blink),
+            _ => next_poll,
+        };
+        if let Either::First(mode) = select(LED_COMMANDS.wait(), Timer::at(wake)).await {
+            led = mode;
+            match mode {
+                LedMode::On | LedMode::Off => {
+                    led_on = mode == LedMode::On;
+                    control.gpio_set(LED_GPIO, led_on).await;
+                }
+                LedMode::Blink { .. } => next_blink = Instant::now(),
+            }
+        }
+    }
+}
+
+/// Monitor the button on GP16, which pulls the pin low when pressed
+#[embassy_executor::task]
+async fn button_monitor(mut button: Input<'static>) -> ! {
+    loop {
+        let pressed = button.is_low();
+        display_state_update(|ds| ds.button_pressed = pressed);
+        mqtt_client::publish_event(mqtt_client::Event::Button(pressed));
+        telemetry::publish_button(pressed);
+        button.wait_for_any_edge().await;
+    }
+}
+
+pub async fn main(spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    // An update on trial has to keep the bootloader's watchdog fed
+    if ota::boot_status() == wifi_boot::BootStatus::Trial {
+        unwrap!(spawner.spawn(ota::watchdog_task()));
+    }
+
+    // Keep the display up to date
+    let (display, touch) = panel::init(p.PIN_12, p.PIN_11, p.PIN_10, p.PIN_13, p.PIN_14, p.PIN_15, p.PIN_9, p.SPI1);
+    static DISPLAY: StaticCell<SharedDisplay> = StaticCell::new();
+    let display = &*DISPLAY.init(SharedDisplay::new(RefCell::new(display)));
+    unwrap!(spawner.spawn(display_refresh(display)));
+    unwrap!(spawner.spawn(touch::touch_monitor(touch)));
+    unwrap!(spawner.spawn(button_monitor(Input::new(p.PIN_16, Pull::Up))));
+
+    // The wifi chip's firmware, from its flash partitions
+    let blobs = match blobs::load() {
+        Ok(blobs) => blobs,
+        Err(e) => {
+            error!("can't start wifi: {:?} {:?}", e.which, Debug2Format(&e.error));
+            display_state_update(|ds| ds.screen = Screen::BlobError(e));
+            return;
+        }
+    };
+
+    let pwr = Output::new(p.PIN_23, Level::Low);
+    let cs = Output::new(p.PIN_25, Level::High);
+    let mut pio = Pio::new(p.PIO0, Irqs);
+    let spi = PioSpi::new(&mut pio.common, pio.sm0, pio.irq0, cs, p.PIN_24, p.PIN_29, p.DMA_CH0);
+
+    static STATE: StaticCell<cyw43::State> = StaticCell::new();
+    let state = STATE.init(cyw43::State::new());
+    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, blobs.fw).await;
+
+    unwrap!(spawner.spawn(wifi_task(runner)));
+
+    control.init(blobs.clm).await;
+    control
+        .set_power_management(cyw43::PowerManagementMode::PowerSave)
+        .await;
+
+    let ssid: String<32> = loop {
+        let mut networks = scan::scan(&mut control).await;
+        networks.retain(|n| credentials_for(n).is_some());
+
+        // The configured network is joined unless another is chosen quickly,
+        // otherwise wait a while for a choice before scanning again
+        let configured = networks.iter().find(|n| n.ssid == env!("WIFI_NETWORK")).cloned();
+        let timeout = match configured {
+            Some(_) => CONFIGURED_NETWORK_DELAY,
+            None => RESCAN_INTERVAL,
+        };
+        // A hidden network isn't in the scan, so the configured network is
+        // tried even when it wasn't heard
+        let network = choose_network(&networks, timeout)
+            .await
+            .or(configured)
+            .unwrap_or_else(configured_network);
+
+        let joined = match unwrap!(credentials_for(&network)) {
+            Credentials::Open => control.join_open(&network.ssid).await,
+            Credentials::Password(password) => control.join_wpa2(&network.ssid, password).await,
+        };
+        match joined {
+            Ok(()) => {
+                display_state_update(|ds| ds.join_failed = None);
+                break network.ssid;
+            }
+            Err(_) => {
+                log_warn!("can't join {}", network.ssid.as_str());
+                display_state_update(|ds| ds.join_failed = Some(network.ssid.clone()));
+            }
+        }
+    };
+
+    let net_config = netconfig::load();
+
+    // Generate random seed
+    entropy::init(p.ADC, p.ADC_TEMP_SENSOR, Irqs).await;
+
+    let stack = crate::new_stack(spawner, net_device, &net_config);
+
+    // Neighbour solicitations for the IPv6 address are sent to its
+    // solicited-node multicast group, and router advertisements to all
+    // nodes, which the wifi chip drops unless told. The address from a
+    // router's prefix has the same solicited-node group as the link-local
+    // one.
+    if let Some(config) = stack.config_v6() {
+        let a = config.address.address().0;
+        if control.add_multicast_address([0x33, 0x33, 0xff, a[13], a[14], a[15]]).await.is_err() {
+            warn!("can't join the solicited-node group, IPv6 won't be reachable");
+        }
+    }
+    if control.add_multicast_address([0x33, 0x33, 0, 0, 0, 1]).await.is_err() {
+        warn!("can't join the all-nodes group, router advertisements won't be heard");
+    }
+    unwrap!(spawner.spawn(control_task(control)));
+
+    crate::start_services(spawner, stack, display, ssid, &net_config).await;
+
+    // The network is up, which is enough for a trial update to be kept
+    let ota_key = net_config.tls.as_ref().map(|tls| tls.psk.clone());
+    unwrap!(spawner.spawn(ota::ota_task(stack, embassy_rp::flash::Flash::new_blocking(p.FLASH), ota_key)));
+}
+
+/// How long the network list waits for a choice before joining the
+/// network configured at build time, when it's in range
+const CONFIGURED_NETWORK_DELAY: Duration = Duration::from_secs(3);
+
+/// How long the network list waits for a choice before scanning again, when
+/// the configured network isn't in range
+const RESCAN_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Show the scanned networks, and let the user pick one by touch, giving up
+/// after `timeout` without a touch. Each touch restarts the timeout.
+async fn choose_network(networks: &Networks, timeout: Duration) -> Option<ScanEntry> {
+    let mut view = ScanView::default();
+    display_state_update(|ds| {
+        ds.screen = Screen::Scan;
+        ds.networks = networks.clone();
+        ds.scan_view = view;
+    });
+
+    loop {
+        let p = match with_timeout(timeout, TOUCH_EVENTS.receive()).await {
+            Ok(p) => p,
+            Err(_) => {
+                info!("no network chosen");
+                return None;
+            }
+        };
+        match view.hit_test(networks, p) {
+            Some(ScanTouch::ScrollUp) => view.scroll_up(),
+            Some(ScanTouch::ScrollDown) => view.scroll_down(networks),
+            Some(ScanTouch::Select(i)) => {
+                info!("chose {}", networks[i].ssid.as_str());
+                return Some(networks[i].clone());
+            }
+            None => continue,
+        }
+        display_state_update(|ds| ds.scan_view = view);
+    }
+}
+
+/// The network configured at build time, as though it had been scanned
+fn configured_network() -> ScanEntry {
+    ScanEntry {
+        ssid: unwrap!(String::try_from(env!("WIFI_NETWORK")).ok()),
+        channel: 0,
+        rssi: 0,
+        security: Security::Secured,
+    }
+}
+
+/// How to join a network
+enum Credentials {
+    Open,
+    Password(&'static str),
+}
+
+/// How to join a scanned network, or None if it can't be. There's no
+/// on-screen keyboard yet, so the only secured network that can be joined is
+/// the one configured at build time, with its password.
+fn credentials_for(network: &ScanEntry) -> Option<Credentials> {
+    match network.security {
+        Security::Open => Some(Credentials::Open),
+        Security::Secured if network.ssid == env!("WIFI_NETWORK") => Some(Credentials::Password(env!("WIFI_PASSWORD"))),
+        Security::Secured => None,
+    }
+}
+
+/// The board's reset, for the shell's reboot command
+pub fn reboot() -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
@@ -0,0 +1,233 @@
+//! Lets a tcp client draw on the display. See `wifi_protocol::draw` for the
+//! protocol. While a client is connected the status screen is not drawn.
+
+use defmt::*;
+use embassy_net::tcp::TcpSocket;
+use embedded_graphics::mono_font::{MonoFont, MonoTextStyle};
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Circle, PrimitiveStyleBuilder, Rectangle};
+use embedded_graphics::text::{Baseline, Text};
+use embedded_io_async::Write;
+use wifi_protocol::draw::{self, Command, ErrorCode};
+
+use crate::display::{Display, SharedDisplay};
+use crate::{display_state_update, NetStack, Screen};
+
+pub const PORT: u16 = 7001;
+
+/// Fonts, by their id in the protocol
+const FONTS: [&MonoFont<'static>; 7] = [
+    &profont::PROFONT_7_POINT,
+    &profont::PROFONT_9_POINT,
+    &profont::PROFONT_10_POINT,
+    &profont::PROFONT_12_POINT,
+    &profont::PROFONT_14_POINT,
+    &profont::PROFONT_18_POINT,
+    &profont::PROFONT_24_POINT,
+];
+
+/// Serve one drawing client at a time
+#[embassy_executor::task]
+pub async fn remote_draw_task(stack: &'static NetStack, display: &'static SharedDisplay) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 64];
+    let mut buf = [0u8; draw::HEADER_LEN + draw::MAX_PAYLOAD];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(embassy_time::Duration::from_secs(60)));
+
+        info!("Remote drawing listening on TCP:{}...", PORT);
+        if let Err(e) = socket.accept(PORT).await {
+            warn!("remote draw accept error: {:?}", e);
+            continue;
+        }
+        info!("Remote drawing from {:?}", socket.remote_endpoint());
+        display_state_update(|ds| ds.screen = Screen::Remote);
+
+        let mut len = 0;
+        'connection: loop {
+            let n = match socket.read(&mut buf[len..]).await {
+                Ok(0) => {
+                    warn!("remote draw read EOF");
+                    break;
+                }
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("remote draw read error: {:?}", e);
+                    break;
+                }
+            };
+            len += n;
+
+            let mut used = 0;
+            loop {
+                let (command, command_len) = match draw::decode(&buf[used..len]) {
+                    Ok(Some(decoded)) => decoded,
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("remote draw protocol error: {:?}", Debug2Format(&e));
+                        break 'connection;
+                    }
+                };
+                let code = display.lock(|d| execute(&mut d.borrow_mut(), &command));
+                if let Err(e) = socket.write_all(&[code as u8]).await {
+                    warn!("remote draw write error: {:?}", e);
+                    break 'connection;
+                }
+                used += command_len;
+            }
+            buf.copy_within(used..len, 0);
+            len -= used;
+        }
+
+        socket.close();
+        // Give the display back to the status screen
+        display_state_update(|ds| ds.screen = Screen::Status);
+    }
+}
+
+fn color(c: u16) -> Rgb565 {
+    RawU16::new(c).into()
+}
+
+/// Whether `area` lies wholly on the display
+fn on_display(display: &Display, area: &Rectangle) -> bool {
+    let bounds = display.interface.bounding_box();
+    area.intersection(&bounds) == *area
+}
+
+fn execute(display: &mut Display, command: &Command) -> ErrorCode {
+    match *command {
+        Command::Clear { color: c } => {
+            display.interface.clear(color(c)).ok();
+        }
+        Command::FillRect {
+            x,
+            y,
+            width,
+            height,
+            color: c,
+        } => {
+            let area = Rectangle::new(Point::new(x.into(), y.into()), Size::new(width.into(), height.into()));
+            if !on_display(display, &area) {
+                return ErrorCode::OutOfBounds;
+            }
+            display.interface.fill_solid(&area, color(c)).ok();
+        }
+        Command::Text {
+            x,
+            y,
+            font,
+            color: c,
+            text,
+        } => {
+            let font = match FONTS.get(font as usize) {
+                Some(font) => font,
+                None => return ErrorCode::UnknownFont,
+            };
+            let position = Point::new(x.into(), y.into());
+            if !display.interface.bounding_box().contains(position) {
+                return ErrorCode::OutOfBounds;
+            }
+            let style = MonoTextStyle::new(font, color(c));
+            Text::with_baseline(text, position, style, Baseline::Top)
+                .draw(&mut display.interface)
+                .ok();
+        }
+        Command::Circle {
+            x,
+            y,
+            diameter,
+            stroke_width,
+            stroke,
+            fill,
+        } => {
+            let mut style = PrimitiveStyleBuilder::new();
+            if let Some(c) = stroke {
+                style = style.stroke_color(color(c)).stroke_width(stroke_width.into());
+            }
+            if let Some(c) = fill {
+                style = style.fill_color(color(c));
+            }
+            // The stroke is centred on the circle's edge, so half of it lies outside
+            let circle = Circle::new(Point::new(x.into(), y.into()), diameter.into()).into_styled(style.build());
+            if !on_display(display, &circle.bounding_box()) {
+                return ErrorCode::OutOfBounds;
+            }
+            circle.draw(&mut display.interface).ok();
+        }
+        Command::Blit {
+            x,
+            y,
+            width,
+            height,
+            pixels,
+        } => {
+            let area = Rectangle::new(Point::new(x.into(), y.into()), Size::new(width.into(), height.into()));
+            if !on_display(display, &area) {
+                return ErrorCode::OutOfBounds;
+            }
+            let colors = pixels
+                .chunks_exact(2)
+                .map(|p| color(u16::from_be_bytes([p[0], p[1]])));
+            display.interface.fill_contiguous(&area, colors).ok();
+        }
+    }
+    ErrorCode::Ok
+}
+
+#[cfg(test)]
+mod tests {
+    use wifi_protocol::draw::{Command, ErrorCode};
+
+    use super::execute;
+
+    fn circle(x: i16, diameter: u16, stroke_width: u8) -> Command<'static> {
+        Command::Circle {
+            x,
+            y: 100,
+            diameter,
+            stroke_width,
+            stroke: Some(0xffff),
+            fill: Some(0),
+        }
+    }
+
+    #[test]
+    fn circle_bounds_include_the_stroke() {
+        let mut display = crate::host::display();
+        assert_eq!(execute(&mut display, &circle(0, 20, 0)), ErrorCode::Ok);
+        assert_eq!(execute(&mut display, &circle(300, 20, 1)), ErrorCode::Ok);
+        // Half of a wide stroke lies outside the circle
+        assert_eq!(execute(&mut display, &circle(0, 20, 4)), ErrorCode::OutOfBounds);
+        assert_eq!(execute(&mut display, &circle(2, 20, 4)), ErrorCode::Ok);
+        assert_eq!(execute(&mut display, &circle(298, 20, 4)), ErrorCode::Ok);
+        assert_eq!(execute(&mut display, &circle(299, 20, 4)), ErrorCode::OutOfBounds);
+    }
+
+    #[test]
+    fn rejects_areas_off_the_display() {
+        let mut display = crate::host::display();
+        let blit = |x, width| Command::Blit {
+            x,
+            y: 0,
+            width,
+            height: 1,
+            pixels: &[0; 8][..width as usize * 2],
+        };
+        assert_eq!(execute(&mut display, &blit(316, 4)), ErrorCode::Ok);
+        assert_eq!(execute(&mut display, &blit(317, 4)), ErrorCode::OutOfBounds);
+        assert_eq!(execute(&mut display, &blit(-1, 4)), ErrorCode::OutOfBounds);
+        let text = Command::Text {
+            x: 0,
+            y: 0,
+            font: 7,
+            color: 0,
+            text: "hi",
+        };
+        assert_eq!(execute(&mut display, &text), ErrorCode::UnknownFont);
+    }
+}
@@ -0,0 +1,110 @@
+//! Posts the status as json to a collector, if one is configured, at a
+//! regular interval.
+
+use defmt::*;
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_time::{Duration, Timer};
+use embedded_io_async::Write;
+use heapless::String;
+use ufmt::uwrite;
+
+use crate::dns::{self, ConnectError};
+use crate::netconfig::ReportConfig;
+use crate::shell::Board;
+use crate::shell_server::RemoteBoard;
+use crate::status;
+use crate::NetStack;
+
+const BUFFER_SIZE: usize = 512;
+
+const MAX_BODY: usize = 512;
+
+#[derive(Debug, Format)]
+enum ReportError {
+    Connect(ConnectError),
+    Tcp(tcp::Error),
+    Format,
+    /// The collector closed the connection without a response
+    NoResponse,
+    BadResponse,
+}
+
+impl From<tcp::Error> for ReportError {
+    fn from(e: tcp::Error) -> Self {
+        ReportError::Tcp(e)
+    }
+}
+
+/// Post a status report every interval
+#[embassy_executor::task]
+pub async fn report_task(stack: &'static NetStack, config: ReportConfig) -> ! {
+    let mut rx_buffer = [0; BUFFER_SIZE];
+    let mut tx_buffer = [0; BUFFER_SIZE];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
+        match post(stack, &mut socket, &config).await {
+            Ok(code) if (200..300).contains(&code) => debug!("status reported to {}", config.host),
+            Ok(code) => log_warn!("status report to {} got {}", config.host.as_str(), code),
+            Err(e) => warn!("status report to {} failed: {:?}", config.host, e),
+        }
+        socket.abort();
+        Timer::after(Duration::from_secs(config.interval_secs as u64)).await;
+    }
+}
+
+/// Post one report, returning the response's status code
+async fn post(
+    stack: &'static NetStack,
+    socket: &mut TcpSocket<'_>,
+    config: &ReportConfig,
+) -> Result<u16, ReportError> {
+    let mut body = String::<MAX_BODY>::new();
+    status::write_json(&RemoteBoard.status(), &mut body).map_err(|_| ReportError::Format)?;
+
+    let mut head = String::<256>::new();
+    uwrite!(head, "POST {} HTTP/1.1\r\nHost: {}", config.path.as_str(), config.host.as_str())
+        .map_err(|_| ReportError::Format)?;
+    if config.port != 80 {
+        uwrite!(head, ":{}", config.port).map_err(|_| ReportError::Format)?;
+    }
+    uwrite!(
+        head,
+        "\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )
+    .map_err(|_| ReportError::Format)?;
+
+    dns::connect(stack, socket, &config.host, config.port)
+        .await
+        .map_err(ReportError::Connect)?;
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.flush().await?;
+
+    // Only the status line is of interest
+    let mut buf = [0; 64];
+    let mut len = 0;
+    while len < buf.len() && !buf[..len].contains(&b'\n') {
+        match socket.read(&mut buf[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    if len == 0 {
+        return Err(ReportError::NoResponse);
+    }
+    parse_status_code(&buf[..len]).ok_or(ReportError::BadResponse)
+}
+
+/// The code from an http status line, eg `HTTP/1.1 200 OK`
+fn parse_status_code(response: &[u8]) -> Option<u16> {
+    let line = response.split(|b| *b == b'\n').next()?;
+    let line = core::str::from_utf8(line).ok()?;
+    let mut parts = line.split(' ');
+    if !parts.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    parts.next()?.trim().parse().ok()
+}
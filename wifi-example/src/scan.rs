@@ -0,0 +1,232 @@
+//! Scanning for wifi networks, and a touch driven list for choosing one.
+
+use defmt::*;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyleBuilder, Rectangle, Triangle};
+use embedded_graphics::text::Text;
+use heapless::{String, Vec};
+use ufmt::uwrite;
+
+use crate::display::Display;
+
+pub const MAX_NETWORKS: usize = 16;
+
+/// Rows of the list shown on the display at once
+pub const VISIBLE_ROWS: usize = 10;
+
+const ROW_HEIGHT: i32 = 20;
+const LIST_TOP: i32 = 20;
+const SCROLL_BAR_LEFT: i32 = 280;
+
+/// 802.11 capability flag indicating that the network requires encryption
+const CAPABILITY_PRIVACY: u16 = 0x0010;
+
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum Security {
+    Open,
+    Secured,
+}
+
+#[derive(Clone)]
+pub struct ScanEntry {
+    pub ssid: String<32>,
+    pub channel: u8,
+    pub rssi: i16,
+    pub security: Security,
+}
+
+pub type Networks = Vec<ScanEntry, MAX_NETWORKS>;
+
+/// Run a scan, returning the networks heard, strongest first.
+#[cfg(target_os = "none")]
+pub async fn scan(control: &mut cyw43::Control<'_>) -> Networks {
+    let mut networks = Networks::new();
+    let mut scanner = control.scan().await;
+    while let Some(bss) = scanner.next().await {
+        let ssid = &bss.ssid[..(bss.ssid_len as usize).min(bss.ssid.len())];
+        let ssid = match core::str::from_utf8(ssid) {
+            // Hidden networks have an empty ssid, and can't be chosen from a list
+            Ok(ssid) if !ssid.is_empty() => ssid,
+            _ => continue,
+        };
+        let security = if bss.capability & CAPABILITY_PRIVACY != 0 {
+            Security::Secured
+        } else {
+            Security::Open
+        };
+        let entry = ScanEntry {
+            ssid: unwrap!(String::try_from(ssid).ok()),
+            channel: (bss.chanspec & 0xff) as u8,
+            rssi: bss.rssi,
+            security,
+        };
+        debug!(
+            "scan: {} ch {} rssi {} {}",
+            ssid, entry.channel, entry.rssi, entry.security
+        );
+        insert(&mut networks, entry);
+    }
+    log_info!("scan found {} networks", networks.len());
+    networks
+}
+
+/// Add a network to the list, keeping only the strongest report for each ssid,
+/// ordered from strongest to weakest. When the list is full the weakest is dropped.
+pub fn insert(networks: &mut Networks, entry: ScanEntry) {
+    if let Some(i) = networks.iter().position(|n| n.ssid == entry.ssid) {
+        if networks[i].rssi >= entry.rssi {
+            return;
+        }
+        networks.remove(i);
+    }
+    let at = networks
+        .iter()
+        .position(|n| n.rssi < entry.rssi)
+        .unwrap_or(networks.len());
+    if networks.is_full() {
+        if at == networks.len() {
+            return;
+        }
+        networks.pop();
+    }
+    networks.insert(at, entry).ok();
+}
+
+/// What a touch on the scan list means
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum ScanTouch {
+    ScrollUp,
+    ScrollDown,
+    Select(usize),
+}
+
+/// Which part of the network list is on screen
+#[derive(Clone, Copy, Default)]
+pub struct ScanView {
+    pub scroll: usize,
+}
+
+impl ScanView {
+    /// Interpret a touch at display point `p`
+    pub fn hit_test(&self, networks: &Networks, p: Point) -> Option<ScanTouch> {
+        if p.y < LIST_TOP {
+            return None;
+        }
+        if p.x >= SCROLL_BAR_LEFT {
+            let middle = LIST_TOP + ROW_HEIGHT * VISIBLE_ROWS as i32 / 2;
+            return Some(if p.y < middle {
+                ScanTouch::ScrollUp
+            } else {
+                ScanTouch::ScrollDown
+            });
+        }
+        let row = ((p.y - LIST_TOP) / ROW_HEIGHT) as usize;
+        let index = self.scroll + row;
+        if row < VISIBLE_ROWS && index < networks.len() {
+            Some(ScanTouch::Select(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = self.scroll.saturating_sub(VISIBLE_ROWS);
+    }
+
+    pub fn scroll_down(&mut self, networks: &Networks) {
+        let max_scroll = networks.len().saturating_sub(VISIBLE_ROWS);
+        self.scroll = (self.scroll + VISIBLE_ROWS).min(max_scroll);
+    }
+}
+
+/// Draw the network list, headed by the network that couldn't be joined if
+/// there was one. Assumes the display has already been cleared.
+pub fn render(display: &mut Display, networks: &Networks, view: &ScanView, join_failed: Option<&str>) {
+    let mut title = String::<48>::new();
+    match join_failed {
+        Some(ssid) => uwrite!(title, "Couldn't join {}", ssid).unwrap(),
+        None => title.push_str("Choose a network").unwrap(),
+    }
+    Text::with_text_style(
+        &title,
+        Point::new(14, 0),
+        display.styles.char,
+        display.styles.text,
+    )
+    .draw(&mut display.interface)
+    .unwrap();
+
+    if networks.is_empty() {
+        Text::with_text_style(
+            "no networks found",
+            Point::new(14, LIST_TOP),
+            display.styles.char,
+            display.styles.text,
+        )
+        .draw(&mut display.interface)
+        .unwrap();
+    }
+
+    for (row, network) in networks
+        .iter()
+        .skip(view.scroll)
+        .take(VISIBLE_ROWS)
+        .enumerate()
+    {
+        let y = LIST_TOP + row as i32 * ROW_HEIGHT;
+        let mut line = String::<48>::new();
+        let lock = match network.security {
+            Security::Open => " ",
+            Security::Secured => "*",
+        };
+        uwrite!(
+            line,
+            "{}{} ch{} {}",
+            lock,
+            network.rssi,
+            network.channel,
+            network.ssid.as_str()
+        )
+        .unwrap();
+        Text::with_text_style(
+            &line,
+            Point::new(14, y),
+            display.styles.char,
+            display.styles.text,
+        )
+        .draw(&mut display.interface)
+        .unwrap();
+    }
+
+    // Scroll buttons
+    let arrow_style = PrimitiveStyleBuilder::new()
+        .fill_color(Rgb565::CSS_DARK_GRAY)
+        .build();
+    let x = SCROLL_BAR_LEFT + 10;
+    let bottom = LIST_TOP + ROW_HEIGHT * VISIBLE_ROWS as i32;
+    if view.scroll > 0 {
+        Triangle::new(
+            Point::new(x, LIST_TOP + 40),
+            Point::new(x + 30, LIST_TOP + 40),
+            Point::new(x + 15, LIST_TOP + 10),
+        )
+        .into_styled(arrow_style)
+        .draw(&mut display.interface)
+        .unwrap();
+    }
+    if view.scroll + VISIBLE_ROWS < networks.len() {
+        Triangle::new(
+            Point::new(x, bottom - 40),
+            Point::new(x + 30, bottom - 40),
+            Point::new(x + 15, bottom - 10),
+        )
+        .into_styled(arrow_style)
+        .draw(&mut display.interface)
+        .unwrap();
+    }
+    Rectangle::new(Point::new(SCROLL_BAR_LEFT, LIST_TOP), Size::new(1, (bottom - LIST_TOP) as u32))
+        .into_styled(display.styles.white_fill)
+        .draw(&mut display.interface)
+        .unwrap();
+}
@@ -0,0 +1,292 @@
+//! A line based command shell, for driving the board remotely.
+//!
+//! Commands:
+//!
+//! ```text
+//! led on|off|blink <ms>
+//! button?
+//! touch?
+//! display text <row> <msg>
+//! status
+//! reboot
+//! help
+//! ```
+//!
+//! Each command gets one or more lines of response, the last starting with
+//! `ok` or `error:`. Parsing and dispatch don't allocate, and only depend on
+//! the `Board` trait, so they are independent of the hardware. The argument
+//! parsing and line editing are `display-shell`'s, shared with the display
+//! demos' consoles; the commands are the board's own.
+
+use ufmt::{uWrite, uwrite};
+
+use display_shell::{no_more, parse_blink_ms, parse_number, split_word};
+pub use display_shell::{LedMode, ParseError};
+
+use crate::status::{self, Status};
+
+/// Longest accepted command line, excluding the line terminator
+pub const MAX_LINE: usize = 80;
+
+/// Rows available to `display text`
+pub const TEXT_ROWS: usize = 2;
+
+/// Longest accepted `display text` message
+pub const MAX_TEXT: usize = 32;
+
+/// Assembles received bytes into command lines
+pub type LineEditor = display_shell::LineEditor<MAX_LINE>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command<'a> {
+    Led(LedMode),
+    Button,
+    Touch,
+    DisplayText { row: usize, text: &'a str },
+    Status,
+    Reboot,
+    Help,
+}
+
+/// Parse a single command line, without its line terminator
+pub fn parse(line: &str) -> Result<Command<'_>, ParseError> {
+    let line = line.trim();
+    let (word, rest) = split_word(line);
+    let command = match word {
+        "" => return Err(ParseError::Empty),
+        "led" => Command::Led(parse_led(rest)?),
+        "button?" => no_more(rest, Command::Button)?,
+        "touch?" => no_more(rest, Command::Touch)?,
+        "display" => {
+            let (what, rest) = split_word(rest);
+            match what {
+                "text" => {
+                    let (row, text) = parse_display_text(rest)?;
+                    Command::DisplayText { row, text }
+                }
+                "" => return Err(ParseError::MissingArgument),
+                _ => return Err(ParseError::BadArgument),
+            }
+        }
+        "status" => no_more(rest, Command::Status)?,
+        "reboot" => no_more(rest, Command::Reboot)?,
+        "help" => no_more(rest, Command::Help)?,
+        _ => return Err(ParseError::UnknownCommand),
+    };
+    Ok(command)
+}
+
+/// Parse the arguments of the led command: `on`, `off` or `blink <ms>`
+pub fn parse_led(args: &str) -> Result<LedMode, ParseError> {
+    let (mode, rest) = split_word(args);
+    let mode = match mode {
+        "on" => LedMode::On,
+        "off" => LedMode::Off,
+        "blink" => {
+            let (period, rest) = split_word(rest);
+            let period_ms = parse_blink_ms(period)?;
+            return no_more(rest, LedMode::Blink { period_ms });
+        }
+        "" => return Err(ParseError::MissingArgument),
+        _ => return Err(ParseError::BadArgument),
+    };
+    no_more(rest, mode)
+}
+
+/// Parse the arguments of the display text command: `<row> <msg>`
+pub fn parse_display_text(args: &str) -> Result<(usize, &str), ParseError> {
+    let (row, text) = split_word(args);
+    let row = parse_number(row)? as usize;
+    if row >= TEXT_ROWS || text.len() > MAX_TEXT {
+        return Err(ParseError::BadArgument);
+    }
+    Ok((row, text))
+}
+
+/// What the shell can do to the board
+pub trait Board {
+    fn set_led(&mut self, mode: LedMode);
+    fn button_pressed(&self) -> bool;
+    /// The most recently touched point
+    fn last_touch(&self) -> Option<(i32, i32)>;
+    fn display_text(&mut self, row: usize, text: &str);
+    fn status(&self) -> Status;
+}
+
+/// What the connection should do after a command
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Continue,
+    /// The response should be flushed, and then the board rebooted
+    Reboot,
+}
+
+/// Parse and run a command line, writing the response to `out`
+pub fn run<B: Board, W: uWrite>(board: &mut B, line: &str, out: &mut W) -> Result<Outcome, W::Error> {
+    let command = match parse(line) {
+        Ok(command) => command,
+        Err(ParseError::Empty) => return Ok(Outcome::Continue),
+        Err(e) => {
+            uwrite!(out, "error: {}\r\n", e.message())?;
+            return Ok(Outcome::Continue);
+        }
+    };
+
+    match command {
+        Command::Led(mode) => board.set_led(mode),
+        Command::Button => {
+            let state = if board.button_pressed() { "pressed" } else { "released" };
+            uwrite!(out, "button: {}\r\n", state)?;
+        }
+        Command::Touch => match board.last_touch() {
+            Some((x, y)) => uwrite!(out, "touch: {},{}\r\n", x, y)?,
+            None => uwrite!(out, "touch: none\r\n")?,
+        },
+        Command::DisplayText { row, text } => board.display_text(row, text),
+        Command::Status => status::write_text(&board.status(), out)?,
+        Command::Reboot => {
+            uwrite!(out, "ok\r\n")?;
+            return Ok(Outcome::Reboot);
+        }
+        Command::Help => {
+            uwrite!(out, "led on|off|blink <ms>\r\nbutton?\r\ntouch?\r\n")?;
+            uwrite!(out, "display text <row> <msg>\r\nstatus\r\nreboot\r\n")?;
+        }
+    }
+    uwrite!(out, "ok\r\n")?;
+    Ok(Outcome::Continue)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use heapless::String;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse("led on"), Ok(Command::Led(LedMode::On)));
+        assert_eq!(parse("  led   off  "), Ok(Command::Led(LedMode::Off)));
+        assert_eq!(parse("led blink 500"), Ok(Command::Led(LedMode::Blink { period_ms: 500 })));
+        assert_eq!(parse("button?"), Ok(Command::Button));
+        assert_eq!(parse("touch?"), Ok(Command::Touch));
+        assert_eq!(
+            parse("display text 1 hello  there"),
+            Ok(Command::DisplayText { row: 1, text: "hello  there" })
+        );
+        assert_eq!(parse("display text 0"), Ok(Command::DisplayText { row: 0, text: "" }));
+        assert_eq!(parse("status"), Ok(Command::Status));
+        assert_eq!(parse("reboot"), Ok(Command::Reboot));
+        assert_eq!(parse("help"), Ok(Command::Help));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert_eq!(parse("   "), Err(ParseError::Empty));
+        assert_eq!(parse("dance"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("LED on"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("led"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("led dim"), Err(ParseError::BadArgument));
+        assert_eq!(parse("led on now"), Err(ParseError::TooManyArguments));
+        assert_eq!(parse("led blink"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("led blink fast"), Err(ParseError::BadArgument));
+        assert_eq!(parse("led blink 19"), Err(ParseError::BadArgument));
+        assert_eq!(parse("led blink 10001"), Err(ParseError::BadArgument));
+        assert_eq!(parse("led blink 20 10"), Err(ParseError::TooManyArguments));
+        assert_eq!(parse("status please"), Err(ParseError::TooManyArguments));
+        assert_eq!(parse("display"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("display image"), Err(ParseError::BadArgument));
+        assert_eq!(parse("display text"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("display text 2 hi"), Err(ParseError::BadArgument));
+        let long = "display text 0 123456789012345678901234567890123";
+        assert_eq!(parse(long), Err(ParseError::BadArgument));
+    }
+
+    #[derive(Default)]
+    pub(crate) struct MockBoard {
+        pub led: Option<LedMode>,
+        pub pressed: bool,
+        pub touch: Option<(i32, i32)>,
+        pub text: Option<(usize, std::string::String)>,
+    }
+
+    impl Board for MockBoard {
+        fn set_led(&mut self, mode: LedMode) {
+            self.led = Some(mode);
+        }
+
+        fn button_pressed(&self) -> bool {
+            self.pressed
+        }
+
+        fn last_touch(&self) -> Option<(i32, i32)> {
+            self.touch
+        }
+
+        fn display_text(&mut self, row: usize, text: &str) {
+            self.text = Some((row, text.into()));
+        }
+
+        fn status(&self) -> Status {
+            Status {
+                ssid: "home".try_into().unwrap(),
+                address: Some(([10, 0, 0, 2], 24)),
+                address6: None,
+                mode: Some(crate::netconfig::ActiveMode::Dhcp),
+                rssi: Some(-50),
+                channel: Some(6),
+                led: self.led.unwrap_or(LedMode::Off),
+                button_pressed: self.pressed,
+                clients: 1,
+                uptime_secs: 42,
+            }
+        }
+    }
+
+    fn run_line(board: &mut MockBoard, line: &str) -> (Outcome, String<512>) {
+        let mut out = String::new();
+        let outcome = run(board, line, &mut out).unwrap();
+        (outcome, out)
+    }
+
+    #[test]
+    fn runs_commands() {
+        let mut board = MockBoard::default();
+        assert_eq!(run_line(&mut board, "led blink 100").1, "ok\r\n");
+        assert_eq!(board.led, Some(LedMode::Blink { period_ms: 100 }));
+
+        board.pressed = true;
+        assert_eq!(run_line(&mut board, "button?").1, "button: pressed\r\nok\r\n");
+
+        assert_eq!(run_line(&mut board, "touch?").1, "touch: none\r\nok\r\n");
+        board.touch = Some((12, -3));
+        assert_eq!(run_line(&mut board, "touch?").1, "touch: 12,-3\r\nok\r\n");
+
+        assert_eq!(run_line(&mut board, "display text 1 hi").1, "ok\r\n");
+        assert_eq!(board.text, Some((1, "hi".into())));
+
+        assert_eq!(
+            run_line(&mut board, "status").1,
+            "ssid: home\r\naddress: 10.0.0.2/24 (DHCP)\r\nrssi: -50 dBm\r\nchannel: 6\r\n\
+             led: blink\r\nbutton: pressed\r\nclients: 1\r\nuptime: 42s\r\nok\r\n"
+        );
+        assert!(run_line(&mut board, "help").1.ends_with("reboot\r\nok\r\n"));
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut board = MockBoard::default();
+        assert_eq!(run_line(&mut board, ""), (Outcome::Continue, String::new()));
+        assert_eq!(
+            run_line(&mut board, "dance").1,
+            "error: unknown command, try help\r\n"
+        );
+        assert_eq!(run_line(&mut board, "led").1, "error: missing argument\r\n");
+        assert_eq!(board.led, None);
+    }
+
+    #[test]
+    fn reboot_flushes_ok_first() {
+        let mut board = MockBoard::default();
+        assert_eq!(run_line(&mut board, "reboot"), (Outcome::Reboot, "ok\r\n".try_into().unwrap()));
+    }
+}
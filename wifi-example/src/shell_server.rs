@@ -0,0 +1,149 @@
+//! Serves the command shell over tcp.
+
+use defmt::*;
+use embassy_net::tcp::TcpSocket;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{Error, Read, Write};
+use heapless::String;
+use ufmt::uwrite;
+
+use display_shell::Input;
+
+use crate::shell::{self, Board, LedMode, LineEditor, Outcome};
+use crate::status::Status;
+use crate::telemetry;
+use crate::{display_state_read, display_state_update, set_peer, NetStack, LED_COMMANDS};
+
+pub const PORT: u16 = 1234;
+
+/// The number of shell clients that can be served at once, each by its own task.
+/// Must match the `pool_size` of `shell_task`.
+pub const MAX_CONNECTIONS: usize = 2;
+
+const BUFFER_SIZE: usize = 1024;
+
+/// Longest response to a single command
+const MAX_RESPONSE: usize = 512;
+
+/// Serve one shell client at a time, recording the client in `slot` of the
+/// displayed peer list.
+#[embassy_executor::task(pool_size = 2)]
+pub async fn shell_task(stack: &'static NetStack, slot: usize) -> ! {
+    let mut rx_buffer = [0; BUFFER_SIZE];
+    let mut tx_buffer = [0; BUFFER_SIZE];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        // Shell sessions are interactive, so allow for some thinking time
+        socket.set_timeout(Some(embassy_time::Duration::from_secs(300)));
+
+        info!("[{}] Shell listening on TCP:{}...", slot, PORT);
+        if let Err(e) = socket.accept(PORT).await {
+            warn!("[{}] accept error: {:?}", slot, e);
+            continue;
+        }
+
+        info!("[{}] Shell connection from {:?}", slot, socket.remote_endpoint());
+        set_peer(slot, socket.remote_endpoint());
+
+        let reboot = serve(&mut socket, slot).await;
+
+        if reboot {
+            socket.flush().await.ok();
+            socket.close();
+            // Give the stack a chance to send the response
+            Timer::after(Duration::from_millis(200)).await;
+            warn!("rebooting at the request of {:?}", socket.remote_endpoint());
+            crate::reboot();
+        }
+
+        set_peer(slot, None);
+    }
+}
+
+/// Run shell commands from `conn` until it's closed, returning whether a
+/// reboot was requested. Shared by the plain and TLS shells.
+pub async fn serve<C: Read + Write>(conn: &mut C, slot: usize) -> bool {
+    let mut buf = [0; 128];
+    let mut editor = LineEditor::new();
+    let mut board = RemoteBoard;
+    loop {
+        let n = match conn.read(&mut buf).await {
+            Ok(0) => {
+                warn!("[{}] read EOF", slot);
+                return false;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                warn!("[{}] read error: {:?}", slot, e.kind());
+                return false;
+            }
+        };
+
+        for &b in &buf[..n] {
+            let mut response = String::<MAX_RESPONSE>::new();
+            // The client's terminal does its own echoing and line editing
+            let outcome = match editor.push(b) {
+                Input::None | Input::Char(_) | Input::Erase => continue,
+                Input::Line(line) => {
+                    log_info!("[{}] shell: {}", slot, line);
+                    // The response is truncated if it doesn't fit
+                    shell::run(&mut board, line, &mut response).unwrap_or(Outcome::Continue)
+                }
+                Input::Invalid => {
+                    uwrite!(response, "error: invalid line\r\n").ok();
+                    Outcome::Continue
+                }
+            };
+
+            if let Err(e) = conn.write_all(response.as_bytes()).await {
+                warn!("[{}] write error: {:?}", slot, e.kind());
+                return false;
+            }
+            if outcome == Outcome::Reboot {
+                return true;
+            }
+        }
+    }
+}
+
+/// The board, as seen by remote clients
+pub struct RemoteBoard;
+
+impl Board for RemoteBoard {
+    fn set_led(&mut self, mode: LedMode) {
+        LED_COMMANDS.signal(mode);
+        display_state_update(|ds| ds.led = mode);
+        telemetry::publish_led(mode);
+    }
+
+    fn button_pressed(&self) -> bool {
+        display_state_read(|ds| ds.button_pressed)
+    }
+
+    fn last_touch(&self) -> Option<(i32, i32)> {
+        display_state_read(|ds| ds.last_touch.map(|p| (p.x, p.y)))
+    }
+
+    fn display_text(&mut self, row: usize, text: &str) {
+        display_state_update(|ds| {
+            ds.text_rows[row].clear();
+            ds.text_rows[row].push_str(text).ok();
+        });
+    }
+
+    fn status(&self) -> Status {
+        display_state_read(|ds| Status {
+            ssid: ds.ssid.clone(),
+            address: ds.address.map(|a| (a.address().0, a.prefix_len())),
+            address6: ds.address6.map(|a| (a.address().0, a.prefix_len())),
+            mode: ds.net_mode,
+            rssi: ds.link.map(|l| l.rssi),
+            channel: ds.link.map(|l| l.channel),
+            led: ds.led,
+            button_pressed: ds.button_pressed,
+            clients: ds.peers.iter().flatten().count(),
+            uptime_secs: Instant::now().as_secs(),
+        })
+    }
+}
@@ -0,0 +1,481 @@
+//! Stateless address autoconfiguration (RFC 4862): a global IPv6 address
+//! made from the prefix in a router advertisement and the MAC address, with
+//! the advertising router as the gateway.
+//!
+//! embassy-net has no raw sockets, so the advertisements are picked out of
+//! the received frames by `RouterWatch`, which wraps the network device, and
+//! router solicitations are sent through it the same way. `slaac_task` turns
+//! what's advertised into the stack's IPv6 configuration, and goes back to
+//! the link-local address if the prefix runs out.
+//!
+//! Only /64 prefixes that allow autonomous configuration are used, and only
+//! the first in an advertisement. There's room for one IPv6 address, so the
+//! global address replaces the link-local one. Duplicate address detection
+//! isn't done: the interface identifier comes from the MAC address, which
+//! should be unique on the link.
+
+use core::sync::atomic::Ordering;
+use core::task::Context;
+
+use defmt::info;
+use embassy_futures::select::{select, Either};
+use embassy_net::{ConfigV6, Ipv6Address, Ipv6Cidr, StaticConfigV6};
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+use portable_atomic::AtomicBool;
+
+use crate::NetStack;
+
+/// Solicitations sent before waiting for the routers' periodic
+/// advertisements, and the time between them (RFC 4861 section 10)
+const MAX_RTR_SOLICITATIONS: u32 = 3;
+const RTR_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV6_HEADER_LEN: usize = 40;
+const NEXT_HEADER_ICMPV6: u8 = 58;
+const ICMPV6_ROUTER_SOLICIT: u8 = 133;
+const ICMPV6_ROUTER_ADVERT: u8 = 134;
+/// Neighbour discovery messages from off the link have a lower hop limit
+const NDISC_HOP_LIMIT: u8 = 255;
+
+const OPTION_SOURCE_LINK_ADDRESS: u8 = 1;
+const OPTION_PREFIX_INFO: u8 = 3;
+const PREFIX_AUTONOMOUS: u8 = 0x40;
+
+/// The all-routers group, ff02::2
+const ALL_ROUTERS: [u8; 16] = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02];
+
+/// A lifetime of all ones doesn't run out
+const INFINITE: u32 = 0xffff_ffff;
+
+/// A prefix for autoconfiguration, from a router advertisement
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Prefix {
+    /// The first half of the address, as only /64 prefixes are used
+    prefix: [u8; 8],
+    /// Seconds the prefix can be used for
+    valid_lifetime: u32,
+}
+
+/// What's used from a router advertisement
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Advert {
+    /// The router's link-local address
+    router: [u8; 16],
+    /// Seconds the router can be used as a default router for, or 0 if it
+    /// shouldn't be
+    router_lifetime: u16,
+    prefix: Option<Prefix>,
+}
+
+/// Advertisements seen by `RouterWatch`, the latest only
+static ADVERTS: Signal<CriticalSectionRawMutex, Advert> = Signal::new();
+
+/// Set when a router solicitation is to be sent
+static SOLICIT: AtomicBool = AtomicBool::new(false);
+
+/// The stack's waker, so it sends a solicitation when one's asked for
+static STACK_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Ask `RouterWatch` to send a router solicitation
+fn solicit() {
+    SOLICIT.store(true, Ordering::Relaxed);
+    STACK_WAKER.wake();
+}
+
+/// Wraps the network device, to pick out router advertisements as frames are
+/// received and send router solicitations when asked
+pub struct RouterWatch<D> {
+    inner: D,
+}
+
+impl<D: Driver> RouterWatch<D> {
+    pub fn new(inner: D) -> RouterWatch<D> {
+        RouterWatch { inner }
+    }
+
+    fn mac(&self) -> [u8; 6] {
+        match self.inner.hardware_address() {
+            HardwareAddress::Ethernet(mac) => mac,
+            _ => [0; 6],
+        }
+    }
+}
+
+impl<D: Driver> Driver for RouterWatch<D> {
+    type RxToken<'a> = RxToken<D::RxToken<'a>> where Self: 'a;
+    type TxToken<'a> = D::TxToken<'a> where Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        // The stack polls for received frames whenever it's woken, which is
+        // the chance to send a solicitation
+        STACK_WAKER.register(cx.waker());
+        if SOLICIT.swap(false, Ordering::Relaxed) {
+            let mac = self.mac();
+            match self.inner.transmit(cx) {
+                Some(tx) => embassy_net_driver::TxToken::consume(tx, SOLICITATION_LEN, |buf| {
+                    router_solicitation(mac, buf.try_into().unwrap())
+                }),
+                // Try again when there's room
+                None => SOLICIT.store(true, Ordering::Relaxed),
+            }
+        }
+        let (rx, tx) = self.inner.receive(cx)?;
+        Some((RxToken(rx), tx))
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        self.inner.transmit(cx)
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken<T>(T);
+
+impl<T: embassy_net_driver::RxToken> embassy_net_driver::RxToken for RxToken<T> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.0.consume(|frame| {
+            if let Some(advert) = parse_advert(frame) {
+                ADVERTS.signal(advert);
+            }
+            f(frame)
+        })
+    }
+}
+
+/// Keep the stack's IPv6 address configured from the routers'
+/// advertisements, soliciting them when there's no prefix
+#[embassy_executor::task]
+pub async fn slaac_task(stack: &'static NetStack) -> ! {
+    let mac = crate::mac_address(stack);
+    // When the address from the current prefix runs out
+    let mut expires: Option<Instant> = None;
+    let mut solicitations = 0;
+    loop {
+        let mut wake = expires.unwrap_or(Instant::MAX);
+        if expires.is_none() && solicitations < MAX_RTR_SOLICITATIONS {
+            solicit();
+            solicitations += 1;
+            wake = Instant::now() + RTR_SOLICITATION_INTERVAL;
+        }
+
+        match select(ADVERTS.wait(), Timer::at(wake)).await {
+            Either::First(advert) => {
+                let prefix = match advert.prefix {
+                    Some(prefix) => prefix,
+                    None => continue,
+                };
+                expires = match prefix.valid_lifetime {
+                    INFINITE => Some(Instant::MAX),
+                    secs => Some(Instant::now() + Duration::from_secs(secs as u64)),
+                };
+                set_config(stack, slaac_config(&advert, &prefix, mac));
+            }
+            Either::Second(()) => {
+                if expires.is_some_and(|at| at <= Instant::now()) {
+                    log_warn!("IPv6 prefix expired");
+                    expires = None;
+                    solicitations = 0;
+                    set_config(stack, crate::static_config_v6(None, mac));
+                }
+            }
+        }
+    }
+}
+
+/// The configuration for the address made from an advertised prefix
+fn slaac_config(advert: &Advert, prefix: &Prefix, mac: [u8; 6]) -> StaticConfigV6 {
+    let mut address = [0; 16];
+    address[..8].copy_from_slice(&prefix.prefix);
+    address[8..].copy_from_slice(&crate::interface_id(mac));
+    StaticConfigV6 {
+        address: Ipv6Cidr::new(Ipv6Address(address), 64),
+        gateway: (advert.router_lifetime != 0).then_some(Ipv6Address(advert.router)),
+        dns_servers: Vec::new(),
+    }
+}
+
+/// Reconfigure the stack, if `config` is any different
+fn set_config(stack: &NetStack, config: StaticConfigV6) {
+    if stack.config_v6().as_ref() == Some(&config) {
+        return;
+    }
+    info!("IPv6 address {}, gateway {:?}", config.address, config.gateway);
+    crate::display_state_update(|ds| ds.address6 = Some(config.address));
+    stack.set_config_v6(ConfigV6::Static(config));
+}
+
+/// Pick out a router advertisement from an ethernet frame, checked as RFC
+/// 4861 section 6.1.2 says
+fn parse_advert(frame: &[u8]) -> Option<Advert> {
+    let ethertype = u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+    if ethertype != ETHERTYPE_IPV6 {
+        return None;
+    }
+    let ip = &frame[ETHERNET_HEADER_LEN..];
+    if ip.len() < IPV6_HEADER_LEN || ip[0] >> 4 != 6 || ip[6] != NEXT_HEADER_ICMPV6 || ip[7] != NDISC_HOP_LIMIT {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+    let icmp = ip.get(IPV6_HEADER_LEN..IPV6_HEADER_LEN + payload_len)?;
+    let source: [u8; 16] = ip[8..24].try_into().unwrap();
+    let destination: [u8; 16] = ip[24..40].try_into().unwrap();
+
+    // Type, code, checksum, hop limit, flags, router lifetime, reachable
+    // time and retransmit timer
+    if icmp.len() < 16 || icmp[0] != ICMPV6_ROUTER_ADVERT || icmp[1] != 0 {
+        return None;
+    }
+    if source[..2] != [0xfe, 0x80] || icmpv6_checksum(&source, &destination, icmp) != 0 {
+        return None;
+    }
+
+    let mut prefix = None;
+    let mut options = &icmp[16..];
+    while !options.is_empty() {
+        let len = *options.get(1)? as usize * 8;
+        if len == 0 {
+            return None;
+        }
+        let option = options.get(..len)?;
+        if option[0] == OPTION_PREFIX_INFO && len == 32 && prefix.is_none() {
+            // Prefix length, flags, valid and preferred lifetimes, reserved,
+            // then the prefix
+            let valid_lifetime = u32::from_be_bytes(option[4..8].try_into().unwrap());
+            let usable = option[2] == 64
+                && option[3] & PREFIX_AUTONOMOUS != 0
+                && valid_lifetime != 0
+                && option[16..18] != [0xfe, 0x80];
+            if usable {
+                prefix = Some(Prefix {
+                    prefix: option[16..24].try_into().unwrap(),
+                    valid_lifetime,
+                });
+            }
+        }
+        options = &options[len..];
+    }
+
+    Some(Advert {
+        router: source,
+        router_lifetime: u16::from_be_bytes([icmp[6], icmp[7]]),
+        prefix,
+    })
+}
+
+/// A router solicitation's length: the ethernet and IPv6 headers, the
+/// solicitation, and its source link-layer address option
+const SOLICITATION_LEN: usize = ETHERNET_HEADER_LEN + IPV6_HEADER_LEN + 16;
+
+/// Write a router solicitation from `mac`, to the all-routers group
+fn router_solicitation(mac: [u8; 6], frame: &mut [u8; SOLICITATION_LEN]) {
+    let source = crate::link_local_address(mac).0;
+    let icmp_len = SOLICITATION_LEN - ETHERNET_HEADER_LEN - IPV6_HEADER_LEN;
+
+    let (ethernet, rest) = frame.split_at_mut(ETHERNET_HEADER_LEN);
+    // The multicast MAC address for the group
+    ethernet[..6].copy_from_slice(&[0x33, 0x33, 0, 0, 0, ALL_ROUTERS[15]]);
+    ethernet[6..12].copy_from_slice(&mac);
+    ethernet[12..].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+
+    let (ip, icmp) = rest.split_at_mut(IPV6_HEADER_LEN);
+    ip[..4].copy_from_slice(&[0x60, 0, 0, 0]);
+    ip[4..6].copy_from_slice(&(icmp_len as u16).to_be_bytes());
+    ip[6] = NEXT_HEADER_ICMPV6;
+    ip[7] = NDISC_HOP_LIMIT;
+    ip[8..24].copy_from_slice(&source);
+    ip[24..].copy_from_slice(&ALL_ROUTERS);
+
+    // Type, code, checksum, reserved, then the option
+    icmp.fill(0);
+    icmp[0] = ICMPV6_ROUTER_SOLICIT;
+    icmp[8] = OPTION_SOURCE_LINK_ADDRESS;
+    icmp[9] = 1;
+    icmp[10..].copy_from_slice(&mac);
+    let checksum = icmpv6_checksum(&source, &ALL_ROUTERS, icmp);
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// The internet checksum of an ICMPv6 message and its pseudo-header, which
+/// is zero for a message with the right checksum in it
+fn icmpv6_checksum(source: &[u8; 16], destination: &[u8; 16], icmp: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    let mut add = |data: &[u8]| {
+        for pair in data.chunks(2) {
+            sum += u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]) as u32;
+        }
+    };
+    add(source);
+    add(destination);
+    add(&(icmp.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, NEXT_HEADER_ICMPV6]);
+    add(icmp);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::{icmpv6_checksum, parse_advert, router_solicitation, slaac_config, Advert, Prefix, SOLICITATION_LEN};
+
+    const MAC: [u8; 6] = [0x28, 0xcd, 0xc1, 0x01, 0x02, 0x03];
+    const ROUTER: [u8; 16] = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    const ALL_NODES: [u8; 16] = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+    /// A router advertisement from `ROUTER` with `options`, as a frame
+    fn advert_frame(router_lifetime: u16, options: &[u8]) -> Vec<u8> {
+        let mut icmp = std::vec![134, 0, 0, 0, 64, 0];
+        icmp.extend_from_slice(&router_lifetime.to_be_bytes());
+        icmp.extend_from_slice(&[0; 8]);
+        icmp.extend_from_slice(options);
+        let checksum = icmpv6_checksum(&ROUTER, &ALL_NODES, &icmp);
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut frame = std::vec![0x33, 0x33, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 1, 0x86, 0xdd];
+        frame.extend_from_slice(&[0x60, 0, 0, 0]);
+        frame.extend_from_slice(&(icmp.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[58, 255]);
+        frame.extend_from_slice(&ROUTER);
+        frame.extend_from_slice(&ALL_NODES);
+        frame.extend_from_slice(&icmp);
+        frame
+    }
+
+    /// A prefix information option
+    fn prefix_info(prefix_len: u8, flags: u8, valid_lifetime: u32, prefix: [u8; 8]) -> Vec<u8> {
+        let mut option = std::vec![3, 4, prefix_len, flags];
+        option.extend_from_slice(&valid_lifetime.to_be_bytes());
+        option.extend_from_slice(&valid_lifetime.to_be_bytes());
+        option.extend_from_slice(&[0; 4]);
+        option.extend_from_slice(&prefix);
+        option.extend_from_slice(&[0; 8]);
+        option
+    }
+
+    const PREFIX: [u8; 8] = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0x01];
+
+    #[test]
+    fn takes_prefixes_from_adverts() {
+        // The source link-layer address option is skipped
+        let mut options = std::vec![1, 1, 0x02, 0, 0, 0, 0, 1];
+        options.extend(prefix_info(64, 0xc0, 3600, PREFIX));
+        let prefix = Prefix {
+            prefix: PREFIX,
+            valid_lifetime: 3600,
+        };
+        let advert = parse_advert(&advert_frame(1800, &options)).unwrap();
+        assert_eq!(
+            advert,
+            Advert {
+                router: ROUTER,
+                router_lifetime: 1800,
+                prefix: Some(prefix),
+            }
+        );
+
+        let config = slaac_config(&advert, &prefix, MAC);
+        assert_eq!(
+            config.address.address().0,
+            [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0x01, 0x2a, 0xcd, 0xc1, 0xff, 0xfe, 0x01, 0x02, 0x03]
+        );
+        assert_eq!(config.address.prefix_len(), 64);
+        assert_eq!(config.gateway.map(|g| g.0), Some(ROUTER));
+
+        // A router that isn't a default router
+        let advert = parse_advert(&advert_frame(0, &options)).unwrap();
+        assert_eq!(slaac_config(&advert, &prefix, MAC).gateway, None);
+    }
+
+    #[test]
+    fn ignores_unusable_prefixes() {
+        for option in [
+            // Not autonomous
+            prefix_info(64, 0x80, 3600, PREFIX),
+            // Not a /64
+            prefix_info(48, 0xc0, 3600, PREFIX),
+            // Withdrawn
+            prefix_info(64, 0xc0, 0, PREFIX),
+            // Link-local
+            prefix_info(64, 0xc0, 3600, [0xfe, 0x80, 0, 0, 0, 0, 0, 0]),
+        ] {
+            assert_eq!(parse_advert(&advert_frame(1800, &option)).unwrap().prefix, None);
+        }
+
+        // Only the first usable prefix is taken
+        let mut options = prefix_info(64, 0x80, 3600, [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0x02]);
+        options.extend(prefix_info(64, 0xc0, 3600, PREFIX));
+        options.extend(prefix_info(64, 0xc0, 3600, [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0x03]));
+        assert_eq!(parse_advert(&advert_frame(1800, &options)).unwrap().prefix.unwrap().prefix, PREFIX);
+    }
+
+    #[test]
+    fn rejects_bad_adverts() {
+        let options = prefix_info(64, 0xc0, 3600, PREFIX);
+        let good = advert_frame(1800, &options);
+        assert!(parse_advert(&good).is_some());
+
+        // Forwarded by a router
+        let mut frame = good.clone();
+        frame[21] = 254;
+        assert_eq!(parse_advert(&frame), None);
+        // A bad checksum
+        let mut frame = good.clone();
+        *frame.last_mut().unwrap() ^= 1;
+        assert_eq!(parse_advert(&frame), None);
+        // Truncated
+        assert_eq!(parse_advert(&good[..good.len() - 1]), None);
+        // A zero length option, which would never end
+        let mut frame = advert_frame(1800, &[3, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(parse_advert(&frame), None);
+        // Not an advertisement
+        frame = advert_frame(1800, &options);
+        frame[54] = 135;
+        assert_eq!(parse_advert(&frame), None);
+        // Not IPv6
+        assert_eq!(parse_advert(&[0xff; 60]), None);
+    }
+
+    #[test]
+    fn solicits_adverts() {
+        let mut frame = [0; SOLICITATION_LEN];
+        router_solicitation(MAC, &mut frame);
+        let link_local = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0x2a, 0xcd, 0xc1, 0xff, 0xfe, 0x01, 0x02, 0x03];
+        let all_routers = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+
+        assert_eq!(frame[..14], [0x33, 0x33, 0, 0, 0, 2, 0x28, 0xcd, 0xc1, 0x01, 0x02, 0x03, 0x86, 0xdd]);
+        assert_eq!(frame[14..22], [0x60, 0, 0, 0, 0, 16, 58, 255]);
+        assert_eq!(frame[22..38], link_local);
+        assert_eq!(frame[38..54], all_routers);
+        let icmp = &frame[54..];
+        assert_eq!(icmp[..2], [133, 0]);
+        assert_eq!(icmp[4..], [0, 0, 0, 0, 1, 1, 0x28, 0xcd, 0xc1, 0x01, 0x02, 0x03]);
+        assert_eq!(icmpv6_checksum(&link_local, &all_routers, icmp), 0);
+    }
+}
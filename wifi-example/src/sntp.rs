@@ -0,0 +1,182 @@
+//! SNTP (RFC 4330) packets: building a client request, and checking and
+//! decoding the server's reply. Kept free of networking.
+
+use defmt::Format;
+
+pub const PORT: u16 = 123;
+
+pub const PACKET_LEN: usize = 48;
+
+/// Seconds from the NTP epoch (1900) to the unix epoch (1970)
+const UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+/// Leap indicator value meaning the server's clock isn't synchronised
+const LEAP_ALARM: u8 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Error {
+    BadLength,
+    /// Not a server reply
+    BadMode,
+    /// The reply isn't to our request
+    WrongOrigin,
+    /// The server isn't synchronised, or asked us to go away
+    Unsynchronised,
+}
+
+/// A 64 bit NTP timestamp: seconds since 1900 and a binary fraction
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    /// Microseconds since the unix epoch
+    pub fn unix_micros(&self) -> i64 {
+        let secs = (self.0 >> 32) as i64 - UNIX_OFFSET_SECS as i64;
+        let fraction = self.0 & 0xffff_ffff;
+        secs * 1_000_000 + ((fraction * 1_000_000) >> 32) as i64
+    }
+}
+
+/// The times a server received our request and sent its reply
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct Reply {
+    pub receive: Timestamp,
+    pub transmit: Timestamp,
+}
+
+/// Build a request. `transmit` is echoed back by the server, which lets the
+/// reply be matched to the request; it needn't be a real time.
+pub fn encode_request(transmit: Timestamp) -> [u8; PACKET_LEN] {
+    let mut packet = [0u8; PACKET_LEN];
+    packet[0] = VERSION << 3 | MODE_CLIENT;
+    packet[40..48].copy_from_slice(&transmit.0.to_be_bytes());
+    packet
+}
+
+/// Decode the reply to a request sent with `transmit`
+pub fn decode_reply(packet: &[u8], transmit: Timestamp) -> Result<Reply, Error> {
+    if packet.len() < PACKET_LEN {
+        return Err(Error::BadLength);
+    }
+    let leap = packet[0] >> 6;
+    let mode = packet[0] & 0x07;
+    let stratum = packet[1];
+    if mode != MODE_SERVER {
+        return Err(Error::BadMode);
+    }
+    let timestamp = |at: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&packet[at..at + 8]);
+        Timestamp(u64::from_be_bytes(bytes))
+    };
+    if timestamp(24) != transmit {
+        return Err(Error::WrongOrigin);
+    }
+    // Stratum 0 is a "kiss of death"
+    if leap == LEAP_ALARM || stratum == 0 {
+        return Err(Error::Unsynchronised);
+    }
+    let reply = Reply {
+        receive: timestamp(32),
+        transmit: timestamp(40),
+    };
+    if reply.transmit.0 == 0 {
+        return Err(Error::Unsynchronised);
+    }
+    Ok(reply)
+}
+
+/// The offset to add to the local clock to get unix time, in microseconds,
+/// given when the request was sent and the reply received by the local clock.
+pub fn clock_offset(reply: &Reply, sent_micros: i64, received_micros: i64) -> i64 {
+    ((reply.receive.unix_micros() - sent_micros) + (reply.transmit.unix_micros() - received_micros)) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-03-01 12:34:56.5 UTC
+    const NOON: Timestamp = Timestamp((1_709_296_496 + UNIX_OFFSET_SECS) << 32 | 0x8000_0000);
+
+    fn reply(transmit: Timestamp) -> [u8; PACKET_LEN] {
+        let mut packet = [0; PACKET_LEN];
+        packet[0] = VERSION << 3 | MODE_SERVER;
+        packet[1] = 2;
+        packet[24..32].copy_from_slice(&transmit.0.to_be_bytes());
+        packet[32..40].copy_from_slice(&NOON.0.to_be_bytes());
+        packet[40..48].copy_from_slice(&(NOON.0 + (1 << 32)).to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn encodes_requests() {
+        let packet = encode_request(Timestamp(0x0102_0304_0506_0708));
+        assert_eq!(packet[0], 0x23);
+        assert!(packet[1..40].iter().all(|b| *b == 0));
+        assert_eq!(packet[40..], [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn converts_to_unix_time() {
+        assert_eq!(NOON.unix_micros(), 1_709_296_496_500_000);
+        assert_eq!(Timestamp(UNIX_OFFSET_SECS << 32).unix_micros(), 0);
+        // Before 1970
+        assert_eq!(Timestamp((UNIX_OFFSET_SECS - 1) << 32).unix_micros(), -1_000_000);
+    }
+
+    #[test]
+    fn decodes_replies() {
+        let transmit = Timestamp(1234);
+        let expected = Reply {
+            receive: NOON,
+            transmit: Timestamp(NOON.0 + (1 << 32)),
+        };
+        assert_eq!(decode_reply(&reply(transmit), transmit), Ok(expected));
+        // A longer packet carries extensions, which are ignored
+        let mut long = [0; 64];
+        long[..PACKET_LEN].copy_from_slice(&reply(transmit));
+        assert_eq!(decode_reply(&long, transmit), Ok(expected));
+    }
+
+    #[test]
+    fn rejects_bad_replies() {
+        let transmit = Timestamp(1234);
+        assert_eq!(decode_reply(&reply(transmit)[..47], transmit), Err(Error::BadLength));
+        assert_eq!(decode_reply(&reply(transmit), Timestamp(1235)), Err(Error::WrongOrigin));
+
+        let mut client = reply(transmit);
+        client[0] = VERSION << 3 | MODE_CLIENT;
+        assert_eq!(decode_reply(&client, transmit), Err(Error::BadMode));
+
+        let mut alarm = reply(transmit);
+        alarm[0] |= LEAP_ALARM << 6;
+        assert_eq!(decode_reply(&alarm, transmit), Err(Error::Unsynchronised));
+
+        let mut kiss_of_death = reply(transmit);
+        kiss_of_death[1] = 0;
+        assert_eq!(decode_reply(&kiss_of_death, transmit), Err(Error::Unsynchronised));
+
+        let mut no_time = reply(transmit);
+        no_time[40..48].fill(0);
+        assert_eq!(decode_reply(&no_time, transmit), Err(Error::Unsynchronised));
+    }
+
+    #[test]
+    fn finds_the_clock_offset() {
+        let reply = Reply {
+            receive: NOON,
+            transmit: Timestamp(NOON.0 + (1 << 32)),
+        };
+        let at_noon = NOON.unix_micros();
+        // A clock 1000s behind, with 20ms each way and 1s at the server
+        let sent = at_noon - 1_000_000_000 - 20_000;
+        let received = sent + 20_000 + 1_000_000 + 20_000;
+        assert_eq!(clock_offset(&reply, sent, received), 1_000_000_000);
+        // A clock further behind
+        assert_eq!(clock_offset(&reply, sent - 10_000, received - 10_000), 1_000_010_000);
+    }
+}
@@ -0,0 +1,217 @@
+//! A snapshot of the board's status, and its text and json renderings.
+
+use heapless::String;
+use ufmt::{uWrite, uwrite};
+
+use crate::netconfig::{ActiveMode, Ipv4, Ipv6};
+use crate::shell::LedMode;
+
+#[derive(Clone)]
+pub struct Status {
+    pub ssid: String<32>,
+    pub address: Option<(Ipv4, u8)>,
+    pub address6: Option<(Ipv6, u8)>,
+    pub mode: Option<ActiveMode>,
+    pub rssi: Option<i32>,
+    pub channel: Option<u32>,
+    pub led: LedMode,
+    pub button_pressed: bool,
+    pub clients: usize,
+    pub uptime_secs: u64,
+}
+
+fn write_address<W: uWrite + ?Sized>(out: &mut W, address: &Ipv4) -> Result<(), W::Error> {
+    uwrite!(out, "{}.{}.{}.{}", address[0], address[1], address[2], address[3])
+}
+
+fn write_address6<W: uWrite + ?Sized>(out: &mut W, address: &Ipv6) -> Result<(), W::Error> {
+    crate::write_ipv6(out, embassy_net::Ipv6Address(*address))
+}
+
+/// Write the status as lines of `key: value`, each terminated with "\r\n"
+pub fn write_text<W: uWrite + ?Sized>(status: &Status, out: &mut W) -> Result<(), W::Error> {
+    uwrite!(out, "ssid: {}\r\n", status.ssid.as_str())?;
+    uwrite!(out, "address: ")?;
+    match (&status.address, status.mode) {
+        (Some((address, prefix_len)), Some(mode)) => {
+            write_address(out, address)?;
+            uwrite!(out, "/{} ({})\r\n", prefix_len, mode.label())?;
+        }
+        _ => uwrite!(out, "none\r\n")?,
+    }
+    if let Some((address, prefix_len)) = &status.address6 {
+        uwrite!(out, "address6: ")?;
+        write_address6(out, address)?;
+        uwrite!(out, "/{}\r\n", prefix_len)?;
+    }
+    if let (Some(rssi), Some(channel)) = (status.rssi, status.channel) {
+        uwrite!(out, "rssi: {} dBm\r\nchannel: {}\r\n", rssi, channel)?;
+    }
+    uwrite!(out, "led: {}\r\n", status.led.label())?;
+    uwrite!(out, "button: {}\r\n", if status.button_pressed { "pressed" } else { "released" })?;
+    uwrite!(out, "clients: {}\r\n", status.clients)?;
+    uwrite!(out, "uptime: {}s\r\n", status.uptime_secs)
+}
+
+/// The longest json rendering of a status: an ssid of control characters,
+/// each escaped to six bytes, and every number at its widest
+pub const MAX_JSON: usize = 461;
+
+/// Write the status as a json object
+pub fn write_json<W: uWrite + ?Sized>(status: &Status, out: &mut W) -> Result<(), W::Error> {
+    out.write_str("{\"ssid\":")?;
+    write_json_str(out, &status.ssid)?;
+    uwrite!(out, ",\"address\":")?;
+    match &status.address {
+        Some((address, prefix_len)) => {
+            uwrite!(out, "\"")?;
+            write_address(out, address)?;
+            uwrite!(out, "/{}\"", prefix_len)?;
+        }
+        None => uwrite!(out, "null")?,
+    }
+    uwrite!(out, ",\"address6\":")?;
+    match &status.address6 {
+        Some((address, prefix_len)) => {
+            uwrite!(out, "\"")?;
+            write_address6(out, address)?;
+            uwrite!(out, "/{}\"", prefix_len)?;
+        }
+        None => uwrite!(out, "null")?,
+    }
+    uwrite!(out, ",\"mode\":")?;
+    match status.mode {
+        Some(mode) => uwrite!(out, "\"{}\"", mode.label())?,
+        None => uwrite!(out, "null")?,
+    }
+    uwrite!(out, ",\"rssi\":")?;
+    match status.rssi {
+        Some(rssi) => uwrite!(out, "{}", rssi)?,
+        None => uwrite!(out, "null")?,
+    }
+    uwrite!(out, ",\"channel\":")?;
+    match status.channel {
+        Some(channel) => uwrite!(out, "{}", channel)?,
+        None => uwrite!(out, "null")?,
+    }
+    uwrite!(out, ",\"led\":\"{}\"", status.led.label())?;
+    if let LedMode::Blink { period_ms } = status.led {
+        uwrite!(out, ",\"blink_ms\":{}", period_ms)?;
+    }
+    uwrite!(
+        out,
+        ",\"button\":{},\"clients\":{},\"uptime\":{}",
+        status.button_pressed,
+        status.clients,
+        status.uptime_secs
+    )?;
+    out.write_str("}")
+}
+
+/// Write a json string literal, escaping as required
+pub fn write_json_str<W: uWrite + ?Sized>(out: &mut W, s: &str) -> Result<(), W::Error> {
+    uwrite!(out, "\"")?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escape = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            c if (c as u32) < 0x20 => "\\u00",
+            _ => continue,
+        };
+        out.write_str(&s[start..i])?;
+        out.write_str(escape)?;
+        if escape == "\\u00" {
+            const HEX: &[u8; 16] = b"0123456789abcdef";
+            let b = c as u8;
+            out.write_char(HEX[(b >> 4) as usize] as char)?;
+            out.write_char(HEX[(b & 0xf) as usize] as char)?;
+        }
+        start = i + c.len_utf8();
+    }
+    out.write_str(&s[start..])?;
+    uwrite!(out, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+
+    use super::{write_json, write_text, Status, MAX_JSON};
+    use crate::netconfig::ActiveMode;
+    use crate::shell::LedMode;
+
+    fn status() -> Status {
+        Status {
+            ssid: "say \"hi\"\n".try_into().unwrap(),
+            address: Some(([10, 0, 0, 2], 24)),
+            address6: Some(([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1], 64)),
+            mode: Some(ActiveMode::Dhcp),
+            rssi: Some(-50),
+            channel: Some(6),
+            led: LedMode::Blink { period_ms: 500 },
+            button_pressed: true,
+            clients: 1,
+            uptime_secs: 42,
+        }
+    }
+
+    #[test]
+    fn writes_json() {
+        let mut out = String::<MAX_JSON>::new();
+        write_json(&status(), &mut out).unwrap();
+        assert_eq!(
+            out,
+            "{\"ssid\":\"say \\\"hi\\\"\\n\",\"address\":\"10.0.0.2/24\",\"address6\":\"fe80::1/64\",\
+             \"mode\":\"DHCP\",\"rssi\":-50,\"channel\":6,\"led\":\"blink\",\"blink_ms\":500,\
+             \"button\":true,\"clients\":1,\"uptime\":42}"
+        );
+
+        let unknown = Status {
+            address: None,
+            address6: None,
+            mode: None,
+            rssi: None,
+            channel: None,
+            led: LedMode::Off,
+            ..status()
+        };
+        out.clear();
+        write_json(&unknown, &mut out).unwrap();
+        assert!(out.contains("\"address\":null,\"address6\":null,\"mode\":null,\"rssi\":null,\"channel\":null,"));
+        assert!(out.contains("\"led\":\"off\",\"button\""));
+    }
+
+    #[test]
+    fn the_longest_json_fits() {
+        let longest = Status {
+            ssid: core::iter::repeat('\x01').take(32).collect(),
+            address: Some(([255; 4], 32)),
+            address6: Some(([0xff; 16], 128)),
+            mode: Some(ActiveMode::Fallback),
+            rssi: Some(i32::MIN),
+            channel: Some(u32::MAX),
+            led: LedMode::Blink { period_ms: u32::MAX },
+            button_pressed: false,
+            clients: usize::MAX,
+            uptime_secs: u64::MAX,
+        };
+        let mut out = String::<MAX_JSON>::new();
+        write_json(&longest, &mut out).unwrap();
+        assert_eq!(out.len(), MAX_JSON);
+    }
+
+    #[test]
+    fn writes_text() {
+        let mut out = String::<512>::new();
+        write_text(&status(), &mut out).unwrap();
+        assert_eq!(
+            out,
+            "ssid: say \"hi\"\n\r\naddress: 10.0.0.2/24 (DHCP)\r\naddress6: fe80::1/64\r\n\
+             rssi: -50 dBm\r\nchannel: 6\r\nled: blink\r\nbutton: pressed\r\nclients: 1\r\nuptime: 42s\r\n"
+        );
+    }
+}
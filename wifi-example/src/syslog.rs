@@ -0,0 +1,327 @@
+//! Forwards log messages to a syslog server over udp (RFC 5424 and 5426), if
+//! one is configured. defmt's output can't be read without the program, so
+//! messages logged with `log_info!`, `log_warn!` and `log_error!` are also
+//! formatted as text, queued here and sent by `syslog_task`.
+//!
+//! Logging can't be allowed to starve the network: messages are sent at a
+//! limited rate, and dropped if they're logged faster than that for long
+//! enough to fill the queue. The number dropped is reported in the next
+//! message that gets through.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// The RP2040 has no atomic read-modify-write instructions
+use portable_atomic::AtomicU32;
+use defmt::*;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::{Deque, String};
+use ufmt::{uWrite, uwrite};
+
+use crate::netconfig::{Ipv4, MAX_HOSTNAME};
+use crate::{clock, NetStack};
+
+pub const DEFAULT_PORT: u16 = 514;
+
+/// Local port the messages are sent from
+const LOCAL_PORT: u16 = 1238;
+
+/// Longest message text, longer ones are truncated
+pub const MAX_MESSAGE: usize = 96;
+
+/// Messages waiting to be sent
+const QUEUE_LEN: usize = 16;
+
+/// Longest datagram: the header, structured data and message
+const MAX_DATAGRAM: usize = 128 + MAX_HOSTNAME + MAX_MESSAGE;
+
+/// Messages are sent at most this often, on average
+const SEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How many messages can be sent at once, after a quiet spell
+const BURST: u32 = 8;
+
+/// Local use 0
+const FACILITY: u8 = 16;
+
+const APP_NAME: &str = "wifi-example";
+
+/// The private enterprise number in the SD-ID of the dropped message count,
+/// RFC 5612's example one until the project registers its own
+const ENTERPRISE_NUMBER: u32 = 32473;
+
+pub type Message = String<MAX_MESSAGE>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Severity {
+    #[cfg_attr(not(target_os = "none"), allow(dead_code))]
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+}
+
+struct Entry {
+    severity: Severity,
+    uptime_ms: u64,
+    /// Microseconds since the unix epoch, if the clock was set
+    unix_micros: Option<i64>,
+    message: Message,
+}
+
+static QUEUE: Mutex<CriticalSectionRawMutex, RefCell<Deque<Entry, QUEUE_LEN>>> =
+    Mutex::new(RefCell::new(Deque::new()));
+
+static QUEUED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Whether a server is configured, so messages are only formatted and queued
+/// when they'll be sent
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Messages dropped since the last one sent
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Queue a message, written by `write`, if a server is configured. Used by
+/// the logging macros.
+pub fn log(severity: Severity, write: impl FnOnce(&mut Message) -> Result<(), ()>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut message = Message::new();
+    // Truncated if it doesn't fit
+    write(&mut message).ok();
+    let entry = Entry {
+        severity,
+        uptime_ms: Instant::now().as_millis(),
+        unix_micros: clock::unix_micros(),
+        message,
+    };
+    QUEUE.lock(|q| enqueue(&mut q.borrow_mut(), entry, &DROPPED));
+    QUEUED.signal(());
+}
+
+/// Queue a message, or count it in `dropped` if the queue's full
+fn enqueue(queue: &mut Deque<Entry, QUEUE_LEN>, entry: Entry, dropped: &AtomicU32) {
+    if queue.push_back(entry).is_err() {
+        dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Send the queued messages to `server`, as from `hostname`
+#[embassy_executor::task]
+pub async fn syslog_task(stack: &'static NetStack, server: (Ipv4, u16), hostname: String<MAX_HOSTNAME>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 0];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2 * MAX_DATAGRAM];
+
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(LOCAL_PORT));
+    let (address, port) = server;
+    let to = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address(address)), port);
+    info!("sending logs to syslog at {:?}", to);
+    ENABLED.store(true, Ordering::Relaxed);
+
+    let mut limit = RateLimit::new(Instant::now());
+    let mut sequence: u32 = 0;
+    loop {
+        let entry = match QUEUE.lock(|q| q.borrow_mut().pop_front()) {
+            Some(entry) => entry,
+            None => {
+                QUEUED.wait().await;
+                continue;
+            }
+        };
+        Timer::at(limit.next_slot(Instant::now())).await;
+
+        // Wraps to 1, as 0 isn't allowed
+        sequence = sequence % 0x7fff_ffff + 1;
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        let mut datagram = String::<MAX_DATAGRAM>::new();
+        // Sized to fit
+        write_message(&mut datagram, &entry, &hostname, sequence, dropped).ok();
+        if let Err(e) = socket.send_to(datagram.as_bytes(), to).await {
+            warn!("syslog send error: {:?}", e);
+            DROPPED.fetch_add(dropped + 1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Spaces messages out to one per `SEND_INTERVAL` on average, allowing up to
+/// `BURST` at once after a quiet spell (a generic cell rate algorithm)
+struct RateLimit {
+    next: Instant,
+}
+
+impl RateLimit {
+    fn new(now: Instant) -> RateLimit {
+        RateLimit { next: now }
+    }
+
+    /// Reserve the next time a message can be sent, given the time now
+    fn next_slot(&mut self, now: Instant) -> Instant {
+        let burst = SEND_INTERVAL * BURST;
+        // After a quiet spell, the whole burst is available again
+        self.next = self.next.max(now) + SEND_INTERVAL;
+        let slot = self.next.checked_sub(burst).unwrap_or(now);
+        slot.max(now)
+    }
+}
+
+/// Write a message in the RFC 5424 format, eg
+///
+/// ```text
+/// <134>1 2026-10-18T09:30:00.250Z pico-demo wifi-example - - [meta sequenceId="7" sysUpTime="1234"] MQTT connected
+/// ```
+///
+/// The time is left out if the clock hasn't been set. Messages dropped
+/// before this one are counted in a `[drops@32473 count="3"]` element.
+fn write_message<W: uWrite>(
+    out: &mut W,
+    entry: &Entry,
+    hostname: &str,
+    sequence: u32,
+    dropped: u32,
+) -> Result<(), W::Error> {
+    uwrite!(out, "<{}>1 ", FACILITY * 8 + entry.severity as u8)?;
+    match entry.unix_micros {
+        Some(micros) => {
+            let secs = micros.div_euclid(1_000_000);
+            let (year, month, day) = clock::date_from_unix(secs);
+            let time = clock::TimeOfDay::from_unix(secs, 0);
+            let millis = micros.rem_euclid(1_000_000) / 1000;
+            uwrite!(out, "{}-", year)?;
+            for (n, separator) in [
+                (month, "-"),
+                (day, "T"),
+                (time.hours, ":"),
+                (time.minutes, ":"),
+                (time.seconds, "."),
+            ] {
+                uwrite!(out, "{}{}{}", if n < 10 { "0" } else { "" }, n, separator)?;
+            }
+            let pad = match millis {
+                0..=9 => "00",
+                10..=99 => "0",
+                _ => "",
+            };
+            uwrite!(out, "{}{}Z", pad, millis as u32)?;
+        }
+        None => uwrite!(out, "-")?,
+    }
+    uwrite!(out, " {} {} - - ", hostname, APP_NAME)?;
+    uwrite!(
+        out,
+        "[meta sequenceId=\"{}\" sysUpTime=\"{}\"",
+        sequence,
+        // In hundredths of a second, as SNMP has it
+        (entry.uptime_ms / 10) as u32
+    )?;
+    if dropped > 0 {
+        uwrite!(out, "][drops@{} count=\"{}\"", ENTERPRISE_NUMBER, dropped)?;
+    }
+    uwrite!(out, "] {}", entry.message.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::Ordering;
+
+    use embassy_time::{Duration, Instant};
+    use heapless::{Deque, String};
+    use portable_atomic::AtomicU32;
+
+    use super::{enqueue, write_message, Entry, Message, RateLimit, Severity, BURST, MAX_DATAGRAM, QUEUE_LEN, SEND_INTERVAL};
+
+    fn entry(severity: Severity, unix_micros: Option<i64>, message: &str) -> Entry {
+        Entry {
+            severity,
+            uptime_ms: 12_345,
+            unix_micros,
+            message: Message::try_from(message).unwrap(),
+        }
+    }
+
+    fn written(entry: &Entry, dropped: u32) -> String<MAX_DATAGRAM> {
+        let mut out = String::new();
+        write_message(&mut out, entry, "pico-demo", 7, dropped).unwrap();
+        out
+    }
+
+    #[test]
+    fn writes_messages() {
+        // 2026-10-18T09:30:00.05Z
+        let entry = entry(Severity::Info, Some(1_792_315_800_050_000), "MQTT connected");
+        assert_eq!(
+            written(&entry, 0),
+            "<134>1 2026-10-18T09:30:00.050Z pico-demo wifi-example - - [meta sequenceId=\"7\" sysUpTime=\"1234\"] MQTT connected"
+        );
+        assert_eq!(
+            written(&entry, 3),
+            "<134>1 2026-10-18T09:30:00.050Z pico-demo wifi-example - - \
+             [meta sequenceId=\"7\" sysUpTime=\"1234\"][drops@32473 count=\"3\"] MQTT connected"
+        );
+    }
+
+    #[test]
+    fn leaves_out_the_time_until_the_clock_is_set() {
+        let entry = entry(Severity::Warning, None, "no DHCP lease");
+        assert_eq!(
+            written(&entry, 0),
+            "<132>1 - pico-demo wifi-example - - [meta sequenceId=\"7\" sysUpTime=\"1234\"] no DHCP lease"
+        );
+        assert_eq!(
+            written(&entry, 1),
+            "<132>1 - pico-demo wifi-example - - [meta sequenceId=\"7\" sysUpTime=\"1234\"][drops@32473 count=\"1\"] no DHCP lease"
+        );
+    }
+
+    #[test]
+    fn limits_the_rate() {
+        let start = Instant::from_secs(100);
+        let mut limit = RateLimit::new(start);
+        // A burst, then spaced out
+        for _ in 0..BURST {
+            assert_eq!(limit.next_slot(start), start);
+        }
+        assert_eq!(limit.next_slot(start), start + SEND_INTERVAL);
+        assert_eq!(limit.next_slot(start), start + SEND_INTERVAL * 2);
+        // Sending at the rate keeps to it
+        let now = start + SEND_INTERVAL * 2;
+        assert_eq!(limit.next_slot(now), now + SEND_INTERVAL);
+
+        // After a quiet spell there's a whole burst again, and no more
+        let later = start + Duration::from_secs(10);
+        for _ in 0..BURST {
+            assert_eq!(limit.next_slot(later), later);
+        }
+        assert_eq!(limit.next_slot(later), later + SEND_INTERVAL);
+    }
+
+    #[test]
+    fn counts_what_the_full_queue_drops() {
+        let mut queue = Deque::<Entry, QUEUE_LEN>::new();
+        let dropped = AtomicU32::new(0);
+        for _ in 0..QUEUE_LEN {
+            enqueue(&mut queue, entry(Severity::Info, None, "kept"), &dropped);
+        }
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+        for _ in 0..3 {
+            enqueue(&mut queue, entry(Severity::Info, None, "dropped"), &dropped);
+        }
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+        assert_eq!(queue.len(), QUEUE_LEN);
+        assert!(queue.iter().all(|e| e.message == "kept"));
+
+        // Once there's room again, messages are queued, and the count stays
+        // until the next one's sent
+        queue.pop_front();
+        enqueue(&mut queue, entry(Severity::Info, None, "after"), &dropped);
+        assert_eq!(queue.back().unwrap().message, "after");
+        assert_eq!(dropped.swap(0, Ordering::Relaxed), 3);
+    }
+}
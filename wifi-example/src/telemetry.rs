@@ -0,0 +1,99 @@
+//! Streams button, touch and LED events over udp, if a listener is
+//! configured. See `wifi_protocol::telemetry` for the format, and
+//! `udplisten` in wifi-tools for a listener.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::*;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Instant;
+use wifi_protocol::telemetry::{self, Direction as WireDirection, Event, Led, Message};
+
+use crate::netconfig::Ipv4;
+use crate::shell::LedMode;
+use crate::touch::{Direction, Gesture};
+use crate::NetStack;
+
+/// Local port the events are sent from
+const LOCAL_PORT: u16 = 1237;
+
+static EVENTS: Channel<CriticalSectionRawMutex, Event, 8> = Channel::new();
+
+/// Whether a listener is configured, so events are only queued when they
+/// will be sent
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Send an event, if a listener is configured. Events are dropped if they
+/// arrive faster than they can be sent.
+fn publish(event: Event) {
+    if ENABLED.load(Ordering::Relaxed) {
+        let _ = EVENTS.try_send(event);
+    }
+}
+
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+pub fn publish_button(pressed: bool) {
+    publish(Event::Button { pressed });
+}
+
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+pub fn publish_gesture(gesture: Gesture) {
+    publish(match gesture {
+        // The display is far smaller than an i16
+        Gesture::Tap(p) => Event::Tap {
+            x: p.x as i16,
+            y: p.y as i16,
+        },
+        Gesture::Swipe(direction) => Event::Swipe(match direction {
+            Direction::Left => WireDirection::Left,
+            Direction::Right => WireDirection::Right,
+            Direction::Up => WireDirection::Up,
+            Direction::Down => WireDirection::Down,
+        }),
+    });
+}
+
+pub fn publish_led(mode: LedMode) {
+    publish(Event::Led(match mode {
+        LedMode::Off => Led::Off,
+        LedMode::On => Led::On,
+        LedMode::Blink { period_ms } => Led::Blink {
+            period_ms: period_ms.min(u16::MAX as u32) as u16,
+        },
+    }));
+}
+
+/// Send events to the listener at `(address, port)`
+#[embassy_executor::task]
+pub async fn telemetry_task(stack: &'static NetStack, listener: (Ipv4, u16)) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 0];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 4 * telemetry::MAX_LEN];
+    let mut out = [0; telemetry::MAX_LEN];
+
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(LOCAL_PORT));
+    let (address, port) = listener;
+    let to = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address(address)), port);
+    info!("sending telemetry to {:?}", to);
+    ENABLED.store(true, Ordering::Relaxed);
+
+    let mut seq: u32 = 0;
+    loop {
+        let event = EVENTS.receive().await;
+        let message = Message {
+            seq,
+            uptime_ms: Instant::now().as_millis() as u32,
+            event,
+        };
+        seq = seq.wrapping_add(1);
+        let n = message.encode(&mut out);
+        if let Err(e) = socket.send_to(&out[..n], to).await {
+            warn!("telemetry send error: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Serves the command shell over TLS, for a control channel that isn't
+//! plaintext on the LAN. Clients authenticate with the pre-shared key from
+//! the network config, eg
+//!
+//! ```text
+//! openssl s_client -connect pico.local:992 -tls1_3 -groups X25519 \
+//!     -psk_identity pico -psk 000102030405060708090a0b0c0d0e0f
+//! ```
+
+use core::ops::Range;
+
+use defmt::*;
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_time::{Duration, Timer};
+use embedded_io_async::{ErrorKind, Read, Write};
+use wifi_tls::{Connection, Psk, Received, HEADER_LEN};
+
+use crate::netconfig::TlsConfig;
+use crate::{entropy, set_peer, shell_server, NetStack};
+
+/// telnets
+pub const PORT: u16 = 992;
+
+/// The number of TLS shell clients that can be served at once
+pub const MAX_CONNECTIONS: usize = 1;
+
+const BUFFER_SIZE: usize = 1024;
+
+/// Largest record that can be received or sent. Interactive clients send
+/// small records, and ClientHellos are well under this.
+const RECORD_SIZE: usize = 2048;
+
+#[derive(Debug)]
+enum Error {
+    Tcp(tcp::Error),
+    /// The connection failed, and why has been logged
+    Tls,
+}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Tcp(tcp::Error::ConnectionReset) => ErrorKind::ConnectionReset,
+            Error::Tls => ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl From<tcp::Error> for Error {
+    fn from(e: tcp::Error) -> Error {
+        Error::Tcp(e)
+    }
+}
+
+/// The shell only sees the kind of an error, so log the details here
+fn tls_error(error: wifi_tls::Error) -> Error {
+    warn!("TLS error: {:?}", Debug2Format(&error));
+    Error::Tls
+}
+
+/// A TLS connection over a TCP socket
+struct TlsStream<'s, 'a, 'k> {
+    socket: &'s mut TcpSocket<'a>,
+    conn: Connection<'k>,
+    rx: [u8; RECORD_SIZE],
+    tx: [u8; RECORD_SIZE],
+    /// Application data in `rx` that hasn't been read yet
+    pending: Range<usize>,
+}
+
+impl<'s, 'a, 'k> TlsStream<'s, 'a, 'k> {
+    fn new(socket: &'s mut TcpSocket<'a>, psk: Psk<'k>) -> Self {
+        let mut random = [0; 32];
+        let mut secret = [0; 32];
+        entropy::fill_bytes(&mut random);
+        entropy::fill_bytes(&mut secret);
+        TlsStream {
+            socket,
+            conn: Connection::new(psk, random, secret),
+            rx: [0; RECORD_SIZE],
+            tx: [0; RECORD_SIZE],
+            pending: 0..0,
+        }
+    }
+
+    /// Fill `range` of the receive buffer from the socket, returning false at
+    /// the end of the stream
+    async fn read_exact(&mut self, range: Range<usize>) -> Result<bool, Error> {
+        let mut pos = range.start;
+        while pos < range.end {
+            match self.socket.read(&mut self.rx[pos..range.end]).await? {
+                0 => return Ok(false),
+                n => pos += n,
+            }
+        }
+        Ok(true)
+    }
+
+    /// Receive records until there's application data, returning false once
+    /// the client has closed the connection
+    async fn receive(&mut self) -> Result<bool, Error> {
+        loop {
+            if !self.read_exact(0..HEADER_LEN).await? {
+                return Ok(false);
+            }
+            let header = self.rx[..HEADER_LEN].try_into().unwrap();
+            let len = match wifi_tls::record_len(header) {
+                Ok(len) if len <= RECORD_SIZE => len,
+                Ok(_) => return Err(self.fail(wifi_tls::Error::RecordOverflow).await),
+                Err(e) => return Err(self.fail(e).await),
+            };
+            if !self.read_exact(HEADER_LEN..len).await? {
+                return Ok(false);
+            }
+
+            match self.conn.receive(&mut self.rx[..len], &mut self.tx) {
+                Ok(Received::Reply(n)) => self.socket.write_all(&self.tx[..n]).await?,
+                Ok(Received::Nothing) => {}
+                // Decrypted in place, just after the header
+                Ok(Received::Data(data)) => {
+                    self.pending = HEADER_LEN..HEADER_LEN + data.len();
+                    if !self.pending.is_empty() {
+                        return Ok(true);
+                    }
+                }
+                Ok(Received::Closed) => return Ok(false),
+                Err(e) => return Err(self.fail(e).await),
+            }
+        }
+    }
+
+    /// Tell the client why the connection failed, returning the error
+    async fn fail(&mut self, error: wifi_tls::Error) -> Error {
+        if let Ok(n) = self.conn.fail(error, &mut self.tx) {
+            self.socket.write_all(&self.tx[..n]).await.ok();
+        }
+        tls_error(error)
+    }
+
+    /// Send a close_notify, if the handshake got that far
+    async fn close(&mut self) {
+        if self.conn.is_established() {
+            if let Ok(n) = self.conn.close(&mut self.tx) {
+                self.socket.write_all(&self.tx[..n]).await.ok();
+            }
+        }
+    }
+}
+
+impl embedded_io_async::ErrorType for TlsStream<'_, '_, '_> {
+    type Error = Error;
+}
+
+impl Read for TlsStream<'_, '_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.pending.is_empty() && !self.receive().await? {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.rx[self.pending.start..self.pending.start + n]);
+        self.pending.start += n;
+        Ok(n)
+    }
+}
+
+impl Write for TlsStream<'_, '_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let (n, sent) = self.conn.send(buf, &mut self.tx).map_err(tls_error)?;
+        self.socket.write_all(&self.tx[..n]).await?;
+        Ok(sent)
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.socket.flush().await?)
+    }
+}
+
+/// Serve one TLS shell client at a time, recording the client in `slot` of
+/// the displayed peer list.
+#[embassy_executor::task]
+pub async fn tls_shell_task(stack: &'static NetStack, slot: usize, config: TlsConfig) -> ! {
+    let mut rx_buffer = [0; BUFFER_SIZE];
+    let mut tx_buffer = [0; BUFFER_SIZE];
+    let psk = Psk {
+        identity: config.identity.as_bytes(),
+        key: &config.psk,
+    };
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(embassy_time::Duration::from_secs(300)));
+
+        info!("[{}] TLS shell listening on TCP:{}...", slot, PORT);
+        if let Err(e) = socket.accept(PORT).await {
+            warn!("[{}] accept error: {:?}", slot, e);
+            continue;
+        }
+
+        info!("[{}] TLS shell connection from {:?}", slot, socket.remote_endpoint());
+        set_peer(slot, socket.remote_endpoint());
+
+        let mut stream = TlsStream::new(&mut socket, psk);
+        let reboot = shell_server::serve(&mut stream, slot).await;
+        stream.close().await;
+
+        socket.flush().await.ok();
+        socket.close();
+        if reboot {
+            // Give the stack a chance to send the response
+            Timer::after(Duration::from_millis(200)).await;
+            warn!("rebooting at the request of {:?}", socket.remote_endpoint());
+            crate::reboot();
+        }
+
+        set_peer(slot, None);
+    }
+}
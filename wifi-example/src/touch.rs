@@ -0,0 +1,175 @@
+//! Driver for the XPT2046 resistive touchscreen sensor, sharing SPI1 with
+//! the display, and classifying presses into gestures. There's no touch
+//! screen on the host.
+
+#[cfg(target_os = "none")]
+use embassy_rp::gpio::Output;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+#[cfg(target_os = "none")]
+use embassy_time::{Duration, Instant, Timer};
+use defmt::Format;
+use embedded_graphics::prelude::Point;
+
+#[cfg(target_os = "none")]
+use crate::panel::{SharedSpiBus, DISPLAY_SPI_FREQUENCY};
+#[cfg(target_os = "none")]
+use crate::display_state_update;
+#[cfg(target_os = "none")]
+use crate::metrics;
+#[cfg(target_os = "none")]
+use crate::mqtt_client::{self, Event};
+#[cfg(target_os = "none")]
+use crate::telemetry;
+
+/// The XPT2046 is much slower than the display
+const TOUCH_SPI_FREQUENCY: u32 = 200_000;
+
+struct Calibration {
+    x1: i32,
+    x2: i32,
+    y1: i32,
+    y2: i32,
+    sx: i32,
+    sy: i32,
+}
+
+const CALIBRATION: Calibration = Calibration {
+    x1: 3880,
+    x2: 340,
+    y1: 262,
+    y2: 3850,
+    sx: 320,
+    sy: 240,
+};
+
+#[cfg(target_os = "none")]
+pub struct Touch {
+    bus: &'static SharedSpiBus,
+    cs: Output<'static>,
+}
+
+#[cfg(target_os = "none")]
+impl Touch {
+    pub fn new(bus: &'static SharedSpiBus, cs: Output<'static>) -> Self {
+        Self { bus, cs }
+    }
+
+    /// Returns the touched point in display coordinates, if any
+    pub fn read(&mut self) -> Option<Point> {
+        let mut x = [0; 2];
+        let mut y = [0; 2];
+        let cs = &mut self.cs;
+        self.bus.lock(|bus| {
+            let mut bus = bus.borrow_mut();
+            bus.set_frequency(TOUCH_SPI_FREQUENCY);
+            cs.set_low();
+            bus.blocking_write(&[0x90]).unwrap();
+            bus.blocking_read(&mut x).unwrap();
+            bus.blocking_write(&[0xd0]).unwrap();
+            bus.blocking_read(&mut y).unwrap();
+            cs.set_high();
+            bus.set_frequency(DISPLAY_SPI_FREQUENCY);
+        });
+
+        let x = (u16::from_be_bytes(x) >> 3) as i32;
+        let y = (u16::from_be_bytes(y) >> 3) as i32;
+
+        let cal = &CALIBRATION;
+
+        let x = ((x - cal.x1) * cal.sx / (cal.x2 - cal.x1)).clamp(0, cal.sx);
+        let y = ((y - cal.y1) * cal.sy / (cal.y2 - cal.y1)).clamp(0, cal.sy);
+        if x == 0 && y == 0 {
+            None
+        } else {
+            Some(Point::new(x, y))
+        }
+    }
+}
+
+/// Touch presses, reported once per press rather than continuously
+pub static TOUCH_EVENTS: Channel<CriticalSectionRawMutex, Point, 4> = Channel::new();
+
+/// How far a press must move before it counts as a swipe rather than a tap
+const SWIPE_DISTANCE: i32 = 40;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Direction::Left => "left",
+            Direction::Right => "right",
+            Direction::Up => "up",
+            Direction::Down => "down",
+        }
+    }
+}
+
+/// A complete press, from touch to release
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Gesture {
+    Tap(Point),
+    Swipe(Direction),
+}
+
+impl Gesture {
+    /// Classify a press by where it started and ended
+    pub fn from_press(start: Point, end: Point) -> Gesture {
+        let d = end - start;
+        if d.x.abs() < SWIPE_DISTANCE && d.y.abs() < SWIPE_DISTANCE {
+            Gesture::Tap(start)
+        } else if d.x.abs() >= d.y.abs() {
+            Gesture::Swipe(if d.x < 0 { Direction::Left } else { Direction::Right })
+        } else {
+            Gesture::Swipe(if d.y < 0 { Direction::Up } else { Direction::Down })
+        }
+    }
+}
+
+/// Poll the touch screen, and publish new presses and completed gestures
+#[cfg(target_os = "none")]
+#[embassy_executor::task]
+pub async fn touch_monitor(mut touch: Touch) {
+    // Where the current press started, and where it was last seen
+    let mut press: Option<(Point, Point)> = None;
+    let mut pressed_at = Instant::now();
+    loop {
+        Timer::after(Duration::from_millis(50)).await;
+        match (touch.read(), press) {
+            (Some(p), None) => {
+                press = Some((p, p));
+                pressed_at = Instant::now();
+                metrics::TOUCH_PRESSES.inc();
+                display_state_update(|ds| ds.last_touch = Some(p));
+                // Drop presses if nobody is listening
+                if TOUCH_EVENTS.try_send(p).is_err() {
+                    metrics::TOUCH_DROPPED.inc();
+                }
+            }
+            (Some(p), Some((start, _))) => press = Some((start, p)),
+            (None, Some((start, end))) => {
+                press = None;
+                metrics::TOUCH_PRESS_TIME.observe(pressed_at.elapsed().as_millis() as u32);
+                let gesture = Gesture::from_press(start, end);
+                match gesture {
+                    Gesture::Tap(_) => metrics::TOUCH_TAPS.inc(),
+                    Gesture::Swipe(_) => metrics::TOUCH_SWIPES.inc(),
+                }
+                display_state_update(|ds| {
+                    ds.gestures = ds.gestures.wrapping_add(1);
+                    ds.last_gesture = Some(gesture);
+                });
+                mqtt_client::publish_event(Event::Touch(gesture));
+                telemetry::publish_gesture(gesture);
+            }
+            (None, None) => {}
+        }
+    }
+}
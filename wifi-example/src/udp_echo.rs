@@ -0,0 +1,37 @@
+//! A udp echo server, sending each datagram back to where it came from.
+
+use defmt::*;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+
+use crate::NetStack;
+
+pub const PORT: u16 = 1234;
+
+const BUFFER_SIZE: usize = 1536;
+
+#[embassy_executor::task]
+pub async fn udp_echo_task(stack: &'static NetStack) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; BUFFER_SIZE];
+    let mut buf = [0; BUFFER_SIZE];
+
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(PORT));
+    info!("Echoing on UDP:{}...", PORT);
+
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("UDP echo receive error: {:?}", e);
+                continue;
+            }
+        };
+        debug!("UDP echo {} bytes from {:?}", n, from);
+        if let Err(e) = socket.send_to(&buf[..n], from).await {
+            warn!("UDP echo send error: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Live events for the websocket clients, generated from changes to the
+//! display state: button presses, touch gestures, the LED, the text rows and
+//! the number of connected clients.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::{PubSubChannel, Subscriber};
+use embassy_sync::signal::Signal;
+use heapless::{String, Vec};
+use ufmt::{uWrite, uwrite};
+
+use crate::shell::{LedMode, MAX_TEXT, TEXT_ROWS};
+use crate::touch::Gesture;
+use crate::{display_state_read, status};
+
+/// The number of clients that can follow the events at once
+pub const MAX_SUBSCRIBERS: usize = 2;
+
+/// Events queued for each client. A client that falls further behind misses
+/// the oldest.
+const QUEUE_LEN: usize = 8;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum UiEvent {
+    Button(bool),
+    Gesture(Gesture),
+    Led(LedMode),
+    Text { row: usize, text: String<MAX_TEXT> },
+    Clients(usize),
+}
+
+/// Signalled by `display_state_update`, so the changes are looked for
+pub static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+static EVENTS: PubSubChannel<CriticalSectionRawMutex, UiEvent, QUEUE_LEN, MAX_SUBSCRIBERS, 1> = PubSubChannel::new();
+
+pub type EventSubscriber = Subscriber<'static, CriticalSectionRawMutex, UiEvent, QUEUE_LEN, MAX_SUBSCRIBERS, 1>;
+
+/// Follow the events, or None if there are already `MAX_SUBSCRIBERS`
+pub fn subscribe() -> Option<EventSubscriber> {
+    EVENTS.subscriber().ok()
+}
+
+/// The parts of the display state that events are generated from
+#[derive(Clone, PartialEq, Eq)]
+struct Snapshot {
+    button_pressed: bool,
+    led: LedMode,
+    gestures: u32,
+    last_gesture: Option<Gesture>,
+    text_rows: [String<MAX_TEXT>; TEXT_ROWS],
+    clients: usize,
+}
+
+impl Snapshot {
+    fn take() -> Snapshot {
+        display_state_read(|ds| Snapshot {
+            button_pressed: ds.button_pressed,
+            led: ds.led,
+            gestures: ds.gestures,
+            last_gesture: ds.last_gesture,
+            text_rows: ds.text_rows.clone(),
+            clients: ds.peers.iter().flatten().count(),
+        })
+    }
+
+    /// The events that lead from `old` to `self`, or that describe all of
+    /// `self` if there's nothing before it
+    fn events(&self, old: Option<&Snapshot>) -> Vec<UiEvent, { 4 + TEXT_ROWS }> {
+        let mut events = Vec::new();
+        // There's room for every kind of event
+        let mut push = |event| events.push(event).ok();
+        if old.map(|o| o.button_pressed) != Some(self.button_pressed) {
+            push(UiEvent::Button(self.button_pressed));
+        }
+        if old.map(|o| o.led) != Some(self.led) {
+            push(UiEvent::Led(self.led));
+        }
+        if let (Some(old), Some(gesture)) = (old, self.last_gesture) {
+            if old.gestures != self.gestures {
+                push(UiEvent::Gesture(gesture));
+            }
+        }
+        for (row, text) in self.text_rows.iter().enumerate() {
+            if old.map(|o| &o.text_rows[row]) != Some(text) {
+                push(UiEvent::Text { row, text: text.clone() });
+            }
+        }
+        if old.map(|o| o.clients) != Some(self.clients) {
+            push(UiEvent::Clients(self.clients));
+        }
+        events
+    }
+}
+
+/// Events describing the current state, for a new client
+pub fn current() -> Vec<UiEvent, { 4 + TEXT_ROWS }> {
+    Snapshot::take().events(None)
+}
+
+/// Publish events as the display state changes
+#[embassy_executor::task]
+pub async fn ui_events_task() -> ! {
+    let publisher = EVENTS.immediate_publisher();
+    let mut last = Snapshot::take();
+    loop {
+        CHANGED.wait().await;
+        let next = Snapshot::take();
+        for event in next.events(Some(&last)) {
+            publisher.publish_immediate(event);
+        }
+        last = next;
+    }
+}
+
+/// Write an event as a json object, eg `{"type":"button","pressed":true}`
+pub fn write_json<W: uWrite>(event: &UiEvent, out: &mut W) -> Result<(), W::Error> {
+    out.write_str("{")?;
+    match event {
+        UiEvent::Button(pressed) => uwrite!(out, "\"type\":\"button\",\"pressed\":{}", pressed)?,
+        UiEvent::Gesture(Gesture::Tap(p)) => {
+            uwrite!(out, "\"type\":\"touch\",\"gesture\":\"tap\",\"x\":{},\"y\":{}", p.x, p.y)?
+        }
+        UiEvent::Gesture(Gesture::Swipe(direction)) => uwrite!(
+            out,
+            "\"type\":\"touch\",\"gesture\":\"swipe\",\"direction\":\"{}\"",
+            direction.label()
+        )?,
+        UiEvent::Led(mode) => {
+            uwrite!(out, "\"type\":\"led\",\"mode\":\"{}\"", mode.label())?;
+            if let LedMode::Blink { period_ms } = mode {
+                uwrite!(out, ",\"blink_ms\":{}", period_ms)?;
+            }
+        }
+        UiEvent::Text { row, text } => {
+            uwrite!(out, "\"type\":\"text\",\"row\":{},\"text\":", row)?;
+            status::write_json_str(out, text)?;
+        }
+        UiEvent::Clients(count) => uwrite!(out, "\"type\":\"clients\",\"count\":{}", count)?,
+    }
+    out.write_str("}")
+}
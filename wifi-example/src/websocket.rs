@@ -0,0 +1,212 @@
+//! Live events for browsers, over a WebSocket upgraded from `GET /events` on
+//! the HTTP server. Each event is a text message holding a json object, eg
+//!
+//! - `{"type":"button","pressed":true}`
+//! - `{"type":"touch","gesture":"tap","x":120,"y":80}` or
+//!   `{"type":"touch","gesture":"swipe","direction":"left"}`
+//! - `{"type":"led","mode":"blink","blink_ms":500}`
+//! - `{"type":"text","row":0,"text":"hello"}`
+//! - `{"type":"clients","count":1}`
+//!
+//! The current state is sent as events when the client connects. Text
+//! messages from the client are run as shell commands, and answered with
+//! `{"type":"reply","text":"..."}`. The client is pinged, and the connection
+//! dropped if it doesn't answer.
+
+use defmt::*;
+use embassy_futures::select::{select3, Either3};
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::Write;
+use heapless::String;
+use ufmt::uWrite;
+use wifi_protocol::websocket::{self, Header, Opcode, Reassembly, MAX_HEADER_LEN};
+
+use crate::shell::{self, Outcome, MAX_LINE};
+use crate::shell_server::RemoteBoard;
+use crate::status;
+use crate::ui_events::{self, EventSubscriber, UiEvent};
+
+pub const PATH: &str = "/events";
+
+const BUFFER_SIZE: usize = 512;
+
+/// Longest message from the client, a shell command
+const MAX_MESSAGE: usize = MAX_LINE;
+
+/// Longest shell response, and the json holding it with room for escaping
+const MAX_RESPONSE: usize = 384;
+const MAX_JSON: usize = 2 * MAX_RESPONSE + 32;
+
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+
+#[derive(Debug)]
+enum SessionError {
+    Tcp(tcp::Error),
+    Protocol(websocket::Error),
+    /// A text message that isn't utf-8, or a binary message
+    Unsupported(u16),
+    /// The client didn't answer a ping
+    Timeout,
+    Closed,
+}
+
+impl From<tcp::Error> for SessionError {
+    fn from(e: tcp::Error) -> Self {
+        SessionError::Tcp(e)
+    }
+}
+
+impl From<websocket::Error> for SessionError {
+    fn from(e: websocket::Error) -> Self {
+        SessionError::Protocol(e)
+    }
+}
+
+/// How a session ended, other than by an error
+enum Ending {
+    /// The client closed the connection, with this status code
+    Closed(u16),
+    Reboot,
+}
+
+/// Serve the events to a client that's been sent the upgrade response,
+/// until it goes away
+pub async fn serve(socket: &mut TcpSocket<'_>, events: EventSubscriber, id: usize) {
+    // Longer than the ping interval, so quiet clients aren't dropped
+    socket.set_timeout(Some(embassy_time::Duration::from_secs(60)));
+
+    let code = match session(socket, events).await {
+        Ok(Ending::Closed(code)) => {
+            info!("[http {}] websocket closed by client", id);
+            Some(code)
+        }
+        Ok(Ending::Reboot) => {
+            send(socket, Opcode::Close, &websocket::CLOSE_GOING_AWAY.to_be_bytes()).await.ok();
+            socket.flush().await.ok();
+            socket.close();
+            // Give the stack a chance to send the response
+            Timer::after(Duration::from_millis(200)).await;
+            warn!("rebooting at the request of {:?}", socket.remote_endpoint());
+            crate::reboot();
+        }
+        Err(SessionError::Protocol(e)) => {
+            warn!("[http {}] websocket error: {:?}", id, Debug2Format(&e));
+            Some(e.close_code())
+        }
+        Err(SessionError::Unsupported(code)) => Some(code),
+        Err(e) => {
+            warn!("[http {}] websocket error: {:?}", id, Debug2Format(&e));
+            None
+        }
+    };
+    if let Some(code) = code {
+        send(socket, Opcode::Close, &code.to_be_bytes()).await.ok();
+    }
+    socket.flush().await.ok();
+}
+
+async fn session(socket: &mut TcpSocket<'_>, mut events: EventSubscriber) -> Result<Ending, SessionError> {
+    let mut buf = [0; BUFFER_SIZE];
+    let mut message = [0; MAX_MESSAGE];
+    let mut reassembly = Reassembly::new();
+    let mut len = 0;
+
+    for event in ui_events::current() {
+        send_event(socket, &event).await?;
+    }
+
+    let mut awaiting_pong = false;
+    let mut next_ping = Instant::now() + PING_INTERVAL;
+    loop {
+        match select3(socket.read(&mut buf[len..]), events.next_message_pure(), Timer::at(next_ping)).await {
+            Either3::First(result) => {
+                let n = result?;
+                if n == 0 {
+                    return Err(SessionError::Closed);
+                }
+                len += n;
+
+                let mut used = 0;
+                // Frames that wouldn't fit in the buffer are refused, so it
+                // can't fill up without one being complete
+                while let Some((frame, frame_len)) =
+                    websocket::decode_frame(&mut buf[used..len], BUFFER_SIZE - MAX_HEADER_LEN)?
+                {
+                    match frame.opcode {
+                        Opcode::Ping => send(socket, Opcode::Pong, frame.payload).await?,
+                        Opcode::Pong => awaiting_pong = false,
+                        Opcode::Close => {
+                            let code = websocket::decode_close(frame.payload)?;
+                            return Ok(Ending::Closed(code.map_or(websocket::CLOSE_NORMAL, |(code, _)| code)));
+                        }
+                        _ => {
+                            if let Some((opcode, contents)) = reassembly.push(&frame, &mut message)? {
+                                let line = match (opcode, core::str::from_utf8(contents)) {
+                                    (Opcode::Text, Ok(line)) => line,
+                                    (Opcode::Text, Err(_)) => {
+                                        return Err(SessionError::Unsupported(websocket::CLOSE_INVALID_DATA))
+                                    }
+                                    _ => return Err(SessionError::Unsupported(websocket::CLOSE_UNSUPPORTED)),
+                                };
+                                if command(socket, line).await? == Outcome::Reboot {
+                                    return Ok(Ending::Reboot);
+                                }
+                            }
+                        }
+                    }
+                    used += frame_len;
+                }
+                buf.copy_within(used..len, 0);
+                len -= used;
+            }
+            Either3::Second(event) => send_event(socket, &event).await?,
+            Either3::Third(()) => {
+                if awaiting_pong {
+                    return Err(SessionError::Timeout);
+                }
+                send(socket, Opcode::Ping, &[]).await?;
+                awaiting_pong = true;
+                next_ping = Instant::now() + PING_INTERVAL;
+            }
+        }
+    }
+}
+
+/// Run a shell command from the client, and send the reply
+async fn command(socket: &mut TcpSocket<'_>, line: &str) -> Result<Outcome, SessionError> {
+    log_info!("websocket shell: {}", line);
+    let mut response = String::<MAX_RESPONSE>::new();
+    // The response is truncated if it doesn't fit
+    let outcome = shell::run(&mut RemoteBoard, line.trim(), &mut response).unwrap_or(Outcome::Continue);
+
+    let mut json = String::<MAX_JSON>::new();
+    json.write_str("{\"type\":\"reply\",\"text\":").ok();
+    status::write_json_str(&mut json, &response).ok();
+    // Only control characters could need more room than allowed for
+    if json.write_str("}").is_ok() {
+        send(socket, Opcode::Text, json.as_bytes()).await?;
+    }
+    Ok(outcome)
+}
+
+async fn send_event(socket: &mut TcpSocket<'_>, event: &UiEvent) -> Result<(), tcp::Error> {
+    let mut json = String::<128>::new();
+    // Events are all small enough to fit
+    ui_events::write_json(event, &mut json).ok();
+    send(socket, Opcode::Text, json.as_bytes()).await
+}
+
+/// Send a whole, unmasked frame
+async fn send(socket: &mut TcpSocket<'_>, opcode: Opcode, payload: &[u8]) -> Result<(), tcp::Error> {
+    let header = Header {
+        fin: true,
+        opcode,
+        mask: None,
+        payload_len: payload.len() as u64,
+    };
+    let mut head = [0; MAX_HEADER_LEN];
+    let n = header.encode(&mut head);
+    socket.write_all(&head[..n]).await?;
+    socket.write_all(payload).await
+}
@@ -1,79 +0,0 @@
-use core::convert::Infallible;
-
-use embassy_rp::gpio::{Flex, Output};
-use embassy_rp::peripherals::{PIN_24, PIN_29};
-use embedded_hal_1::spi::ErrorType;
-use embedded_hal_async::spi::{SpiBusFlush, SpiBusRead, SpiBusWrite};
-
-pub struct WifiSpi {
-  /// SPI clock
-  pub clk: Output<'static, PIN_29>,
-
-  /// 4 signals, all in one!!
-  /// - SPI MISO
-  /// - SPI MOSI
-  /// - IRQ
-  /// - strap to set to gSPI mode on boot.
-  pub dio: Flex<'static, PIN_24>,
-}
-
-impl ErrorType for WifiSpi {
-  type Error = Infallible;
-}
-
-impl SpiBusFlush for WifiSpi {
-  async fn flush(&mut self) -> Result<(), Self::Error> {
-      Ok(())
-  }
-}
-
-impl SpiBusRead<u32> for WifiSpi {
-  async fn read(&mut self, words: &mut [u32]) -> Result<(), Self::Error> {
-      self.dio.set_as_input();
-      for word in words {
-          let mut w = 0;
-          for _ in 0..32 {
-              w = w << 1;
-
-              // rising edge, sample data
-              if self.dio.is_high() {
-                  w |= 0x01;
-              }
-              self.clk.set_high();
-
-              // falling edge
-              self.clk.set_low();
-          }
-          *word = w
-      }
-
-      Ok(())
-  }
-}
-
-impl SpiBusWrite<u32> for WifiSpi {
-  async fn write(&mut self, words: &[u32]) -> Result<(), Self::Error> {
-      self.dio.set_as_output();
-      for word in words {
-          let mut word = *word;
-          for _ in 0..32 {
-              // falling edge, setup data
-              self.clk.set_low();
-              if word & 0x8000_0000 == 0 {
-                  self.dio.set_low();
-              } else {
-                  self.dio.set_high();
-              }
-
-              // rising edge
-              self.clk.set_high();
-
-              word = word << 1;
-          }
-      }
-      self.clk.set_low();
-
-      self.dio.set_as_input();
-      Ok(())
-  }
-}
\ No newline at end of file
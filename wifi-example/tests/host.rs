@@ -0,0 +1,128 @@
+//! Runs the host build over a TAP interface, and talks to its services with
+//! ordinary sockets. The interface must be set up as in the README, with the
+//! host at 192.168.69.100/24; without it the test is skipped.
+#![cfg(not(target_os = "none"))]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const BOARD: &str = "192.168.69.1";
+
+/// How long the program has to bring the network up
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The running program, stopped when dropped
+struct Board(Child);
+
+impl Drop for Board {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+/// Start the program on the TAP interface, if there is one
+fn start() -> Option<Board> {
+    let tap = std::env::var("WIFI_EXAMPLE_TAP").unwrap_or_else(|_| "tap0".into());
+    if !std::path::Path::new("/sys/class/net").join(&tap).exists() {
+        eprintln!("skipping, there's no {} interface", tap);
+        return None;
+    }
+    let config = std::env::temp_dir().join(format!("wifi-example-test-{}.conf", std::process::id()));
+    std::fs::write(&config, format!("mode = static\naddress = {}/24\n", BOARD)).unwrap();
+    let child = Command::new(env!("CARGO_BIN_EXE_cyw43-example-rpi-pico-w"))
+        .env("WIFI_EXAMPLE_TAP", &tap)
+        .env("WIFI_EXAMPLE_CONFIG", &config)
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    Some(Board(child))
+}
+
+/// Connect to a tcp port on the board, waiting for it to come up
+fn connect(port: u16) -> TcpStream {
+    let address: SocketAddr = format!("{}:{}", BOARD, port).parse().unwrap();
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    loop {
+        match TcpStream::connect_timeout(&address, Duration::from_secs(1)) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+                return stream;
+            }
+            Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(200)),
+            Err(e) => panic!("can't connect to port {}: {}", port, e),
+        }
+    }
+}
+
+fn tcp_echo() {
+    let mut stream = connect(7);
+    for message in [&b"hello over tap\n"[..], &[0xaa; 1500]] {
+        stream.write_all(message).unwrap();
+        let mut echo = vec![0; message.len()];
+        stream.read_exact(&mut echo).unwrap();
+        assert_eq!(echo, message);
+    }
+}
+
+fn udp_echo() {
+    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    socket.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+    socket.send_to(b"hello over udp", (BOARD, 1234)).unwrap();
+    let mut buf = [0; 64];
+    let (n, from) = socket.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"hello over udp");
+    assert_eq!(from, format!("{}:1234", BOARD).parse().unwrap());
+}
+
+/// Make a request, returning the head and body of the response
+fn get(path: &str) -> (String, String) {
+    let mut stream = connect(80);
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, BOARD).unwrap();
+    let mut response = Vec::new();
+    let mut buf = [0; 1024];
+    let head_len = loop {
+        if let Some(at) = response.windows(4).position(|w| w == b"\r\n\r\n") {
+            break at + 4;
+        }
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "the response ended in its head");
+        response.extend_from_slice(&buf[..n]);
+    };
+    let head = String::from_utf8(response[..head_len].to_vec()).unwrap();
+    let length: usize = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .expect("no content length")
+        .parse()
+        .unwrap();
+    while response.len() < head_len + length {
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "the response ended in its body");
+        response.extend_from_slice(&buf[..n]);
+    }
+    (head, String::from_utf8(response[head_len..].to_vec()).unwrap())
+}
+
+fn http_metrics() {
+    let (head, body) = get("/metrics");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    // The echo test's connection
+    assert!(body.lines().any(|line| line == "echo_connections_total 1"), "{}", body);
+
+    let (head, _) = get("/nowhere");
+    assert!(head.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", head);
+}
+
+// One test, as there's only one interface for the program to run on
+#[test]
+fn serves_over_tap() {
+    let Some(_board) = start() else { return };
+    tcp_echo();
+    udp_echo();
+    http_metrics();
+}
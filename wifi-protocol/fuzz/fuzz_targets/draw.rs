@@ -0,0 +1,19 @@
+//! Decodes arbitrary bytes as drawing commands, checking that whatever
+//! decodes encodes back to a command that decodes the same.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wifi_protocol::draw::{decode, HEADER_LEN, MAX_PAYLOAD};
+
+fuzz_target!(|data: &[u8]| {
+    let mut rest = data;
+    while let Ok(Some((command, len))) = decode(rest) {
+        assert!(len <= rest.len());
+        assert_eq!(command.encoded_len(), len);
+
+        let mut buf = [0; HEADER_LEN + MAX_PAYLOAD];
+        let encoded = command.encode(&mut buf).unwrap();
+        assert_eq!(decode(&buf[..encoded]), Ok(Some((command, encoded))));
+        rest = &rest[len..];
+    }
+});
@@ -0,0 +1,141 @@
+//! Encodes metrics made from arbitrary bytes, checking that the output parses
+//! back, per the text format, to the same labels and values.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wifi_protocol::metrics::{Encoder, Histogram, Kind};
+
+fuzz_target!(|data: &[u8]| {
+    // Label values and help text from the start, with the rest as numbers
+    let (text, data) = match data.iter().position(|&b| b == 0) {
+        Some(i) => (&data[..i], &data[i + 1..]),
+        None => return,
+    };
+    let text = match core::str::from_utf8(text) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    let mut words = data.chunks_exact(4).map(|w| u32::from_le_bytes(w.try_into().unwrap()));
+    let decimals = words.next().unwrap_or(0) as u8 % 20;
+    let value = words.next().unwrap_or(0) as i32 as i64 * words.next().unwrap_or(1) as i64;
+    let mut bounds = [0; 8];
+    let mut counts = [0; 8];
+    let n = words.next().unwrap_or(0) as usize % 9;
+    let mut bound = 0u32;
+    for i in 0..n {
+        bound = bound.saturating_add(words.next().unwrap_or(1).max(1));
+        bounds[i] = bound;
+        counts[i] = words.next().unwrap_or(0);
+    }
+    let histogram = Histogram {
+        bounds: &bounds[..n],
+        counts: &counts[..n],
+        overflow: words.next().unwrap_or(0),
+        sum: words.next().unwrap_or(0) as u64 * words.next().unwrap_or(1) as u64,
+        decimals,
+    };
+
+    let mut encoder = Encoder::new(String::new());
+    encoder.family("fuzz", Kind::Gauge, text).unwrap();
+    encoder.sample("fuzz", &[("a", text), ("b", "")], value).unwrap();
+    encoder.family("fuzz_seconds", Kind::Histogram, text).unwrap();
+    encoder.histogram("fuzz_seconds", &[("a", text)], &histogram).unwrap();
+    let out = encoder.into_inner();
+
+    let mut lines = out.split_terminator('\n');
+    assert_eq!(unescape(lines.next().unwrap().strip_prefix("# HELP fuzz ").unwrap(), false), text);
+    assert_eq!(lines.next().unwrap(), "# TYPE fuzz gauge");
+    let (labels, v) = parse_sample(lines.next().unwrap(), "fuzz");
+    assert_eq!(labels, [("a".into(), text.into()), ("b".into(), String::new())]);
+    assert_eq!(v, value.to_string());
+
+    assert_eq!(unescape(lines.next().unwrap().strip_prefix("# HELP fuzz_seconds ").unwrap(), false), text);
+    assert_eq!(lines.next().unwrap(), "# TYPE fuzz_seconds histogram");
+    let mut cumulative = 0;
+    for i in 0..=n {
+        let (labels, v) = parse_sample(lines.next().unwrap(), "fuzz_seconds_bucket");
+        assert_eq!(labels[0], ("a".into(), text.into()));
+        assert_eq!(labels[1].0, "le");
+        cumulative += if i < n { counts[i] as u64 } else { histogram.overflow as u64 };
+        if i < n {
+            assert_eq!(labels[1].1, decimal(bounds[i] as u64, decimals));
+        } else {
+            assert_eq!(labels[1].1, "+Inf");
+        }
+        assert_eq!(v, cumulative.to_string());
+    }
+    let (_, v) = parse_sample(lines.next().unwrap(), "fuzz_seconds_sum");
+    assert_eq!(v, decimal(histogram.sum, decimals));
+    let (_, v) = parse_sample(lines.next().unwrap(), "fuzz_seconds_count");
+    assert_eq!(v, cumulative.to_string());
+    assert!(lines.next().is_none());
+});
+
+/// `n` * 10^-decimals, the slow way
+fn decimal(n: u64, decimals: u8) -> String {
+    let digits = format!("{:0>width$}", n, width = decimals as usize + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals as usize);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    }
+}
+
+/// Split a sample line into its labels and value, given the expected name
+fn parse_sample(line: &str, name: &str) -> (Vec<(String, String)>, String) {
+    let mut rest = line.strip_prefix(name).unwrap();
+    let mut labels = Vec::new();
+    if let Some(r) = rest.strip_prefix('{') {
+        rest = r;
+        loop {
+            let (label, r) = rest.split_once("=\"").unwrap();
+            // The value ends at the first unescaped quote
+            let mut end = None;
+            let mut escaped = false;
+            for (i, c) in r.char_indices() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => {
+                        end = Some(i);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let end = end.unwrap();
+            labels.push((label.to_string(), unescape(&r[..end], true)));
+            rest = &r[end + 1..];
+            match rest.as_bytes()[0] {
+                b',' => rest = &rest[1..],
+                b'}' => {
+                    rest = &rest[1..];
+                    break;
+                }
+                _ => panic!("bad labels in {:?}", line),
+            }
+        }
+    }
+    (labels, rest.strip_prefix(' ').unwrap().to_string())
+}
+
+fn unescape(s: &str, quoted: bool) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            assert!(c != '\n' && !(quoted && c == '"'));
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('"') if quoted => out.push('"'),
+            c => panic!("bad escape {:?}", c),
+        }
+    }
+    out
+}
@@ -0,0 +1,79 @@
+//! Feeds arbitrary bytes to the websocket framing, as received by the board,
+//! checking that it never panics and that what it decodes is consistent.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wifi_protocol::websocket::{self, Header, Opcode, Reassembly, MAX_CONTROL_PAYLOAD, MAX_HEADER_LEN};
+
+/// As on the board
+const BUFFER_SIZE: usize = 512;
+const MAX_MESSAGE: usize = 128;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks how much arrives at a time, so frames are split
+    // across reads at different points
+    let (chunk, data) = match data.split_first() {
+        Some((c, rest)) => (*c as usize % 64 + 1, rest),
+        None => return,
+    };
+
+    // Whatever decodes as a header encodes back to the same bytes
+    if let Ok(Some((header, len))) = Header::decode(data) {
+        let mut out = [0; MAX_HEADER_LEN];
+        assert_eq!(header.encode(&mut out), len);
+        assert_eq!(out[..len], data[..len]);
+    }
+    if let Ok(Some((code, reason))) = websocket::decode_close(data) {
+        assert_eq!(code.to_be_bytes(), data[..2]);
+        assert_eq!(reason.len(), data.len() - 2);
+    }
+    if let Ok(key) = core::str::from_utf8(data) {
+        let mut out = [0; websocket::ACCEPT_LEN];
+        websocket::accept_key(key, &mut out);
+    }
+
+    // Receive the rest like the board does, stopping at the first error
+    let mut buf = [0; BUFFER_SIZE];
+    let mut message = [0; MAX_MESSAGE];
+    let mut reassembly = Reassembly::new();
+    let mut len = 0;
+    for piece in data.chunks(chunk) {
+        // Stop if the buffer's full and nothing in it decodes
+        let room = (BUFFER_SIZE - len).min(piece.len());
+        if room == 0 {
+            return;
+        }
+        buf[len..len + room].copy_from_slice(&piece[..room]);
+        len += room;
+
+        let mut used = 0;
+        loop {
+            let (frame, frame_len) = match websocket::decode_frame(&mut buf[used..len], BUFFER_SIZE - MAX_HEADER_LEN) {
+                Ok(Some(f)) => f,
+                Ok(None) => break,
+                Err(_) => return,
+            };
+            assert!(frame_len <= len - used);
+            assert!(frame.payload.len() <= frame_len);
+            if frame.opcode.is_control() {
+                assert!(frame.fin);
+                assert!(frame.payload.len() <= MAX_CONTROL_PAYLOAD);
+                if frame.opcode == Opcode::Close && websocket::decode_close(frame.payload).is_err() {
+                    return;
+                }
+            } else {
+                match reassembly.push(&frame, &mut message) {
+                    Ok(Some((opcode, contents))) => {
+                        assert!(matches!(opcode, Opcode::Text | Opcode::Binary));
+                        assert!(contents.len() <= MAX_MESSAGE);
+                    }
+                    Ok(None) => {}
+                    Err(_) => return,
+                }
+            }
+            used += frame_len;
+        }
+        buf.copy_within(used..len, 0);
+        len -= used;
+    }
+});
@@ -0,0 +1,157 @@
+//! The discovery beacon, broadcast by the board every few seconds so that
+//! host tools can find it without knowing its address. The strings are utf8.
+//!
+//! ```text
+//! beacon: magic:[u8; 4] = "PWBC" board_id:[u8; 6] address:[u8; 4]
+//!         version_len:u8 version:[u8; version_len]
+//!         hostname_len:u8 hostname:[u8; hostname_len]
+//! ```
+//!
+//! The board id is the board's MAC address, and the address its IPv4
+//! address.
+
+pub const MAGIC: [u8; 4] = *b"PWBC";
+
+/// The beacon is broadcast to this port
+pub const PORT: u16 = 1235;
+
+const FIXED_LEN: usize = 4 + 6 + 4;
+
+/// Longest beacon, with the longest strings
+pub const MAX_LEN: usize = FIXED_LEN + 2 * (1 + u8::MAX as usize);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Beacon<'a> {
+    pub board_id: [u8; 6],
+    pub address: [u8; 4],
+    /// The firmware version, eg `0.1.0`
+    pub version: &'a str,
+    pub hostname: &'a str,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Not a beacon, eg some other broadcast on the port
+    BadMagic,
+    /// The beacon ends early
+    Truncated,
+    BadUtf8,
+    /// A string is too long to encode, or the output buffer too small
+    TooLong,
+}
+
+impl<'a> Beacon<'a> {
+    /// Encode the beacon into `buf`, returning its length
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.version.len() > u8::MAX as usize || self.hostname.len() > u8::MAX as usize {
+            return Err(Error::TooLong);
+        }
+        let len = FIXED_LEN + 1 + self.version.len() + 1 + self.hostname.len();
+        let buf = buf.get_mut(..len).ok_or(Error::TooLong)?;
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..10].copy_from_slice(&self.board_id);
+        buf[10..14].copy_from_slice(&self.address);
+        let mut at = FIXED_LEN;
+        for s in [self.version, self.hostname] {
+            buf[at] = s.len() as u8;
+            buf[at + 1..at + 1 + s.len()].copy_from_slice(s.as_bytes());
+            at += 1 + s.len();
+        }
+        Ok(len)
+    }
+
+    pub fn decode(buf: &'a [u8]) -> Result<Beacon<'a>, Error> {
+        if buf.get(0..4) != Some(&MAGIC[..]) {
+            return Err(Error::BadMagic);
+        }
+        let fixed = buf.get(..FIXED_LEN).ok_or(Error::Truncated)?;
+        let mut rest = &buf[FIXED_LEN..];
+        let mut string = || -> Result<&'a str, Error> {
+            let (&len, tail) = rest.split_first().ok_or(Error::Truncated)?;
+            let bytes = tail.get(..len as usize).ok_or(Error::Truncated)?;
+            rest = &tail[len as usize..];
+            core::str::from_utf8(bytes).map_err(|_| Error::BadUtf8)
+        };
+        let version = string()?;
+        let hostname = string()?;
+        Ok(Beacon {
+            board_id: fixed[4..10].try_into().unwrap(),
+            address: fixed[10..14].try_into().unwrap(),
+            version,
+            hostname,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEACON: Beacon = Beacon {
+        board_id: [0x28, 0xcd, 0xc1, 0x01, 0x02, 0x03],
+        address: [192, 168, 1, 42],
+        version: "0.1.0",
+        hostname: "pico",
+    };
+
+    #[test]
+    fn round_trips() {
+        let mut buf = [0; MAX_LEN];
+        let len = BEACON.encode(&mut buf).unwrap();
+        assert_eq!(
+            buf[..len],
+            *b"PWBC\x28\xcd\xc1\x01\x02\x03\xc0\xa8\x01\x2a\x050.1.0\x04pico"
+        );
+        assert_eq!(Beacon::decode(&buf[..len]), Ok(BEACON));
+
+        // The longest strings fit in the longest beacon
+        let long = "x".repeat(255);
+        let beacon = Beacon {
+            version: &long,
+            hostname: &long,
+            ..BEACON
+        };
+        assert_eq!(beacon.encode(&mut buf), Ok(MAX_LEN));
+        assert_eq!(Beacon::decode(&buf), Ok(beacon));
+        let empty = Beacon {
+            version: "",
+            hostname: "",
+            ..BEACON
+        };
+        let len = empty.encode(&mut buf).unwrap();
+        assert_eq!(len, FIXED_LEN + 2);
+        assert_eq!(Beacon::decode(&buf[..len]), Ok(empty));
+    }
+
+    #[test]
+    fn rejects_bad_beacons() {
+        let mut buf = [0; MAX_LEN];
+        let len = BEACON.encode(&mut buf).unwrap();
+        for end in 4..len {
+            assert_eq!(Beacon::decode(&buf[..end]), Err(Error::Truncated), "{}", end);
+        }
+        assert_eq!(Beacon::decode(&buf[..3]), Err(Error::BadMagic));
+        // Another format, or another version of this one
+        for magic in [b"PWBD", b"PWTL", b"pwbc"] {
+            let mut other = buf;
+            other[..4].copy_from_slice(magic);
+            assert_eq!(Beacon::decode(&other[..len]), Err(Error::BadMagic));
+        }
+        let mut bad_utf8 = buf;
+        bad_utf8[FIXED_LEN + 1] = 0xff;
+        assert_eq!(Beacon::decode(&bad_utf8[..len]), Err(Error::BadUtf8));
+    }
+
+    #[test]
+    fn refuses_what_doesnt_fit() {
+        let long = "x".repeat(256);
+        let beacon = Beacon {
+            hostname: &long,
+            ..BEACON
+        };
+        assert_eq!(beacon.encode(&mut [0; 1024]), Err(Error::TooLong));
+        let mut buf = [0; FIXED_LEN + 11];
+        assert_eq!(BEACON.encode(&mut buf[..FIXED_LEN + 10]), Err(Error::TooLong));
+        assert_eq!(BEACON.encode(&mut buf), Ok(FIXED_LEN + 11));
+    }
+}
@@ -0,0 +1,138 @@
+//! The header in front of each cyw43 firmware blob in its flash partition,
+//! so the firmware can check a blob is there and intact before loading it
+//! into the wifi chip. All integers are little endian.
+//!
+//! ```text
+//! partition: magic:[u8; 4] = "CYWB" length:u32 crc32:u32 reserved:u32 = 0
+//!            blob:[u8; length]
+//! ```
+
+use crate::crc32::crc32;
+
+pub const MAGIC: [u8; 4] = *b"CYWB";
+
+pub const HEADER_LEN: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlobHeader {
+    pub length: u32,
+    pub crc32: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// There's no header, eg the partition is erased
+    Missing,
+    /// The reserved field isn't zero, probably a newer format
+    Unsupported,
+    /// The blob runs past the end of the partition
+    TooLarge,
+    /// The blob doesn't match the checksum
+    Corrupt,
+}
+
+impl BlobHeader {
+    /// The header for `blob`
+    pub fn for_blob(blob: &[u8]) -> BlobHeader {
+        BlobHeader {
+            length: blob.len() as u32,
+            crc32: crc32(blob),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&self.length.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.crc32.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8; HEADER_LEN]) -> Result<BlobHeader, Error> {
+        let word = |at: usize| u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+        if buf[0..4] != MAGIC {
+            return Err(Error::Missing);
+        }
+        if word(12) != 0 {
+            return Err(Error::Unsupported);
+        }
+        Ok(BlobHeader {
+            length: word(4),
+            crc32: word(8),
+        })
+    }
+}
+
+/// Check the blob in a partition, returning it without the header
+pub fn open(partition: &[u8]) -> Result<&[u8], Error> {
+    let header = partition.get(..HEADER_LEN).ok_or(Error::Missing)?;
+    let header = BlobHeader::decode(header.try_into().unwrap())?;
+    let blob = partition[HEADER_LEN..]
+        .get(..header.length as usize)
+        .ok_or(Error::TooLarge)?;
+    if crc32(blob) != header.crc32 {
+        return Err(Error::Corrupt);
+    }
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// A partition of `size` bytes holding `blob`, otherwise erased
+    fn partition(blob: &[u8], size: usize) -> Vec<u8> {
+        let mut partition = vec![0xff; size];
+        partition[..HEADER_LEN].copy_from_slice(&BlobHeader::for_blob(blob).encode());
+        partition[HEADER_LEN..HEADER_LEN + blob.len()].copy_from_slice(blob);
+        partition
+    }
+
+    #[test]
+    fn opens_blobs() {
+        let header = BlobHeader::for_blob(b"123456789");
+        assert_eq!(
+            header.encode(),
+            *b"CYWB\x09\x00\x00\x00\x26\x39\xf4\xcb\x00\x00\x00\x00"
+        );
+        assert_eq!(BlobHeader::decode(&header.encode()), Ok(header));
+
+        assert_eq!(open(&partition(b"firmware", 64)), Ok(&b"firmware"[..]));
+        // Filling the partition exactly
+        assert_eq!(open(&partition(&[1; 48], 64)), Ok(&[1; 48][..]));
+        assert_eq!(open(&partition(b"", 64)), Ok(&b""[..]));
+    }
+
+    #[test]
+    fn rejects_bad_partitions() {
+        assert_eq!(open(&[0xff; 64]), Err(Error::Missing));
+        assert_eq!(open(&[0xff; 8]), Err(Error::Missing));
+
+        let mut bad_magic = partition(b"firmware", 64);
+        bad_magic[0] = b'c';
+        assert_eq!(open(&bad_magic), Err(Error::Missing));
+
+        let mut newer = partition(b"firmware", 64);
+        newer[12] = 1;
+        assert_eq!(open(&newer), Err(Error::Unsupported));
+
+        // One byte past the end, and a length that would wrap
+        let mut long = partition(&[1; 48], 64);
+        long[4] = 49;
+        assert_eq!(open(&long), Err(Error::TooLarge));
+        long[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(open(&long), Err(Error::TooLarge));
+
+        let mut corrupt = partition(b"firmware", 64);
+        corrupt[HEADER_LEN + 3] ^= 0x10;
+        assert_eq!(open(&corrupt), Err(Error::Corrupt));
+        let mut corrupt = partition(b"firmware", 64);
+        corrupt[8] ^= 1;
+        assert_eq!(open(&corrupt), Err(Error::Corrupt));
+    }
+}
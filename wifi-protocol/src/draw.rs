@@ -0,0 +1,500 @@
+//! A compact binary protocol for drawing on the display remotely.
+//!
+//! Each command is framed as an opcode, a payload length, and the payload.
+//! The board replies to each command with a single status byte, 0 for
+//! success or an `ErrorCode`. All integers are big endian, coordinates are
+//! signed, and colors are RGB565.
+//!
+//! ```text
+//! command:   opcode:u8 length:u16 payload:[u8; length]
+//!
+//! 0x01 clear      color:u16
+//! 0x02 fill_rect  x:i16 y:i16 width:u16 height:u16 color:u16
+//! 0x03 text       x:i16 y:i16 font:u8 color:u16 text:[u8] (utf8, the rest of the payload)
+//! 0x04 circle     x:i16 y:i16 diameter:u16 stroke_width:u8 stroke:u16 fill:u16 flags:u8
+//!                 (flags bit 0: fill, bit 1: stroke; x, y is the top left corner)
+//! 0x05 blit       x:i16 y:i16 width:u16 height:u16 pixels:[u16; width * height]
+//! ```
+
+pub const OP_CLEAR: u8 = 0x01;
+pub const OP_FILL_RECT: u8 = 0x02;
+pub const OP_TEXT: u8 = 0x03;
+pub const OP_CIRCLE: u8 = 0x04;
+pub const OP_BLIT: u8 = 0x05;
+
+pub const HEADER_LEN: usize = 3;
+
+pub const CIRCLE_FILL: u8 = 0x01;
+pub const CIRCLE_STROKE: u8 = 0x02;
+
+/// Largest payload the board accepts. Larger images must be sent as several
+/// blits.
+pub const MAX_PAYLOAD: usize = 2048;
+
+const BLIT_HEADER_LEN: usize = 8;
+
+/// The most pixels in a single blit
+pub const MAX_BLIT_PIXELS: usize = (MAX_PAYLOAD - BLIT_HEADER_LEN) / 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command<'a> {
+    Clear {
+        color: u16,
+    },
+    FillRect {
+        x: i16,
+        y: i16,
+        width: u16,
+        height: u16,
+        color: u16,
+    },
+    Text {
+        x: i16,
+        y: i16,
+        font: u8,
+        color: u16,
+        text: &'a str,
+    },
+    Circle {
+        x: i16,
+        y: i16,
+        diameter: u16,
+        stroke_width: u8,
+        stroke: Option<u16>,
+        fill: Option<u16>,
+    },
+    Blit {
+        x: i16,
+        y: i16,
+        width: u16,
+        height: u16,
+        /// Big endian RGB565, row major
+        pixels: &'a [u8],
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    UnknownOpcode(u8),
+    /// The payload length doesn't suit the command
+    BadLength,
+    PayloadTooLarge,
+    BadUtf8,
+    /// The output buffer is too small for the encoded command
+    BufferTooSmall,
+}
+
+/// Status codes sent back by the board
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum ErrorCode {
+    Ok = 0,
+    OutOfBounds = 1,
+    UnknownFont = 2,
+}
+
+impl ErrorCode {
+    pub fn from_u8(b: u8) -> Option<ErrorCode> {
+        match b {
+            0 => Some(ErrorCode::Ok),
+            1 => Some(ErrorCode::OutOfBounds),
+            2 => Some(ErrorCode::UnknownFont),
+            _ => None,
+        }
+    }
+}
+
+/// The length of a blit's pixels in bytes, if a `usize` can hold it
+fn blit_len(width: u16, height: u16) -> Result<usize, Error> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(2))
+        .ok_or(Error::PayloadTooLarge)
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() < n {
+            return Err(Error::BadLength);
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16, Error> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn finish(&self) -> Result<(), Error> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::BadLength)
+        }
+    }
+}
+
+/// Decode a command from the start of `buf`, returning it and its length,
+/// or None if `buf` doesn't yet hold a complete command.
+pub fn decode(buf: &[u8]) -> Result<Option<(Command<'_>, usize)>, Error> {
+    if buf.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    let opcode = buf[0];
+    let len = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+    if len > MAX_PAYLOAD {
+        return Err(Error::PayloadTooLarge);
+    }
+    if buf.len() < HEADER_LEN + len {
+        return Ok(None);
+    }
+    let mut r = Reader {
+        buf: &buf[HEADER_LEN..HEADER_LEN + len],
+    };
+    let command = match opcode {
+        OP_CLEAR => Command::Clear { color: r.u16()? },
+        OP_FILL_RECT => Command::FillRect {
+            x: r.i16()?,
+            y: r.i16()?,
+            width: r.u16()?,
+            height: r.u16()?,
+            color: r.u16()?,
+        },
+        OP_TEXT => {
+            let x = r.i16()?;
+            let y = r.i16()?;
+            let font = r.u8()?;
+            let color = r.u16()?;
+            let text = r.take(r.buf.len())?;
+            let text = core::str::from_utf8(text).map_err(|_| Error::BadUtf8)?;
+            Command::Text {
+                x,
+                y,
+                font,
+                color,
+                text,
+            }
+        }
+        OP_CIRCLE => {
+            let x = r.i16()?;
+            let y = r.i16()?;
+            let diameter = r.u16()?;
+            let stroke_width = r.u8()?;
+            let stroke = r.u16()?;
+            let fill = r.u16()?;
+            let flags = r.u8()?;
+            Command::Circle {
+                x,
+                y,
+                diameter,
+                stroke_width,
+                stroke: (flags & CIRCLE_STROKE != 0).then_some(stroke),
+                fill: (flags & CIRCLE_FILL != 0).then_some(fill),
+            }
+        }
+        OP_BLIT => {
+            let x = r.i16()?;
+            let y = r.i16()?;
+            let width = r.u16()?;
+            let height = r.u16()?;
+            let pixels = r.take(blit_len(width, height)?)?;
+            Command::Blit {
+                x,
+                y,
+                width,
+                height,
+                pixels,
+            }
+        }
+        other => return Err(Error::UnknownOpcode(other)),
+    };
+    r.finish()?;
+    Ok(Some((command, HEADER_LEN + len)))
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, v: u8) -> Result<(), Error> {
+        self.put(&[v])
+    }
+
+    fn u16(&mut self, v: u16) -> Result<(), Error> {
+        self.put(&v.to_be_bytes())
+    }
+
+    fn i16(&mut self, v: i16) -> Result<(), Error> {
+        self.put(&v.to_be_bytes())
+    }
+}
+
+impl<'a> Command<'a> {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Command::Clear { .. } => OP_CLEAR,
+            Command::FillRect { .. } => OP_FILL_RECT,
+            Command::Text { .. } => OP_TEXT,
+            Command::Circle { .. } => OP_CIRCLE,
+            Command::Blit { .. } => OP_BLIT,
+        }
+    }
+
+    /// Length of the encoded command, including its header
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN
+            + match self {
+                Command::Clear { .. } => 2,
+                Command::FillRect { .. } => 10,
+                Command::Text { text, .. } => 7 + text.len(),
+                Command::Circle { .. } => 12,
+                Command::Blit { pixels, .. } => BLIT_HEADER_LEN + pixels.len(),
+            }
+    }
+
+    /// Encode the command into `buf`, returning the encoded length
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let payload_len = self.encoded_len() - HEADER_LEN;
+        if payload_len > MAX_PAYLOAD {
+            return Err(Error::PayloadTooLarge);
+        }
+        let mut w = Writer { buf, len: 0 };
+        w.u8(self.opcode())?;
+        w.u16(payload_len as u16)?;
+        match *self {
+            Command::Clear { color } => w.u16(color)?,
+            Command::FillRect {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => {
+                w.i16(x)?;
+                w.i16(y)?;
+                w.u16(width)?;
+                w.u16(height)?;
+                w.u16(color)?;
+            }
+            Command::Text {
+                x,
+                y,
+                font,
+                color,
+                text,
+            } => {
+                w.i16(x)?;
+                w.i16(y)?;
+                w.u8(font)?;
+                w.u16(color)?;
+                w.put(text.as_bytes())?;
+            }
+            Command::Circle {
+                x,
+                y,
+                diameter,
+                stroke_width,
+                stroke,
+                fill,
+            } => {
+                w.i16(x)?;
+                w.i16(y)?;
+                w.u16(diameter)?;
+                w.u8(stroke_width)?;
+                w.u16(stroke.unwrap_or(0))?;
+                w.u16(fill.unwrap_or(0))?;
+                let mut flags = 0;
+                if fill.is_some() {
+                    flags |= CIRCLE_FILL;
+                }
+                if stroke.is_some() {
+                    flags |= CIRCLE_STROKE;
+                }
+                w.u8(flags)?;
+            }
+            Command::Blit {
+                x,
+                y,
+                width,
+                height,
+                pixels,
+            } => {
+                if pixels.len() != blit_len(width, height)? {
+                    return Err(Error::BadLength);
+                }
+                w.i16(x)?;
+                w.i16(y)?;
+                w.u16(width)?;
+                w.u16(height)?;
+                w.put(pixels)?;
+            }
+        }
+        Ok(w.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(command: Command) {
+        let mut buf = [0; HEADER_LEN + MAX_PAYLOAD + 1];
+        let len = command.encode(&mut buf).unwrap();
+        assert_eq!(len, command.encoded_len());
+        assert_eq!(decode(&buf[..len]), Ok(Some((command, len))));
+        // Incomplete until the last byte
+        for end in 0..len {
+            assert_eq!(decode(&buf[..end]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(Command::Clear { color: 0xf800 });
+        round_trip(Command::FillRect {
+            x: -5,
+            y: 300,
+            width: 10,
+            height: 65535,
+            color: 0x07e0,
+        });
+        round_trip(Command::Text {
+            x: 1,
+            y: -1,
+            font: 4,
+            color: 0xffff,
+            text: "héllo",
+        });
+        round_trip(Command::Text {
+            x: 0,
+            y: 0,
+            font: 0,
+            color: 0,
+            text: "",
+        });
+        round_trip(Command::Circle {
+            x: 10,
+            y: 20,
+            diameter: 30,
+            stroke_width: 3,
+            stroke: Some(0x001f),
+            fill: None,
+        });
+        round_trip(Command::Circle {
+            x: 10,
+            y: 20,
+            diameter: 30,
+            stroke_width: 0,
+            stroke: None,
+            fill: Some(0x1234),
+        });
+        round_trip(Command::Blit {
+            x: 3,
+            y: 4,
+            width: 2,
+            height: 2,
+            pixels: &[1, 2, 3, 4, 5, 6, 7, 8],
+        });
+        let pixels = [0xaa; MAX_BLIT_PIXELS * 2];
+        round_trip(Command::Blit {
+            x: 0,
+            y: 0,
+            width: MAX_BLIT_PIXELS as u16,
+            height: 1,
+            pixels: &pixels,
+        });
+    }
+
+    #[test]
+    fn decodes_consecutive_commands() {
+        let mut buf = [0; 64];
+        let first = Command::Clear { color: 1 };
+        let second = Command::Text {
+            x: 0,
+            y: 0,
+            font: 1,
+            color: 2,
+            text: "hi",
+        };
+        let n = first.encode(&mut buf).unwrap();
+        let m = second.encode(&mut buf[n..]).unwrap();
+        assert_eq!(decode(&buf[..n + m]), Ok(Some((first, n))));
+        assert_eq!(decode(&buf[n..n + m]), Ok(Some((second, m))));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert_eq!(decode(&[0x7f, 0, 0]), Err(Error::UnknownOpcode(0x7f)));
+        assert_eq!(decode(&[OP_CLEAR, 0, 1, 0]), Err(Error::BadLength));
+        assert_eq!(decode(&[OP_CLEAR, 0, 3, 0, 0, 0]), Err(Error::BadLength));
+        assert_eq!(decode(&[OP_CLEAR, 0x08, 0x01]), Err(Error::PayloadTooLarge));
+        assert_eq!(decode(&[OP_TEXT, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0xff]), Err(Error::BadUtf8));
+        // The pixels don't match the size
+        assert_eq!(
+            decode(&[OP_BLIT, 0, 10, 0, 0, 0, 0, 0, 1, 0, 2, 0, 0]),
+            Err(Error::BadLength)
+        );
+        // A size far beyond the payload
+        assert_eq!(
+            decode(&[OP_BLIT, 0, 8, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]),
+            Err(Error::BadLength)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_encodes() {
+        let mut buf = [0; HEADER_LEN + MAX_PAYLOAD + 2];
+        let blit = Command::Blit {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 1,
+            pixels: &[0; 2],
+        };
+        assert_eq!(blit.encode(&mut buf), Err(Error::BadLength));
+        let pixels = [0; MAX_BLIT_PIXELS * 2 + 2];
+        let blit = Command::Blit {
+            x: 0,
+            y: 0,
+            width: MAX_BLIT_PIXELS as u16 + 1,
+            height: 1,
+            pixels: &pixels,
+        };
+        assert_eq!(blit.encode(&mut buf), Err(Error::PayloadTooLarge));
+        assert_eq!(Command::Clear { color: 0 }.encode(&mut buf[..4]), Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    fn largest_blit_len() {
+        assert_eq!(
+            blit_len(u16::MAX, u16::MAX).ok(),
+            (u16::MAX as u64 * u16::MAX as u64 * 2).try_into().ok()
+        );
+    }
+}
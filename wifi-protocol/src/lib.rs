@@ -0,0 +1,13 @@
+//! Wire formats shared between the wifi-example firmware and host tools.
+#![no_std]
+
+pub mod beacon;
+pub mod blob;
+pub mod crc32;
+pub mod draw;
+pub mod metrics;
+pub mod mirror;
+pub mod ota;
+pub mod sha1;
+pub mod telemetry;
+pub mod websocket;
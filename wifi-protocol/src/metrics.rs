@@ -0,0 +1,314 @@
+//! The Prometheus text exposition format (version 0.0.4), as served from the
+//! board's `/metrics` endpoint, eg
+//!
+//! ```text
+//! # HELP echo_connections_total Connections accepted by the echo server.
+//! # TYPE echo_connections_total counter
+//! echo_connections_total 3
+//! # HELP display_refresh_seconds Time taken to redraw the display.
+//! # TYPE display_refresh_seconds histogram
+//! display_refresh_seconds_bucket{le="0.01"} 0
+//! display_refresh_seconds_bucket{le="0.05"} 12
+//! display_refresh_seconds_bucket{le="+Inf"} 14
+//! display_refresh_seconds_sum 0.734
+//! display_refresh_seconds_count 14
+//! ```
+//!
+//! Values are integers, or fixed point with a given number of decimals, so
+//! the board needn't format floats.
+
+use core::fmt::{self, Write};
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+/// A label name and value
+pub type Label<'a> = (&'a str, &'a str);
+
+/// A histogram's observations
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Histogram<'a> {
+    /// The buckets' upper bounds, ascending
+    pub bounds: &'a [u32],
+    /// The observations in each bucket, not cumulative. Each counts those no
+    /// larger than its bound, and larger than the one before.
+    pub counts: &'a [u32],
+    /// Observations larger than the last bound
+    pub overflow: u32,
+    pub sum: u64,
+    /// The bounds and sum are in units of 10^-decimals, eg 3 for milliseconds
+    /// where the metric is in seconds. At most 19.
+    pub decimals: u8,
+}
+
+impl Histogram<'_> {
+    pub fn count(&self) -> u64 {
+        self.counts.iter().map(|&c| c as u64).sum::<u64>() + self.overflow as u64
+    }
+}
+
+/// Writes metrics to `out`. Metric and label names aren't checked, so must
+/// be valid: `[a-zA-Z_:][a-zA-Z0-9_:]*` and `[a-zA-Z_][a-zA-Z0-9_]*`.
+pub struct Encoder<W> {
+    out: W,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(out: W) -> Encoder<W> {
+        Encoder { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Describe a metric, once before its samples
+    pub fn family(&mut self, name: &str, kind: Kind, help: &str) -> fmt::Result {
+        self.out.write_str("# HELP ")?;
+        self.out.write_str(name)?;
+        self.out.write_str(" ")?;
+        self.escaped(help, false)?;
+        self.out.write_str("\n# TYPE ")?;
+        self.out.write_str(name)?;
+        self.out.write_str(" ")?;
+        self.out.write_str(kind.name())?;
+        self.out.write_str("\n")
+    }
+
+    /// A counter or gauge sample
+    pub fn sample(&mut self, name: &str, labels: &[Label], value: i64) -> fmt::Result {
+        self.series(name, "", labels, None)?;
+        self.integer(value)?;
+        self.out.write_str("\n")
+    }
+
+    /// A histogram's buckets, sum and count
+    pub fn histogram(&mut self, name: &str, labels: &[Label], histogram: &Histogram) -> fmt::Result {
+        let mut cumulative = 0;
+        for (&bound, &count) in histogram.bounds.iter().zip(histogram.counts) {
+            cumulative += count as u64;
+            self.series(name, "_bucket", labels, Some(Le::Bound(bound, histogram.decimals)))?;
+            self.integer(cumulative as i64)?;
+            self.out.write_str("\n")?;
+        }
+        let count = histogram.count();
+        self.series(name, "_bucket", labels, Some(Le::Inf))?;
+        self.integer(count as i64)?;
+        self.out.write_str("\n")?;
+
+        self.series(name, "_sum", labels, None)?;
+        self.fixed(histogram.sum, histogram.decimals)?;
+        self.out.write_str("\n")?;
+        self.series(name, "_count", labels, None)?;
+        self.integer(count as i64)?;
+        self.out.write_str("\n")
+    }
+
+    /// Write the series name and labels, up to the value
+    fn series(&mut self, name: &str, suffix: &str, labels: &[Label], le: Option<Le>) -> fmt::Result {
+        self.out.write_str(name)?;
+        self.out.write_str(suffix)?;
+        if !labels.is_empty() || le.is_some() {
+            self.out.write_str("{")?;
+            for (i, (label, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.out.write_str(",")?;
+                }
+                self.out.write_str(label)?;
+                self.out.write_str("=\"")?;
+                self.escaped(value, true)?;
+                self.out.write_str("\"")?;
+            }
+            if let Some(le) = le {
+                if !labels.is_empty() {
+                    self.out.write_str(",")?;
+                }
+                self.out.write_str("le=\"")?;
+                match le {
+                    Le::Bound(bound, decimals) => self.fixed(bound as u64, decimals)?,
+                    Le::Inf => self.out.write_str("+Inf")?,
+                }
+                self.out.write_str("\"")?;
+            }
+            self.out.write_str("}")?;
+        }
+        self.out.write_str(" ")
+    }
+
+    /// Write help text, or a label value if `quoted`, escaping as the format
+    /// requires
+    fn escaped(&mut self, s: &str, quoted: bool) -> fmt::Result {
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            let escape = match c {
+                '\\' => "\\\\",
+                '\n' => "\\n",
+                '"' if quoted => "\\\"",
+                _ => continue,
+            };
+            self.out.write_str(&s[start..i])?;
+            self.out.write_str(escape)?;
+            start = i + 1;
+        }
+        self.out.write_str(&s[start..])
+    }
+
+    fn integer(&mut self, n: i64) -> fmt::Result {
+        if n < 0 {
+            self.out.write_str("-")?;
+        }
+        self.unsigned(n.unsigned_abs())
+    }
+
+    fn unsigned(&mut self, mut n: u64) -> fmt::Result {
+        let mut digits = [0; 20];
+        let mut i = digits.len();
+        loop {
+            i -= 1;
+            digits[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        // Only ascii digits were written
+        self.out.write_str(core::str::from_utf8(&digits[i..]).unwrap_or_default())
+    }
+
+    /// Write `n` * 10^-decimals, without trailing zeros
+    fn fixed(&mut self, n: u64, decimals: u8) -> fmt::Result {
+        let scale = 10u64.pow(decimals as u32);
+        self.unsigned(n / scale)?;
+        let mut fraction = n % scale;
+        let mut digits = [0; 19];
+        let digits = &mut digits[..decimals as usize];
+        for d in digits.iter_mut().rev() {
+            *d = b'0' + (fraction % 10) as u8;
+            fraction /= 10;
+        }
+        match digits.iter().rposition(|&d| d != b'0') {
+            Some(last) => {
+                self.out.write_str(".")?;
+                // Only ascii digits were written
+                self.out.write_str(core::str::from_utf8(&digits[..=last]).unwrap_or_default())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Le {
+    Bound(u32, u8),
+    Inf,
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+
+    use super::*;
+
+    fn encode(f: impl FnOnce(&mut Encoder<String>) -> fmt::Result) -> String {
+        let mut encoder = Encoder::new(String::new());
+        f(&mut encoder).unwrap();
+        encoder.into_inner()
+    }
+
+    #[test]
+    fn encodes_counters() {
+        let text = encode(|e| {
+            e.family("echo_connections_total", Kind::Counter, "Connections accepted by the echo server.")?;
+            e.sample("echo_connections_total", &[], 3)
+        });
+        assert_eq!(
+            text,
+            "# HELP echo_connections_total Connections accepted by the echo server.\n\
+             # TYPE echo_connections_total counter\n\
+             echo_connections_total 3\n"
+        );
+    }
+
+    #[test]
+    fn encodes_gauges() {
+        let text = encode(|e| {
+            e.family("wifi_rssi_dbm", Kind::Gauge, "Signal strength.")?;
+            e.sample("wifi_rssi_dbm", &[("ssid", "home"), ("band", "2.4")], -61)?;
+            e.sample("wifi_rssi_dbm", &[("ssid", "home"), ("band", "5")], 0)?;
+            e.sample("wifi_rssi_dbm", &[], i64::MIN)
+        });
+        assert_eq!(
+            text,
+            "# HELP wifi_rssi_dbm Signal strength.\n\
+             # TYPE wifi_rssi_dbm gauge\n\
+             wifi_rssi_dbm{ssid=\"home\",band=\"2.4\"} -61\n\
+             wifi_rssi_dbm{ssid=\"home\",band=\"5\"} 0\n\
+             wifi_rssi_dbm -9223372036854775808\n"
+        );
+    }
+
+    #[test]
+    fn encodes_histograms() {
+        let histogram = Histogram {
+            bounds: &[10, 50, 1000],
+            counts: &[0, 12, 1],
+            overflow: 1,
+            sum: 734,
+            decimals: 3,
+        };
+        let text = encode(|e| {
+            e.family("display_refresh_seconds", Kind::Histogram, "Time taken to redraw the display.")?;
+            e.histogram("display_refresh_seconds", &[], &histogram)?;
+            e.histogram("display_refresh_seconds", &[("panel", "main")], &Histogram { sum: 5000, ..histogram })
+        });
+        assert_eq!(
+            text,
+            "# HELP display_refresh_seconds Time taken to redraw the display.\n\
+             # TYPE display_refresh_seconds histogram\n\
+             display_refresh_seconds_bucket{le=\"0.01\"} 0\n\
+             display_refresh_seconds_bucket{le=\"0.05\"} 12\n\
+             display_refresh_seconds_bucket{le=\"1\"} 13\n\
+             display_refresh_seconds_bucket{le=\"+Inf\"} 14\n\
+             display_refresh_seconds_sum 0.734\n\
+             display_refresh_seconds_count 14\n\
+             display_refresh_seconds_bucket{panel=\"main\",le=\"0.01\"} 0\n\
+             display_refresh_seconds_bucket{panel=\"main\",le=\"0.05\"} 12\n\
+             display_refresh_seconds_bucket{panel=\"main\",le=\"1\"} 13\n\
+             display_refresh_seconds_bucket{panel=\"main\",le=\"+Inf\"} 14\n\
+             display_refresh_seconds_sum{panel=\"main\"} 5\n\
+             display_refresh_seconds_count{panel=\"main\"} 14\n"
+        );
+    }
+
+    #[test]
+    fn escapes_help_and_labels() {
+        let text = encode(|e| {
+            e.family("shell_commands_total", Kind::Counter, "Commands \"run\"\\typed\nover the shell.")?;
+            e.sample("shell_commands_total", &[("command", "say \"hi\\\"\nthere")], 1)
+        });
+        assert_eq!(
+            text,
+            "# HELP shell_commands_total Commands \"run\"\\\\typed\\nover the shell.\n\
+             # TYPE shell_commands_total counter\n\
+             shell_commands_total{command=\"say \\\"hi\\\\\\\"\\nthere\"} 1\n"
+        );
+    }
+}
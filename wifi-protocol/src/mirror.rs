@@ -0,0 +1,154 @@
+//! Framing for streaming the display contents.
+//!
+//! The stream starts with a hello message giving the display size, followed
+//! by rectangles of pixels. All integers are big endian.
+//!
+//! ```text
+//! hello: 'H' width:u16 height:u16
+//! rect:  'R' x:u16 y:u16 width:u16 height:u16 pixels:[u16; width * height]
+//! ```
+//!
+//! Pixels are RGB565, in row major order.
+
+pub const HELLO: u8 = b'H';
+pub const RECT: u8 = b'R';
+
+pub const HELLO_LEN: usize = 5;
+pub const RECT_HEADER_LEN: usize = 9;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RectHeader {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl RectHeader {
+    /// Length of the pixel data following the header, in bytes, or None if
+    /// that's more than a `usize` can hold
+    pub fn pixels_len(&self) -> Option<usize> {
+        (self.width as usize).checked_mul(self.height as usize)?.checked_mul(2)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Message<'a> {
+    Hello { width: u16, height: u16 },
+    Rect { header: RectHeader, pixels: &'a [u8] },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    UnknownMessage(u8),
+    /// The rectangle's pixels are too many to address
+    TooLarge,
+}
+
+pub fn encode_hello(width: u16, height: u16) -> [u8; HELLO_LEN] {
+    let w = width.to_be_bytes();
+    let h = height.to_be_bytes();
+    [HELLO, w[0], w[1], h[0], h[1]]
+}
+
+pub fn encode_rect_header(header: &RectHeader) -> [u8; RECT_HEADER_LEN] {
+    let mut buf = [0u8; RECT_HEADER_LEN];
+    buf[0] = RECT;
+    buf[1..3].copy_from_slice(&header.x.to_be_bytes());
+    buf[3..5].copy_from_slice(&header.y.to_be_bytes());
+    buf[5..7].copy_from_slice(&header.width.to_be_bytes());
+    buf[7..9].copy_from_slice(&header.height.to_be_bytes());
+    buf
+}
+
+fn be16(buf: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([buf[at], buf[at + 1]])
+}
+
+/// Decode a message from the start of `buf`, returning it and its length,
+/// or None if `buf` doesn't yet hold a complete message.
+pub fn decode(buf: &[u8]) -> Result<Option<(Message<'_>, usize)>, Error> {
+    match buf.first() {
+        None => Ok(None),
+        Some(&HELLO) => {
+            if buf.len() < HELLO_LEN {
+                return Ok(None);
+            }
+            let message = Message::Hello {
+                width: be16(buf, 1),
+                height: be16(buf, 3),
+            };
+            Ok(Some((message, HELLO_LEN)))
+        }
+        Some(&RECT) => {
+            if buf.len() < RECT_HEADER_LEN {
+                return Ok(None);
+            }
+            let header = RectHeader {
+                x: be16(buf, 1),
+                y: be16(buf, 3),
+                width: be16(buf, 5),
+                height: be16(buf, 7),
+            };
+            let len = header
+                .pixels_len()
+                .and_then(|len| len.checked_add(RECT_HEADER_LEN))
+                .ok_or(Error::TooLarge)?;
+            if buf.len() < len {
+                return Ok(None);
+            }
+            let message = Message::Rect {
+                header,
+                pixels: &buf[RECT_HEADER_LEN..len],
+            };
+            Ok(Some((message, len)))
+        }
+        Some(&other) => Err(Error::UnknownMessage(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let hello = encode_hello(320, 240);
+        assert_eq!(
+            decode(&hello),
+            Ok(Some((Message::Hello { width: 320, height: 240 }, HELLO_LEN)))
+        );
+
+        let header = RectHeader {
+            x: 1,
+            y: 2,
+            width: 2,
+            height: 1,
+        };
+        let mut buf = encode_rect_header(&header).to_vec();
+        buf.extend_from_slice(&[0xf8, 0x00, 0x07, 0xe0, b'H']);
+        let message = Message::Rect {
+            header,
+            pixels: &[0xf8, 0x00, 0x07, 0xe0],
+        };
+        assert_eq!(decode(&buf), Ok(Some((message, RECT_HEADER_LEN + 4))));
+        assert_eq!(decode(&buf[..RECT_HEADER_LEN + 3]), Ok(None));
+        assert_eq!(decode(&buf[..RECT_HEADER_LEN - 1]), Ok(None));
+        assert_eq!(decode(&[]), Ok(None));
+        assert_eq!(decode(b"X"), Err(Error::UnknownMessage(b'X')));
+    }
+
+    #[test]
+    fn largest_rect_length() {
+        let header = RectHeader {
+            x: 0,
+            y: 0,
+            width: u16::MAX,
+            height: u16::MAX,
+        };
+        let expected = (u16::MAX as u64 * u16::MAX as u64 * 2).try_into().ok();
+        assert_eq!(header.pixels_len(), expected);
+        // Not enough data, or too large to ever be
+        assert!(matches!(decode(&encode_rect_header(&header)), Ok(None) | Err(Error::TooLarge)));
+    }
+}
@@ -0,0 +1,101 @@
+//! Framing for pushing a firmware update to the board.
+//!
+//! On connecting, the board sends a random challenge. The client replies
+//! with a header and a tag covering it, then the image: the raw application
+//! binary, linked to run from the active slot, and a tag covering it all.
+//! The board checks the header's tag before it touches flash, and the final
+//! tag and the image before it requests the update, then replies with a line
+//! of text, `ok` or `error: <reason>`. All integers are little endian.
+//!
+//! ```text
+//! challenge:  [u8; 16]
+//! header:     magic:[u8; 4] = "OTA2" length:u32 crc32:u32 reserved:u32 = 0
+//! header tag: [u8; 32] = HMAC-SHA256(key, challenge || header)
+//! image:      [u8; length]
+//! tag:        [u8; 32] = HMAC-SHA256(key, challenge || header || image)
+//! ```
+//!
+//! The key is the board's TLS pre-shared key, so only clients holding it can
+//! update the board, and a board without one takes no updates. The challenge
+//! keeps a recorded update from being replayed. The CRC guards against
+//! corruption in flash: the bootloader checks it again before swapping the
+//! update in.
+
+use crate::crc32::crc32;
+
+pub const MAGIC: [u8; 4] = *b"OTA2";
+
+pub const CHALLENGE_LEN: usize = 16;
+
+pub const HEADER_LEN: usize = 16;
+
+pub const TAG_LEN: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ImageHeader {
+    pub length: u32,
+    pub crc32: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    BadMagic,
+    /// The reserved field isn't zero, probably a newer format
+    Unsupported,
+}
+
+impl ImageHeader {
+    /// The header for `image`
+    pub fn for_image(image: &[u8]) -> ImageHeader {
+        ImageHeader {
+            length: image.len() as u32,
+            crc32: crc32(image),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&self.length.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.crc32.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8; HEADER_LEN]) -> Result<ImageHeader, Error> {
+        let word = |at: usize| u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+        if buf[0..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if word(12) != 0 {
+            return Err(Error::Unsupported);
+        }
+        Ok(ImageHeader {
+            length: word(4),
+            crc32: word(8),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_headers() {
+        let header = ImageHeader::for_image(b"123456789");
+        assert_eq!(header.crc32, 0xcbf43926);
+        let buf = header.encode();
+        assert_eq!(&buf[..4], b"OTA2");
+        assert_eq!(ImageHeader::decode(&buf), Ok(header));
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        let mut buf = ImageHeader::for_image(b"image").encode();
+        buf[12] = 1;
+        assert_eq!(ImageHeader::decode(&buf), Err(Error::Unsupported));
+        buf[12] = 0;
+        buf[..4].copy_from_slice(b"GET ");
+        assert_eq!(ImageHeader::decode(&buf), Err(Error::BadMagic));
+    }
+}
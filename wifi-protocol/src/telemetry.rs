@@ -0,0 +1,207 @@
+//! The telemetry stream, a datagram for each button, touch and LED event on
+//! the board, sent to a listener named in the board's configuration. All
+//! integers are big endian.
+//!
+//! ```text
+//! event: magic:[u8; 4] = "PWTL" seq:u32 uptime_ms:u32 kind:u8 payload
+//!
+//! 0x01 button  pressed:u8
+//! 0x02 tap     x:i16 y:i16
+//! 0x03 swipe   direction:u8 (0 left, 1 right, 2 up, 3 down)
+//! 0x04 led     mode:u8 (0 off, 1 on, 2 blink) period_ms:u16 (0 unless blinking)
+//! ```
+//!
+//! The sequence number goes up by one for each event, so a listener can tell
+//! when events have been lost.
+
+pub const MAGIC: [u8; 4] = *b"PWTL";
+
+/// The listener's port, unless configured otherwise
+pub const DEFAULT_PORT: u16 = 1236;
+
+pub const KIND_BUTTON: u8 = 0x01;
+pub const KIND_TAP: u8 = 0x02;
+pub const KIND_SWIPE: u8 = 0x03;
+pub const KIND_LED: u8 = 0x04;
+
+const HEADER_LEN: usize = 13;
+
+/// Longest message, a tap or an LED change
+pub const MAX_LEN: usize = HEADER_LEN + 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Left = 0,
+    Right = 1,
+    Up = 2,
+    Down = 3,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Led {
+    Off,
+    On,
+    Blink { period_ms: u16 },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    Button { pressed: bool },
+    Tap { x: i16, y: i16 },
+    Swipe(Direction),
+    Led(Led),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Message {
+    pub seq: u32,
+    pub uptime_ms: u32,
+    pub event: Event,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    BadMagic,
+    UnknownKind(u8),
+    /// The payload length or a field doesn't suit the event
+    BadPayload,
+}
+
+impl Message {
+    /// Encode the message into `buf`, returning its length
+    pub fn encode(&self, buf: &mut [u8; MAX_LEN]) -> usize {
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&self.seq.to_be_bytes());
+        buf[8..12].copy_from_slice(&self.uptime_ms.to_be_bytes());
+        let payload = &mut buf[HEADER_LEN..];
+        let (kind, len) = match self.event {
+            Event::Button { pressed } => {
+                payload[0] = pressed as u8;
+                (KIND_BUTTON, 1)
+            }
+            Event::Tap { x, y } => {
+                payload[0..2].copy_from_slice(&x.to_be_bytes());
+                payload[2..4].copy_from_slice(&y.to_be_bytes());
+                (KIND_TAP, 4)
+            }
+            Event::Swipe(direction) => {
+                payload[0] = direction as u8;
+                (KIND_SWIPE, 1)
+            }
+            Event::Led(led) => {
+                let (mode, period_ms) = match led {
+                    Led::Off => (0, 0u16),
+                    Led::On => (1, 0),
+                    Led::Blink { period_ms } => (2, period_ms),
+                };
+                payload[0] = mode;
+                payload[1..3].copy_from_slice(&period_ms.to_be_bytes());
+                (KIND_LED, 3)
+            }
+        };
+        buf[12] = kind;
+        HEADER_LEN + len
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Message, Error> {
+        if buf.get(0..4) != Some(&MAGIC[..]) {
+            return Err(Error::BadMagic);
+        }
+        let header = buf.get(..HEADER_LEN).ok_or(Error::BadPayload)?;
+        let word = |at: usize| u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+        let payload = &buf[HEADER_LEN..];
+        let event = match (header[12], payload) {
+            (KIND_BUTTON, &[pressed]) => Event::Button { pressed: pressed != 0 },
+            (KIND_TAP, &[x0, x1, y0, y1]) => Event::Tap {
+                x: i16::from_be_bytes([x0, x1]),
+                y: i16::from_be_bytes([y0, y1]),
+            },
+            (KIND_SWIPE, &[direction]) => Event::Swipe(match direction {
+                0 => Direction::Left,
+                1 => Direction::Right,
+                2 => Direction::Up,
+                3 => Direction::Down,
+                _ => return Err(Error::BadPayload),
+            }),
+            (KIND_LED, &[mode, p0, p1]) => Event::Led(match mode {
+                0 => Led::Off,
+                1 => Led::On,
+                2 => Led::Blink {
+                    period_ms: u16::from_be_bytes([p0, p1]),
+                },
+                _ => return Err(Error::BadPayload),
+            }),
+            (KIND_BUTTON | KIND_TAP | KIND_SWIPE | KIND_LED, _) => return Err(Error::BadPayload),
+            (kind, _) => return Err(Error::UnknownKind(kind)),
+        };
+        Ok(Message {
+            seq: word(4),
+            uptime_ms: word(8),
+            event,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(event: Event) -> Message {
+        Message {
+            seq: 0x0102_0304,
+            uptime_ms: 0xa0b0_c0d0,
+            event,
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let header = *b"PWTL\x01\x02\x03\x04\xa0\xb0\xc0\xd0";
+        for (event, kind, payload) in [
+            (Event::Button { pressed: true }, KIND_BUTTON, &[1][..]),
+            (Event::Button { pressed: false }, KIND_BUTTON, &[0]),
+            (Event::Tap { x: 319, y: -1 }, KIND_TAP, &[0x01, 0x3f, 0xff, 0xff]),
+            (Event::Swipe(Direction::Left), KIND_SWIPE, &[0]),
+            (Event::Swipe(Direction::Down), KIND_SWIPE, &[3]),
+            (Event::Led(Led::Off), KIND_LED, &[0, 0, 0]),
+            (Event::Led(Led::On), KIND_LED, &[1, 0, 0]),
+            (Event::Led(Led::Blink { period_ms: 500 }), KIND_LED, &[2, 0x01, 0xf4]),
+        ] {
+            let mut buf = [0; MAX_LEN];
+            let len = message(event).encode(&mut buf);
+            assert_eq!(buf[..12], header);
+            assert_eq!(buf[12], kind);
+            assert_eq!(buf[HEADER_LEN..len], *payload);
+            assert_eq!(Message::decode(&buf[..len]), Ok(message(event)));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_messages() {
+        let mut buf = [0; MAX_LEN];
+        let len = message(Event::Tap { x: 1, y: 2 }).encode(&mut buf);
+        for end in 4..len {
+            assert_eq!(Message::decode(&buf[..end]), Err(Error::BadPayload), "{}", end);
+        }
+        assert_eq!(Message::decode(&buf[..3]), Err(Error::BadMagic));
+        // Another format, or another version of this one
+        for magic in [b"PWTM", b"PWBC", b"pwtl"] {
+            let mut other = buf;
+            other[..4].copy_from_slice(magic);
+            assert_eq!(Message::decode(&other[..len]), Err(Error::BadMagic));
+        }
+
+        let mut long = [0; MAX_LEN + 1];
+        long[..len].copy_from_slice(&buf[..len]);
+        assert_eq!(Message::decode(&long[..len + 1]), Err(Error::BadPayload));
+
+        let len = message(Event::Swipe(Direction::Up)).encode(&mut buf);
+        buf[HEADER_LEN] = 4;
+        assert_eq!(Message::decode(&buf[..len]), Err(Error::BadPayload));
+        let len = message(Event::Led(Led::On)).encode(&mut buf);
+        buf[HEADER_LEN] = 3;
+        assert_eq!(Message::decode(&buf[..len]), Err(Error::BadPayload));
+        buf[12] = 0x05;
+        assert_eq!(Message::decode(&buf[..len]), Err(Error::UnknownKind(0x05)));
+    }
+}
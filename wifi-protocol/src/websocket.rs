@@ -0,0 +1,404 @@
+//! WebSocket framing (RFC 6455), for the server side, and the key that
+//! accepts the HTTP upgrade. All integers are big endian.
+//!
+//! ```text
+//! frame: fin:1 rsv:3 opcode:4 masked:1 len:7 [len:u16 | len:u64] [mask:[u8; 4]] payload
+//! ```
+//!
+//! A `len` of 126 or 127 means the real length follows as a u16 or a u64,
+//! and the shortest form has to be used. Frames from the client are masked,
+//! by xoring the payload with the mask; frames from the server aren't.
+//!
+//! Messages can be split over several frames, the first with the message's
+//! opcode and the rest continuations, the last marked `fin`. Control frames
+//! (close, ping and pong) can come between them, but aren't split.
+
+use crate::sha1::{Sha1, DIGEST_LEN};
+
+/// Appended to the client's key to make the accept key
+const GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Length of the `Sec-WebSocket-Accept` value
+pub const ACCEPT_LEN: usize = 28;
+
+pub const MAX_HEADER_LEN: usize = 14;
+
+/// Largest payload of a close, ping or pong
+pub const MAX_CONTROL_PAYLOAD: usize = 125;
+
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_UNSUPPORTED: u16 = 1003;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xa,
+}
+
+impl Opcode {
+    pub fn from_u8(value: u8) -> Option<Opcode> {
+        Some(match value {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xa => Opcode::Pong,
+            _ => return None,
+        })
+    }
+
+    pub fn is_control(&self) -> bool {
+        *self as u8 & 0x8 != 0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Reserved bits are set, without an extension to explain them
+    Reserved,
+    UnknownOpcode(u8),
+    /// A control frame that's split or too long
+    BadControlFrame,
+    /// A length that's out of range, or not in its shortest form
+    BadLength,
+    /// A frame from the client that isn't masked
+    Unmasked,
+    /// A continuation outside a message, or a new message inside one
+    UnexpectedContinuation,
+    /// A frame or message too large for the buffer
+    TooLarge,
+    /// A close payload with half a status code, or a reason that isn't utf-8
+    BadClose,
+}
+
+impl Error {
+    /// The status code to close the connection with
+    pub fn close_code(&self) -> u16 {
+        match self {
+            Error::TooLarge => CLOSE_TOO_BIG,
+            Error::BadClose => CLOSE_INVALID_DATA,
+            _ => CLOSE_PROTOCOL_ERROR,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Header {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub mask: Option<[u8; 4]>,
+    pub payload_len: u64,
+}
+
+impl Header {
+    /// Decode a header from the start of `buf`, returning it and its length,
+    /// or None if more is needed
+    pub fn decode(buf: &[u8]) -> Result<Option<(Header, usize)>, Error> {
+        let (first, second) = match buf {
+            [first, second, ..] => (*first, *second),
+            _ => return Ok(None),
+        };
+        if first & 0x70 != 0 {
+            return Err(Error::Reserved);
+        }
+        let fin = first & 0x80 != 0;
+        let opcode = Opcode::from_u8(first & 0x0f).ok_or(Error::UnknownOpcode(first & 0x0f))?;
+        let masked = second & 0x80 != 0;
+
+        let (payload_len, mut len) = match second & 0x7f {
+            126 => match buf.get(2..4) {
+                Some(b) => (u16::from_be_bytes([b[0], b[1]]) as u64, 4),
+                None => return Ok(None),
+            },
+            127 => match buf.get(2..10) {
+                Some(b) => (u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]), 10),
+                None => return Ok(None),
+            },
+            n => (n as u64, 2),
+        };
+        let shortest = match payload_len {
+            0..=125 => 2,
+            126..=0xffff => 4,
+            _ => 10,
+        };
+        if len != shortest || payload_len >> 63 != 0 {
+            return Err(Error::BadLength);
+        }
+        if opcode.is_control() && (!fin || payload_len > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(Error::BadControlFrame);
+        }
+
+        let mask = if masked {
+            let mask = match buf.get(len..len + 4) {
+                Some(m) => [m[0], m[1], m[2], m[3]],
+                None => return Ok(None),
+            };
+            len += 4;
+            Some(mask)
+        } else {
+            None
+        };
+        let header = Header {
+            fin,
+            opcode,
+            mask,
+            payload_len,
+        };
+        Ok(Some((header, len)))
+    }
+
+    /// Encode the header into `buf`, returning its length
+    pub fn encode(&self, buf: &mut [u8; MAX_HEADER_LEN]) -> usize {
+        buf[0] = (self.fin as u8) << 7 | self.opcode as u8;
+        let masked = (self.mask.is_some() as u8) << 7;
+        let mut len = match self.payload_len {
+            n @ 0..=125 => {
+                buf[1] = masked | n as u8;
+                2
+            }
+            n @ 126..=0xffff => {
+                buf[1] = masked | 126;
+                buf[2..4].copy_from_slice(&(n as u16).to_be_bytes());
+                4
+            }
+            n => {
+                buf[1] = masked | 127;
+                buf[2..10].copy_from_slice(&n.to_be_bytes());
+                10
+            }
+        };
+        if let Some(mask) = self.mask {
+            buf[len..len + 4].copy_from_slice(&mask);
+            len += 4;
+        }
+        len
+    }
+}
+
+/// Xor `data` with the mask, given its offset in the payload. Masking twice
+/// unmasks.
+pub fn apply_mask(mask: [u8; 4], offset: usize, data: &mut [u8]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[(offset + i) % 4];
+    }
+}
+
+/// A whole frame, unmasked
+#[derive(PartialEq, Eq, Debug)]
+pub struct Frame<'a> {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: &'a [u8],
+}
+
+/// Decode a whole frame from the client, from the start of `buf`, unmasking
+/// it in place. Returns the frame and its length, or None if more is needed.
+/// Frames with payloads longer than `max_payload` are refused, so the caller
+/// needn't wait for ones that wouldn't fit.
+pub fn decode_frame(buf: &mut [u8], max_payload: usize) -> Result<Option<(Frame<'_>, usize)>, Error> {
+    let (header, header_len) = match Header::decode(buf)? {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+    let mask = header.mask.ok_or(Error::Unmasked)?;
+    if header.payload_len > max_payload as u64 {
+        return Err(Error::TooLarge);
+    }
+    let len = header_len + header.payload_len as usize;
+    let payload = match buf.get_mut(header_len..len) {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    apply_mask(mask, 0, payload);
+    let frame = Frame {
+        fin: header.fin,
+        opcode: header.opcode,
+        payload,
+    };
+    Ok(Some((frame, len)))
+}
+
+/// Joins the frames of a message, in a buffer given by the caller
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Reassembly {
+    /// The opcode and length so far of a message that's been started
+    partial: Option<(Opcode, usize)>,
+}
+
+impl Reassembly {
+    pub const fn new() -> Reassembly {
+        Reassembly { partial: None }
+    }
+
+    /// Add a text, binary or continuation frame to the message in `buf`,
+    /// returning the message's opcode and contents once it's complete. The
+    /// same buffer has to be given for every frame of a message.
+    pub fn push<'b>(&mut self, frame: &Frame<'_>, buf: &'b mut [u8]) -> Result<Option<(Opcode, &'b [u8])>, Error> {
+        let (opcode, start) = match (frame.opcode, self.partial) {
+            (Opcode::Text | Opcode::Binary, None) => (frame.opcode, 0),
+            (Opcode::Continuation, Some(partial)) => partial,
+            (Opcode::Text | Opcode::Binary | Opcode::Continuation, _) => return Err(Error::UnexpectedContinuation),
+            _ => return Err(Error::BadControlFrame),
+        };
+        let end = start + frame.payload.len();
+        let dest = match buf.get_mut(start..end) {
+            Some(d) => d,
+            None => {
+                self.partial = None;
+                return Err(Error::TooLarge);
+            }
+        };
+        dest.copy_from_slice(frame.payload);
+        if frame.fin {
+            self.partial = None;
+            Ok(Some((opcode, &buf[..end])))
+        } else {
+            self.partial = Some((opcode, end));
+            Ok(None)
+        }
+    }
+}
+
+/// Decode the payload of a close frame, giving its status code and reason
+/// if it has them
+pub fn decode_close(payload: &[u8]) -> Result<Option<(u16, &str)>, Error> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(Error::BadClose),
+        [high, low, reason @ ..] => {
+            let reason = core::str::from_utf8(reason).map_err(|_| Error::BadClose)?;
+            Ok(Some((u16::from_be_bytes([*high, *low]), reason)))
+        }
+    }
+}
+
+/// Write the `Sec-WebSocket-Accept` value for the client's
+/// `Sec-WebSocket-Key` to `out`, returning it
+pub fn accept_key<'a>(key: &str, out: &'a mut [u8; ACCEPT_LEN]) -> &'a str {
+    let mut sha = Sha1::new();
+    sha.update(key.trim().as_bytes());
+    sha.update(GUID);
+    let digest = sha.finish();
+    base64(&digest, out);
+    // Base64 is ascii
+    core::str::from_utf8(out).unwrap()
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8; DIGEST_LEN], out: &mut [u8; ACCEPT_LEN]) {
+    // 20 bytes are six whole groups of three, then two bytes and a pad
+    for (chunk, out) in data.chunks(3).zip(out.chunks_exact_mut(4)) {
+        let b = [chunk[0], chunk[1], chunk.get(2).copied().unwrap_or(0)];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for (i, c) in out.iter_mut().enumerate() {
+            *c = BASE64[(n >> (18 - 6 * i) & 0x3f) as usize];
+        }
+        if chunk.len() < 3 {
+            out[3] = b'=';
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_rfc_example_key() {
+        let mut out = [0; ACCEPT_LEN];
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ==", &mut out), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        // Surrounding whitespace in the header's value is ignored
+        assert_eq!(accept_key(" dGhlIHNhbXBsZSBub25jZQ== ", &mut out), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn decodes_extended_lengths() {
+        let mut buf = [0; MAX_HEADER_LEN];
+        for (payload_len, header_len) in [(125, 6), (126, 8), (0xffff, 8), (0x10000, 14), (1 << 40, 14)] {
+            let header = Header {
+                fin: true,
+                opcode: Opcode::Binary,
+                mask: Some([1, 2, 3, 4]),
+                payload_len,
+            };
+            assert_eq!(header.encode(&mut buf), header_len);
+            assert_eq!(Header::decode(&buf[..header_len]), Ok(Some((header, header_len))));
+            assert_eq!(Header::decode(&buf[..header_len - 1]), Ok(None));
+        }
+
+        // 126 and 127 give 16 and 64 bit lengths
+        assert_eq!(
+            Header::decode(&[0x82, 126, 0x01, 0x00]),
+            Ok(Some((
+                Header {
+                    fin: true,
+                    opcode: Opcode::Binary,
+                    mask: None,
+                    payload_len: 256
+                },
+                4
+            )))
+        );
+        assert_eq!(
+            Header::decode(&[0x02, 127, 0, 0, 0, 1, 0, 0, 0, 0]),
+            Ok(Some((
+                Header {
+                    fin: false,
+                    opcode: Opcode::Binary,
+                    mask: None,
+                    payload_len: 1 << 32
+                },
+                10
+            )))
+        );
+    }
+
+    #[test]
+    fn rejects_lengths_not_in_their_shortest_form() {
+        assert_eq!(Header::decode(&[0x82, 126, 0, 125]), Err(Error::BadLength));
+        assert_eq!(Header::decode(&[0x82, 127, 0, 0, 0, 0, 0, 0, 0xff, 0xff]), Err(Error::BadLength));
+        // The top bit of a 64 bit length has to be clear
+        assert_eq!(Header::decode(&[0x82, 127, 0x80, 0, 0, 0, 0, 0, 0, 0]), Err(Error::BadLength));
+    }
+
+    #[test]
+    fn rejects_bad_control_frames() {
+        // A fragmented ping
+        assert_eq!(Header::decode(&[0x09, 0x80, 1, 2, 3, 4]), Err(Error::BadControlFrame));
+        // A close too long to be a control frame
+        assert_eq!(Header::decode(&[0x88, 0x80 | 126, 0, 126]), Err(Error::BadControlFrame));
+        // Reserved bits and opcodes
+        assert_eq!(Header::decode(&[0xc1, 0x80]), Err(Error::Reserved));
+        assert_eq!(Header::decode(&[0x83, 0x80]), Err(Error::UnknownOpcode(3)));
+    }
+
+    #[test]
+    fn decodes_masked_frames() {
+        // RFC 6455 section 5.7's masked "Hello"
+        let mut buf = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58, 0xff];
+        let frame = Frame {
+            fin: true,
+            opcode: Opcode::Text,
+            payload: b"Hello",
+        };
+        assert_eq!(decode_frame(&mut buf[..10], 125), Ok(None));
+        assert_eq!(decode_frame(&mut buf, 125), Ok(Some((frame, 11))));
+
+        // The same frame from the client unmasked is refused
+        let mut buf = *b"\x81\x05Hello";
+        assert_eq!(decode_frame(&mut buf, 125), Err(Error::Unmasked));
+
+        let mut buf = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        assert_eq!(decode_frame(&mut buf, 4), Err(Error::TooLarge));
+    }
+}
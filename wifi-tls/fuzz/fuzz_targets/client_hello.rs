@@ -0,0 +1,14 @@
+//! Parses arbitrary bytes as a ClientHello, checking that it never panics
+//! and that what it finds lies within the message.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wifi_tls::ClientHello;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(hello) = ClientHello::parse(data, b"pico") {
+        assert!(hello.truncated_len <= data.len());
+        assert!(hello.session_id.len() <= 32);
+        assert!(hello.binder.len() <= data.len() - hello.truncated_len);
+    }
+});
@@ -0,0 +1,38 @@
+//! Feeds arbitrary records to a server connection, as read off the network,
+//! checking that it never panics and stops at the first error like the board.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wifi_tls::{record_len, Connection, Psk, Received, HEADER_LEN, OVERHEAD};
+
+/// As on the board
+const RECORD_SIZE: usize = 2048;
+
+fuzz_target!(|data: &[u8]| {
+    let psk = Psk {
+        identity: b"pico",
+        key: &[0x42; 16],
+    };
+    let mut conn = Connection::new(psk, [9; 32], [3; 32]);
+    let mut record = [0; RECORD_SIZE];
+    let mut out = [0; RECORD_SIZE];
+    let mut data = data;
+    while data.len() >= HEADER_LEN {
+        let len = match record_len(data[..HEADER_LEN].try_into().unwrap()) {
+            Ok(len) if len <= RECORD_SIZE && len <= data.len() => len,
+            _ => return,
+        };
+        record[..len].copy_from_slice(&data[..len]);
+        data = &data[len..];
+        match conn.receive(&mut record[..len], &mut out) {
+            Ok(Received::Reply(n)) => assert!(n <= out.len()),
+            Ok(Received::Data(d)) => assert!(d.len() + OVERHEAD <= len),
+            Ok(Received::Closed) => return,
+            Ok(Received::Nothing) => {}
+            Err(e) => {
+                conn.fail(e, &mut out).ok();
+                return;
+            }
+        }
+    }
+});
@@ -0,0 +1,279 @@
+//! Parsing the ClientHello, and writing the server's handshake messages.
+
+use crate::Error;
+
+pub const CLIENT_HELLO: u8 = 1;
+pub const SERVER_HELLO: u8 = 2;
+pub const ENCRYPTED_EXTENSIONS: u8 = 8;
+pub const FINISHED: u8 = 20;
+
+pub const HEADER_LEN: usize = 4;
+
+pub const TLS_AES_128_GCM_SHA256: u16 = 0x1301;
+pub const X25519: u16 = 0x001d;
+const TLS13: u16 = 0x0304;
+const PSK_DHE_KE: u8 = 1;
+
+const EXT_PRE_SHARED_KEY: u16 = 41;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+const EXT_PSK_KEY_EXCHANGE_MODES: u16 = 45;
+const EXT_KEY_SHARE: u16 = 51;
+
+const MAX_SESSION_ID: usize = 32;
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() < n {
+            return Err(Error::Decode);
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    pub fn take_rest(&mut self) -> &'a [u8] {
+        let rest = self.buf;
+        self.buf = &[];
+        rest
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u24(&mut self) -> Result<usize, Error> {
+        let b = self.take(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+
+    /// A vector with a one byte length
+    pub fn vec8(&mut self) -> Result<Reader<'a>, Error> {
+        let n = self.u8()? as usize;
+        Ok(Reader::new(self.take(n)?))
+    }
+
+    /// A vector with a two byte length
+    pub fn vec16(&mut self) -> Result<Reader<'a>, Error> {
+        let n = self.u16()? as usize;
+        Ok(Reader::new(self.take(n)?))
+    }
+}
+
+/// What the server needs from a ClientHello
+pub struct ClientHello<'a> {
+    pub session_id: &'a [u8],
+    /// The client's x25519 public key
+    pub key_share: [u8; 32],
+    /// The binder for the PSK offered with `identity`
+    pub binder: &'a [u8],
+    /// The index of the chosen identity in the client's list
+    pub identity_index: u16,
+    /// The length of the message up to the binders, which is what they
+    /// cover
+    pub truncated_len: usize,
+}
+
+impl<'a> ClientHello<'a> {
+    /// Parse a whole ClientHello message, header included, choosing the PSK
+    /// with `identity`
+    pub fn parse(message: &'a [u8], identity: &[u8]) -> Result<ClientHello<'a>, Error> {
+        let mut r = Reader::new(message);
+        if r.u8()? != CLIENT_HELLO {
+            return Err(Error::UnexpectedMessage);
+        }
+        if r.u24()? != r.remaining() {
+            return Err(Error::Decode);
+        }
+        let _legacy_version = r.u16()?;
+        let _random = r.take(32)?;
+        let session_id = r.vec8()?.take_rest();
+        if session_id.len() > MAX_SESSION_ID {
+            return Err(Error::Decode);
+        }
+        let mut suites = r.vec16()?;
+        let mut aes128 = false;
+        while suites.remaining() > 0 {
+            aes128 |= suites.u16()? == TLS_AES_128_GCM_SHA256;
+        }
+        let _compression = r.vec8()?;
+
+        let mut tls13 = false;
+        let mut dhe = false;
+        let mut key_share = None;
+        let mut psk = None;
+        let mut extensions = r.vec16()?;
+        if r.remaining() != 0 {
+            return Err(Error::Decode);
+        }
+        while extensions.remaining() > 0 {
+            let ext_type = extensions.u16()?;
+            let mut data = extensions.vec16()?;
+            if psk.is_some() {
+                // The pre_shared_key extension has to be the last
+                return Err(Error::IllegalParameter);
+            }
+            match ext_type {
+                EXT_SUPPORTED_VERSIONS => {
+                    let mut versions = data.vec8()?;
+                    while versions.remaining() > 0 {
+                        tls13 |= versions.u16()? == TLS13;
+                    }
+                }
+                EXT_PSK_KEY_EXCHANGE_MODES => {
+                    let mut modes = data.vec8()?;
+                    while modes.remaining() > 0 {
+                        dhe |= modes.u8()? == PSK_DHE_KE;
+                    }
+                }
+                EXT_KEY_SHARE => {
+                    let mut shares = data.vec16()?;
+                    while shares.remaining() > 0 {
+                        let group = shares.u16()?;
+                        let key = shares.vec16()?.take_rest();
+                        if group == X25519 {
+                            key_share = Some(key.try_into().map_err(|_| Error::IllegalParameter)?);
+                        }
+                    }
+                }
+                EXT_PRE_SHARED_KEY => {
+                    let mut identities = data.vec16()?;
+                    let mut chosen = None;
+                    let mut index = 0;
+                    while identities.remaining() > 0 {
+                        let offered = identities.vec16()?.take_rest();
+                        let _obfuscated_ticket_age = identities.take(4)?;
+                        if chosen.is_none() && offered == identity {
+                            chosen = Some(index);
+                        }
+                        index += 1;
+                    }
+                    let truncated_len = message.len() - data.remaining();
+                    let mut binders = data.vec16()?;
+                    let mut binder = None;
+                    let mut index = 0;
+                    while binders.remaining() > 0 {
+                        let offered = binders.vec8()?.take_rest();
+                        if Some(index) == chosen {
+                            binder = Some(offered);
+                        }
+                        index += 1;
+                    }
+                    psk = Some((chosen, binder, truncated_len));
+                }
+                _ => {}
+            }
+        }
+
+        if !tls13 {
+            return Err(Error::ProtocolVersion);
+        }
+        let (chosen, binder, truncated_len) = psk.ok_or(Error::MissingExtension)?;
+        let identity_index = chosen.ok_or(Error::UnknownPskIdentity)?;
+        let binder = binder.ok_or(Error::IllegalParameter)?;
+        if !aes128 || !dhe {
+            return Err(Error::HandshakeFailure);
+        }
+        // Without support for HelloRetryRequest, the client has to have
+        // guessed x25519
+        let key_share = key_share.ok_or(Error::HandshakeFailure)?;
+        Ok(ClientHello {
+            session_id,
+            key_share,
+            binder,
+            identity_index,
+            truncated_len,
+        })
+    }
+}
+
+/// Appends to a buffer, failing if it's full
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer { buf, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.len + data.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    pub fn u16(&mut self, n: u16) -> Result<(), Error> {
+        self.bytes(&n.to_be_bytes())
+    }
+
+    /// Start a handshake message, to be finished by `end_message`
+    pub fn begin_message(&mut self, msg_type: u8) -> Result<usize, Error> {
+        let start = self.len;
+        self.bytes(&[msg_type, 0, 0, 0])?;
+        Ok(start)
+    }
+
+    /// Fill in the length of the message begun at `start`, returning it
+    pub fn end_message(&mut self, start: usize) -> &[u8] {
+        let len = (self.len - start - HEADER_LEN) as u32;
+        self.buf[start + 1..start + 4].copy_from_slice(&len.to_be_bytes()[1..]);
+        &self.buf[start..self.len]
+    }
+}
+
+/// Write the ServerHello accepting `hello`
+pub fn write_server_hello(
+    w: &mut Writer<'_>,
+    random: &[u8; 32],
+    hello: &ClientHello<'_>,
+    public_key: &[u8; 32],
+) -> Result<usize, Error> {
+    let start = w.begin_message(SERVER_HELLO)?;
+    w.u16(0x0303)?;
+    w.bytes(random)?;
+    w.bytes(&[hello.session_id.len() as u8])?;
+    w.bytes(hello.session_id)?;
+    w.u16(TLS_AES_128_GCM_SHA256)?;
+    w.bytes(&[0])?;
+
+    // supported_versions, key_share and pre_shared_key
+    w.u16(6 + 40 + 6)?;
+    w.u16(EXT_SUPPORTED_VERSIONS)?;
+    w.u16(2)?;
+    w.u16(TLS13)?;
+    w.u16(EXT_KEY_SHARE)?;
+    w.u16(36)?;
+    w.u16(X25519)?;
+    w.u16(32)?;
+    w.bytes(public_key)?;
+    w.u16(EXT_PRE_SHARED_KEY)?;
+    w.u16(2)?;
+    w.u16(hello.identity_index)?;
+    Ok(start)
+}
@@ -0,0 +1,230 @@
+//! The TLS 1.3 key schedule (RFC 8446 section 7), for SHA-256.
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+pub type Secret = [u8; HASH_LEN];
+
+pub const HASH_LEN: usize = 32;
+
+pub fn hash(data: &[u8]) -> Secret {
+    Sha256::digest(data).into()
+}
+
+pub fn extract(salt: &[u8], ikm: &[u8]) -> Secret {
+    Hkdf::<Sha256>::extract(Some(salt), ikm).0.into()
+}
+
+/// HKDF-Expand-Label
+pub fn expand_label(secret: &Secret, label: &str, context: &[u8], out: &mut [u8]) {
+    // The longest label used is "c hs traffic"
+    let mut info = [0u8; 2 + 1 + 6 + 12 + 1 + HASH_LEN];
+    let label_len = 6 + label.len();
+    info[0..2].copy_from_slice(&(out.len() as u16).to_be_bytes());
+    info[2] = label_len as u8;
+    info[3..9].copy_from_slice(b"tls13 ");
+    info[9..9 + label.len()].copy_from_slice(label.as_bytes());
+    info[3 + label_len] = context.len() as u8;
+    info[4 + label_len..4 + label_len + context.len()].copy_from_slice(context);
+    let info = &info[..4 + label_len + context.len()];
+    // Only fails for lengths far beyond those used
+    Hkdf::<Sha256>::from_prk(secret)
+        .unwrap()
+        .expand(info, out)
+        .unwrap();
+}
+
+/// Derive-Secret, given the hash of the transcript
+pub fn derive_secret(secret: &Secret, label: &str, transcript_hash: &Secret) -> Secret {
+    let mut out = [0u8; HASH_LEN];
+    expand_label(secret, label, transcript_hash, &mut out);
+    out
+}
+
+pub fn finished_key(base_key: &Secret) -> Secret {
+    let mut key = [0u8; HASH_LEN];
+    expand_label(base_key, "finished", &[], &mut key);
+    key
+}
+
+/// The verify data of a Finished message, or a PSK binder
+pub fn verify_data(base_key: &Secret, transcript_hash: &Secret) -> Secret {
+    finished_mac(base_key, transcript_hash).finalize().into_bytes().into()
+}
+
+/// Check the verify data of a Finished message or PSK binder, in constant
+/// time
+pub fn check_verify_data(base_key: &Secret, transcript_hash: &Secret, verify_data: &[u8]) -> bool {
+    finished_mac(base_key, transcript_hash).verify_slice(verify_data).is_ok()
+}
+
+fn finished_mac(base_key: &Secret, transcript_hash: &Secret) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&finished_key(base_key)).unwrap();
+    mac.update(transcript_hash);
+    mac
+}
+
+/// The secrets derived from an external PSK
+pub struct EarlySecrets {
+    early: Secret,
+}
+
+impl EarlySecrets {
+    pub fn new(psk: &[u8]) -> EarlySecrets {
+        EarlySecrets {
+            early: extract(&[0; HASH_LEN], psk),
+        }
+    }
+
+    /// The key that PSK binders are made with
+    pub fn binder_key(&self) -> Secret {
+        derive_secret(&self.early, "ext binder", &hash(&[]))
+    }
+
+    /// Move on to the handshake secrets, given the (EC)DHE shared secret
+    pub fn handshake(&self, shared: &[u8]) -> HandshakeSecrets {
+        let derived = derive_secret(&self.early, "derived", &hash(&[]));
+        HandshakeSecrets {
+            handshake: extract(&derived, shared),
+        }
+    }
+}
+
+pub struct HandshakeSecrets {
+    handshake: Secret,
+}
+
+impl HandshakeSecrets {
+    /// The client and server handshake traffic secrets, given the hash of
+    /// the transcript up to the ServerHello
+    pub fn traffic(&self, transcript_hash: &Secret) -> (Secret, Secret) {
+        (
+            derive_secret(&self.handshake, "c hs traffic", transcript_hash),
+            derive_secret(&self.handshake, "s hs traffic", transcript_hash),
+        )
+    }
+
+    /// The client and server application traffic secrets, given the hash of
+    /// the transcript up to the server's Finished
+    pub fn application(&self, transcript_hash: &Secret) -> (Secret, Secret) {
+        let derived = derive_secret(&self.handshake, "derived", &hash(&[]));
+        let master = extract(&derived, &[0; HASH_LEN]);
+        (
+            derive_secret(&master, "c ap traffic", transcript_hash),
+            derive_secret(&master, "s ap traffic", transcript_hash),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        s.chunks(2)
+            .map(|pair| u8::from_str_radix(core::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn secret(s: &str) -> Secret {
+        hex(s).try_into().unwrap()
+    }
+
+    fn shared(private: &str, peer_private: &str) -> [u8; 32] {
+        let peer = PublicKey::from(&StaticSecret::from(secret(peer_private)));
+        *StaticSecret::from(secret(private)).diffie_hellman(&peer).as_bytes()
+    }
+
+    // RFC 8448 section 4, a resumed handshake: the key schedule of a PSK
+    // with psk_dhe_ke. A resumption PSK's binder key has its own label, but
+    // is otherwise made the same as an external one's.
+    #[test]
+    fn derives_psk_secrets() {
+        let psk = secret("4e cd 0e b6 ec 3b 4d 87 f5 d6 02 8f 92 2c a4 c5 85 1a 27 7f d4 13 11 c9 e6 2d 2c 94 92 e1 c4 f3");
+        let early = EarlySecrets::new(&psk);
+        assert_eq!(
+            early.early,
+            secret("9b 21 88 e9 b2 fc 6d 64 d7 1d c3 29 90 0e 20 bb 41 91 50 00 f6 78 aa 83 9c bb 79 7c b7 d8 33 2c")
+        );
+        assert_eq!(
+            derive_secret(&early.early, "res binder", &hash(&[])),
+            secret("69 fe 13 1a 3b ba d5 d6 3c 64 ee bc c3 0e 39 5b 9d 81 07 72 6a 13 d0 74 e3 89 db c8 a4 e4 72 56")
+        );
+
+        let shared = shared(
+            "bf f9 11 88 28 38 46 dd 6a 21 34 ef 71 80 ca 2b 0b 14 fb 10 dc e7 07 b5 09 8c 0d dd c8 13 b2 df",
+            "de 5b 44 76 e7 b4 90 b2 65 2d 33 8a cb f2 94 80 66 f2 55 f9 44 0e 23 b9 8f c6 98 35 29 8d c1 07",
+        );
+        assert_eq!(
+            early.handshake(&shared).handshake,
+            secret("00 5c b1 12 fd 8e b4 cc c6 23 bb 88 a0 7c 64 b3 ed e1 60 53 63 fc 7d 0d f8 c7 ce 4f f0 fb 4a e6")
+        );
+    }
+
+    // RFC 8448 section 3, a full handshake without a PSK, which is the same
+    // key schedule with a PSK of zeros
+    #[test]
+    fn derives_handshake_secrets() {
+        let early = EarlySecrets::new(&[0; HASH_LEN]);
+        assert_eq!(
+            early.early,
+            secret("33 ad 0a 1c 60 7e c0 3b 09 e6 cd 98 93 68 0c e2 10 ad f3 00 aa 1f 26 60 e1 b2 2e 10 f1 70 f9 2a")
+        );
+
+        let shared = shared(
+            "b1 58 0e ea df 6d d5 89 b8 ef 4f 2d 56 52 57 8c c8 10 e9 98 01 91 ec 8d 05 83 08 ce a2 16 a2 1e",
+            "49 af 42 ba 7f 79 94 85 2d 71 3e f2 78 4b cb ca a7 91 1d e2 6a dc 56 42 cb 63 45 40 e7 ea 50 05",
+        );
+        assert_eq!(
+            shared,
+            secret("8b d4 05 4f b5 5b 9d 63 fd fb ac f9 f0 4b 9f 0d 35 e6 d6 3f 53 75 63 ef d4 62 72 90 0f 89 49 2d")
+        );
+        let handshake = early.handshake(&shared);
+        assert_eq!(
+            handshake.handshake,
+            secret("1d c8 26 e9 36 06 aa 6f dc 0a ad c1 2f 74 1b 01 04 6a a6 b9 9f 69 1e d2 21 a9 f0 ca 04 3f be ac")
+        );
+
+        // The transcript up to the ServerHello
+        let transcript_hash =
+            secret("86 0c 06 ed c0 78 58 ee 8e 78 f0 e7 42 8c 58 ed d6 b4 3f 2c a3 e6 e9 5f 02 ed 06 3c f0 e1 ca d8");
+        let (client, server) = handshake.traffic(&transcript_hash);
+        assert_eq!(
+            client,
+            secret("b3 ed db 12 6e 06 7f 35 a7 80 b3 ab f4 5e 2d 8f 3b 1a 95 07 38 f5 2e 96 00 74 6a 0e 27 a5 5a 21")
+        );
+        assert_eq!(
+            server,
+            secret("b6 7b 7d 69 0c c1 6c 4e 75 e5 42 13 cb 2d 37 b4 e9 c9 12 bc de d9 10 5d 42 be fd 59 d3 91 ad 38")
+        );
+
+        let (mut key, mut iv) = ([0; 16], [0; 12]);
+        expand_label(&server, "key", &[], &mut key);
+        expand_label(&server, "iv", &[], &mut iv);
+        assert_eq!(key[..], hex("3f ce 51 60 09 c2 17 27 d0 f2 e4 e8 6e e4 03 bc"));
+        assert_eq!(iv[..], hex("5d 31 3e b2 67 12 76 ee 13 00 0b 30"));
+        assert_eq!(
+            finished_key(&server),
+            secret("00 8d 3b 66 f8 16 ea 55 9f 96 b5 37 e8 85 c3 1f c0 68 bf 49 2c 65 2f 01 f2 88 a1 d8 cd c1 9f c8")
+        );
+    }
+
+    #[test]
+    fn checks_verify_data() {
+        let (key, transcript_hash) = ([1; HASH_LEN], hash(b"transcript"));
+        let mut data = verify_data(&key, &transcript_hash);
+        assert!(check_verify_data(&key, &transcript_hash, &data));
+        assert!(!check_verify_data(&key, &transcript_hash, &data[..31]));
+        assert!(!check_verify_data(&[2; HASH_LEN], &transcript_hash, &data));
+        data[31] ^= 1;
+        assert!(!check_verify_data(&key, &transcript_hash, &data));
+    }
+}
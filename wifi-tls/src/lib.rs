@@ -0,0 +1,571 @@
+//! A minimal TLS 1.3 server (RFC 8446), for securing the board's services.
+//! `no_std`, allocation free, and independent of the network stack, so it
+//! can be tried out on the host against an ordinary TLS client.
+//!
+//! Only what a small device needs is supported:
+//!
+//! - authentication by an external pre-shared key (PSK), with an x25519 key
+//!   exchange for forward secrecy (`psk_dhe_ke`); there are no certificates
+//! - the TLS_AES_128_GCM_SHA256 cipher suite
+//! - whole handshake messages in single records, as clients send them
+//!
+//! A client that doesn't offer an x25519 key share is refused rather than
+//! asked to retry. Session tickets, early data and key updates aren't
+//! supported.
+//!
+//! The caller moves records between the network and a `Connection`: read
+//! the header, then the rest of the record, and hand it to
+//! `Connection::receive`, sending back anything it writes.
+#![no_std]
+
+mod handshake;
+mod key_schedule;
+mod record;
+
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+// Public for the fuzz targets
+#[cfg(fuzzing)]
+pub use handshake::ClientHello;
+#[cfg(not(fuzzing))]
+use handshake::ClientHello;
+use handshake::Writer;
+use key_schedule::{EarlySecrets, Secret};
+pub use record::{record_len, HEADER_LEN, MAX_PLAINTEXT};
+use record::{RecordKeys, ALERT, APPLICATION_DATA, CHANGE_CIPHER_SPEC, HANDSHAKE, TAG_LEN};
+
+/// Extra bytes in a protected record, beyond its content
+pub const OVERHEAD: usize = HEADER_LEN + 1 + TAG_LEN;
+
+const ALERT_WARNING: u8 = 1;
+const ALERT_FATAL: u8 = 2;
+const CLOSE_NOTIFY: u8 = 0;
+
+/// An external pre-shared key, and the identity the client knows it by
+#[derive(Clone, Copy)]
+pub struct Psk<'a> {
+    pub identity: &'a [u8],
+    pub key: &'a [u8],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// A record or message that doesn't belong at this point
+    UnexpectedMessage,
+    /// A malformed message
+    Decode,
+    /// A record longer than TLS allows
+    RecordOverflow,
+    /// The client doesn't support TLS 1.3
+    ProtocolVersion,
+    /// The client doesn't support the cipher suite, x25519 or the
+    /// `psk_dhe_ke` mode
+    HandshakeFailure,
+    /// The client didn't offer a PSK
+    MissingExtension,
+    /// The client offered a PSK, but not ours
+    UnknownPskIdentity,
+    /// A PSK binder or Finished didn't check out, eg the PSK is wrong
+    DecryptError,
+    /// A record failed to decrypt
+    BadRecordMac,
+    IllegalParameter,
+    /// The output buffer is too small
+    BufferTooSmall,
+    /// The client sent a fatal alert, with this description
+    Alert(u8),
+}
+
+impl Error {
+    /// The alert description to send the client
+    fn description(&self) -> u8 {
+        match self {
+            Error::UnexpectedMessage => 10,
+            Error::BadRecordMac => 20,
+            Error::RecordOverflow => 22,
+            Error::HandshakeFailure => 40,
+            Error::IllegalParameter => 47,
+            Error::Decode => 50,
+            Error::DecryptError => 51,
+            Error::ProtocolVersion => 70,
+            Error::BufferTooSmall | Error::Alert(_) => 80,
+            Error::MissingExtension => 109,
+            Error::UnknownPskIdentity => 115,
+        }
+    }
+}
+
+/// The result of receiving a record
+#[derive(PartialEq, Eq, Debug)]
+pub enum Received<'r> {
+    /// Send the first `n` bytes of the output buffer back to the client
+    Reply(usize),
+    Nothing,
+    /// Application data from the client
+    Data(&'r [u8]),
+    /// The client closed the connection
+    Closed,
+}
+
+enum State {
+    ClientHello,
+    /// Waiting for the client's Finished, with the secret it's keyed by and
+    /// the transcript hash it covers
+    ClientFinished {
+        handshake_secret: Secret,
+        transcript_hash: Secret,
+    },
+    Established,
+    Closed,
+}
+
+/// The server side of one TLS connection
+pub struct Connection<'k> {
+    psk: Psk<'k>,
+    random: [u8; 32],
+    secret: StaticSecret,
+    state: State,
+    read: Option<RecordKeys>,
+    write: Option<RecordKeys>,
+    /// The client's keys once it's sent its Finished
+    next_read: Option<RecordKeys>,
+}
+
+impl<'k> Connection<'k> {
+    /// Start a connection. `random` and `secret` must be fresh random bytes,
+    /// for the ServerHello's random and the key exchange.
+    pub fn new(psk: Psk<'k>, random: [u8; 32], secret: [u8; 32]) -> Connection<'k> {
+        Connection {
+            psk,
+            random,
+            secret: StaticSecret::from(secret),
+            state: State::ClientHello,
+            read: None,
+            write: None,
+            next_read: None,
+        }
+    }
+
+    /// Whether the handshake is complete, so application data can be sent
+    pub fn is_established(&self) -> bool {
+        matches!(self.state, State::Established)
+    }
+
+    /// Handle a whole record from the client, of the length given by
+    /// `record_len`. It's decrypted in place. Any reply is written to `out`.
+    pub fn receive<'r>(&mut self, record: &'r mut [u8], out: &mut [u8]) -> Result<Received<'r>, Error> {
+        let header: &[u8; HEADER_LEN] = record
+            .get(..HEADER_LEN)
+            .and_then(|h| h.try_into().ok())
+            .ok_or(Error::Decode)?;
+        if record_len(header)? != record.len() {
+            return Err(Error::Decode);
+        }
+        let content_type = header[0];
+
+        let (content_type, content) = match (content_type, &mut self.read) {
+            // Sent for middlebox compatibility, and otherwise ignored
+            (CHANGE_CIPHER_SPEC, _) if matches!(self.state, State::ClientFinished { .. }) => {
+                return match &record[HEADER_LEN..] {
+                    [1] => Ok(Received::Nothing),
+                    _ => Err(Error::UnexpectedMessage),
+                };
+            }
+            (APPLICATION_DATA, Some(keys)) => keys.open(record)?,
+            (HANDSHAKE | ALERT, None) => (content_type, &mut record[HEADER_LEN..]),
+            _ => return Err(Error::UnexpectedMessage),
+        };
+
+        match (content_type, &self.state) {
+            (HANDSHAKE, State::ClientHello) => self.client_hello(content, out).map(Received::Reply),
+            (HANDSHAKE, State::ClientFinished { .. }) => {
+                self.client_finished(content)?;
+                Ok(Received::Nothing)
+            }
+            (APPLICATION_DATA, State::Established) => Ok(Received::Data(content)),
+            (ALERT, _) => match *content {
+                [_, CLOSE_NOTIFY] => {
+                    self.state = State::Closed;
+                    Ok(Received::Closed)
+                }
+                [ALERT_FATAL, description] => {
+                    self.state = State::Closed;
+                    Err(Error::Alert(description))
+                }
+                // Other warnings are ignored
+                [ALERT_WARNING, _] => Ok(Received::Nothing),
+                _ => Err(Error::Decode),
+            },
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    fn client_hello(&mut self, message: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+        let hello = ClientHello::parse(message, self.psk.identity)?;
+
+        let early = EarlySecrets::new(self.psk.key);
+        let truncated = key_schedule::hash(&message[..hello.truncated_len]);
+        if !key_schedule::check_verify_data(&early.binder_key(), &truncated, hello.binder) {
+            return Err(Error::DecryptError);
+        }
+        let mut transcript = Sha256::new();
+        transcript.update(message);
+
+        let shared = self.secret.diffie_hellman(&PublicKey::from(hello.key_share));
+        if !shared.was_contributory() {
+            return Err(Error::IllegalParameter);
+        }
+        let handshake = early.handshake(shared.as_bytes());
+
+        // The ServerHello, in the clear
+        let mut w = Writer::new(out.get_mut(HEADER_LEN..).ok_or(Error::BufferTooSmall)?);
+        let public_key = PublicKey::from(&self.secret);
+        let start = handshake::write_server_hello(&mut w, &self.random, &hello, public_key.as_bytes())?;
+        transcript.update(w.end_message(start));
+        let hello_len = w.len();
+        record::write_header(out, HANDSHAKE, hello_len);
+        let mut len = HEADER_LEN + hello_len;
+
+        // Only a client in middlebox compatibility mode expects this
+        if !hello.session_id.is_empty() {
+            len += record::write_plaintext(&mut out[len..], CHANGE_CIPHER_SPEC, &[1])?;
+        }
+
+        let (client_secret, server_secret) = handshake.traffic(&transcript.clone().finalize().into());
+        let mut server_keys = RecordKeys::new(&server_secret);
+        len += server_keys.seal(&mut out[len..], HANDSHAKE, |buf| {
+            let mut w = Writer::new(buf);
+            let start = w.begin_message(handshake::ENCRYPTED_EXTENSIONS)?;
+            w.u16(0)?;
+            transcript.update(w.end_message(start));
+
+            let verify_data = key_schedule::verify_data(&server_secret, &transcript.clone().finalize().into());
+            let start = w.begin_message(handshake::FINISHED)?;
+            w.bytes(&verify_data)?;
+            transcript.update(w.end_message(start));
+            Ok(w.len())
+        })?;
+
+        let transcript_hash: Secret = transcript.finalize().into();
+        let (client_application, server_application) = handshake.application(&transcript_hash);
+        self.read = Some(RecordKeys::new(&client_secret));
+        self.write = Some(RecordKeys::new(&server_application));
+        self.next_read = Some(RecordKeys::new(&client_application));
+        self.state = State::ClientFinished {
+            handshake_secret: client_secret,
+            transcript_hash,
+        };
+        Ok(len)
+    }
+
+    fn client_finished(&mut self, message: &[u8]) -> Result<(), Error> {
+        let (handshake_secret, transcript_hash) = match &self.state {
+            State::ClientFinished {
+                handshake_secret,
+                transcript_hash,
+            } => (handshake_secret, transcript_hash),
+            _ => return Err(Error::UnexpectedMessage),
+        };
+        let verify_data = match message {
+            [handshake::FINISHED, 0, 0, 32, verify_data @ ..] if verify_data.len() == 32 => verify_data,
+            _ => return Err(Error::UnexpectedMessage),
+        };
+        if !key_schedule::check_verify_data(handshake_secret, transcript_hash, verify_data) {
+            return Err(Error::DecryptError);
+        }
+        self.read = self.next_read.take();
+        self.state = State::Established;
+        Ok(())
+    }
+
+    /// Write `data` to `out` as a record of application data, returning the
+    /// record's length and how much of `data` it holds. At most
+    /// `out.len() - OVERHEAD` bytes fit in a record.
+    pub fn send(&mut self, data: &[u8], out: &mut [u8]) -> Result<(usize, usize), Error> {
+        let keys = match (&self.state, &mut self.write) {
+            (State::Established, Some(keys)) => keys,
+            _ => return Err(Error::UnexpectedMessage),
+        };
+        let mut sent = 0;
+        let len = keys.seal(out, APPLICATION_DATA, |buf| {
+            sent = data.len().min(buf.len());
+            buf[..sent].copy_from_slice(&data[..sent]);
+            Ok(sent)
+        })?;
+        Ok((len, sent))
+    }
+
+    /// Write a close_notify alert to `out`, returning its length
+    pub fn close(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+        self.state = State::Closed;
+        self.alert(ALERT_WARNING, CLOSE_NOTIFY, out)
+    }
+
+    /// Write the fatal alert for `error` to `out`, returning its length
+    pub fn fail(&mut self, error: Error, out: &mut [u8]) -> Result<usize, Error> {
+        self.state = State::Closed;
+        self.alert(ALERT_FATAL, error.description(), out)
+    }
+
+    fn alert(&mut self, level: u8, description: u8, out: &mut [u8]) -> Result<usize, Error> {
+        let alert = [level, description];
+        match &mut self.write {
+            Some(keys) => keys.seal(out, ALERT, |buf| {
+                buf.get_mut(..2).ok_or(Error::BufferTooSmall)?.copy_from_slice(&alert);
+                Ok(2)
+            }),
+            None => record::write_plaintext(out, ALERT, &alert),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+    use key_schedule::{check_verify_data, hash, verify_data};
+
+    const PSK: Psk<'static> = Psk {
+        identity: b"pico",
+        key: &[0x42; 16],
+    };
+
+    const SERVER_SECRET: [u8; 32] = [3; 32];
+    const CLIENT_SECRET: [u8; 32] = [5; 32];
+
+    fn vec8(out: &mut Vec<u8>, data: &[u8]) {
+        out.push(data.len() as u8);
+        out.extend_from_slice(data);
+    }
+
+    fn vec16(out: &mut Vec<u8>, data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(data);
+    }
+
+    fn extension(out: &mut Vec<u8>, ext_type: u16, data: &[u8]) {
+        out.extend_from_slice(&ext_type.to_be_bytes());
+        vec16(out, data);
+    }
+
+    /// A ClientHello message offering `psk`, with its binder
+    fn client_hello(psk: Psk<'_>, public_key: &[u8; 32], session_id: &[u8]) -> Vec<u8> {
+        let mut extensions = Vec::new();
+        extension(&mut extensions, 43, &[2, 3, 4]);
+        extension(&mut extensions, 45, &[1, 1]);
+        let mut share = handshake::X25519.to_be_bytes().to_vec();
+        vec16(&mut share, public_key);
+        let mut shares = Vec::new();
+        vec16(&mut shares, &share);
+        extension(&mut extensions, 51, &shares);
+        // pre_shared_key, with the binder filled in below
+        let mut identity = Vec::new();
+        vec16(&mut identity, psk.identity);
+        identity.extend_from_slice(&[0; 4]);
+        let mut offer = Vec::new();
+        vec16(&mut offer, &identity);
+        let mut binders = Vec::new();
+        vec8(&mut binders, &[0; 32]);
+        vec16(&mut offer, &binders);
+        extension(&mut extensions, 41, &offer);
+
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&[7; 32]);
+        vec8(&mut body, session_id);
+        vec16(&mut body, &handshake::TLS_AES_128_GCM_SHA256.to_be_bytes());
+        vec8(&mut body, &[0]);
+        vec16(&mut body, &extensions);
+        let mut message = vec![handshake::CLIENT_HELLO];
+        message.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        message.extend_from_slice(&body);
+
+        // The binder covers the message up to the binders
+        let binder_at = message.len() - 32;
+        let truncated = hash(&message[..binder_at - 3]);
+        let binder = verify_data(&EarlySecrets::new(psk.key).binder_key(), &truncated);
+        message[binder_at..].copy_from_slice(&binder);
+        message
+    }
+
+    fn plaintext(content_type: u8, content: &[u8]) -> Vec<u8> {
+        let mut record = vec![0; HEADER_LEN + content.len()];
+        record::write_plaintext(&mut record, content_type, content).unwrap();
+        record
+    }
+
+    fn seal(keys: &mut RecordKeys, content_type: u8, content: &[u8]) -> Vec<u8> {
+        let mut record = vec![0; content.len() + OVERHEAD];
+        let len = keys
+            .seal(&mut record, content_type, |buf| {
+                buf[..content.len()].copy_from_slice(content);
+                Ok(content.len())
+            })
+            .unwrap();
+        record.truncate(len);
+        record
+    }
+
+    fn records(mut flight: &[u8]) -> Vec<Vec<u8>> {
+        let mut records = Vec::new();
+        while !flight.is_empty() {
+            let len = record_len(flight[..HEADER_LEN].try_into().unwrap()).unwrap();
+            records.push(flight[..len].to_vec());
+            flight = &flight[len..];
+        }
+        records
+    }
+
+    /// A client's application traffic keys
+    struct ClientKeys {
+        read: RecordKeys,
+        write: RecordKeys,
+    }
+
+    /// Complete a handshake with `conn`, as a client holding `psk`
+    fn handshake(conn: &mut Connection<'_>, psk: Psk<'_>, session_id: &[u8]) -> Result<ClientKeys, Error> {
+        let secret = StaticSecret::from(CLIENT_SECRET);
+        let hello = client_hello(psk, PublicKey::from(&secret).as_bytes(), session_id);
+        let mut out = [0; 512];
+        let len = match conn.receive(&mut plaintext(HANDSHAKE, &hello), &mut out)? {
+            Received::Reply(len) => len,
+            other => panic!("{:?}", other),
+        };
+        let mut flight = records(&out[..len]).into_iter();
+
+        let server_hello = flight.next().unwrap();
+        assert_eq!(server_hello[0], HANDSHAKE);
+        let server_hello = &server_hello[HEADER_LEN..];
+        assert_eq!(server_hello[0], handshake::SERVER_HELLO);
+        assert_eq!(&server_hello[39..39 + session_id.len()], session_id);
+        let server_public = PublicKey::from(&StaticSecret::from(SERVER_SECRET));
+        assert!(server_hello.windows(32).any(|w| w == server_public.as_bytes()));
+        if !session_id.is_empty() {
+            assert_eq!(flight.next().unwrap(), [CHANGE_CIPHER_SPEC, 3, 3, 0, 1, 1]);
+        }
+
+        let shared = secret.diffie_hellman(&server_public);
+        let secrets = EarlySecrets::new(psk.key).handshake(shared.as_bytes());
+        let mut transcript = hello.clone();
+        transcript.extend_from_slice(server_hello);
+        let (client_secret, server_secret) = secrets.traffic(&hash(&transcript));
+
+        let mut protected = flight.next().unwrap();
+        assert!(flight.next().is_none());
+        let (content_type, content) = RecordKeys::new(&server_secret).open(&mut protected).unwrap();
+        assert_eq!(content_type, HANDSHAKE);
+        let (extensions, finished) = content.split_at(6);
+        assert_eq!(extensions, [handshake::ENCRYPTED_EXTENSIONS, 0, 0, 2, 0, 0]);
+        transcript.extend_from_slice(extensions);
+        assert_eq!(finished[..4], [handshake::FINISHED, 0, 0, 32]);
+        assert!(check_verify_data(&server_secret, &hash(&transcript), &finished[4..]));
+        transcript.extend_from_slice(finished);
+        let transcript_hash = hash(&transcript);
+
+        if !session_id.is_empty() {
+            let mut record = plaintext(CHANGE_CIPHER_SPEC, &[1]);
+            let received = conn.receive(&mut record, &mut out);
+            assert_eq!(received, Ok(Received::Nothing));
+        }
+        let mut finished = vec![handshake::FINISHED, 0, 0, 32];
+        finished.extend_from_slice(&verify_data(&client_secret, &transcript_hash));
+        let mut record = seal(&mut RecordKeys::new(&client_secret), HANDSHAKE, &finished);
+        assert_eq!(conn.receive(&mut record, &mut out)?, Received::Nothing);
+
+        let (client_application, server_application) = secrets.application(&transcript_hash);
+        Ok(ClientKeys {
+            read: RecordKeys::new(&server_application),
+            write: RecordKeys::new(&client_application),
+        })
+    }
+
+    #[test]
+    fn exchanges_data() {
+        // Without and with middlebox compatibility
+        for session_id in [&[][..], &[0x55; 32]] {
+            let mut conn = Connection::new(PSK, [9; 32], SERVER_SECRET);
+            let mut keys = handshake(&mut conn, PSK, session_id).unwrap();
+            assert!(conn.is_established());
+            let mut out = [0; 64];
+
+            let mut record = seal(&mut keys.write, APPLICATION_DATA, b"ping");
+            assert_eq!(conn.receive(&mut record, &mut out), Ok(Received::Data(b"ping")));
+
+            let (len, sent) = conn.send(b"pong", &mut out).unwrap();
+            assert_eq!(sent, 4);
+            assert_eq!(keys.read.open(&mut out[..len]), Ok((APPLICATION_DATA, &mut [b'p', b'o', b'n', b'g'][..])));
+
+            // More than a record holds is split
+            let (len, sent) = conn.send(&[1; 100], &mut out).unwrap();
+            assert_eq!((len, sent), (64, 64 - OVERHEAD));
+            assert_eq!(keys.read.open(&mut out[..len]).unwrap().1, [1; 64 - OVERHEAD]);
+
+            let mut record = seal(&mut keys.write, ALERT, &[ALERT_WARNING, CLOSE_NOTIFY]);
+            assert_eq!(conn.receive(&mut record, &mut out), Ok(Received::Closed));
+            assert!(!conn.is_established());
+        }
+    }
+
+    #[test]
+    fn refuses_the_wrong_key() {
+        let mut conn = Connection::new(PSK, [9; 32], SERVER_SECRET);
+        let wrong = Psk {
+            identity: PSK.identity,
+            key: &[0x43; 16],
+        };
+        assert_eq!(handshake(&mut conn, wrong, &[]).err(), Some(Error::DecryptError));
+        let mut out = [0; 16];
+        let len = conn.fail(Error::DecryptError, &mut out).unwrap();
+        assert_eq!(out[..len], [ALERT, 3, 3, 0, 2, ALERT_FATAL, 51]);
+
+        let mut conn = Connection::new(PSK, [9; 32], SERVER_SECRET);
+        let unknown = Psk {
+            identity: b"other",
+            key: PSK.key,
+        };
+        assert_eq!(handshake(&mut conn, unknown, &[]).err(), Some(Error::UnknownPskIdentity));
+    }
+
+    #[test]
+    fn refuses_bad_binders() {
+        let public_key = PublicKey::from(&StaticSecret::from(CLIENT_SECRET));
+        let hello = client_hello(PSK, public_key.as_bytes(), &[]);
+        let mut out = [0; 512];
+        // The binder itself, and the message it covers
+        for at in [hello.len() - 1, 10] {
+            let mut hello = hello.clone();
+            hello[at] ^= 1;
+            let mut conn = Connection::new(PSK, [9; 32], SERVER_SECRET);
+            let mut record = plaintext(HANDSHAKE, &hello);
+            let received = conn.receive(&mut record, &mut out);
+            assert_eq!(received, Err(Error::DecryptError));
+            assert!(!conn.is_established());
+        }
+    }
+
+    #[test]
+    fn refuses_records_out_of_turn() {
+        let mut conn = Connection::new(PSK, [9; 32], SERVER_SECRET);
+        let mut out = [0; 64];
+        let mut record = plaintext(APPLICATION_DATA, b"early");
+        let received = conn.receive(&mut record, &mut out);
+        assert_eq!(received, Err(Error::UnexpectedMessage));
+        assert_eq!(conn.send(b"early", &mut out), Err(Error::UnexpectedMessage));
+        let mut record = plaintext(CHANGE_CIPHER_SPEC, &[1]);
+        let received = conn.receive(&mut record, &mut out);
+        assert_eq!(received, Err(Error::UnexpectedMessage));
+
+        let mut keys = handshake(&mut conn, PSK, &[]).unwrap();
+        // Once encrypted, records in the clear aren't taken
+        let mut record = plaintext(HANDSHAKE, &[1, 0, 0, 0]);
+        let received = conn.receive(&mut record, &mut out);
+        assert_eq!(received, Err(Error::UnexpectedMessage));
+        let mut record = seal(&mut keys.write, APPLICATION_DATA, b"data");
+        record[HEADER_LEN] ^= 1;
+        assert_eq!(conn.receive(&mut record, &mut out), Err(Error::BadRecordMac));
+    }
+}
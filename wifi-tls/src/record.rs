@@ -0,0 +1,127 @@
+//! Record framing and protection, with TLS_AES_128_GCM_SHA256.
+
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes128Gcm, KeyInit, Nonce, Tag};
+
+use crate::key_schedule::{expand_label, Secret};
+use crate::Error;
+
+pub const HEADER_LEN: usize = 5;
+
+pub const CHANGE_CIPHER_SPEC: u8 = 20;
+pub const ALERT: u8 = 21;
+pub const HANDSHAKE: u8 = 22;
+pub const APPLICATION_DATA: u8 = 23;
+
+const LEGACY_VERSION: [u8; 2] = [0x03, 0x03];
+
+pub const TAG_LEN: usize = 16;
+
+/// Largest plaintext a record can carry
+pub const MAX_PLAINTEXT: usize = 1 << 14;
+
+/// Largest record body allowed, for a protected record
+const MAX_BODY: usize = MAX_PLAINTEXT + 256;
+
+/// The length of a whole record, given its header
+pub fn record_len(header: &[u8; HEADER_LEN]) -> Result<usize, Error> {
+    let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+    if len > MAX_BODY {
+        return Err(Error::RecordOverflow);
+    }
+    Ok(HEADER_LEN + len)
+}
+
+pub fn write_header(out: &mut [u8], content_type: u8, len: usize) {
+    out[0] = content_type;
+    out[1..3].copy_from_slice(&LEGACY_VERSION);
+    out[3..5].copy_from_slice(&(len as u16).to_be_bytes());
+}
+
+/// Write a plaintext record, returning its length
+pub fn write_plaintext(out: &mut [u8], content_type: u8, content: &[u8]) -> Result<usize, Error> {
+    let len = HEADER_LEN + content.len();
+    let out = out.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+    write_header(out, content_type, content.len());
+    out[HEADER_LEN..].copy_from_slice(content);
+    Ok(len)
+}
+
+/// The keys protecting the records sent one way
+pub struct RecordKeys {
+    aead: Aes128Gcm,
+    iv: [u8; 12],
+    seq: u64,
+}
+
+impl RecordKeys {
+    pub fn new(traffic_secret: &Secret) -> RecordKeys {
+        let mut key = [0u8; 16];
+        let mut iv = [0u8; 12];
+        expand_label(traffic_secret, "key", &[], &mut key);
+        expand_label(traffic_secret, "iv", &[], &mut iv);
+        RecordKeys {
+            aead: Aes128Gcm::new(&key.into()),
+            iv,
+            seq: 0,
+        }
+    }
+
+    fn next_nonce(&mut self) -> Nonce<aes_gcm::aead::consts::U12> {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..].iter_mut().zip(self.seq.to_be_bytes()) {
+            *n ^= s;
+        }
+        self.seq += 1;
+        nonce.into()
+    }
+
+    /// Write a protected record, returning its length. The content is
+    /// written by `fill`, into the buffer it's given, which returns its
+    /// length.
+    pub fn seal(
+        &mut self,
+        out: &mut [u8],
+        content_type: u8,
+        fill: impl FnOnce(&mut [u8]) -> Result<usize, Error>,
+    ) -> Result<usize, Error> {
+        if out.len() < HEADER_LEN + 1 + TAG_LEN {
+            return Err(Error::BufferTooSmall);
+        }
+        let room = (out.len() - HEADER_LEN - 1 - TAG_LEN).min(MAX_PLAINTEXT);
+        let content_len = fill(&mut out[HEADER_LEN..HEADER_LEN + room])?;
+        out[HEADER_LEN + content_len] = content_type;
+        let body_len = content_len + 1 + TAG_LEN;
+        write_header(out, APPLICATION_DATA, body_len);
+
+        let nonce = self.next_nonce();
+        let (header, body) = out.split_at_mut(HEADER_LEN);
+        let (plaintext, tag) = body[..body_len].split_at_mut(content_len + 1);
+        let computed = self
+            .aead
+            .encrypt_in_place_detached(&nonce, header, plaintext)
+            .map_err(|_| Error::BufferTooSmall)?;
+        tag.copy_from_slice(&computed);
+        Ok(HEADER_LEN + body_len)
+    }
+
+    /// Decrypt a protected record in place, returning its content type and
+    /// content
+    pub fn open<'r>(&mut self, record: &'r mut [u8]) -> Result<(u8, &'r mut [u8]), Error> {
+        if record.len() < HEADER_LEN + 1 + TAG_LEN {
+            return Err(Error::BadRecordMac);
+        }
+        let nonce = self.next_nonce();
+        let (header, body) = record.split_at_mut(HEADER_LEN);
+        let (ciphertext, tag) = body.split_at_mut(body.len() - TAG_LEN);
+        self.aead
+            .decrypt_in_place_detached(&nonce, header, ciphertext, Tag::from_slice(tag))
+            .map_err(|_| Error::BadRecordMac)?;
+        // The content type is the last byte that isn't padding
+        let end = ciphertext
+            .iter()
+            .rposition(|b| *b != 0)
+            .ok_or(Error::UnexpectedMessage)?;
+        Ok((ciphertext[end], &mut ciphertext[..end]))
+    }
+}
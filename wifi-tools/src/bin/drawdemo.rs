@@ -0,0 +1,34 @@
+//! Draws a test pattern on a board using the remote drawing protocol.
+//!
+//! Usage: drawdemo <address>[:port]
+
+use std::error::Error;
+
+use wifi_tools::draw::{rgb, DrawClient, DEFAULT_PORT};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: {} <address>[:port]", args[0]);
+        std::process::exit(1);
+    }
+    let address = if args[1].contains(':') {
+        args[1].clone()
+    } else {
+        format!("{}:{}", args[1], DEFAULT_PORT)
+    };
+
+    let mut client = DrawClient::connect(&address)?;
+    client.clear(rgb(0, 0, 0))?;
+    client.text(14, 0, 4, rgb(255, 255, 255), "Remote drawing")?;
+    client.fill_rect(14, 40, 80, 40, rgb(255, 0, 0))?;
+    client.circle(120, 40, 40, 2, Some(rgb(255, 255, 255)), Some(rgb(0, 128, 0)))?;
+
+    // A gradient, large enough to need several blits
+    let (width, height) = (128u16, 96u16);
+    let pixels: Vec<u16> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| rgb((x * 2) as u8, (y * 2) as u8, 128)))
+        .collect();
+    client.blit(180, 100, width, height, &pixels)?;
+    Ok(())
+}
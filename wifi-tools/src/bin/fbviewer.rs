@@ -0,0 +1,137 @@
+//! Receives the display mirror stream from a board, and writes the display
+//! contents to a png file whenever the stream goes quiet after an update.
+//!
+//! Usage: fbviewer <address>[:port] [output.png]
+//!
+//! If the output path contains `{}`, it is replaced with a frame number so
+//! that every frame is kept.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use wifi_protocol::mirror::{self, Message};
+
+const DEFAULT_PORT: u16 = 7000;
+
+/// How long the stream must be quiet before a frame is written
+const QUIET_TIME: Duration = Duration::from_millis(200);
+
+struct Frame {
+    width: usize,
+    height: usize,
+    /// RGB888
+    pixels: Vec<u8>,
+}
+
+impl Frame {
+    fn new(width: usize, height: usize) -> Frame {
+        Frame {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    fn apply_rect(&mut self, header: &mirror::RectHeader, pixels: &[u8]) {
+        let mut pixels = pixels.chunks_exact(2);
+        for y in header.y as usize..(header.y + header.height) as usize {
+            for x in header.x as usize..(header.x + header.width) as usize {
+                let p = match pixels.next() {
+                    Some(p) => u16::from_be_bytes([p[0], p[1]]),
+                    None => return,
+                };
+                if x >= self.width || y >= self.height {
+                    continue;
+                }
+                let at = (y * self.width + x) * 3;
+                self.pixels[at..at + 3].copy_from_slice(&rgb565_to_rgb888(p));
+            }
+        }
+    }
+
+    fn write_png(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        // Write then rename, so that viewers never see a partial file
+        let tmp = format!("{}.tmp", path);
+        let file = BufWriter::new(File::create(&tmp)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+fn rgb565_to_rgb888(p: u16) -> [u8; 3] {
+    let r = ((p >> 11) & 0x1f) as u8;
+    let g = ((p >> 5) & 0x3f) as u8;
+    let b = (p & 0x1f) as u8;
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <address>[:port] [output.png]", args[0]);
+        std::process::exit(1);
+    }
+    let address = if args[1].contains(':') {
+        args[1].clone()
+    } else {
+        format!("{}:{}", args[1], DEFAULT_PORT)
+    };
+    let output = args.get(2).cloned().unwrap_or_else(|| "display.png".to_owned());
+
+    let mut stream = TcpStream::connect(&address)?;
+    stream.set_read_timeout(Some(QUIET_TIME))?;
+    eprintln!("connected to {}", address);
+
+    let mut buf: Vec<u8> = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let mut frame: Option<Frame> = None;
+    let mut updated = false;
+    let mut frame_number = 0;
+
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => {
+                eprintln!("connection closed");
+                return Ok(());
+            }
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                if let (true, Some(frame)) = (updated, &frame) {
+                    let path = output.replace("{}", &format!("{:05}", frame_number));
+                    frame.write_png(&path)?;
+                    eprintln!("wrote {}", path);
+                    frame_number += 1;
+                    updated = false;
+                }
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut used = 0;
+        while let Some((message, len)) = mirror::decode(&buf[used..]).map_err(|e| format!("{:?}", e))? {
+            match message {
+                Message::Hello { width, height } => {
+                    eprintln!("display is {}x{}", width, height);
+                    frame = Some(Frame::new(width as usize, height as usize));
+                }
+                Message::Rect { header, pixels } => match &mut frame {
+                    Some(frame) => {
+                        frame.apply_rect(&header, pixels);
+                        updated = true;
+                    }
+                    None => return Err("rectangle before hello".into()),
+                },
+            }
+            used += len;
+        }
+        buf.drain(..used);
+    }
+}
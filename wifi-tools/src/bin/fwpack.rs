@@ -0,0 +1,24 @@
+//! Prefixes a cyw43 firmware blob with the header the firmware checks it
+//! against, ready for flashing to its partition.
+//!
+//! Usage: fwpack <blob.bin> <output>
+
+use std::error::Error;
+
+use wifi_protocol::blob::BlobHeader;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <blob.bin> <output>", args[0]);
+        std::process::exit(1);
+    }
+    let blob = std::fs::read(&args[1])?;
+    let header = BlobHeader::for_blob(&blob);
+
+    let mut packed = header.encode().to_vec();
+    packed.extend_from_slice(&blob);
+    std::fs::write(&args[2], &packed)?;
+    println!("{}: {} bytes, crc32 {:08x}", args[2], header.length, header.crc32);
+    Ok(())
+}
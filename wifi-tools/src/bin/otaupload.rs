@@ -0,0 +1,73 @@
+//! Sends a firmware update to a board.
+//!
+//! Usage: otaupload <address>[:port] <image.bin> <psk in hex>
+//!
+//! The image is the raw binary of `wifi-example`, without the boot2 section.
+//! The psk is the board's `tls_psk`, which the update is authenticated with.
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use wifi_protocol::ota::{self, ImageHeader};
+
+const DEFAULT_PORT: u16 = 3232;
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    // An odd length leaves half a byte at the end, which fails to parse
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 4 {
+        eprintln!("usage: {} <address>[:port] <image.bin> <psk in hex>", args[0]);
+        std::process::exit(1);
+    }
+    let address = if args[1].contains(':') {
+        args[1].clone()
+    } else {
+        format!("{}:{}", args[1], DEFAULT_PORT)
+    };
+    let image = std::fs::read(&args[2])?;
+    let key = parse_hex(&args[3]).ok_or("the psk must be hex")?;
+    let header = ImageHeader::for_image(&image).encode();
+
+    let mut stream = TcpStream::connect(&address)?;
+    let mut challenge = [0; ota::CHALLENGE_LEN];
+    stream.read_exact(&mut challenge)?;
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key)?;
+    mac.update(&challenge);
+    mac.update(&header);
+    let header_tag = mac.clone().finalize().into_bytes();
+    mac.update(&image);
+    let tag = mac.finalize().into_bytes();
+
+    println!("sending {} bytes", image.len());
+    let sent = (|| {
+        stream.write_all(&header)?;
+        stream.write_all(&header_tag)?;
+        stream.write_all(&image)?;
+        stream.write_all(&tag)
+    })();
+
+    // The board checks the image once it's all written to flash. If it
+    // refused the header instead, sending the rest fails, but the reply
+    // says why.
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply).ok();
+    if reply.is_empty() {
+        sent?;
+    }
+    match reply.trim_end() {
+        "ok" => println!("update accepted, the board is rebooting"),
+        "" => return Err("connection closed without a reply".into()),
+        other => return Err(other.into()),
+    }
+    Ok(())
+}
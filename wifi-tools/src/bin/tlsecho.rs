@@ -0,0 +1,116 @@
+//! Runs the board's TLS server on the host, echoing back whatever is sent,
+//! for trying it against an ordinary TLS client.
+//!
+//! Usage: tlsecho <port> <identity> <psk in hex>
+//!
+//! eg `tlsecho 4433 pico 00112233445566778899aabbccddeeff`, then
+//!
+//! ```text
+//! openssl s_client -connect localhost:4433 -tls1_3 -groups X25519 \
+//!     -psk_identity pico -psk 00112233445566778899aabbccddeeff
+//! ```
+
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use wifi_tls::{Connection, Psk, Received, HEADER_LEN};
+
+/// As on the board
+const BUFFER_SIZE: usize = 2048;
+
+fn random() -> Result<[u8; 32], Box<dyn Error>> {
+    let mut bytes = [0; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    // An odd length leaves half a byte at the end, which fails to parse
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Read one whole record into `buf`, returning its length, or None at the
+/// end of the stream
+fn read_record(stream: &mut TcpStream, buf: &mut [u8]) -> Result<Option<usize>, Box<dyn Error>> {
+    let mut header = [0; HEADER_LEN];
+    match stream.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = wifi_tls::record_len(&header).map_err(|e| format!("{:?}", e))?;
+    let record = buf.get_mut(..len).ok_or("record too large")?;
+    record[..HEADER_LEN].copy_from_slice(&header);
+    stream.read_exact(&mut record[HEADER_LEN..])?;
+    Ok(Some(len))
+}
+
+fn serve(stream: &mut TcpStream, psk: Psk<'_>) -> Result<(), Box<dyn Error>> {
+    let mut conn = Connection::new(psk, random()?, random()?);
+    let mut rx = [0; BUFFER_SIZE];
+    let mut tx = [0; BUFFER_SIZE];
+    loop {
+        let len = match read_record(stream, &mut rx)? {
+            Some(len) => len,
+            None => return Err("connection closed".into()),
+        };
+        match conn.receive(&mut rx[..len], &mut tx) {
+            Ok(Received::Reply(n)) => stream.write_all(&tx[..n])?,
+            Ok(Received::Nothing) => {
+                if conn.is_established() {
+                    println!("handshake complete");
+                }
+            }
+            Ok(Received::Data(data)) => {
+                print!("{}", String::from_utf8_lossy(data));
+                let mut data = data;
+                while !data.is_empty() {
+                    let (n, sent) = conn.send(data, &mut tx).map_err(|e| format!("{:?}", e))?;
+                    stream.write_all(&tx[..n])?;
+                    data = &data[sent..];
+                }
+            }
+            Ok(Received::Closed) => {
+                let n = conn.close(&mut tx).map_err(|e| format!("{:?}", e))?;
+                stream.write_all(&tx[..n])?;
+                return Ok(());
+            }
+            Err(e) => {
+                if let Ok(n) = conn.fail(e, &mut tx) {
+                    stream.write_all(&tx[..n]).ok();
+                }
+                return Err(format!("{:?}", e).into());
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 4 {
+        eprintln!("usage: {} <port> <identity> <psk in hex>", args[0]);
+        std::process::exit(1);
+    }
+    let port: u16 = args[1].parse()?;
+    let key = parse_hex(&args[3]).ok_or("the psk must be hex")?;
+    let psk = Psk {
+        identity: args[2].as_bytes(),
+        key: &key,
+    };
+
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    println!("listening on tcp port {}", port);
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        println!("connection from {}", stream.peer_addr()?);
+        match serve(&mut stream, psk) {
+            Ok(()) => println!("closed"),
+            Err(e) => println!("failed: {}", e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+//! Listens for boards' discovery beacons and telemetry, and prints them.
+//!
+//! Usage: udplisten [telemetry port]
+//!
+//! Telemetry is only sent once the board is configured with this host's
+//! address, eg `telemetry = 192.168.1.20`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::UdpSocket;
+use std::thread;
+
+use wifi_protocol::beacon::{self, Beacon};
+use wifi_protocol::telemetry::{self, Event, Led, Message};
+
+fn listen_beacons(socket: UdpSocket) {
+    let mut buf = [0u8; beacon::MAX_LEN];
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("beacon receive error: {}", e);
+                continue;
+            }
+        };
+        match Beacon::decode(&buf[..n]) {
+            Ok(beacon) => {
+                let id: Vec<String> = beacon.board_id.iter().map(|b| format!("{:02x}", b)).collect();
+                let address: Vec<String> = beacon.address.iter().map(|b| b.to_string()).collect();
+                println!(
+                    "beacon {} {} {} version {}",
+                    id.join(":"),
+                    address.join("."),
+                    beacon.hostname,
+                    beacon.version
+                );
+            }
+            Err(e) => eprintln!("bad beacon from {}: {:?}", from, e),
+        }
+    }
+}
+
+fn listen_telemetry(socket: UdpSocket) {
+    let mut buf = [0u8; 64];
+    // The next sequence number expected from each board
+    let mut next_seq = HashMap::new();
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("telemetry receive error: {}", e);
+                continue;
+            }
+        };
+        let message = match Message::decode(&buf[..n]) {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("bad telemetry from {}: {:?}", from, e);
+                continue;
+            }
+        };
+        if let Some(expected) = next_seq.insert(from.ip(), message.seq.wrapping_add(1)) {
+            let lost = message.seq.wrapping_sub(expected);
+            if lost > 0 && lost < u32::MAX / 2 {
+                println!("{} lost {} events", from.ip(), lost);
+            }
+        }
+        let event = match message.event {
+            Event::Button { pressed: true } => "button pressed".to_owned(),
+            Event::Button { pressed: false } => "button released".to_owned(),
+            Event::Tap { x, y } => format!("tap {} {}", x, y),
+            Event::Swipe(direction) => format!("swipe {:?}", direction).to_lowercase(),
+            Event::Led(Led::Off) => "led off".to_owned(),
+            Event::Led(Led::On) => "led on".to_owned(),
+            Event::Led(Led::Blink { period_ms }) => format!("led blink {}ms", period_ms),
+        };
+        println!(
+            "{} {:>10.3}s #{} {}",
+            from.ip(),
+            message.uptime_ms as f64 / 1000.0,
+            message.seq,
+            event
+        );
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let telemetry_port = match args.len() {
+        1 => telemetry::DEFAULT_PORT,
+        2 => args[1].parse()?,
+        _ => {
+            eprintln!("usage: {} [telemetry port]", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    let beacons = UdpSocket::bind(("0.0.0.0", beacon::PORT))?;
+    let telemetry = UdpSocket::bind(("0.0.0.0", telemetry_port))?;
+    println!(
+        "listening for beacons on udp port {} and telemetry on {}",
+        beacon::PORT,
+        telemetry_port
+    );
+
+    thread::spawn(move || listen_beacons(beacons));
+    listen_telemetry(telemetry);
+    Ok(())
+}
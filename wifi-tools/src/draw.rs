@@ -0,0 +1,143 @@
+//! A client for the remote drawing protocol. See `wifi_protocol::draw`.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use wifi_protocol::draw::{self, Command, ErrorCode};
+
+pub const DEFAULT_PORT: u16 = 7001;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Protocol(draw::Error),
+    /// The board rejected the command
+    Board(ErrorCode),
+    /// The board replied with an unknown status
+    BadReply(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Protocol(e) => write!(f, "protocol error: {:?}", e),
+            Error::Board(e) => write!(f, "board rejected command: {:?}", e),
+            Error::BadReply(b) => write!(f, "unexpected reply: {}", b),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<draw::Error> for Error {
+    fn from(e: draw::Error) -> Self {
+        Error::Protocol(e)
+    }
+}
+
+/// Convert an RGB888 color to RGB565
+pub fn rgb(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+pub struct DrawClient {
+    stream: TcpStream,
+}
+
+impl DrawClient {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> Result<DrawClient, Error> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(DrawClient { stream })
+    }
+
+    /// Send a command, and wait for the board's reply
+    pub fn send(&mut self, command: &Command) -> Result<(), Error> {
+        let mut buf = vec![0u8; command.encoded_len()];
+        command.encode(&mut buf)?;
+        self.stream.write_all(&buf)?;
+        let mut reply = [0u8; 1];
+        self.stream.read_exact(&mut reply)?;
+        match ErrorCode::from_u8(reply[0]) {
+            Some(ErrorCode::Ok) => Ok(()),
+            Some(e) => Err(Error::Board(e)),
+            None => Err(Error::BadReply(reply[0])),
+        }
+    }
+
+    pub fn clear(&mut self, color: u16) -> Result<(), Error> {
+        self.send(&Command::Clear { color })
+    }
+
+    pub fn fill_rect(&mut self, x: i16, y: i16, width: u16, height: u16, color: u16) -> Result<(), Error> {
+        self.send(&Command::FillRect {
+            x,
+            y,
+            width,
+            height,
+            color,
+        })
+    }
+
+    pub fn text(&mut self, x: i16, y: i16, font: u8, color: u16, text: &str) -> Result<(), Error> {
+        self.send(&Command::Text {
+            x,
+            y,
+            font,
+            color,
+            text,
+        })
+    }
+
+    pub fn circle(
+        &mut self,
+        x: i16,
+        y: i16,
+        diameter: u16,
+        stroke_width: u8,
+        stroke: Option<u16>,
+        fill: Option<u16>,
+    ) -> Result<(), Error> {
+        self.send(&Command::Circle {
+            x,
+            y,
+            diameter,
+            stroke_width,
+            stroke,
+            fill,
+        })
+    }
+
+    /// Draw an RGB565 image, row major. Large images are sent in bands of rows.
+    pub fn blit(&mut self, x: i16, y: i16, width: u16, height: u16, pixels: &[u16]) -> Result<(), Error> {
+        if pixels.len() != width as usize * height as usize {
+            return Err(Error::Protocol(draw::Error::BadLength));
+        }
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let band_rows = (draw::MAX_BLIT_PIXELS / width as usize).max(1);
+        if band_rows * (width as usize) > draw::MAX_BLIT_PIXELS {
+            return Err(Error::Protocol(draw::Error::PayloadTooLarge));
+        }
+        for (i, band) in pixels.chunks(band_rows * width as usize).enumerate() {
+            let bytes: Vec<u8> = band.iter().flat_map(|p| p.to_be_bytes()).collect();
+            self.send(&Command::Blit {
+                x,
+                y: y + (i * band_rows) as i16,
+                width,
+                height: (band.len() / width as usize) as u16,
+                pixels: &bytes,
+            })?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,3 @@
+//! Host side tools for talking to the wifi-example firmware.
+
+pub mod draw;